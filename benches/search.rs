@@ -0,0 +1,120 @@
+//! Times searches of a RocksDB store
+//!
+//! Run with "cargo bench --bench search". Each query is searched for against the same index
+//! of 10000 documents and the average time of a search is printed.
+
+extern crate rusticsearch;
+extern crate fnv;
+
+use std::fs::remove_dir_all;
+use std::time::Instant;
+
+use fnv::FnvHashMap;
+
+use rusticsearch::search::term::Term;
+use rusticsearch::search::token::Token;
+use rusticsearch::search::schema::{FieldType, FieldId, FIELD_INDEXED, FIELD_STORED};
+use rusticsearch::search::document::{Document, FieldValue};
+use rusticsearch::search::query::Query;
+use rusticsearch::search::query::term_scorer::TermScorer;
+use rusticsearch::search::collectors::top_score::TopScoreCollector;
+use rusticsearch::search::backends::Reader;
+
+use rusticsearch::search::backends::rocksdb::RocksDBStore;
+
+
+const INDEX_PATH: &'static str = "test_indices/bench_search";
+
+/// The number of times each query is searched for, after a few searches to warm up the caches
+const ITERATIONS: u32 = 100;
+const WARMUP_ITERATIONS: u32 = 10;
+
+
+/// Builds an index of 10000 documents
+///
+/// The bodies are made from a small vocabulary, so the common words match most documents and
+/// occur more than once in each of them.
+fn make_store(path: &str) -> (RocksDBStore, FieldId) {
+    let _ = remove_dir_all(path);
+
+    let mut store = RocksDBStore::create(path).unwrap();
+    let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+    let id_field = store.add_field("id".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+    let mut docs = Vec::new();
+    for i in 0..10000 {
+        let mut tokens = Vec::new();
+        for t in 0..50 {
+            // Lower numbered words are more common
+            let word = (i * 7 + t * 13) % (t + 2);
+            tokens.push(Token {
+                term: Term::from_string(&word.to_string()),
+                position: t as u32 + 1,
+            });
+        }
+
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(body_field, tokens.into());
+
+        let mut stored_fields = FnvHashMap::default();
+        stored_fields.insert(id_field, FieldValue::Integer(i as i64));
+
+        docs.push(Document {
+            key: i.to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: stored_fields,
+        });
+    }
+
+    let conditions = vec![None; docs.len()];
+    store.insert_or_update_documents(&docs, &conditions).unwrap();
+
+    (store, body_field)
+}
+
+fn term_query(field: FieldId, word: &str) -> Query {
+    Query::Term {
+        field: field,
+        term: Term::from_string(word),
+        scorer: TermScorer::default(),
+    }
+}
+
+/// Searches for the query repeatedly and prints the average time of each search
+fn bench_search(name: &str, store: &RocksDBStore, query: &Query) {
+    let search = || {
+        let mut collector = TopScoreCollector::new(10);
+        store.reader().search(&mut collector, query).unwrap();
+        collector.into_sorted_vec()
+    };
+
+    for _ in 0..WARMUP_ITERATIONS {
+        search();
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        search();
+    }
+    let elapsed = start.elapsed();
+
+    let elapsed_ns = elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64;
+    println!("{:<20} {:>12} ns/iter", name, elapsed_ns / ITERATIONS as u64);
+}
+
+fn main() {
+    let (store, body_field) = make_store(INDEX_PATH);
+
+    bench_search("search_term", &store, &term_query(body_field, "1"));
+
+    bench_search("search_disjunction", &store, &Query::Disjunction {
+        queries: vec![
+            term_query(body_field, "0"),
+            term_query(body_field, "1"),
+            term_query(body_field, "5"),
+        ],
+    });
+
+    drop(store);
+    let _ = remove_dir_all(INDEX_PATH);
+}
@@ -0,0 +1,734 @@
+//! Runs aggregations on the documents matched by a search
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, BTreeMap};
+
+use serde_json::{self, Map};
+use chrono::{DateTime, Utc, TimeZone, Datelike, Duration};
+use search::document::{DocId, FieldValue};
+use search::collectors::{Collector, DocumentMatch, FieldValueReader};
+
+use aggregations::{Aggregations, Aggregation, Metric, RangeBucket, DateInterval};
+
+
+/// The maximum number of buckets that a histogram will add to fill gaps between values
+const MAX_EMPTY_BUCKETS: usize = 10000;
+
+
+/// A field value that can be used as the key of a bucket
+///
+/// Datetimes are converted into milliseconds since the epoch
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum BucketKey {
+    Boolean(bool),
+    Integer(i64),
+    DateTime(i64),
+    String(String),
+}
+
+
+impl BucketKey {
+    fn from_field_value(value: &FieldValue) -> BucketKey {
+        match *value {
+            FieldValue::String(ref string) => BucketKey::String(string.clone()),
+            FieldValue::Integer(value) => BucketKey::Integer(value),
+            FieldValue::Boolean(value) => BucketKey::Boolean(value),
+            FieldValue::DateTime(ref value) => BucketKey::DateTime(datetime_to_millis(value)),
+        }
+    }
+
+    fn add_to_json(&self, bucket: &mut Map<String, serde_json::Value>) {
+        match *self {
+            BucketKey::Boolean(value) => {
+                let key = if value { 1 } else { 0 };
+                bucket.insert("key".to_string(), json!(key));
+                bucket.insert("key_as_string".to_string(), json!(value.to_string()));
+            }
+            BucketKey::Integer(value) => {
+                bucket.insert("key".to_string(), json!(value));
+            }
+            BucketKey::DateTime(value) => {
+                bucket.insert("key".to_string(), json!(value));
+                bucket.insert("key_as_string".to_string(), json!(millis_to_datetime(value).to_rfc3339()));
+            }
+            BucketKey::String(ref value) => {
+                bucket.insert("key".to_string(), json!(value));
+            }
+        }
+    }
+}
+
+
+/// Integer division that rounds towards negative infinity
+fn floor_div(a: i64, b: i64) -> i64 {
+    let quotient = a / b;
+
+    if a % b != 0 && (a < 0) != (b < 0) {
+        quotient - 1
+    } else {
+        quotient
+    }
+}
+
+
+fn datetime_to_millis(datetime: &DateTime<Utc>) -> i64 {
+    datetime.timestamp() * 1000 + datetime.timestamp_subsec_millis() as i64
+}
+
+
+fn millis_to_datetime(millis: i64) -> DateTime<Utc> {
+    let seconds = floor_div(millis, 1000);
+    let nanos = (millis - seconds * 1000) * 1000000;
+    Utc.timestamp(seconds, nanos as u32)
+}
+
+
+/// Converts a field value into a number, for aggregations that do arithmetic
+fn numeric_value(value: &FieldValue) -> Option<f64> {
+    match *value {
+        FieldValue::String(ref string) => string.parse::<f64>().ok(),
+        FieldValue::Integer(value) => Some(value as f64),
+        FieldValue::Boolean(value) => Some(if value { 1.0 } else { 0.0 }),
+        FieldValue::DateTime(ref value) => Some(datetime_to_millis(value) as f64),
+    }
+}
+
+
+/// Finds the start of the date histogram bucket that contains the given time
+fn round_date(interval: DateInterval, millis: i64) -> i64 {
+    let datetime = millis_to_datetime(millis);
+
+    let bucket_start = match interval {
+        DateInterval::Year => Utc.ymd(datetime.year(), 1, 1).and_hms(0, 0, 0),
+        DateInterval::Quarter => Utc.ymd(datetime.year(), datetime.month0() / 3 * 3 + 1, 1).and_hms(0, 0, 0),
+        DateInterval::Month => Utc.ymd(datetime.year(), datetime.month(), 1).and_hms(0, 0, 0),
+        DateInterval::Week => {
+            // Weeks start on Monday
+            let date = datetime.date() - Duration::days(datetime.weekday().num_days_from_monday() as i64);
+            date.and_hms(0, 0, 0)
+        }
+        DateInterval::Fixed(interval) => return floor_div(millis, interval) * interval,
+    };
+
+    datetime_to_millis(&bucket_start)
+}
+
+
+/// Finds the start of the date histogram bucket after the one starting at the given time
+fn next_date(interval: DateInterval, bucket_start: i64) -> i64 {
+    let datetime = millis_to_datetime(bucket_start);
+
+    let add_months = |months: i64| {
+        let month_number = datetime.year() as i64 * 12 + datetime.month0() as i64 + months;
+        let year = floor_div(month_number, 12);
+        let month0 = month_number - year * 12;
+
+        datetime_to_millis(&Utc.ymd(year as i32, month0 as u32 + 1, 1).and_hms(0, 0, 0))
+    };
+
+    match interval {
+        DateInterval::Year => add_months(12),
+        DateInterval::Quarter => add_months(3),
+        DateInterval::Month => add_months(1),
+        DateInterval::Week => bucket_start + 7 * 86400000,
+        DateInterval::Fixed(interval) => bucket_start + interval,
+    }
+}
+
+
+#[derive(Debug)]
+struct Bucket {
+    doc_count: u64,
+    aggs: Vec<AggregationState>,
+}
+
+
+impl Bucket {
+    fn new(aggs: &Aggregations) -> Bucket {
+        Bucket {
+            doc_count: 0,
+            aggs: aggs.iter().map(|&(_, ref aggregation)| AggregationState::new(aggregation)).collect(),
+        }
+    }
+
+    fn collect<R: FieldValueReader + ?Sized>(&mut self, aggs: &Aggregations, reader: &R, doc_id: DocId) {
+        self.doc_count += 1;
+        collect_aggregations(aggs, &mut self.aggs, reader, doc_id);
+    }
+
+    /// Adds the document count and the results of the sub aggregations to a bucket's JSON
+    fn to_json(&self, aggs: &Aggregations, mut bucket_json: Map<String, serde_json::Value>) -> serde_json::Value {
+        bucket_json.insert("doc_count".to_string(), json!(self.doc_count));
+
+        for (&(ref name, ref aggregation), state) in aggs.iter().zip(self.aggs.iter()) {
+            bucket_json.insert(name.clone(), state.to_json(aggregation));
+        }
+
+        serde_json::Value::Object(bucket_json)
+    }
+}
+
+
+#[derive(Debug)]
+struct MetricState {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    distinct_values: HashSet<BucketKey>,
+}
+
+
+impl MetricState {
+    fn new() -> MetricState {
+        MetricState {
+            count: 0,
+            sum: 0.0,
+            min: ::std::f64::INFINITY,
+            max: ::std::f64::NEG_INFINITY,
+            distinct_values: HashSet::new(),
+        }
+    }
+
+    fn collect(&mut self, metric: Metric, value: &FieldValue) {
+        match metric {
+            Metric::ValueCount => {
+                self.count += 1;
+            }
+            Metric::Cardinality => {
+                self.distinct_values.insert(BucketKey::from_field_value(value));
+            }
+            _ => {
+                if let Some(value) = numeric_value(value) {
+                    self.count += 1;
+                    self.sum += value;
+
+                    if value < self.min {
+                        self.min = value;
+                    }
+
+                    if value > self.max {
+                        self.max = value;
+                    }
+                }
+            }
+        }
+    }
+
+    fn to_json(&self, metric: Metric) -> serde_json::Value {
+        let (min, max, avg) = if self.count > 0 {
+            (Some(self.min), Some(self.max), Some(self.sum / self.count as f64))
+        } else {
+            (None, None, None)
+        };
+
+        match metric {
+            Metric::Min => json!({"value": min}),
+            Metric::Max => json!({"value": max}),
+            Metric::Avg => json!({"value": avg}),
+            Metric::Sum => json!({"value": self.sum}),
+            Metric::Stats => {
+                json!({
+                    "count": self.count,
+                    "min": min,
+                    "max": max,
+                    "avg": avg,
+                    "sum": self.sum,
+                })
+            }
+            Metric::ValueCount => json!({"value": self.count}),
+            Metric::Cardinality => json!({"value": self.distinct_values.len()}),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+enum AggregationState {
+    Terms(HashMap<BucketKey, Bucket>),
+
+    /// Buckets are keyed by their value divided by the interval
+    Histogram(BTreeMap<i64, Bucket>),
+
+    /// Buckets are keyed by the time they start at
+    DateHistogram(BTreeMap<i64, Bucket>),
+
+    Range(Vec<Bucket>),
+    Metric(MetricState),
+}
+
+
+impl AggregationState {
+    fn new(aggregation: &Aggregation) -> AggregationState {
+        match *aggregation {
+            Aggregation::Terms { .. } => AggregationState::Terms(HashMap::new()),
+            Aggregation::Histogram { .. } => AggregationState::Histogram(BTreeMap::new()),
+            Aggregation::DateHistogram { .. } => AggregationState::DateHistogram(BTreeMap::new()),
+            Aggregation::Range { ref ranges, ref aggs, .. } => {
+                AggregationState::Range(ranges.iter().map(|_| Bucket::new(aggs)).collect())
+            }
+            Aggregation::Metric { .. } => AggregationState::Metric(MetricState::new()),
+        }
+    }
+
+    fn collect<R: FieldValueReader + ?Sized>(&mut self, aggregation: &Aggregation, reader: &R, doc_id: DocId) {
+        match (aggregation, self) {
+            (&Aggregation::Terms { field, ref aggs, .. }, &mut AggregationState::Terms(ref mut buckets)) => {
+                if let Some(value) = reader.read_field_value(field, doc_id) {
+                    buckets.entry(BucketKey::from_field_value(&value))
+                        .or_insert_with(|| Bucket::new(aggs))
+                        .collect(aggs, reader, doc_id);
+                }
+            }
+            (&Aggregation::Histogram { field, interval, ref aggs, .. }, &mut AggregationState::Histogram(ref mut buckets)) => {
+                if let Some(value) = reader.read_field_value(field, doc_id).as_ref().and_then(numeric_value) {
+                    buckets.entry((value / interval).floor() as i64)
+                        .or_insert_with(|| Bucket::new(aggs))
+                        .collect(aggs, reader, doc_id);
+                }
+            }
+            (&Aggregation::DateHistogram { field, interval, ref aggs, .. }, &mut AggregationState::DateHistogram(ref mut buckets)) => {
+                let millis = match reader.read_field_value(field, doc_id) {
+                    Some(FieldValue::DateTime(ref value)) => datetime_to_millis(value),
+                    Some(FieldValue::Integer(value)) => value,
+                    _ => return,
+                };
+
+                buckets.entry(round_date(interval, millis))
+                    .or_insert_with(|| Bucket::new(aggs))
+                    .collect(aggs, reader, doc_id);
+            }
+            (&Aggregation::Range { field, ref ranges, ref aggs }, &mut AggregationState::Range(ref mut buckets)) => {
+                if let Some(value) = reader.read_field_value(field, doc_id).as_ref().and_then(numeric_value) {
+                    for (range, bucket) in ranges.iter().zip(buckets.iter_mut()) {
+                        let after_from = range.from.map(|from| value >= from).unwrap_or(true);
+                        let before_to = range.to.map(|to| value < to).unwrap_or(true);
+
+                        if after_from && before_to {
+                            bucket.collect(aggs, reader, doc_id);
+                        }
+                    }
+                }
+            }
+            (&Aggregation::Metric { field, metric }, &mut AggregationState::Metric(ref mut state)) => {
+                if let Some(value) = reader.read_field_value(field, doc_id) {
+                    state.collect(metric, &value);
+                }
+            }
+            _ => panic!("aggregation state doesn't match the aggregation"),
+        }
+    }
+
+    fn to_json(&self, aggregation: &Aggregation) -> serde_json::Value {
+        match (aggregation, self) {
+            (&Aggregation::Terms { size, min_doc_count, ref aggs, .. }, &AggregationState::Terms(ref buckets)) => {
+                let mut buckets = buckets.iter()
+                    .filter(|&(_, bucket)| bucket.doc_count >= min_doc_count)
+                    .collect::<Vec<_>>();
+
+                // Most frequent terms first
+                buckets.sort_by(|&(a_key, a), &(b_key, b)| {
+                    match b.doc_count.cmp(&a.doc_count) {
+                        Ordering::Equal => a_key.cmp(b_key),
+                        ordering => ordering,
+                    }
+                });
+
+                let sum_other_doc_count = buckets.iter().skip(size).map(|&(_, bucket)| bucket.doc_count).sum::<u64>();
+                buckets.truncate(size);
+
+                let buckets_json = buckets.into_iter().map(|(key, bucket)| {
+                    let mut bucket_json = Map::new();
+                    key.add_to_json(&mut bucket_json);
+                    bucket.to_json(aggs, bucket_json)
+                }).collect::<Vec<_>>();
+
+                json!({
+                    "doc_count_error_upper_bound": 0,
+                    "sum_other_doc_count": sum_other_doc_count,
+                    "buckets": buckets_json,
+                })
+            }
+            (&Aggregation::Histogram { interval, min_doc_count, ref aggs, .. }, &AggregationState::Histogram(ref buckets)) => {
+                // Fill the gaps between buckets with empty ones
+                let mut indices = buckets.keys().cloned().collect::<Vec<i64>>();
+                if min_doc_count == 0 && !indices.is_empty() {
+                    let first = indices[0];
+                    let last = indices[indices.len() - 1];
+
+                    if ((last - first) as usize) < MAX_EMPTY_BUCKETS {
+                        indices = (first..last + 1).collect();
+                    }
+                }
+
+                let empty_bucket = Bucket::new(aggs);
+                let buckets_json = indices.into_iter()
+                    .map(|index| (index, buckets.get(&index).unwrap_or(&empty_bucket)))
+                    .filter(|&(_, bucket)| bucket.doc_count >= min_doc_count)
+                    .map(|(index, bucket)| {
+                        let mut bucket_json = Map::new();
+                        bucket_json.insert("key".to_string(), json!(index as f64 * interval));
+                        bucket.to_json(aggs, bucket_json)
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({
+                    "buckets": buckets_json,
+                })
+            }
+            (&Aggregation::DateHistogram { interval, min_doc_count, ref aggs, .. }, &AggregationState::DateHistogram(ref buckets)) => {
+                // Fill the gaps between buckets with empty ones
+                let mut bucket_starts = buckets.keys().cloned().collect::<Vec<i64>>();
+                if min_doc_count == 0 && !bucket_starts.is_empty() {
+                    let last = bucket_starts[bucket_starts.len() - 1];
+                    let mut all_bucket_starts = vec![bucket_starts[0]];
+
+                    while all_bucket_starts.len() <= MAX_EMPTY_BUCKETS {
+                        let bucket_start = next_date(interval, all_bucket_starts[all_bucket_starts.len() - 1]);
+                        if bucket_start > last {
+                            bucket_starts = all_bucket_starts;
+                            break;
+                        }
+
+                        all_bucket_starts.push(bucket_start);
+                    }
+                }
+
+                let empty_bucket = Bucket::new(aggs);
+                let buckets_json = bucket_starts.into_iter()
+                    .map(|bucket_start| (bucket_start, buckets.get(&bucket_start).unwrap_or(&empty_bucket)))
+                    .filter(|&(_, bucket)| bucket.doc_count >= min_doc_count)
+                    .map(|(bucket_start, bucket)| {
+                        let mut bucket_json = Map::new();
+                        BucketKey::DateTime(bucket_start).add_to_json(&mut bucket_json);
+                        bucket.to_json(aggs, bucket_json)
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({
+                    "buckets": buckets_json,
+                })
+            }
+            (&Aggregation::Range { ref ranges, ref aggs, .. }, &AggregationState::Range(ref buckets)) => {
+                let buckets_json = ranges.iter().zip(buckets.iter())
+                    .map(|(range, bucket)| {
+                        let mut bucket_json = Map::new();
+                        bucket_json.insert("key".to_string(), json!(range_key(range)));
+
+                        if let Some(from) = range.from {
+                            bucket_json.insert("from".to_string(), json!(from));
+                        }
+
+                        if let Some(to) = range.to {
+                            bucket_json.insert("to".to_string(), json!(to));
+                        }
+
+                        bucket.to_json(aggs, bucket_json)
+                    })
+                    .collect::<Vec<_>>();
+
+                json!({
+                    "buckets": buckets_json,
+                })
+            }
+            (&Aggregation::Metric { metric, .. }, &AggregationState::Metric(ref state)) => state.to_json(metric),
+            _ => panic!("aggregation state doesn't match the aggregation"),
+        }
+    }
+}
+
+
+/// Generates a key for a range bucket that doesn't have one (eg, "100.0-200.0" or "*-100.0")
+fn range_key(range: &RangeBucket) -> String {
+    if let Some(ref key) = range.key {
+        return key.clone();
+    }
+
+    let from = range.from.map(|from| format!("{:?}", from)).unwrap_or_else(|| "*".to_string());
+    let to = range.to.map(|to| format!("{:?}", to)).unwrap_or_else(|| "*".to_string());
+
+    format!("{}-{}", from, to)
+}
+
+
+fn collect_aggregations<R: FieldValueReader + ?Sized>(aggregations: &Aggregations, states: &mut Vec<AggregationState>, reader: &R, doc_id: DocId) {
+    for (&(_, ref aggregation), state) in aggregations.iter().zip(states.iter_mut()) {
+        state.collect(aggregation, reader, doc_id);
+    }
+}
+
+
+/// A collector that runs aggregations
+pub struct AggregationCollector<'a, R: FieldValueReader + ?Sized + 'a> {
+    aggregations: &'a Aggregations,
+    reader: &'a R,
+    states: Vec<AggregationState>,
+}
+
+
+impl<'a, R: FieldValueReader + ?Sized + 'a> AggregationCollector<'a, R> {
+    pub fn new(aggregations: &'a Aggregations, reader: &'a R) -> AggregationCollector<'a, R> {
+        AggregationCollector {
+            aggregations: aggregations,
+            reader: reader,
+            states: aggregations.iter().map(|&(_, ref aggregation)| AggregationState::new(aggregation)).collect(),
+        }
+    }
+
+    /// Converts the results into the "aggregations" section of an Elasticsearch search response
+    pub fn into_json(self) -> serde_json::Value {
+        let mut results = Map::new();
+
+        for (&(ref name, ref aggregation), state) in self.aggregations.iter().zip(self.states.iter()) {
+            results.insert(name.clone(), state.to_json(aggregation));
+        }
+
+        serde_json::Value::Object(results)
+    }
+}
+
+
+impl<'a, R: FieldValueReader + ?Sized + 'a> Collector for AggregationCollector<'a, R> {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        collect_aggregations(self.aggregations, &mut self.states, self.reader, DocId::from_u64(doc.doc_id()));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{TimeZone, Utc};
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::segment::SegmentId;
+    use search::collectors::{Collector, DocumentMatch, FieldValueReader};
+    use aggregations::{Aggregation, Metric, RangeBucket, DateInterval};
+
+    use super::AggregationCollector;
+
+    const CATEGORY: FieldId = FieldId(1);
+    const PRICE: FieldId = FieldId(2);
+    const PUBLISHED: FieldId = FieldId(3);
+
+    struct TestReader {
+        values: HashMap<(FieldId, DocId), FieldValue>,
+    }
+
+    impl FieldValueReader for TestReader {
+        fn read_field_value(&self, field: FieldId, doc_id: DocId) -> Option<FieldValue> {
+            self.values.get(&(field, doc_id)).cloned()
+        }
+    }
+
+    fn make_test_reader() -> TestReader {
+        let docs = vec![
+            ("fruit", 10, Utc.ymd(2017, 1, 15).and_hms(12, 0, 0)),
+            ("fruit", 30, Utc.ymd(2017, 1, 20).and_hms(12, 0, 0)),
+            ("vegetable", 5, Utc.ymd(2017, 3, 1).and_hms(12, 0, 0)),
+        ];
+
+        let mut values = HashMap::new();
+        for (i, (category, price, published)) in docs.into_iter().enumerate() {
+            let doc_id = DocId(SegmentId(1), i as u32);
+            values.insert((CATEGORY, doc_id), FieldValue::String(category.to_string()));
+            values.insert((PRICE, doc_id), FieldValue::Integer(price));
+            values.insert((PUBLISHED, doc_id), FieldValue::DateTime(published));
+        }
+
+        TestReader {
+            values: values,
+        }
+    }
+
+    fn collect_all<R: FieldValueReader>(collector: &mut AggregationCollector<R>) {
+        // The last document doesn't have any values
+        for i in 0..4 {
+            collector.collect(DocumentMatch::new_unscored(DocId(SegmentId(1), i).as_u64()));
+        }
+    }
+
+    #[test]
+    fn test_terms_aggregation() {
+        let reader = make_test_reader();
+        let aggs = vec![
+            ("categories".to_string(), Aggregation::Terms {
+                field: CATEGORY,
+                size: 10,
+                min_doc_count: 1,
+                aggs: vec![
+                    ("avg_price".to_string(), Aggregation::Metric {
+                        field: PRICE,
+                        metric: Metric::Avg,
+                    }),
+                ],
+            }),
+        ];
+
+        let mut collector = AggregationCollector::new(&aggs, &reader);
+        collect_all(&mut collector);
+
+        assert_eq!(collector.into_json(), json!({
+            "categories": {
+                "doc_count_error_upper_bound": 0,
+                "sum_other_doc_count": 0,
+                "buckets": [
+                    {"key": "fruit", "doc_count": 2, "avg_price": {"value": 20.0}},
+                    {"key": "vegetable", "doc_count": 1, "avg_price": {"value": 5.0}},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_terms_aggregation_size() {
+        let reader = make_test_reader();
+        let aggs = vec![
+            ("categories".to_string(), Aggregation::Terms {
+                field: CATEGORY,
+                size: 1,
+                min_doc_count: 1,
+                aggs: vec![],
+            }),
+        ];
+
+        let mut collector = AggregationCollector::new(&aggs, &reader);
+        collect_all(&mut collector);
+
+        assert_eq!(collector.into_json(), json!({
+            "categories": {
+                "doc_count_error_upper_bound": 0,
+                "sum_other_doc_count": 1,
+                "buckets": [
+                    {"key": "fruit", "doc_count": 2},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_histogram_aggregation() {
+        let reader = make_test_reader();
+        let aggs = vec![
+            ("prices".to_string(), Aggregation::Histogram {
+                field: PRICE,
+                interval: 10.0,
+                min_doc_count: 0,
+                aggs: vec![],
+            }),
+        ];
+
+        let mut collector = AggregationCollector::new(&aggs, &reader);
+        collect_all(&mut collector);
+
+        // Gaps between buckets should be filled
+        assert_eq!(collector.into_json(), json!({
+            "prices": {
+                "buckets": [
+                    {"key": 0.0, "doc_count": 1},
+                    {"key": 10.0, "doc_count": 1},
+                    {"key": 20.0, "doc_count": 0},
+                    {"key": 30.0, "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_date_histogram_aggregation() {
+        let reader = make_test_reader();
+        let aggs = vec![
+            ("per_month".to_string(), Aggregation::DateHistogram {
+                field: PUBLISHED,
+                interval: DateInterval::Month,
+                min_doc_count: 0,
+                aggs: vec![],
+            }),
+        ];
+
+        let mut collector = AggregationCollector::new(&aggs, &reader);
+        collect_all(&mut collector);
+
+        let january = Utc.ymd(2017, 1, 1).and_hms(0, 0, 0);
+        let february = Utc.ymd(2017, 2, 1).and_hms(0, 0, 0);
+        let march = Utc.ymd(2017, 3, 1).and_hms(0, 0, 0);
+
+        assert_eq!(collector.into_json(), json!({
+            "per_month": {
+                "buckets": [
+                    {"key": january.timestamp() * 1000, "key_as_string": january.to_rfc3339(), "doc_count": 2},
+                    {"key": february.timestamp() * 1000, "key_as_string": february.to_rfc3339(), "doc_count": 0},
+                    {"key": march.timestamp() * 1000, "key_as_string": march.to_rfc3339(), "doc_count": 1},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_range_aggregation() {
+        let reader = make_test_reader();
+        let aggs = vec![
+            ("prices".to_string(), Aggregation::Range {
+                field: PRICE,
+                ranges: vec![
+                    RangeBucket { key: None, from: None, to: Some(10.0) },
+                    RangeBucket { key: Some("expensive".to_string()), from: Some(10.0), to: None },
+                ],
+                aggs: vec![],
+            }),
+        ];
+
+        let mut collector = AggregationCollector::new(&aggs, &reader);
+        collect_all(&mut collector);
+
+        assert_eq!(collector.into_json(), json!({
+            "prices": {
+                "buckets": [
+                    {"key": "*-10.0", "to": 10.0, "doc_count": 1},
+                    {"key": "expensive", "from": 10.0, "doc_count": 2},
+                ]
+            }
+        }));
+    }
+
+    #[test]
+    fn test_metric_aggregations() {
+        let reader = make_test_reader();
+        let aggs = vec![
+            ("price_stats".to_string(), Aggregation::Metric { field: PRICE, metric: Metric::Stats }),
+            ("num_categories".to_string(), Aggregation::Metric { field: CATEGORY, metric: Metric::Cardinality }),
+            ("num_prices".to_string(), Aggregation::Metric { field: PRICE, metric: Metric::ValueCount }),
+        ];
+
+        let mut collector = AggregationCollector::new(&aggs, &reader);
+        collect_all(&mut collector);
+
+        assert_eq!(collector.into_json(), json!({
+            "price_stats": {"count": 3, "min": 5.0, "max": 30.0, "avg": 15.0, "sum": 45.0},
+            "num_categories": {"value": 2},
+            "num_prices": {"value": 3},
+        }));
+    }
+
+    #[test]
+    fn test_metric_aggregation_without_values() {
+        let reader = TestReader { values: HashMap::new() };
+        let aggs = vec![
+            ("min_price".to_string(), Aggregation::Metric { field: PRICE, metric: Metric::Min }),
+            ("total_price".to_string(), Aggregation::Metric { field: PRICE, metric: Metric::Sum }),
+        ];
+
+        let mut collector = AggregationCollector::new(&aggs, &reader);
+        collect_all(&mut collector);
+
+        assert_eq!(collector.into_json(), json!({
+            "min_price": {"value": null},
+            "total_price": {"value": 0.0},
+        }));
+    }
+}
@@ -0,0 +1,100 @@
+//! Aggregations summarise the documents matched by a search
+//!
+//! They are parsed from the "aggs" key of a search request and run by an
+//! `AggregationCollector`, which can be used alongside any other collector. Field values
+//! are read from stored fields.
+
+pub mod parse;
+pub mod collector;
+
+use search::schema::FieldId;
+
+pub use self::parse::{parse, AggregationParseError};
+pub use self::collector::AggregationCollector;
+
+
+/// A list of named aggregations
+pub type Aggregations = Vec<(String, Aggregation)>;
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Aggregation {
+    /// Puts documents into a bucket for each distinct value of a field
+    Terms {
+        field: FieldId,
+        size: usize,
+        min_doc_count: u64,
+        aggs: Aggregations,
+    },
+
+    /// Puts documents into fixed-width buckets of a numeric field
+    Histogram {
+        field: FieldId,
+        interval: f64,
+        min_doc_count: u64,
+        aggs: Aggregations,
+    },
+
+    /// Puts documents into buckets of a datetime field
+    DateHistogram {
+        field: FieldId,
+        interval: DateInterval,
+        min_doc_count: u64,
+        aggs: Aggregations,
+    },
+
+    /// Puts documents into a bucket for each range that their value falls into
+    Range {
+        field: FieldId,
+        ranges: Vec<RangeBucket>,
+        aggs: Aggregations,
+    },
+
+    /// Calculates a single statistic for a field
+    Metric {
+        field: FieldId,
+        metric: Metric,
+    },
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    Min,
+    Max,
+    Avg,
+    Sum,
+
+    /// Min, max, avg, sum and count together
+    Stats,
+
+    /// The number of values
+    ValueCount,
+
+    /// The number of distinct values (this is exact)
+    Cardinality,
+}
+
+
+/// One of the buckets of a range aggregation
+///
+/// Values are in the bucket if they are greater than or equal to "from" and less than "to".
+/// Datetimes are compared as milliseconds since the epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeBucket {
+    pub key: Option<String>,
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateInterval {
+    Year,
+    Quarter,
+    Month,
+    Week,
+
+    /// A fixed number of milliseconds
+    Fixed(i64),
+}
@@ -0,0 +1,551 @@
+//! Parses Elasticsearch aggregations
+
+use serde_json::Value as Json;
+use serde_json::Map;
+use search::schema::{Schema, FieldId, FieldType, FIELD_STORED};
+
+use query_parser::utils::parse_datetime;
+use aggregations::{Aggregations, Aggregation, Metric, RangeBucket, DateInterval};
+
+
+#[derive(Debug, PartialEq)]
+pub enum AggregationParseError {
+    UnrecognisedAggregationType(String),
+    ExpectedAggregationType,
+    ExpectedSingleAggregationType,
+    FieldDoesntExist(String),
+    FieldNotStored(String),
+    UnrecognisedKey(String),
+    ExpectedKey(&'static str),
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedString,
+    ExpectedPositiveInteger,
+    ExpectedNumber,
+    InvalidInterval(String),
+}
+
+
+/// Parses the value of the "aggs" key of a search request
+pub fn parse(json: &Json, schema: &Schema) -> Result<Aggregations, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let mut aggregations = Vec::with_capacity(object.len());
+    for (name, aggregation_json) in object.iter() {
+        aggregations.push((name.clone(), parse_aggregation(aggregation_json, schema)?));
+    }
+
+    Ok(aggregations)
+}
+
+
+fn parse_aggregation(json: &Json, schema: &Schema) -> Result<Aggregation, AggregationParseError> {
+    let object = json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    // The object should contain the aggregation type and, optionally, some sub aggregations
+    let mut aggregation_type = None;
+    let mut aggs = Vec::new();
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "aggs" | "aggregations" => {
+                aggs = parse(value, schema)?;
+            }
+            _ => {
+                if aggregation_type.is_some() {
+                    return Err(AggregationParseError::ExpectedSingleAggregationType);
+                }
+
+                aggregation_type = Some((key, value));
+            }
+        }
+    }
+
+    let (aggregation_type, params) = aggregation_type.ok_or(AggregationParseError::ExpectedAggregationType)?;
+    let params = params.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+    let metric = match aggregation_type.as_ref() {
+        "terms" => return parse_terms_aggregation(params, schema, aggs),
+        "histogram" => return parse_histogram_aggregation(params, schema, aggs),
+        "date_histogram" => return parse_date_histogram_aggregation(params, schema, aggs),
+        "range" => return parse_range_aggregation(params, schema, aggs),
+        "min" => Metric::Min,
+        "max" => Metric::Max,
+        "avg" => Metric::Avg,
+        "sum" => Metric::Sum,
+        "stats" => Metric::Stats,
+        "value_count" => Metric::ValueCount,
+        "cardinality" => Metric::Cardinality,
+        _ => return Err(AggregationParseError::UnrecognisedAggregationType(aggregation_type.clone())),
+    };
+
+    // Metrics don't have buckets to run sub aggregations on
+    if !aggs.is_empty() {
+        return Err(AggregationParseError::UnrecognisedKey("aggs".to_string()));
+    }
+
+    let mut field = None;
+
+    for (key, value) in params.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_field(value, schema)?);
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    Ok(Aggregation::Metric {
+        field: field.ok_or(AggregationParseError::ExpectedKey("field"))?,
+        metric: metric,
+    })
+}
+
+
+fn parse_terms_aggregation(params: &Map<String, Json>, schema: &Schema, aggs: Aggregations) -> Result<Aggregation, AggregationParseError> {
+    let mut field = None;
+    let mut size = 10;
+    let mut min_doc_count = 1;
+
+    for (key, value) in params.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_field(value, schema)?);
+            }
+            "size" => {
+                size = parse_positive_integer(value)? as usize;
+            }
+            "min_doc_count" => {
+                min_doc_count = parse_positive_integer(value)?;
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    Ok(Aggregation::Terms {
+        field: field.ok_or(AggregationParseError::ExpectedKey("field"))?,
+        size: size,
+        min_doc_count: min_doc_count,
+        aggs: aggs,
+    })
+}
+
+
+fn parse_histogram_aggregation(params: &Map<String, Json>, schema: &Schema, aggs: Aggregations) -> Result<Aggregation, AggregationParseError> {
+    let mut field = None;
+    let mut interval = None;
+    let mut min_doc_count = 0;
+
+    for (key, value) in params.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_field(value, schema)?);
+            }
+            "interval" => {
+                let value = parse_number(value)?;
+
+                if value <= 0.0 {
+                    return Err(AggregationParseError::InvalidInterval(value.to_string()));
+                }
+
+                interval = Some(value);
+            }
+            "min_doc_count" => {
+                min_doc_count = parse_positive_integer(value)?;
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    Ok(Aggregation::Histogram {
+        field: field.ok_or(AggregationParseError::ExpectedKey("field"))?,
+        interval: interval.ok_or(AggregationParseError::ExpectedKey("interval"))?,
+        min_doc_count: min_doc_count,
+        aggs: aggs,
+    })
+}
+
+
+fn parse_date_histogram_aggregation(params: &Map<String, Json>, schema: &Schema, aggs: Aggregations) -> Result<Aggregation, AggregationParseError> {
+    let mut field = None;
+    let mut interval = None;
+    let mut min_doc_count = 0;
+
+    for (key, value) in params.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_field(value, schema)?);
+            }
+            "interval" => {
+                let value = value.as_str().ok_or(AggregationParseError::ExpectedString)?;
+                interval = Some(parse_date_interval(value).ok_or_else(|| AggregationParseError::InvalidInterval(value.to_string()))?);
+            }
+            "min_doc_count" => {
+                min_doc_count = parse_positive_integer(value)?;
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    Ok(Aggregation::DateHistogram {
+        field: field.ok_or(AggregationParseError::ExpectedKey("field"))?,
+        interval: interval.ok_or(AggregationParseError::ExpectedKey("interval"))?,
+        min_doc_count: min_doc_count,
+        aggs: aggs,
+    })
+}
+
+
+fn parse_range_aggregation(params: &Map<String, Json>, schema: &Schema, aggs: Aggregations) -> Result<Aggregation, AggregationParseError> {
+    let mut field = None;
+    let mut ranges_json = None;
+
+    for (key, value) in params.iter() {
+        match key.as_ref() {
+            "field" => {
+                field = Some(parse_field(value, schema)?);
+            }
+            "ranges" => {
+                ranges_json = Some(value.as_array().ok_or(AggregationParseError::ExpectedArray)?);
+            }
+            _ => return Err(AggregationParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    let field = field.ok_or(AggregationParseError::ExpectedKey("field"))?;
+    let ranges_json = ranges_json.ok_or(AggregationParseError::ExpectedKey("ranges"))?;
+
+    // Datetime fields may use dates for their boundaries
+    let is_datetime = schema.get(&field).map(|field_info| field_info.field_type == FieldType::DateTime).unwrap_or(false);
+
+    let mut ranges = Vec::with_capacity(ranges_json.len());
+    for range_json in ranges_json.iter() {
+        let range_object = range_json.as_object().ok_or(AggregationParseError::ExpectedObject)?;
+
+        let mut range = RangeBucket {
+            key: None,
+            from: None,
+            to: None,
+        };
+
+        for (key, value) in range_object.iter() {
+            match key.as_ref() {
+                "key" => {
+                    range.key = Some(value.as_str().ok_or(AggregationParseError::ExpectedString)?.to_string());
+                }
+                "from" => {
+                    range.from = Some(parse_range_boundary(value, is_datetime)?);
+                }
+                "to" => {
+                    range.to = Some(parse_range_boundary(value, is_datetime)?);
+                }
+                _ => return Err(AggregationParseError::UnrecognisedKey(key.clone())),
+            }
+        }
+
+        ranges.push(range);
+    }
+
+    Ok(Aggregation::Range {
+        field: field,
+        ranges: ranges,
+        aggs: aggs,
+    })
+}
+
+
+/// Finds a field by name, checking that its values can be read
+fn parse_field(json: &Json, schema: &Schema) -> Result<FieldId, AggregationParseError> {
+    let field_name = json.as_str().ok_or(AggregationParseError::ExpectedString)?;
+    let field = schema.get_field_by_name(field_name).ok_or_else(|| AggregationParseError::FieldDoesntExist(field_name.to_string()))?;
+
+    match schema.get(&field) {
+        Some(field_info) if field_info.field_flags.contains(FIELD_STORED) => Ok(field),
+        _ => Err(AggregationParseError::FieldNotStored(field_name.to_string())),
+    }
+}
+
+
+fn parse_positive_integer(json: &Json) -> Result<u64, AggregationParseError> {
+    json.as_u64().ok_or(AggregationParseError::ExpectedPositiveInteger)
+}
+
+
+fn parse_number(json: &Json) -> Result<f64, AggregationParseError> {
+    json.as_f64().ok_or(AggregationParseError::ExpectedNumber)
+}
+
+
+fn parse_range_boundary(json: &Json, is_datetime: bool) -> Result<f64, AggregationParseError> {
+    match *json {
+        Json::String(ref string) if is_datetime => {
+            match parse_datetime(string) {
+                Some(datetime) => Ok(datetime.timestamp() as f64 * 1000.0 + datetime.timestamp_subsec_millis() as f64),
+                None => Err(AggregationParseError::ExpectedNumber),
+            }
+        }
+        _ => parse_number(json),
+    }
+}
+
+
+/// Parses a date histogram interval, either a calendar unit ("month") or a fixed length ("12h")
+fn parse_date_interval(string: &str) -> Option<DateInterval> {
+    match string {
+        "year" | "1y" => return Some(DateInterval::Year),
+        "quarter" | "1q" => return Some(DateInterval::Quarter),
+        "month" | "1M" => return Some(DateInterval::Month),
+        "week" | "1w" => return Some(DateInterval::Week),
+        "day" => return Some(DateInterval::Fixed(86400000)),
+        "hour" => return Some(DateInterval::Fixed(3600000)),
+        "minute" => return Some(DateInterval::Fixed(60000)),
+        "second" => return Some(DateInterval::Fixed(1000)),
+        _ => {}
+    }
+
+    // Split the string into the number and the unit
+    let unit_start = string.find(|c: char| !c.is_digit(10)).unwrap_or(string.len());
+    let (number, unit) = string.split_at(unit_start);
+
+    let number = match number.parse::<i64>() {
+        Ok(number) if number > 0 => number,
+        _ => return None,
+    };
+
+    let unit_millis = match unit {
+        "d" => 86400000,
+        "h" => 3600000,
+        "m" => 60000,
+        "s" => 1000,
+        "ms" => 1,
+        _ => return None,
+    };
+
+    Some(DateInterval::Fixed(number * unit_millis))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use aggregations::{Aggregation, Metric, RangeBucket, DateInterval};
+
+    use super::{parse, AggregationParseError};
+
+    fn make_test_schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.add_field("category".to_string(), FieldType::PlainString, FIELD_INDEXED | FIELD_STORED).unwrap();
+        schema.add_field("price".to_string(), FieldType::I64, FIELD_INDEXED | FIELD_STORED).unwrap();
+        schema.add_field("published".to_string(), FieldType::DateTime, FIELD_INDEXED | FIELD_STORED).unwrap();
+        schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        schema
+    }
+
+    #[test]
+    fn test_terms_aggregation() {
+        let schema = make_test_schema();
+
+        let aggs = parse(&serde_json::from_str("
+        {
+            \"categories\": {
+                \"terms\": {
+                    \"field\": \"category\",
+                    \"size\": 5
+                }
+            }
+        }
+        ").unwrap(), &schema);
+
+        assert_eq!(aggs, Ok(vec![
+            ("categories".to_string(), Aggregation::Terms {
+                field: schema.get_field_by_name("category").unwrap(),
+                size: 5,
+                min_doc_count: 1,
+                aggs: vec![],
+            }),
+        ]));
+    }
+
+    #[test]
+    fn test_sub_aggregations() {
+        let schema = make_test_schema();
+
+        let aggs = parse(&serde_json::from_str("
+        {
+            \"categories\": {
+                \"terms\": {
+                    \"field\": \"category\"
+                },
+                \"aggs\": {
+                    \"avg_price\": {
+                        \"avg\": {
+                            \"field\": \"price\"
+                        }
+                    }
+                }
+            }
+        }
+        ").unwrap(), &schema);
+
+        assert_eq!(aggs, Ok(vec![
+            ("categories".to_string(), Aggregation::Terms {
+                field: schema.get_field_by_name("category").unwrap(),
+                size: 10,
+                min_doc_count: 1,
+                aggs: vec![
+                    ("avg_price".to_string(), Aggregation::Metric {
+                        field: schema.get_field_by_name("price").unwrap(),
+                        metric: Metric::Avg,
+                    }),
+                ],
+            }),
+        ]));
+    }
+
+    #[test]
+    fn test_date_histogram_aggregation() {
+        let schema = make_test_schema();
+
+        let aggs = parse(&serde_json::from_str("
+        {
+            \"per_month\": {
+                \"date_histogram\": {
+                    \"field\": \"published\",
+                    \"interval\": \"month\"
+                }
+            },
+            \"per_twelve_hours\": {
+                \"date_histogram\": {
+                    \"field\": \"published\",
+                    \"interval\": \"12h\"
+                }
+            }
+        }
+        ").unwrap(), &schema);
+
+        assert_eq!(aggs, Ok(vec![
+            ("per_month".to_string(), Aggregation::DateHistogram {
+                field: schema.get_field_by_name("published").unwrap(),
+                interval: DateInterval::Month,
+                min_doc_count: 0,
+                aggs: vec![],
+            }),
+            ("per_twelve_hours".to_string(), Aggregation::DateHistogram {
+                field: schema.get_field_by_name("published").unwrap(),
+                interval: DateInterval::Fixed(12 * 3600000),
+                min_doc_count: 0,
+                aggs: vec![],
+            }),
+        ]));
+    }
+
+    #[test]
+    fn test_range_aggregation() {
+        let schema = make_test_schema();
+
+        let aggs = parse(&serde_json::from_str("
+        {
+            \"prices\": {
+                \"range\": {
+                    \"field\": \"price\",
+                    \"ranges\": [
+                        {\"to\": 100},
+                        {\"key\": \"medium\", \"from\": 100, \"to\": 200},
+                        {\"from\": 200}
+                    ]
+                }
+            }
+        }
+        ").unwrap(), &schema);
+
+        assert_eq!(aggs, Ok(vec![
+            ("prices".to_string(), Aggregation::Range {
+                field: schema.get_field_by_name("price").unwrap(),
+                ranges: vec![
+                    RangeBucket { key: None, from: None, to: Some(100.0) },
+                    RangeBucket { key: Some("medium".to_string()), from: Some(100.0), to: Some(200.0) },
+                    RangeBucket { key: None, from: Some(200.0), to: None },
+                ],
+                aggs: vec![],
+            }),
+        ]));
+    }
+
+    #[test]
+    fn test_gives_error_for_unstored_field() {
+        let schema = make_test_schema();
+
+        let aggs = parse(&serde_json::from_str("
+        {
+            \"words\": {
+                \"terms\": {
+                    \"field\": \"body\"
+                }
+            }
+        }
+        ").unwrap(), &schema);
+
+        assert_eq!(aggs, Err(AggregationParseError::FieldNotStored("body".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_sub_aggregations_on_metric() {
+        let schema = make_test_schema();
+
+        let aggs = parse(&serde_json::from_str("
+        {
+            \"max_price\": {
+                \"max\": {
+                    \"field\": \"price\"
+                },
+                \"aggs\": {
+                    \"min_price\": {
+                        \"min\": {
+                            \"field\": \"price\"
+                        }
+                    }
+                }
+            }
+        }
+        ").unwrap(), &schema);
+
+        assert_eq!(aggs, Err(AggregationParseError::UnrecognisedKey("aggs".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_aggregation_type() {
+        let schema = make_test_schema();
+
+        let aggs = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"percentiles\": {
+                    \"field\": \"price\"
+                }
+            }
+        }
+        ").unwrap(), &schema);
+
+        assert_eq!(aggs, Err(AggregationParseError::UnrecognisedAggregationType("percentiles".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_interval() {
+        let schema = make_test_schema();
+
+        let aggs = parse(&serde_json::from_str("
+        {
+            \"per_fortnight\": {
+                \"date_histogram\": {
+                    \"field\": \"published\",
+                    \"interval\": \"fortnight\"
+                }
+            }
+        }
+        ").unwrap(), &schema);
+
+        assert_eq!(aggs, Err(AggregationParseError::InvalidInterval("fortnight".to_string())));
+    }
+}
@@ -1,102 +1,689 @@
 use std::io::Read;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use serde_json;
+use search::Document;
+use search::version::{DocumentVersion, VersionCondition, next_version};
+use search::backends::DocumentWriteError;
+use uuid::Uuid;
 
 use document::DocumentSource;
+use index::Index;
+use cluster::metadata::{ClusterMetadata, IndexRef};
+use system::System;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
-use api::utils::{json_response};
+use api::utils::{json_response, read_meta_field, build_version_condition, build_create_condition, insert_version_fields};
 use api::router::Router;
 
 
-pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
-    let ref system = get_system!(req);
+/// An error that occurred while running one of the actions in a bulk request
+///
+/// These are reported in the item for that action and don't affect any other actions
+#[derive(Debug)]
+struct BulkItemError {
+    status: u16,
+    error_type: &'static str,
+    reason: String,
+}
 
-    // Lock cluster metedata
-    let cluster_metadata = system.metadata.read().unwrap();
 
-    // Load data from body
-    let mut payload = String::new();
-    req.body.read_to_string(&mut payload).unwrap();
+impl BulkItemError {
+    fn new(status: u16, error_type: &'static str, reason: String) -> BulkItemError {
+        BulkItemError {
+            status: status,
+            error_type: error_type,
+            reason: reason,
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "type": self.error_type,
+            "reason": self.reason,
+        })
+    }
+}
 
-    let mut items = Vec::new();
 
-    // Iterate
-    let mut payload_lines = payload.split('\n');
-    loop {
-        let action_line = payload_lines.next();
+/// The outcome of a successful action
+struct BulkItemResult {
+    status: u16,
+    result: &'static str,
 
-        // Check if end of input
-        if action_line == None || action_line == Some("") {
-            break;
+    /// The version that the action gave the document. This is None for documents that haven't
+    /// been written yet, their versions are added to the item when they are
+    version: Option<DocumentVersion>,
+}
+
+
+/// Documents that have been prepared but not written to their index yet
+#[derive(Default)]
+struct PendingDocuments {
+    docs: Vec<Document>,
+
+    /// The version condition of each pending document
+    conditions: Vec<Option<VersionCondition>>,
+
+    /// The keys of the pending documents. This allows "index" actions to see documents that
+    /// were added earlier in the same request
+    keys: HashSet<String>,
+
+    /// The position of each pending document's item in the response. If writing the
+    /// documents fails, these items are changed to errors
+    items: Vec<usize>,
+
+    /// The number of documents to write into each segment, from the index's "bulk.chunk_size" setting
+    chunk_size: usize,
+}
+
+
+/// Copies the fields of "changes" into "target", merging any objects that exist in both
+fn merge_json(target: &mut serde_json::Value, changes: &serde_json::Value) {
+    match (target, changes) {
+        (&mut serde_json::Value::Object(ref mut target), &serde_json::Value::Object(ref changes)) => {
+            for (key, value) in changes.iter() {
+                let merge_objects = match target.get(key) {
+                    Some(existing_value) => existing_value.is_object() && value.is_object(),
+                    None => false,
+                };
+
+                if merge_objects {
+                    merge_json(target.get_mut(key).unwrap(), value);
+                } else {
+                    target.insert(key.clone(), value.clone());
+                }
+            }
         }
+        (target, changes) => {
+            *target = changes.clone();
+        }
+    }
+}
 
-        // Parse action line
-        let action_json = parse_json!(&action_line.unwrap());
 
-        // Check action
+/// The names of the actions that can be used in a bulk request
+const BULK_ACTION_NAMES: [&'static str; 4] = ["index", "create", "update", "delete"];
+
+
+/// An action line of a bulk request, with the line containing the document (if it has one)
+#[derive(Debug)]
+struct BulkAction<'a> {
+    name: String,
+    params: serde_json::Value,
+    source_line: Option<&'a str>,
+}
+
+
+/// Returns true if the line is an object with a single key that names an action
+fn is_action_line(line: &str) -> bool {
+    match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(serde_json::Value::Object(ref object)) if object.len() == 1 => {
+            object.keys().all(|key| BULK_ACTION_NAMES.contains(&key.as_ref()))
+        }
+        _ => false,
+    }
+}
+
+
+/// Splits a bulk request into its actions
+///
+/// Action lines that can't be read give an error in place of their action. We can't tell whether
+/// these were meant to be followed by a document, so the next line is skipped as well unless it
+/// looks like an action. This stops a document being read as the next action.
+fn split_actions(payload: &str) -> Vec<Result<BulkAction, BulkItemError>> {
+    let mut payload_lines = payload.split('\n').peekable();
+    let mut actions = Vec::new();
+
+    while let Some(action_line) = payload_lines.next() {
+        if action_line.trim().is_empty() {
+            continue;
+        }
+
+        // Parse action line
         // Action should be an object with only one key, the key name indicates the action and
         // the value is the parameters for that action
-        let action_name = action_json.as_object().unwrap().keys().nth(0).unwrap();
-        let action_params = action_json.as_object()
-                                       .unwrap()
-                                       .get(action_name)
-                                       .unwrap()
-                                       .as_object()
-                                       .unwrap();
-
-        let doc_id = action_params.get("_id").unwrap().as_str().unwrap();
-        let doc_type = action_params.get("_type").unwrap().as_str().unwrap();
-        let doc_index = action_params.get("_index").unwrap().as_str().unwrap();
-
-        match action_name.as_ref() {
-            "index" => {
-                let doc_line = payload_lines.next();
-                let doc_json = parse_json!(&doc_line.unwrap());;
-
-                // Find index
-                let index = get_index_or_404!(cluster_metadata, doc_index);
-                let index_metadata = index.metadata.read().unwrap();
-
-                let doc = {
-                    // Find mapping
-                    let mapping = match index_metadata.mappings.get(doc_type) {
-                        Some(mapping) => mapping,
-                        None => {
-                            return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
+        let action = match serde_json::from_str::<serde_json::Value>(action_line) {
+            Ok(action_json) => {
+                match action_json.as_object() {
+                    Some(action_object) if action_object.len() == 1 => {
+                        let (action_name, action_params) = action_object.iter().next().unwrap();
+                        Ok((action_name.clone(), action_params.clone()))
+                    }
+                    _ => Err(BulkItemError::new(400, "action_request_validation_exception", "malformed action line, expected an object with one key".to_string())),
+                }
+            }
+            Err(error) => Err(BulkItemError::new(400, "parse_exception", format!("failed to parse action: {}", error))),
+        };
+
+        match action {
+            Ok((action_name, action_params)) => {
+                // All actions apart from delete are followed by a line containing the document
+                let source_line = match action_name.as_ref() {
+                    "index" | "create" | "update" => payload_lines.next(),
+                    _ => None,
+                };
+
+                actions.push(Ok(BulkAction {
+                    name: action_name,
+                    params: action_params,
+                    source_line: source_line,
+                }));
+            }
+            Err(error) => {
+                while payload_lines.peek().map(|line| line.trim().is_empty()).unwrap_or(false) {
+                    payload_lines.next();
+                }
+
+                if payload_lines.peek().map(|line| !is_action_line(line)).unwrap_or(false) {
+                    payload_lines.next();
+                }
+
+                actions.push(Err(error));
+            }
+        }
+    }
+
+    actions
+}
+
+
+/// Runs the actions in a bulk request
+struct BulkProcessor<'a> {
+    system: &'a System,
+    cluster_metadata: &'a ClusterMetadata,
+    default_index: Option<&'a str>,
+    default_mapping: Option<&'a str>,
+    pending: HashMap<IndexRef, PendingDocuments>,
+    items: Vec<serde_json::Value>,
+}
+
+
+impl<'a> BulkProcessor<'a> {
+    fn new(system: &'a System, cluster_metadata: &'a ClusterMetadata, default_index: Option<&'a str>, default_mapping: Option<&'a str>) -> BulkProcessor<'a> {
+        BulkProcessor {
+            system: system,
+            cluster_metadata: cluster_metadata,
+            default_index: default_index,
+            default_mapping: default_mapping,
+            pending: HashMap::new(),
+            items: Vec::new(),
+        }
+    }
+
+    fn run(mut self, payload: &str) -> serde_json::Value {
+        for action in split_actions(payload) {
+            match action {
+                Ok(action) => self.run_action(&action.name, &action.params, action.source_line),
+                Err(error) => self.push_error_item("index", json!({}), error),
+            }
+        }
+
+        // Write remaining documents
+        let index_refs = self.pending.keys().cloned().collect::<Vec<IndexRef>>();
+        for index_ref in index_refs {
+            self.flush(index_ref);
+        }
+
+        let errors = self.items.iter().any(|item| {
+            item.as_object()
+                .and_then(|item| item.iter().next())
+                .map(|(_, item)| item.get("error").is_some())
+                .unwrap_or(false)
+        });
+
+        json!({
+            "took": self.items.len(),
+            "errors": errors,
+            "items": self.items,
+        })
+    }
+
+    fn run_action(&mut self, action_name: &str, action_params: &serde_json::Value, source_line: Option<&str>) {
+        let action_params = match action_params.as_object() {
+            Some(action_params) => action_params,
+            None => {
+                let error = BulkItemError::new(400, "action_request_validation_exception", "action parameters must be an object".to_string());
+                self.push_error_item(action_name, json!({}), error);
+                return;
+            }
+        };
+
+        // Get metadata, falling back to the defaults from the URL
+        let index_name = action_params.get("_index").and_then(|value| value.as_str()).or(self.default_index);
+        let mapping_name = action_params.get("_type").and_then(|value| value.as_str()).or(self.default_mapping);
+        let doc_id = match action_params.get("_id") {
+            Some(&serde_json::Value::String(ref doc_id)) => Some(doc_id.clone()),
+            Some(&serde_json::Value::Number(ref doc_id)) => Some(doc_id.to_string()),
+            _ => None,
+        };
+
+        // Documents that are being added may have their ID generated
+        let doc_id = match (action_name, doc_id) {
+            (_, Some(doc_id)) => Some(doc_id),
+            ("index", None) | ("create", None) => Some(Uuid::new_v4().to_string()),
+            (_, None) => None,
+        };
+
+        let item = json!({
+            "_index": index_name,
+            "_type": mapping_name,
+            "_id": doc_id,
+        });
+
+        let result = match (index_name, mapping_name, doc_id) {
+            (None, _, _) => Err(BulkItemError::new(400, "action_request_validation_exception", "index is missing".to_string())),
+            (_, None, _) => Err(BulkItemError::new(400, "action_request_validation_exception", "type is missing".to_string())),
+            (_, _, None) => Err(BulkItemError::new(400, "action_request_validation_exception", "id is missing".to_string())),
+            (Some(index_name), Some(mapping_name), Some(doc_id)) => {
+                parse_version_condition(action_params).and_then(|condition| {
+                    match action_name {
+                        "index" => self.run_index_action(index_name, mapping_name, &doc_id, source_line, condition, false),
+                        "create" => self.run_index_action(index_name, mapping_name, &doc_id, source_line, condition, true),
+                        "update" => self.run_update_action(index_name, mapping_name, &doc_id, source_line, condition),
+                        "delete" => self.run_delete_action(index_name, mapping_name, &doc_id, condition),
+                        _ => {
+                            warn!(self.system.log, "unrecognised bulk action"; "action" => action_name);
+                            Err(BulkItemError::new(400, "illegal_argument_exception", format!("unrecognised action [{}]", action_name)))
                         }
-                    };
-
-                    // Create document
-                    let document_source = DocumentSource {
-                        key: doc_id,
-                        data: doc_json.as_object().unwrap(),
-                    };
-                    document_source.prepare(mapping).unwrap()
+                    }
+                })
+            }
+        };
+
+        match result {
+            Ok(result) => {
+                let mut item = item;
+                {
+                    let item_object = item.as_object_mut().unwrap();
+                    if let Some(version) = result.version {
+                        insert_version_fields(item_object, &version);
+                    }
+                    item_object.insert("result".to_string(), json!(result.result));
+                    item_object.insert("status".to_string(), json!(result.status));
+                }
+
+                let mut item_wrapper = serde_json::Map::new();
+                item_wrapper.insert(action_name.to_string(), item);
+                self.items.push(serde_json::Value::Object(item_wrapper));
+
+                self.flush_full_chunks();
+            }
+            Err(error) => {
+                self.push_error_item(action_name, item, error);
+            }
+        }
+    }
+
+    fn run_index_action(&mut self, index_name: &str, mapping_name: &str, doc_id: &str, source_line: Option<&str>, condition: Option<VersionCondition>, create: bool) -> Result<BulkItemResult, BulkItemError> {
+        let (index_ref, index) = try!(self.get_index(index_name));
+        let source = try!(parse_source_line(source_line));
+
+        if create {
+            // The document must not exist when it's written, this is checked by the store along
+            // with the other conditions so that two creates of the same document can't both succeed
+            let condition = try!(build_create_condition(condition).map_err(|error| {
+                BulkItemError::new(400, "action_request_validation_exception", error)
+            }));
+
+            try!(self.queue_document(index_ref, index, mapping_name, doc_id, source, condition));
+
+            return Ok(BulkItemResult { status: 201, result: "created", version: None });
+        }
+
+        // This only decides how the result is reported, the write doesn't depend on it
+        let exists = self.document_exists(index_ref, index, doc_id);
+
+        try!(self.queue_document(index_ref, index, mapping_name, doc_id, source, condition));
+
+        Ok(if exists {
+            BulkItemResult { status: 200, result: "updated", version: None }
+        } else {
+            BulkItemResult { status: 201, result: "created", version: None }
+        })
+    }
+
+    fn run_update_action(&mut self, index_name: &str, mapping_name: &str, doc_id: &str, source_line: Option<&str>, condition: Option<VersionCondition>) -> Result<BulkItemResult, BulkItemError> {
+        let (index_ref, index) = try!(self.get_index(index_name));
+        let update = try!(parse_source_line(source_line));
+
+        let mut changes = None;
+        let mut upsert = None;
+        let mut doc_as_upsert = false;
+
+        for (key, value) in update.as_object().unwrap().iter() {
+            match key.as_ref() {
+                "doc" if value.is_object() => changes = Some(value),
+                "upsert" if value.is_object() => upsert = Some(value),
+                "doc_as_upsert" if value.is_boolean() => doc_as_upsert = value.as_bool().unwrap(),
+                _ => {
+                    return Err(BulkItemError::new(400, "illegal_argument_exception", format!("invalid value for [{}] in update", key)));
+                }
+            }
+        }
+
+        let changes = match changes {
+            Some(changes) => changes,
+            None => {
+                return Err(BulkItemError::new(400, "action_request_validation_exception", "update requires a doc".to_string()));
+            }
+        };
+
+        // The update is written on the condition that the document hasn't changed since it was
+        // read, so a write that happens in between can't be lost. External versions can't be used
+        // as the version of the update would have to come from the client
+        match condition {
+            Some(VersionCondition::External(_)) | Some(VersionCondition::ExternalGte(_)) => {
+                return Err(BulkItemError::new(400, "action_request_validation_exception", "external versioning is not supported by the update API".to_string()));
+            }
+            _ => {}
+        }
+
+        let check_condition = |current: Option<DocumentVersion>| {
+            next_version(current, None, condition).map_err(|conflict| {
+                BulkItemError::new(409, "version_conflict_engine_exception", format!("[{}][{}]: {}", mapping_name, doc_id, conflict))
+            })
+        };
+
+        // Apply the changes to the current version of the document
+        let (source, condition, result) = match try!(self.get_source(index_ref, index, doc_id)) {
+            Some((mut source, version)) => {
+                try!(check_condition(Some(version)));
+                merge_json(&mut source, changes);
+                let condition = VersionCondition::SeqNo { seq_no: version.seq_no, primary_term: version.primary_term };
+                (source, condition, BulkItemResult { status: 200, result: "updated", version: None })
+            }
+            None => {
+                let source = match upsert {
+                    Some(upsert) => upsert.clone(),
+                    None if doc_as_upsert => changes.clone(),
+                    None => {
+                        return Err(BulkItemError::new(404, "document_missing_exception", format!("[{}][{}]: document missing", mapping_name, doc_id)));
+                    }
                 };
+                try!(check_condition(None));
+
+                (source, VersionCondition::MustNotExist, BulkItemResult { status: 201, result: "created", version: None })
+            }
+        };
+
+        // If the document was written since it was read, this fails with a version conflict
+        try!(self.queue_document(index_ref, index, mapping_name, doc_id, source, Some(condition)));
+
+        Ok(result)
+    }
+
+    fn run_delete_action(&mut self, index_name: &str, mapping_name: &str, doc_id: &str, condition: Option<VersionCondition>) -> Result<BulkItemResult, BulkItemError> {
+        let (index_ref, index) = try!(self.get_index(index_name));
+
+        // Write any documents that are waiting, the document may be one of them
+        self.flush(index_ref);
+
+        match index.store.remove_document_by_key(doc_id, condition) {
+            Ok(Some(version)) => Ok(BulkItemResult { status: 200, result: "deleted", version: Some(version) }),
+            Ok(None) => Ok(BulkItemResult { status: 404, result: "not_found", version: None }),
+            Err(DocumentWriteError::VersionConflict(conflict)) => {
+                Err(BulkItemError::new(409, "version_conflict_engine_exception", format!("[{}][{}]: {}", mapping_name, doc_id, conflict)))
+            }
+            Err(DocumentWriteError::StorageError(error)) => {
+                Err(BulkItemError::new(500, "exception", format!("failed to delete document: {}", error)))
+            }
+        }
+    }
 
-                index.store.insert_or_update_document(&doc).unwrap();
+    fn get_index(&self, index_name: &str) -> Result<(IndexRef, &'a Index), BulkItemError> {
+        let not_found_error = || BulkItemError::new(404, "index_not_found_exception", format!("no such index [{}]", index_name));
+
+        let index_ref = try!(self.cluster_metadata.names.find_canonical(index_name).ok_or_else(&not_found_error));
+        let index = try!(self.cluster_metadata.indices.get(&index_ref).ok_or_else(&not_found_error));
+
+        Ok((index_ref, index))
+    }
 
-                // Insert into "items" array
-                let mut item = HashMap::new();
-                // TODO: "create" may not always be right
-                item.insert("create", action_params.clone());
-                items.push(item);
+    fn document_exists(&self, index_ref: IndexRef, index: &Index, doc_id: &str) -> bool {
+        if let Some(pending) = self.pending.get(&index_ref) {
+            if pending.keys.contains(doc_id) {
+                return true;
             }
-            _ => {
-                warn!(system.log, "unrecognised action! {}", action_name);
+        }
+
+        index.store.reader().contains_document_key(doc_id)
+    }
+
+    /// Finds the current source and version of a document
+    ///
+    /// If the document is waiting to be written, the pending documents are written first so that
+    /// its version is known.
+    fn get_source(&mut self, index_ref: IndexRef, index: &Index, doc_id: &str) -> Result<Option<(serde_json::Value, DocumentVersion)>, BulkItemError> {
+        if self.pending.get(&index_ref).map_or(false, |pending| pending.keys.contains(doc_id)) {
+            self.flush(index_ref);
+        }
+
+        // The version is read first, if the document is written after this the source will be
+        // newer than the version and writing the update will fail
+        let index_reader = index.store.reader();
+        let version = match index_reader.get_document_version(doc_id) {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+
+        let internal_doc_id = match index_reader.get_document_by_key(doc_id) {
+            Some(internal_doc_id) => internal_doc_id,
+            None => return Ok(None),
+        };
+
+        match read_meta_field(&index_reader, "_source", internal_doc_id).map(|source| serde_json::from_str::<serde_json::Value>(&source)) {
+            Some(Ok(source)) => Ok(Some((source, version))),
+            _ => Err(BulkItemError::new(400, "illegal_argument_exception", format!("document [{}] has no source to update", doc_id))),
+        }
+    }
+
+    /// Prepares a document and adds it to the pending documents for the index
+    fn queue_document(&mut self, index_ref: IndexRef, index: &Index, mapping_name: &str, doc_id: &str, source: serde_json::Value, condition: Option<VersionCondition>) -> Result<(), BulkItemError> {
+        let (doc, chunk_size) = {
+            let index_metadata = index.metadata.read().unwrap();
+
+            // Find mapping
+            let mapping = match index_metadata.mappings.get(mapping_name) {
+                Some(mapping) => mapping,
+                None => {
+                    return Err(BulkItemError::new(404, "type_missing_exception", format!("mapping [{}] not found", mapping_name)));
+                }
+            };
+
+            // Create document
+            let document_source = DocumentSource {
+                key: doc_id,
+                mapping_name: mapping_name,
+                data: source.as_object().unwrap(),
+            };
+
+            match document_source.prepare(mapping) {
+                Ok(doc) => (doc, index_metadata.bulk_chunk_size),
+                Err(error) => {
+                    return Err(BulkItemError::new(400, "mapper_parsing_exception", format!("failed to parse document: {:?}", error)));
+                }
             }
+        };
+
+        let pending = self.pending.entry(index_ref).or_insert_with(PendingDocuments::default);
+        pending.chunk_size = chunk_size;
+        pending.docs.push(doc);
+        pending.conditions.push(condition);
+        pending.keys.insert(doc_id.to_string());
+
+        // This document's item will be pushed next
+        pending.items.push(self.items.len());
+
+        Ok(())
+    }
+
+    /// Writes the pending documents of any index that has filled a chunk
+    fn flush_full_chunks(&mut self) {
+        let full_chunks = self.pending.iter()
+            .filter(|&(_, pending)| pending.docs.len() >= pending.chunk_size)
+            .map(|(index_ref, _)| *index_ref)
+            .collect::<Vec<IndexRef>>();
+
+        for index_ref in full_chunks {
+            self.flush(index_ref);
         }
     }
 
-    return Ok(json_response(status::Ok,
-                            json!({
-                                "took": items.len(),
-                                "items": items,
-                            })));
+    /// Writes the pending documents for an index
+    ///
+    /// All documents are written into a single segment (unless there's too many to fit in one)
+    fn flush(&mut self, index_ref: IndexRef) {
+        let pending = match self.pending.remove(&index_ref) {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        let index = self.cluster_metadata.indices.get(&index_ref).unwrap();
+
+        match index.store.insert_or_update_documents(&pending.docs, &pending.conditions) {
+            Ok(results) => {
+                for ((result, position), doc) in results.into_iter().zip(pending.items).zip(pending.docs.iter()) {
+                    match result {
+                        Ok(version) => set_item_version(&mut self.items[position], &version),
+                        Err(conflict) => {
+                            let mapping_name = item_field(&self.items[position], "_type").unwrap_or_default();
+                            let error = BulkItemError::new(409, "version_conflict_engine_exception", format!("[{}][{}]: {}", mapping_name, doc.key, conflict));
+                            set_item_error(&mut self.items[position], &error);
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                error!(self.system.log, "failed to write bulk documents"; "index" => index.canonical_name(), "error" => format!("{}", error));
+
+                let error = BulkItemError::new(500, "exception", format!("failed to write document: {}", error));
+                for position in pending.items {
+                    set_item_error(&mut self.items[position], &error);
+                }
+            }
+        }
+    }
+
+    fn push_error_item(&mut self, action_name: &str, item: serde_json::Value, error: BulkItemError) {
+        let mut item_wrapper = serde_json::Map::new();
+        item_wrapper.insert(action_name.to_string(), item);
+        let mut item_wrapper = serde_json::Value::Object(item_wrapper);
+
+        set_item_error(&mut item_wrapper, &error);
+        self.items.push(item_wrapper);
+    }
+}
+
+
+/// Parses the line following an index/create/update action
+fn parse_source_line(source_line: Option<&str>) -> Result<serde_json::Value, BulkItemError> {
+    let source_line = match source_line {
+        Some(source_line) if !source_line.trim().is_empty() => source_line,
+        _ => {
+            return Err(BulkItemError::new(400, "action_request_validation_exception", "source is missing".to_string()));
+        }
+    };
+
+    match serde_json::from_str::<serde_json::Value>(source_line) {
+        Ok(source) => {
+            if source.is_object() {
+                Ok(source)
+            } else {
+                Err(BulkItemError::new(400, "mapper_parsing_exception", "source must be an object".to_string()))
+            }
+        }
+        Err(error) => {
+            Err(BulkItemError::new(400, "mapper_parsing_exception", format!("failed to parse source: {}", error)))
+        }
+    }
+}
+
+
+/// Reads the version condition from the parameters of an action
+fn parse_version_condition(action_params: &serde_json::Map<String, serde_json::Value>) -> Result<Option<VersionCondition>, BulkItemError> {
+    // Parameters may be given with or without an underscore
+    let get_param = |name: &str| {
+        action_params.get(name).or_else(|| action_params.get(&format!("_{}", name)))
+    };
+
+    let get_number_param = |name: &str| {
+        match get_param(name) {
+            Some(value) => {
+                match value.as_u64() {
+                    Some(value) => Ok(Some(value)),
+                    None => Err(BulkItemError::new(400, "illegal_argument_exception", format!("[{}] must be a positive integer", name))),
+                }
+            }
+            None => Ok(None),
+        }
+    };
+
+    let version = try!(get_number_param("version"));
+    let if_seq_no = try!(get_number_param("if_seq_no"));
+    let if_primary_term = try!(get_number_param("if_primary_term"));
+    let version_type = match get_param("version_type") {
+        Some(value) => {
+            match value.as_str() {
+                Some(value) => Some(value),
+                None => return Err(BulkItemError::new(400, "illegal_argument_exception", "[version_type] must be a string".to_string())),
+            }
+        }
+        None => None,
+    };
+
+    build_version_condition(version, version_type, if_seq_no, if_primary_term).map_err(|error| {
+        BulkItemError::new(400, "action_request_validation_exception", error)
+    })
+}
+
+
+/// Reads one of the string fields of a response item
+fn item_field(item_wrapper: &serde_json::Value, field_name: &str) -> Option<String> {
+    item_wrapper.as_object()
+        .and_then(|item_wrapper| item_wrapper.iter().next())
+        .and_then(|(_, item)| item.get(field_name))
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_string())
+}
+
+
+/// Adds the version that a document was written with to its response item
+fn set_item_version(item_wrapper: &mut serde_json::Value, version: &DocumentVersion) {
+    if let Some(item) = item_wrapper.as_object_mut().and_then(|item_wrapper| item_wrapper.iter_mut().next()).and_then(|(_, item)| item.as_object_mut()) {
+        insert_version_fields(item, version);
+    }
+}
+
+
+/// Changes a response item into an error
+fn set_item_error(item_wrapper: &mut serde_json::Value, error: &BulkItemError) {
+    if let Some(item) = item_wrapper.as_object_mut().and_then(|item_wrapper| item_wrapper.iter_mut().next()).and_then(|(_, item)| item.as_object_mut()) {
+        item.remove("_version");
+        item.remove("_seq_no");
+        item.remove("_primary_term");
+        item.remove("result");
+        item.insert("status".to_string(), json!(error.status));
+        item.insert("error".to_string(), error.to_json());
+    }
+}
+
+
+pub fn view_post_bulk(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+
+    // Lock cluster metedata
+    let cluster_metadata = system.metadata.read().unwrap();
+
+    // Load data from body
+    let mut payload = String::new();
+    req.body.read_to_string(&mut payload).unwrap();
+
+    let response = BulkProcessor::new(system, &cluster_metadata, None, None).run(&payload);
+
+    return Ok(json_response(status::Ok, response));
 }
 
 
@@ -107,82 +694,75 @@ pub fn view_post_index_bulk(req: &mut Request) -> IronResult<Response> {
     // Lock cluster metedata
     let cluster_metadata = system.metadata.read().unwrap();
 
-    // Get index
-    let index = get_index_or_404!(cluster_metadata, *index_name);
-    let index_metadata = index.metadata.read().unwrap();
+    // Load data from body
+    let mut payload = String::new();
+    req.body.read_to_string(&mut payload).unwrap();
+
+    let response = BulkProcessor::new(system, &cluster_metadata, Some(*index_name), None).run(&payload);
+
+    return Ok(json_response(status::Ok, response));
+}
+
+
+pub fn view_post_mapping_bulk(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+
+    // Lock cluster metedata
+    let cluster_metadata = system.metadata.read().unwrap();
 
     // Load data from body
     let mut payload = String::new();
     req.body.read_to_string(&mut payload).unwrap();
 
-    let mut items = Vec::new();
+    let response = BulkProcessor::new(system, &cluster_metadata, Some(*index_name), Some(*mapping_name)).run(&payload);
 
-    // Iterate
-    let mut payload_lines = payload.split('\n');
-    loop {
-        let action_line = payload_lines.next();
+    return Ok(json_response(status::Ok, response));
+}
 
-        // Check if end of input
-        if action_line == None || action_line == Some("") {
-            break;
-        }
 
-        // Parse action line
-        let action_json = parse_json!(&action_line.unwrap());
+#[cfg(test)]
+mod tests {
+    use super::split_actions;
 
-        // Check action
-        // Action should be an object with only one key, the key name indicates the action and
-        // the value is the parameters for that action
-        let action_name = action_json.as_object().unwrap().keys().nth(0).unwrap();
-        let action_params = action_json.as_object()
-                                       .unwrap()
-                                       .get(action_name)
-                                       .unwrap()
-                                       .as_object()
-                                       .unwrap();
-
-        let doc_id = action_params.get("_id").unwrap().as_str().unwrap();
-        let doc_type = action_params.get("_type").unwrap().as_str().unwrap();
-
-        match action_name.as_ref() {
-            "index" => {
-                let doc_line = payload_lines.next();
-                let doc_json = parse_json!(&doc_line.unwrap());;
-
-                let doc = {
-                    // Find mapping
-                    let mapping = match index_metadata.mappings.get(doc_type) {
-                        Some(mapping) => mapping,
-                        None => {
-                            return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
-                        }
-                    };
-
-                    // Create document
-                    let document_source = DocumentSource {
-                        key: doc_id,
-                        data: doc_json.as_object().unwrap(),
-                    };
-                    document_source.prepare(mapping).unwrap()
-                };
+    #[test]
+    fn test_split_actions() {
+        let actions = split_actions("{\"index\": {\"_id\": \"1\"}}\n{\"title\": \"hello\"}\n{\"delete\": {\"_id\": \"2\"}}\n");
 
-                index.store.insert_or_update_document(&doc).unwrap();
+        assert_eq!(actions.len(), 2);
 
-                // Insert into "items" array
-                let mut item = HashMap::new();
-                // TODO: "create" may not always be right
-                item.insert("create", action_params.clone());
-                items.push(item);
-            }
-            _ => {
-                warn!(system.log, "unrecognised action! {}", action_name);
-            }
-        }
+        let action = actions[0].as_ref().unwrap();
+        assert_eq!(action.name, "index");
+        assert_eq!(action.params, json!({"_id": "1"}));
+        assert_eq!(action.source_line, Some("{\"title\": \"hello\"}"));
+
+        let action = actions[1].as_ref().unwrap();
+        assert_eq!(action.name, "delete");
+        assert_eq!(action.source_line, None);
     }
 
-    return Ok(json_response(status::Ok,
-                            json!({
-                                "took": items.len(),
-                                "items": items,
-                            })));
+    #[test]
+    fn test_split_actions_skips_source_of_malformed_action() {
+        // The document that follows the first malformed action shouldn't be read as an action
+        let actions = split_actions("{\"index\": {\"_id\": \"1\"}\n\n{\"title\": \"hello\"}\n{\"index\": {}, \"create\": {}}\n{\"delete\": {\"_id\": \"2\"}}\n{\"index\": {\"_id\": \"3\"}}\n{\"title\": \"world\"}\n");
+
+        assert_eq!(actions.len(), 4);
+
+        let error = actions[0].as_ref().unwrap_err();
+        assert_eq!(error.error_type, "parse_exception");
+
+        // The line after this one is an action, so it isn't skipped
+        let error = actions[1].as_ref().unwrap_err();
+        assert_eq!(error.error_type, "action_request_validation_exception");
+
+        let action = actions[2].as_ref().unwrap();
+        assert_eq!(action.name, "delete");
+        assert_eq!(action.params, json!({"_id": "2"}));
+
+        let action = actions[3].as_ref().unwrap();
+        assert_eq!(action.name, "index");
+        assert_eq!(action.params, json!({"_id": "3"}));
+        assert_eq!(action.source_line, Some("{\"title\": \"world\"}"));
+    }
 }
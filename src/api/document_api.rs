@@ -1,6 +1,9 @@
 use std::io::Read;
 
 use serde_json;
+use url::form_urlencoded;
+use search::version::{VersionCondition, VersionConflict};
+use search::backends::DocumentWriteError;
 
 use document::DocumentSource;
 
@@ -8,14 +11,74 @@ use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, read_meta_field, build_version_condition, build_create_condition, insert_version_fields};
+
+
+/// Reads the version condition of a write from the URL parameters
+fn read_version_condition(req: &Request) -> Result<Option<VersionCondition>, String> {
+    let mut version = None;
+    let mut version_type = None;
+    let mut if_seq_no = None;
+    let mut if_primary_term = None;
+
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            let number = match key.as_ref() {
+                "version" => &mut version,
+                "if_seq_no" => &mut if_seq_no,
+                "if_primary_term" => &mut if_primary_term,
+                "version_type" => {
+                    version_type = Some(value.into_owned());
+                    continue;
+                }
+                _ => continue,
+            };
+
+            *number = match value.as_ref().parse::<u64>() {
+                Ok(value) => Some(value),
+                Err(_) => return Err(format!("[{}] must be a positive integer", key)),
+            };
+        }
+    }
+
+    build_version_condition(version, version_type.as_ref().map(|version_type| version_type.as_ref()), if_seq_no, if_primary_term)
+}
+
+
+/// Reads the "op_type" URL parameter, returns true if the write must create the document
+fn read_op_type(req: &Request) -> Result<bool, String> {
+    if let Some(ref url_query) = req.url.query() {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key == "op_type" {
+                return match value.as_ref() {
+                    "index" => Ok(false),
+                    "create" => Ok(true),
+                    op_type => Err(format!("opType must be 'create' or 'index', found: [{}]", op_type)),
+                };
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+
+fn version_conflict_response(mapping_name: &str, doc_key: &str, conflict: &VersionConflict) -> Response {
+    json_response(status::Conflict, json!({
+        "error": {
+            "type": "version_conflict_engine_exception",
+            "reason": format!("[{}][{}]: {}", mapping_name, doc_key, conflict),
+        },
+        "status": 409,
+    }))
+}
 
 
 pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
-    // let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
 
     // Get index
     let cluster_metadata = system.metadata.read().unwrap();
@@ -27,26 +90,51 @@ pub fn view_get_doc(req: &mut Request) -> IronResult<Response> {
         return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
     }
 
+    let not_found_response = json_response(status::NotFound, json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+        "found": false,
+    }));
+
     // Find document
-    /*
     let index_reader = index.store.reader();
-    let doc = match index_reader.get_document_by_key(doc_key) {
-        Some(doc) => doc,
-        None => {
-            return Ok(json_response(status::NotFound, "{\"message\": \"Document not found\"}"));
-        }
+    let doc_id = match index_reader.get_document_by_key(doc_key) {
+        Some(doc_id) => doc_id,
+        None => return Ok(not_found_response),
     };
-    */
 
+    // Check that the document belongs to this mapping
+    if let Some(doc_type) = read_meta_field(&index_reader, "_type", doc_id) {
+        if doc_type != *mapping_name {
+            return Ok(not_found_response);
+        }
+    }
 
     // Build JSON document
-    // TODO: This is probably completely wrong
-    // let json_object = BTreeMap::new();
-    // FIXME: for (field_name, field_value) in doc.fields.iter() {
-    // FIXME:     json_object.insert(field_name.clone(), Json::Array(field_value.iter().map(|v| v.term.as_json()).collect::<Vec<_>>()));
-    // FIXME: }
+    let mut doc_json = json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+        "found": true,
+    });
+
+    if let Some(version) = index_reader.get_document_version(doc_key) {
+        insert_version_fields(doc_json.as_object_mut().unwrap(), &version);
+    }
+
+    if let Some(source) = read_meta_field(&index_reader, "_source", doc_id) {
+        match serde_json::from_str::<serde_json::Value>(&source) {
+            Ok(source) => {
+                doc_json.as_object_mut().unwrap().insert("_source".to_string(), source);
+            }
+            Err(error) => {
+                warn!(system.log, "unable to parse document source"; "index" => *index_name, "doc" => *doc_key, "error" => format!("{}", error));
+            }
+        }
+    }
 
-    return Ok(json_response(status::Ok, json!({})));
+    return Ok(json_response(status::Ok, doc_json));
 }
 
 
@@ -56,6 +144,29 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
     let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
 
+    let create = match read_op_type(req) {
+        Ok(create) => create,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
+        }
+    };
+
+    // Creates check that the document doesn't exist when it's written
+    let condition = read_version_condition(req).and_then(|condition| {
+        if create {
+            build_create_condition(condition)
+        } else {
+            Ok(condition)
+        }
+    });
+
+    let condition = match condition {
+        Ok(condition) => condition,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
+        }
+    };
+
     // Get index
     let cluster_metadata = system.metadata.read().unwrap();
     let index = get_index_or_404!(cluster_metadata, *index_name);
@@ -74,6 +185,7 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
         if let Some(data) = json_from_request_body!(req) {
             let document_source = DocumentSource {
                 key: doc_key,
+                mapping_name: mapping_name,
                 data: data.as_object().unwrap(),
             };
             document_source.prepare(mapping).unwrap()
@@ -82,10 +194,30 @@ pub fn view_put_doc(req: &mut Request) -> IronResult<Response> {
         }
     };
 
-    index.store.insert_or_update_document(&doc).unwrap();
+    // This only decides how the result is reported, the write doesn't depend on it
+    let exists = !create && index.store.reader().contains_document_key(doc_key);
+
+    let version = match index.store.insert_or_update_document(&doc, condition) {
+        Ok(version) => version,
+        Err(DocumentWriteError::VersionConflict(conflict)) => {
+            return Ok(version_conflict_response(mapping_name, doc_key, &conflict));
+        }
+        Err(DocumentWriteError::StorageError(error)) => {
+            error!(system.log, "failed to write document"; "index" => *index_name, "doc" => *doc_key, "error" => error.clone());
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("Failed to write document: {}", error)})));
+        }
+    };
+
+    let mut response = json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+        "result": if exists { "updated" } else { "created" },
+        "created": !exists,
+    });
+    insert_version_fields(response.as_object_mut().unwrap(), &version);
 
-    // TODO: {"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5378","_version":1,"created":true}
-    return Ok(json_response(status::Ok, json!({})));
+    return Ok(json_response(if exists { status::Ok } else { status::Created }, response));
 }
 
 
@@ -95,6 +227,13 @@ pub fn view_delete_doc(req: &mut Request) -> IronResult<Response> {
     let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
     let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
 
+    let condition = match read_version_condition(req) {
+        Ok(condition) => condition,
+        Err(error) => {
+            return Ok(json_response(status::BadRequest, json!({"message": error})));
+        }
+    };
+
     // Get index
     let cluster_metadata = system.metadata.read().unwrap();
     let index = get_index_or_404!(cluster_metadata, *index_name);
@@ -105,13 +244,29 @@ pub fn view_delete_doc(req: &mut Request) -> IronResult<Response> {
         return Ok(json_response(status::NotFound, json!({"message": "Mapping not found"})));
     }
 
-    // Make sure the document exists
-    if !index.store.reader().contains_document_key(doc_key) {
-        return Ok(json_response(status::NotFound, json!({"message": "Document not found"})));
-    }
-
     // Delete document
-    index.store.remove_document_by_key(doc_key).unwrap();
+    let version = match index.store.remove_document_by_key(doc_key, condition) {
+        Ok(Some(version)) => version,
+        Ok(None) => {
+            return Ok(json_response(status::NotFound, json!({"message": "Document not found"})));
+        }
+        Err(DocumentWriteError::VersionConflict(conflict)) => {
+            return Ok(version_conflict_response(mapping_name, doc_key, &conflict));
+        }
+        Err(DocumentWriteError::StorageError(error)) => {
+            error!(system.log, "failed to delete document"; "index" => *index_name, "doc" => *doc_key, "error" => error.clone());
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("Failed to delete document: {}", error)})));
+        }
+    };
+
+    let mut response = json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+        "result": "deleted",
+        "found": true,
+    });
+    insert_version_fields(response.as_object_mut().unwrap(), &version);
 
-    return Ok(json_response(status::Ok, json!({})));
+    return Ok(json_response(status::Ok, response));
 }
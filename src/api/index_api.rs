@@ -1,19 +1,24 @@
 use std::fs;
 use std::io::Read;
+use std::sync::Arc;
 
 use serde_json;
+use url::form_urlencoded;
+use search::backends::Store;
 use search::backends::rocksdb::RocksDBStore;
+use search::backends::memory::MemoryStore;
 use uuid::Uuid;
 
 use index::Index;
-use index::metadata::IndexMetadata;
+use index::metadata::{IndexMetadata, StoreType};
 use index::metadata::parse::parse as parse_index_metadata;
+use system::System;
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, index_not_found_response};
 
 
 pub fn view_get_index(req: &mut Request) -> IronResult<Response> {
@@ -69,10 +74,20 @@ pub fn view_put_index(req: &mut Request) -> IronResult<Response> {
             }
 
             // Create index
-            let mut indices_dir = system.get_indices_dir();
-            indices_dir.push(index_name);
-            let index = Index::new(Uuid::new_v4(), index_name.clone().to_owned(), metadata, RocksDBStore::create(indices_dir).unwrap());
-            index.metadata.read().unwrap().save(index.metadata_path()).unwrap();
+            let store: Box<Store> = match metadata.store {
+                StoreType::RocksDB => {
+                    let mut indices_dir = system.get_indices_dir();
+                    indices_dir.push(index_name);
+                    Box::new(RocksDBStore::create(indices_dir).unwrap())
+                }
+                StoreType::Memory => Box::new(MemoryStore::new()),
+            };
+            let index = Index::new(Uuid::new_v4(), index_name.clone().to_owned(), metadata, store);
+
+            // Memory indices aren't loaded on startup, so there's no need to save their metadata
+            if let Some(metadata_path) = index.metadata_path() {
+                index.metadata.read().unwrap().save(metadata_path).unwrap();
+            }
             let index_ref = cluster_metadata.insert_index(index);
 
             // If there's an alias with the new indexes name, delete it.
@@ -104,10 +119,10 @@ pub fn view_delete_index(req: &mut Request) -> IronResult<Response> {
 
     // Remove indices
     for index_ref in cluster_metadata.names.find(*index_selector) {
-        // Get the index name
-        let index_name = {
+        // Get the index name and the directory it's stored in
+        let (index_name, index_path) = {
             if let Some(index) = cluster_metadata.indices.get(&index_ref) {
-                index.canonical_name().to_string()
+                (index.canonical_name().to_string(), index.store.path().map(|path| path.to_path_buf()))
             } else {
                 // Index doesn't exist
                 continue;
@@ -120,13 +135,13 @@ pub fn view_delete_index(req: &mut Request) -> IronResult<Response> {
         // Delete canonical name
         cluster_metadata.names.delete_canonical(&index_name, index_ref).unwrap();
 
-        // Delete file
-        let mut indices_dir = system.get_indices_dir();
-        indices_dir.push(&index_name);
-        match fs::remove_dir_all(&indices_dir) {
-            Ok(()) => {},
-            Err(e) => {
-                warn!(system.log, "failed to delete index data"; "index" => format!("{}", index_name), "error" => format!("{}", e));
+        // Delete files
+        if let Some(index_path) = index_path {
+            match fs::remove_dir_all(&index_path) {
+                Ok(()) => {},
+                Err(e) => {
+                    warn!(system.log, "failed to delete index data"; "index" => format!("{}", index_name), "error" => format!("{}", e));
+                }
             }
         }
 
@@ -159,3 +174,160 @@ pub fn view_post_refresh_index(_req: &mut Request) -> IronResult<Response> {
     // TODO: {"_shards":{"total":10,"successful":5,"failed":0}}
     return Ok(json_response(status::Ok, json!({"acknowledged": true})));
 }
+
+
+/// Reads the "max_num_segments" parameter of a force merge
+/// Indices are merged down to a single segment unless told otherwise
+fn parse_max_num_segments(url_query: Option<&str>) -> Result<usize, ()> {
+    let mut max_num_segments = 1;
+
+    if let Some(url_query) = url_query {
+        for (key, value) in form_urlencoded::parse(url_query.as_bytes()) {
+            if key.as_ref() == "max_num_segments" {
+                max_num_segments = match value.as_ref().parse::<usize>() {
+                    Ok(max_num_segments) if max_num_segments > 0 => max_num_segments,
+                    _ => return Err(()),
+                };
+            }
+        }
+    }
+
+    Ok(max_num_segments)
+}
+
+
+/// Finds an index by its canonical name
+/// The cluster metadata lock is released before this returns, so the index can be used for long running tasks
+fn find_index(system: &System, index_name: &str) -> Option<Arc<Index>> {
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index_ref = cluster_metadata.names.find_canonical(index_name)?;
+    cluster_metadata.indices.get(&index_ref).cloned()
+}
+
+
+pub fn view_post_forcemerge_index(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    let max_num_segments = match parse_max_num_segments(req.url.query()) {
+        Ok(max_num_segments) => max_num_segments,
+        Err(()) => {
+            return Ok(json_response(status::BadRequest, json!({"message": "max_num_segments must be a positive integer"})));
+        }
+    };
+
+    // Get index
+    // The merge may take a long time, so it mustn't block other requests from changing the cluster metadata
+    let index = match find_index(system, *index_name) {
+        Some(index) => index,
+        None => return Ok(index_not_found_response()),
+    };
+
+    if let Err(error) = index.force_merge(max_num_segments) {
+        error!(system.log, "failed to force merge index"; "index" => index.canonical_name(), "error" => error.clone());
+        return Ok(json_response(status::InternalServerError, json!({"message": format!("Force merge error: {}", error)})));
+    }
+
+    info!(system.log, "force merged index"; "index" => index.canonical_name(), "max_num_segments" => max_num_segments);
+
+    return Ok(json_response(status::Ok, json!({"acknowledged": true})));
+}
+
+
+pub fn view_get_index_stats(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+
+    let filter_cache = index.store.filter_cache_stats();
+
+    return Ok(json_response(status::Ok, json!({
+        "filter_cache": {
+            "memory_size_in_bytes": filter_cache.memory_size,
+            "cache_size": filter_cache.entries,
+            "hit_count": filter_cache.hits,
+            "miss_count": filter_cache.misses,
+            "evictions": filter_cache.evictions,
+        }
+    })));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::path::{Path, PathBuf};
+
+    use slog;
+    use fnv::FnvHashMap;
+    use uuid::Uuid;
+    use search::{Term, Token, Document};
+    use search::schema::{FieldType, FIELD_INDEXED};
+    use search::backends::rocksdb::RocksDBStore;
+
+    use index::Index;
+    use index::metadata::IndexMetadata;
+    use system::System;
+
+    use super::{parse_max_num_segments, find_index};
+
+    fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
+        match remove_dir_all(&path) {
+            Ok(_) => {}
+            Err(_) => {}  // Don't care if this fails
+        }
+    }
+
+    #[test]
+    fn test_parse_max_num_segments() {
+        assert_eq!(parse_max_num_segments(None), Ok(1));
+        assert_eq!(parse_max_num_segments(Some("max_num_segments=5")), Ok(5));
+        assert_eq!(parse_max_num_segments(Some("flush=true&max_num_segments=5")), Ok(5));
+        assert_eq!(parse_max_num_segments(Some("max_num_segments=0")), Err(()));
+        assert_eq!(parse_max_num_segments(Some("max_num_segments=lots")), Err(()));
+    }
+
+    #[test]
+    fn test_force_merge_releases_cluster_metadata() {
+        remove_dir_all_ignore_error("test_indices/test_force_merge_releases_cluster_metadata");
+
+        let mut store = RocksDBStore::create("test_indices/test_force_merge_releases_cluster_metadata").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for i in 0..3 {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: format!("doc_{}", i),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+            }, None).unwrap();
+        }
+
+        let system = System::new(slog::Logger::root(slog::Discard, o!()), PathBuf::from("test_indices"));
+        {
+            let mut cluster_metadata = system.metadata.write().unwrap();
+            let index = Index::new(Uuid::new_v4(), "test".to_string(), IndexMetadata::default(), Box::new(store));
+            let index_ref = cluster_metadata.insert_index(index);
+            cluster_metadata.names.insert_canonical("test".to_string(), index_ref).unwrap();
+        }
+
+        assert!(find_index(&system, "missing").is_none());
+        let index = find_index(&system, "test").unwrap();
+
+        // Other requests can change the cluster metadata while the index is being merged
+        assert!(system.metadata.try_write().is_ok());
+
+        index.force_merge(1).unwrap();
+        assert_eq!(index.store.as_rocksdb().unwrap().get_segment_statistics().unwrap().len(), 1);
+    }
+}
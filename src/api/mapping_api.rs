@@ -2,9 +2,9 @@ use std::io::Read;
 use std::collections::HashMap;
 
 use serde_json;
-use search::schema::{FieldType, FieldFlags, FIELD_INDEXED, FIELD_STORED};
+use search::schema::{Schema, FieldType, FieldFlags, FIELD_INDEXED, FIELD_STORED};
 
-use mapping::{self, MappingProperty};
+use mapping::{self, Mapping, MappingProperty, FieldMapping};
 use mapping::parse::parse as parse_mapping;
 
 use api::persistent;
@@ -14,6 +14,70 @@ use api::router::Router;
 use api::utils::json_response;
 
 
+/// Works out the type of the store field that holds the values of a field mapping
+///
+/// Strings that are indexed without being analyzed are kept whole, so they are given keyword
+/// fields. These have doc values, which are used for sorting and aggregating.
+fn store_field_type(field_mapping: &FieldMapping) -> FieldType {
+    match field_mapping.data_type {
+        mapping::FieldType::String => {
+            if field_mapping.is_indexed && field_mapping.index_analyzer().is_none() {
+                FieldType::PlainString
+            } else {
+                FieldType::Text
+            }
+        }
+        mapping::FieldType::Integer => FieldType::I64,
+        mapping::FieldType::Boolean => FieldType::Boolean,
+        mapping::FieldType::Date => FieldType::DateTime,
+    }
+}
+
+
+/// Finds the fields of a mapping that need to be added to the store
+///
+/// Returns an error if any of the fields are already in the store with a different type or flags.
+fn find_new_fields(schema: &Schema, mapping: &Mapping) -> Result<HashMap<String, (FieldType, FieldFlags)>, ()> {
+    let mut new_fields: HashMap<String, (FieldType, FieldFlags)>  = HashMap::new();
+    for (name, property) in mapping.properties.iter() {
+        if let MappingProperty::Field(ref field_mapping) = *property {
+            let field_type = store_field_type(field_mapping);
+
+            // Flags
+            let mut field_flags = FieldFlags::empty();
+
+            if field_mapping.is_indexed {
+                field_flags |= FIELD_INDEXED;
+            }
+
+            if field_mapping.is_stored {
+                field_flags |= FIELD_STORED;
+            }
+
+            // Check if this field already exists
+            if let Some(field_ref) = schema.get_field_by_name(&name) {
+                let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldId");
+
+                // Not analyzed strings were given text fields before keyword fields were used
+                // for them. These still work, they just don't have doc values
+                let same_type = field_info.field_type == field_type || (field_info.field_type == FieldType::Text && field_type == FieldType::PlainString);
+
+                // Field already exists. Check for conflicting type or flags, otherwise ignore.
+                if same_type && field_info.field_flags == field_flags {
+                    continue;
+                } else {
+                    return Err(());
+                }
+            }
+
+            new_fields.insert(name.clone(), (field_type, field_flags));
+        }
+    }
+
+    Ok(new_fields)
+}
+
+
 pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
@@ -52,49 +116,13 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
     let is_updating = index_metadata.mappings.contains_key(*mapping_name);
 
     // Find list of new fields that need to be added to the store
-    let new_fields = {
-        let index_reader = index.store.reader();
-        let schema = index_reader.schema();
-        let mut new_fields: HashMap<String, (FieldType, FieldFlags)>  = HashMap::new();
-        for (name, property) in mapping.properties.iter() {
-            if let MappingProperty::Field(ref field_mapping) = *property {
-                let field_type = match field_mapping.data_type {
-                    mapping::FieldType::String => FieldType::Text,
-                    mapping::FieldType::Integer => FieldType::I64,
-                    mapping::FieldType::Boolean => FieldType::Boolean,
-                    mapping::FieldType::Date => FieldType::DateTime,
-                };
-
-                // Flags
-                let mut field_flags = FieldFlags::empty();
-
-                if field_mapping.is_indexed {
-                    field_flags |= FIELD_INDEXED;
-                }
-
-                if field_mapping.is_stored {
-                    field_flags |= FIELD_STORED;
-                }
-
-                // Check if this field already exists
-                if let Some(field_ref) = schema.get_field_by_name(&name) {
-                    let field_info = schema.get(&field_ref).expect("get_field_by_name returned an invalid FieldId");
-
-                    // Field already exists. Check for conflicting type or flags, otherwise ignore.
-                    if field_info.field_type == field_type && field_info.field_flags == field_flags {
-                        continue;
-                    } else {
-                        // Conflict!
-                        // TODO: Better error
-                        return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
-                    }
-                }
-
-                new_fields.insert(name.clone(), (field_type, field_flags));
-            }
+    let new_fields = match find_new_fields(index.store.reader().schema(), &mapping) {
+        Ok(new_fields) => new_fields,
+        Err(()) => {
+            // Conflict!
+            // TODO: Better error
+            return Ok(json_response(status::BadRequest, json!({"acknowledged": false})));
         }
-
-        new_fields
     };
 
     // Add new fields into the store
@@ -119,7 +147,9 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
     }
 
     index_metadata.mappings.insert(mapping_name.clone().to_owned(), mapping);
-    index_metadata.save(index.metadata_path()).unwrap();
+    if let Some(metadata_path) = index.metadata_path() {
+        index_metadata.save(metadata_path).unwrap();
+    }
 
     if is_updating {
         // TODO: New mapping should be merged with existing one
@@ -130,3 +160,107 @@ pub fn view_put_mapping(req: &mut Request) -> IronResult<Response> {
 
     return Ok(json_response(status::Ok, json!({"acknowledged": true})));
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::path::Path;
+
+    use search::document::DocId;
+    use search::query::Query;
+    use search::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::doc_values::DocValues;
+    use search::collectors::top_sorted::{TopSortedCollector, SortKey, SortBy, SortOrder, SortMissing, SortValue};
+    use search::backends::Reader;
+    use search::backends::rocksdb::RocksDBStore;
+
+    use mapping::MappingProperty;
+    use mapping::parse::parse as parse_mapping;
+    use document::DocumentSource;
+    use index::metadata::IndexMetadata;
+
+    use super::find_new_fields;
+
+    fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
+        match remove_dir_all(&path) {
+            Ok(_) => {}
+            Err(_) => {}  // Don't care if this fails
+        }
+    }
+
+    #[test]
+    fn test_sort_on_keyword_field() {
+        remove_dir_all_ignore_error("test_indices/test_sort_on_keyword_field");
+
+        let mut store = RocksDBStore::create("test_indices/test_sort_on_keyword_field").unwrap();
+
+        let mut mapping = parse_mapping(&json!({
+            "properties": {
+                "title": {
+                    "type": "string",
+                    "store": true
+                },
+                "category": {
+                    "type": "string",
+                    "index": "not_analyzed",
+                    "store": true
+                }
+            }
+        })).unwrap().build(&IndexMetadata::default());
+
+        // Not analyzed strings get keyword fields
+        let new_fields = find_new_fields(store.reader().schema(), &mapping).unwrap();
+        assert_eq!(new_fields["title"], (FieldType::Text, FIELD_INDEXED | FIELD_STORED));
+        assert_eq!(new_fields["category"], (FieldType::PlainString, FIELD_INDEXED | FIELD_STORED));
+
+        for (field_name, (field_type, field_flags)) in new_fields {
+            store.add_field(field_name, field_type, field_flags).unwrap();
+        }
+
+        for (name, property) in mapping.properties.iter_mut() {
+            if let MappingProperty::Field(ref mut field_mapping) = *property {
+                field_mapping.index_ref = store.reader().schema().get_field_by_name(&name);
+            }
+        }
+
+        // Putting the same mapping again doesn't add any fields
+        assert!(find_new_fields(store.reader().schema(), &mapping).unwrap().is_empty());
+
+        let sources = vec![
+            ("doc_a", json!({"title": "Hello", "category": "cats"})),
+            ("doc_b", json!({"title": "Hello", "category": "apples"})),
+            ("doc_c", json!({"title": "Hello", "category": "bananas"})),
+        ];
+        let docs = sources.iter().map(|&(key, ref data)| {
+            DocumentSource {
+                key: key,
+                mapping_name: "test",
+                data: data.as_object().unwrap(),
+            }.prepare(&mapping).unwrap()
+        }).collect::<Vec<_>>();
+        store.insert_or_update_documents(&docs, &vec![None; docs.len()]).unwrap();
+
+        let index_reader = store.reader();
+        let category_field = index_reader.schema().get_field_by_name("category").unwrap();
+
+        let sort = [SortKey { by: SortBy::Field(category_field), order: SortOrder::Asc, missing: SortMissing::Last }];
+        let mut collector = TopSortedCollector::new(&sort, None, &index_reader, 10);
+        index_reader.search(&mut collector, &Query::All { score: 1.0 }).unwrap();
+        let hits = collector.into_sorted_vec();
+
+        let values = hits.iter().map(|&(_, ref sort_values)| sort_values[0].clone()).collect::<Vec<_>>();
+        assert_eq!(values, vec![
+            SortValue::String("apples".to_string()),
+            SortValue::String("bananas".to_string()),
+            SortValue::String("cats".to_string()),
+        ]);
+
+        // The values were read from the segment's keyword column
+        let segment = DocId::from_u64(hits[0].0.doc_id()).0;
+        match index_reader.load_doc_values(category_field, segment).as_ref().map(|doc_values| &**doc_values) {
+            Some(&DocValues::Keyword { ref terms, .. }) => assert_eq!(terms, &["apples", "bananas", "cats"]),
+            doc_values => panic!("expected a keyword column, got {:?}", doc_values),
+        }
+    }
+}
@@ -44,15 +44,20 @@ fn get_router() -> Router {
             get "/:index/_alias/:alias" => alias_api::view_get_alias,
             put "/:index/_alias/:alias" => alias_api::view_put_alias,
             get "/:index/:mapping/:doc" => document_api::view_get_doc,
+            get "/:index/:mapping/:doc/_explain" => search_api::view_explain,
+            post "/:index/:mapping/:doc/_explain" => search_api::view_explain,
             put "/:index/:mapping/:doc" => document_api::view_put_doc,
             delete "/:index/:mapping/:doc" => document_api::view_delete_doc,
             get "/:index" => index_api::view_get_index,
             put "/:index" => index_api::view_put_index,
             delete "/:index" => index_api::view_delete_index,
             post "/:index/_refresh" => index_api::view_post_refresh_index,
+            post "/:index/_forcemerge" => index_api::view_post_forcemerge_index,
+            get "/:index/_stats" => index_api::view_get_index_stats,
             put "/:index/_mapping/:mapping" => mapping_api::view_put_mapping,
             post "/_bulk" => bulk_api::view_post_bulk,
-            post "/:index/_bulk" => bulk_api::view_post_index_bulk)
+            post "/:index/_bulk" => bulk_api::view_post_index_bulk,
+            post "/:index/:mapping/_bulk" => bulk_api::view_post_mapping_bulk)
 }
 
 
@@ -5,16 +5,30 @@ use serde_json;
 use url::form_urlencoded;
 use search::document::DocId;
 use search::query::Query;
+use search::backends::Reader;
+use search::collectors::{Collector, DocumentMatch};
 use search::collectors::top_score::TopScoreCollector;
+use search::collectors::top_sorted::{TopSortedCollector, SortValue};
 use search::collectors::total_count::TotalCountCollector;
+use search::collectors::multi::MultiCollector;
+use search::collectors::deadline::DeadlineCollector;
+use search::collectors::terminate_after::TerminateAfterCollector;
 
 use query_parser::{QueryBuildContext, parse as parse_query};
+use query_parser::sort::{parse_sort, parse_search_after};
+use aggregations::{AggregationCollector, parse as parse_aggregations};
 
 use api::persistent;
 use api::iron::prelude::*;
 use api::iron::status;
 use api::router::Router;
-use api::utils::json_response;
+use api::utils::{json_response, field_value_to_json, sort_value_to_json, read_meta_field, parse_time_value};
+
+
+/// Reads the "terminate_after" URL parameter of a search
+fn parse_terminate_after(value: &str) -> Result<u64, ()> {
+    value.parse::<u64>().map_err(|_| ())
+}
 
 
 pub fn view_count(req: &mut Request) -> IronResult<Response> {
@@ -59,6 +73,28 @@ pub fn view_count(req: &mut Request) -> IronResult<Response> {
 }
 
 
+/// Runs a search, passing documents to the extra collectors as well if there are any
+///
+/// The extra collectors count the total, run aggregations and stop the search early. The top
+/// documents can be found more quickly when none of them need to see every document, as
+/// documents that can't score highly enough are skipped.
+fn search_with_extra_collectors<'a, C: Collector>(index_reader: &Reader, collector: &'a mut C, extra_collectors: Vec<&'a mut Collector>, query: &Query) {
+    if extra_collectors.is_empty() {
+        index_reader.search(collector, query).unwrap();
+        return;
+    }
+
+    let mut multi_collector = MultiCollector::new();
+    multi_collector.add(collector);
+
+    for extra_collector in extra_collectors {
+        multi_collector.add(extra_collector);
+    }
+
+    index_reader.search(&mut multi_collector, query).unwrap();
+}
+
+
 pub fn view_search(req: &mut Request) -> IronResult<Response> {
     let ref system = get_system!(req);
     let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
@@ -77,8 +113,73 @@ pub fn view_search(req: &mut Request) -> IronResult<Response> {
 
             match query {
                 Ok(query) => {
-                    let mut from = 0;
-                    let mut size = 10;
+                    // Parse aggregations
+                    let aggregations = match query_json.get("aggs").or(query_json.get("aggregations")) {
+                        Some(aggregations_json) => {
+                            match parse_aggregations(aggregations_json, &index_reader.schema()) {
+                                Ok(aggregations) => Some(aggregations),
+                                Err(error) => {
+                                    return Ok(json_response(status::BadRequest, json!({"message": format!("Aggregation error: {:?}", error)})));
+                                }
+                            }
+                        }
+                        None => None,
+                    };
+
+                    // Parse sort
+                    let sort = match query_json.get("sort") {
+                        Some(sort_json) => {
+                            match parse_sort(sort_json, &index_reader.schema()) {
+                                Ok(sort) => Some(sort),
+                                Err(error) => {
+                                    return Ok(json_response(status::BadRequest, json!({"message": format!("Sort error: {:?}", error)})));
+                                }
+                            }
+                        }
+                        None => None,
+                    };
+
+                    let search_after = match (query_json.get("search_after"), sort.as_ref()) {
+                        (Some(search_after_json), Some(sort)) => {
+                            match parse_search_after(search_after_json, sort, &index_reader.schema()) {
+                                Ok(search_after) => Some(search_after),
+                                Err(error) => {
+                                    return Ok(json_response(status::BadRequest, json!({"message": format!("Search after error: {:?}", error)})));
+                                }
+                            }
+                        }
+                        (Some(_), None) => {
+                            return Ok(json_response(status::BadRequest, json!({"message": "search_after requires sort to be set"})));
+                        }
+                        (None, _) => None,
+                    };
+
+                    let mut from = query_json.get("from").and_then(|from| from.as_u64()).unwrap_or(0) as usize;
+                    let mut size = query_json.get("size").and_then(|size| size.as_u64()).unwrap_or(10) as usize;
+                    let mut explain = query_json.get("explain").and_then(|explain| explain.as_bool()).unwrap_or(false);
+                    let mut track_total_hits = query_json.get("track_total_hits").and_then(|track_total_hits| track_total_hits.as_bool()).unwrap_or(true);
+                    let mut terminate_after = match query_json.get("terminate_after") {
+                        Some(terminate_after_json) => {
+                            match terminate_after_json.as_u64() {
+                                Some(terminate_after) => terminate_after,
+                                None => {
+                                    return Ok(json_response(status::BadRequest, json!({"message": "terminate_after must be a positive integer"})));
+                                }
+                            }
+                        }
+                        None => 0,
+                    };
+                    let mut timeout = match query_json.get("timeout") {
+                        Some(timeout_json) => {
+                            match timeout_json.as_str().and_then(parse_time_value) {
+                                Some(timeout) => Some(timeout),
+                                None => {
+                                    return Ok(json_response(status::BadRequest, json!({"message": "timeout must be a time value, such as \"10ms\""})));
+                                }
+                            }
+                        }
+                        None => None,
+                    };
                     let mut fields = Vec::new();
 
                     // TODO: Rewrite this
@@ -104,10 +205,29 @@ pub fn view_search(req: &mut Request) -> IronResult<Response> {
                                         fields.push((field_name.to_owned(), field_ref));
                                     }
                                 }
-                                // terminate_after
-                                // explain
+                                "explain" => {
+                                    explain = value.as_ref() != "false";
+                                }
+                                "track_total_hits" => {
+                                    track_total_hits = value.as_ref() != "false";
+                                }
+                                "terminate_after" => {
+                                    terminate_after = match parse_terminate_after(&value) {
+                                        Ok(terminate_after) => terminate_after,
+                                        Err(()) => {
+                                            return Ok(json_response(status::BadRequest, json!({"message": "terminate_after must be a positive integer"})));
+                                        }
+                                    };
+                                }
+                                "timeout" => {
+                                    timeout = match parse_time_value(&value) {
+                                        Some(timeout) => Some(timeout),
+                                        None => {
+                                            return Ok(json_response(status::BadRequest, json!({"message": "timeout must be a time value, such as \"10ms\""})));
+                                        }
+                                    };
+                                }
                                 // version
-                                // timeout
                                 // fielddata_fields
                                 // track_scores
                                 // stats
@@ -117,38 +237,120 @@ pub fn view_search(req: &mut Request) -> IronResult<Response> {
                         }
                     }
 
+                    if search_after.is_some() && from > 0 {
+                        return Ok(json_response(status::BadRequest, json!({"message": "from must be 0 when search_after is set"})));
+                    }
+
                     // Do the search
-                    let mut collector = TopScoreCollector::new(from + size);
-                    index_reader.search(&mut collector, &query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &index_reader.schema())).unwrap();
+                    let query = query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &index_reader.schema());
+                    let mut aggregation_collector = aggregations.as_ref().map(|aggregations| AggregationCollector::new(aggregations, &*index_reader));
+                    let mut total_count_collector = if track_total_hits { Some(TotalCountCollector::new()) } else { None };
+
+                    // A terminate_after of 0 means the search isn't stopped early
+                    let mut terminate_after_collector = if terminate_after > 0 { Some(TerminateAfterCollector::new(terminate_after)) } else { None };
+
+                    // The time is counted from here, so the time taken to parse the query isn't included
+                    let mut deadline_collector = timeout.map(DeadlineCollector::after);
+
+                    let mut extra_collectors: Vec<&mut Collector> = Vec::new();
+                    if let Some(ref mut total_count_collector) = total_count_collector {
+                        extra_collectors.push(total_count_collector);
+                    }
+                    if let Some(ref mut aggregation_collector) = aggregation_collector {
+                        extra_collectors.push(aggregation_collector);
+                    }
+                    if let Some(ref mut terminate_after_collector) = terminate_after_collector {
+                        extra_collectors.push(terminate_after_collector);
+                    }
+                    if let Some(ref mut deadline_collector) = deadline_collector {
+                        extra_collectors.push(deadline_collector);
+                    }
+
+                    let doc_matches: Vec<(DocumentMatch, Option<Vec<SortValue>>)> = match sort {
+                        Some(ref sort) => {
+                            let mut collector = TopSortedCollector::new(sort, search_after.as_ref().map(|search_after| &search_after[..]), &*index_reader, from + size);
+                            search_with_extra_collectors(&index_reader, &mut collector, extra_collectors, &query);
+                            collector.into_sorted_vec().into_iter().map(|(doc_match, sort_values)| (doc_match, Some(sort_values))).collect()
+                        }
+                        None => {
+                            let mut collector = TopScoreCollector::new(from + size);
+                            search_with_extra_collectors(&index_reader, &mut collector, extra_collectors, &query);
+                            collector.into_sorted_vec().into_iter().map(|doc_match| (doc_match, None)).collect()
+                        }
+                    };
+
+                    let aggregations_json = aggregation_collector.map(|aggregation_collector| aggregation_collector.into_json());
 
                     // Convert hits into JSON
                     let mut hits = Vec::new();
-                    for doc_match in collector.into_sorted_vec().iter().skip(from) {
-                        let mut field_values = BTreeMap::new();
+                    for &(ref doc_match, ref sort_values) in doc_matches.iter().skip(from) {
+                        let doc_id = DocId::from_u64(doc_match.doc_id());
+
+                        let mut hit = json!({
+                            "_index": index.canonical_name(),
+                            "_type": read_meta_field(&index_reader, "_type", doc_id),
+                            "_id": read_meta_field(&index_reader, "_id", doc_id),
+                            "_score": doc_match.score(),
+                        });
+
+                        if let Some(ref sort_values) = *sort_values {
+                            let sort_values = sort_values.iter().map(sort_value_to_json).collect::<Vec<_>>();
+                            hit.as_object_mut().unwrap().insert("sort".to_string(), json!(sort_values));
+                        }
+
+                        if let Some(source) = read_meta_field(&index_reader, "_source", doc_id) {
+                            if let Ok(source) = serde_json::from_str::<serde_json::Value>(&source) {
+                                hit.as_object_mut().unwrap().insert("_source".to_string(), source);
+                            }
+                        }
 
-                        for &(ref field_name, field_ref) in fields.iter() {
-                            let value = match index_reader.read_stored_field(field_ref, DocId::from_u64(doc_match.doc_id())) {
-                                Ok(Some(value)) => vec![value],
-                                Ok(None) => vec![],
-                                Err(_) => vec![],
-                            };
+                        if explain {
+                            if let Ok(Some(explanation)) = index_reader.explain(&query, doc_id) {
+                                hit.as_object_mut().unwrap().insert("_explanation".to_string(), serde_json::to_value(&explanation).unwrap());
+                            }
+                        }
 
-                            field_values.insert(field_name.clone(), value);
+                        if !fields.is_empty() {
+                            let mut field_values = BTreeMap::new();
+
+                            for &(ref field_name, field_ref) in fields.iter() {
+                                let value = match index_reader.read_stored_field(field_ref, doc_id) {
+                                    Ok(Some(value)) => vec![field_value_to_json(&value)],
+                                    Ok(None) => vec![],
+                                    Err(_) => vec![],
+                                };
+
+                                field_values.insert(field_name.clone(), value);
+                            }
+
+                            hit.as_object_mut().unwrap().insert("fields".to_string(), json!(field_values));
                         }
 
-                        hits.push(json!({
-                            "_score": doc_match.score().unwrap(),
-                            "fields": "FIXME",
-                        }));
+                        hits.push(hit);
                     }
 
                     // TODO: {"took":5,"timed_out":false,"_shards":{"total":5,"successful":5,"failed":0},"hits":{"total":4,"max_score":1.0,"hits":[{"_index":"wagtail","_type":"searchtests_searchtest_searchtests_searchtestchild","_id":"searchtests_searchtest:5380","_score":1.0,"fields":{"pk":["5380"]}},{"_index":"wagtail","_type":"searchtests_searchtest","_id":"searchtests_searchtest:5379","_score":1.0,"fields":{"pk":["5379"]}}]}}
-                    Ok(json_response(status::Ok,
-                                     json!({
-                                         "hits": {
-                                             "total": hits.len(),
-                                             "hits": hits
-                                        }})))
+                    let mut response = json!({
+                        "timed_out": deadline_collector.map(|deadline_collector| deadline_collector.timed_out()).unwrap_or(false),
+                        "hits": {
+                            "hits": hits
+                        }
+                    });
+
+                    if let Some(terminate_after_collector) = terminate_after_collector {
+                        response.as_object_mut().unwrap().insert("terminated_early".to_string(), json!(terminate_after_collector.terminated_early()));
+                    }
+
+                    // The total is left out if it wasn't counted
+                    if let Some(total_count_collector) = total_count_collector {
+                        response["hits"].as_object_mut().unwrap().insert("total".to_string(), json!(total_count_collector.get_total_count()));
+                    }
+
+                    if let Some(aggregations_json) = aggregations_json {
+                        response.as_object_mut().unwrap().insert("aggregations".to_string(), aggregations_json);
+                    }
+
+                    Ok(json_response(status::Ok, response))
                 }
                 Err(_) => {
                     // TODO: What specifically is bad about the Query?
@@ -162,3 +364,88 @@ pub fn view_search(req: &mut Request) -> IronResult<Response> {
         None => Ok(json_response(status::BadRequest, json!({"message": "Missing query"}))),
     }
 }
+
+
+pub fn view_explain(req: &mut Request) -> IronResult<Response> {
+    let ref system = get_system!(req);
+    let ref index_name = read_path_parameter!(req, "index").unwrap_or("");
+    let ref mapping_name = read_path_parameter!(req, "mapping").unwrap_or("");
+    let ref doc_key = read_path_parameter!(req, "doc").unwrap_or("");
+
+    // Get index
+    let cluster_metadata = system.metadata.read().unwrap();
+    let index = get_index_or_404!(cluster_metadata, *index_name);
+    let index_reader = index.store.reader();
+    let index_metadata = index.metadata.read().unwrap();
+
+    // Parse query
+    let query = match json_from_request_body!(req) {
+        Some(query_json) => {
+            match query_json.get("query").map(parse_query) {
+                Some(Ok(query)) => query,
+                Some(Err(_)) | None => {
+                    return Ok(json_response(status::BadRequest, json!({"message": "Query error"})));
+                }
+            }
+        }
+        None => return Ok(json_response(status::BadRequest, json!({"message": "Missing query"}))),
+    };
+
+    let not_found_response = json_response(status::NotFound, json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+        "matched": false,
+    }));
+
+    // Find document
+    let doc_id = match index_reader.get_document_by_key(doc_key) {
+        Some(doc_id) => doc_id,
+        None => return Ok(not_found_response),
+    };
+
+    // Check that the document belongs to this mapping
+    if let Some(doc_type) = read_meta_field(&index_reader, "_type", doc_id) {
+        if doc_type != *mapping_name {
+            return Ok(not_found_response);
+        }
+    }
+
+    // Explain
+    let query = query.build(&QueryBuildContext::new().set_index_metadata(&index_metadata), &index_reader.schema());
+    let mut response = json!({
+        "_index": index.canonical_name(),
+        "_type": *mapping_name,
+        "_id": *doc_key,
+    });
+
+    match index_reader.explain(&query, doc_id) {
+        Ok(Some(explanation)) => {
+            response.as_object_mut().unwrap().insert("matched".to_string(), json!(true));
+            response.as_object_mut().unwrap().insert("explanation".to_string(), serde_json::to_value(&explanation).unwrap());
+        }
+        Ok(None) => {
+            response.as_object_mut().unwrap().insert("matched".to_string(), json!(false));
+        }
+        Err(error) => {
+            return Ok(json_response(status::InternalServerError, json!({"message": format!("Explain error: {}", error)})));
+        }
+    }
+
+    Ok(json_response(status::Ok, response))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::parse_terminate_after;
+
+    #[test]
+    fn test_parse_terminate_after() {
+        assert_eq!(parse_terminate_after("10"), Ok(10));
+        assert_eq!(parse_terminate_after("0"), Ok(0));
+        assert_eq!(parse_terminate_after("lots"), Err(()));
+        assert_eq!(parse_terminate_after("-1"), Err(()));
+        assert_eq!(parse_terminate_after(""), Err(()));
+    }
+}
@@ -1,4 +1,10 @@
+use std::time::Duration;
+
 use serde_json;
+use search::document::{DocId, FieldValue};
+use search::backends::Reader;
+use search::version::{DocumentVersion, VersionCondition};
+use search::collectors::top_sorted::SortValue;
 
 use api::iron::prelude::*;
 use api::iron::status;
@@ -32,6 +38,128 @@ pub fn index_not_found_response() -> Response {
 }
 
 
+pub fn index_busy_response() -> Response {
+    json_response(status::Conflict, json!({"message": "Index is being force merged"}))
+}
+
+
+pub fn field_value_to_json(value: &FieldValue) -> serde_json::Value {
+    match *value {
+        FieldValue::String(ref string) => json!(string),
+        FieldValue::Integer(value) => json!(value),
+        FieldValue::Boolean(value) => json!(value),
+        FieldValue::DateTime(ref value) => json!(value.to_rfc3339()),
+    }
+}
+
+
+/// Converts a value that a hit was sorted by into JSON, these can be passed back in "search_after"
+pub fn sort_value_to_json(value: &SortValue) -> serde_json::Value {
+    match *value {
+        SortValue::Missing => serde_json::Value::Null,
+        SortValue::Boolean(value) => json!(value),
+        SortValue::Integer(value) => json!(value),
+        SortValue::Float(value) => json!(value),
+        SortValue::String(ref string) => json!(string),
+    }
+}
+
+
+/// Reads one of the string fields stored with each document (eg, "_id" or "_source")
+///
+/// Returns None if the field isn't in the index or if the document doesn't have a value for it.
+/// Documents that were indexed before these fields were introduced won't have values for them.
+pub fn read_meta_field(index_reader: &Reader, field_name: &str, doc_id: DocId) -> Option<String> {
+    let field_ref = match index_reader.schema().get_field_by_name(field_name) {
+        Some(field_ref) => field_ref,
+        None => return None,
+    };
+
+    match index_reader.read_stored_field(field_ref, doc_id) {
+        Ok(Some(FieldValue::String(value))) => Some(value),
+        _ => None,
+    }
+}
+
+
+/// Builds the version condition of a write from its "version", "version_type", "if_seq_no" and "if_primary_term" parameters
+///
+/// Returns None if the write doesn't have a condition and an error message if the parameters aren't valid.
+pub fn build_version_condition(version: Option<u64>, version_type: Option<&str>, if_seq_no: Option<u64>, if_primary_term: Option<u64>) -> Result<Option<VersionCondition>, String> {
+    match (if_seq_no, if_primary_term) {
+        (Some(seq_no), Some(primary_term)) => {
+            if version.is_some() || version_type.is_some() {
+                return Err("internal versioning can not be used for optimistic concurrency control. Please use `if_seq_no` and `if_primary_term` instead".to_string());
+            }
+
+            return Ok(Some(VersionCondition::SeqNo {
+                seq_no: seq_no,
+                primary_term: primary_term,
+            }));
+        }
+        (Some(_), None) => return Err("if_seq_no is set, but if_primary_term is missing".to_string()),
+        (None, Some(_)) => return Err("if_primary_term is set, but if_seq_no is missing".to_string()),
+        (None, None) => {}
+    }
+
+    match (version, version_type.unwrap_or("internal")) {
+        (None, "internal") => Ok(None),
+        (Some(version), "internal") => Ok(Some(VersionCondition::Internal(version))),
+        (Some(version), "external") | (Some(version), "external_gt") => Ok(Some(VersionCondition::External(version))),
+        (Some(version), "external_gte") => Ok(Some(VersionCondition::ExternalGte(version))),
+        (None, version_type @ "external") | (None, version_type @ "external_gt") | (None, version_type @ "external_gte") => {
+            Err(format!("version type [{}] requires a version", version_type))
+        }
+        (_, version_type) => Err(format!("no version type matched [{}]", version_type)),
+    }
+}
+
+
+/// Gives a write that creates a document its condition, the document must not already exist
+///
+/// Creates can't be given a version condition of their own.
+pub fn build_create_condition(condition: Option<VersionCondition>) -> Result<Option<VersionCondition>, String> {
+    match condition {
+        None => Ok(Some(VersionCondition::MustNotExist)),
+        Some(_) => Err("create operations do not support versioning. use index instead".to_string()),
+    }
+}
+
+
+/// Parses a time value, such as the "timeout" of a search (eg, "10ms", "1s" or "2m")
+///
+/// Returns None if the value doesn't have a unit or the unit isn't recognised.
+pub fn parse_time_value(value: &str) -> Option<Duration> {
+    // Split the string into the number and the unit
+    let unit_start = value.find(|c: char| !c.is_digit(10)).unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_start);
+
+    let number = match number.parse::<u64>() {
+        Ok(number) => number,
+        Err(_) => return None,
+    };
+
+    match unit {
+        "d" => number.checked_mul(86400).map(Duration::from_secs),
+        "h" => number.checked_mul(3600).map(Duration::from_secs),
+        "m" => number.checked_mul(60).map(Duration::from_secs),
+        "s" => Some(Duration::from_secs(number)),
+        "ms" => Some(Duration::from_millis(number)),
+        "micros" => Some(Duration::from_micros(number)),
+        "nanos" => Some(Duration::from_nanos(number)),
+        _ => None,
+    }
+}
+
+
+/// Adds the "_version", "_seq_no" and "_primary_term" fields that are returned for each write
+pub fn insert_version_fields(object: &mut serde_json::Map<String, serde_json::Value>, version: &DocumentVersion) {
+    object.insert("_version".to_string(), json!(version.version));
+    object.insert("_seq_no".to_string(), json!(version.seq_no));
+    object.insert("_primary_term".to_string(), json!(version.primary_term));
+}
+
+
 macro_rules! get_index_or_404 {
     ($cluster_metadata: expr, $index_name: expr) => {{
         use api::utils::index_not_found_response;
@@ -55,7 +183,8 @@ macro_rules! get_index_or_404 {
 
 macro_rules! get_index_or_404_mut {
     ($cluster_metadata: expr, $index_name: expr) => {{
-        use api::utils::index_not_found_response;
+        use std::sync::Arc;
+        use api::utils::{index_not_found_response, index_busy_response};
 
         let index_ref = match $cluster_metadata.names.find_canonical($index_name) {
             Some(index_ref) => index_ref,
@@ -65,7 +194,15 @@ macro_rules! get_index_or_404_mut {
         };
 
         match $cluster_metadata.indices.get_mut(&index_ref) {
-            Some(index) => index,
+            Some(index) => {
+                // Force merges hold their own reference to the index, outside of the cluster metadata lock
+                match Arc::get_mut(index) {
+                    Some(index) => index,
+                    None => {
+                        return Ok(index_busy_response());
+                    }
+                }
+            }
             None => {
                 return Ok(index_not_found_response());
             }
@@ -1,6 +1,7 @@
 pub mod name_registry;
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use uuid::Uuid;
 
@@ -22,7 +23,7 @@ impl IndexRef {
 
 #[derive(Debug)]
 pub struct ClusterMetadata {
-    pub indices: HashMap<IndexRef, Index>,
+    pub indices: HashMap<IndexRef, Arc<Index>>,
     pub names: NameRegistry,
 }
 
@@ -37,7 +38,7 @@ impl ClusterMetadata {
 
     pub fn insert_index(&mut self, index: Index) -> IndexRef {
         let index_ref = IndexRef(index.id().clone());
-        self.indices.insert(index_ref, index);
+        self.indices.insert(index_ref, Arc::new(index));
 
         index_ref
     }
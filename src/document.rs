@@ -1,5 +1,6 @@
 use serde_json;
 use search::Document;
+use search::document::FieldValue;
 use fnv::FnvHashMap;
 
 use mapping::{Mapping, MappingProperty, FieldValueError};
@@ -8,6 +9,7 @@ use mapping::{Mapping, MappingProperty, FieldValueError};
 #[derive(Debug)]
 pub struct DocumentSource<'a> {
     pub key: &'a str,
+    pub mapping_name: &'a str,
     pub data: &'a serde_json::Map<String, serde_json::Value>,
 }
 
@@ -118,6 +120,22 @@ impl<'a> DocumentSource<'a> {
             }
         }
 
+        // Insert metadata fields
+        let meta_field_values = [
+            ("_id", self.key.to_string()),
+            ("_type", self.mapping_name.to_string()),
+            ("_source", serde_json::to_string(self.data).unwrap()),
+        ];
+
+        for &(field_name, ref value) in meta_field_values.iter() {
+            if let Some(&MappingProperty::Field(ref field_mapping)) = mapping.properties.get(field_name) {
+                // Mappings that were created before metadata fields were introduced won't have these in the store
+                if let Some(field_ref) = field_mapping.index_ref {
+                    stored_fields.insert(field_ref, FieldValue::String(value.clone()));
+                }
+            }
+        }
+
         Ok(Document {
             key: self.key.to_string(),
             indexed_fields: indexed_fields,
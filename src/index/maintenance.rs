@@ -1,83 +1,186 @@
+use search::backends::rocksdb::{RocksDBStore, SegmentStatistics};
+
 use index::Index;
+use index::merge_policy::MergePolicy;
 
 
 impl Index {
+    /// Drops segments that have had all of their documents deleted and rewrites segments that
+    /// have many deletions. Returns the statistics of the segments that are left
+    fn remove_deleted_documents(&self, store: &RocksDBStore, merge_policy: &MergePolicy) -> Result<Vec<(u32, SegmentStatistics)>, String> {
+        let segment_stats = store.get_segment_statistics()?;
+
+        // Deactivate segments with 100% deletions
+        // There's nothing left in these to search or merge, so they can be dropped without rewriting anything
+        let fully_deleted_segments = segment_stats.iter()
+            .filter(|&&(_, ref stats)| stats.total_docs() > 0 && stats.deleted_docs() >= stats.total_docs())
+            .map(|&(segment, _)| segment)
+            .collect::<Vec<_>>();
+
+        if !fully_deleted_segments.is_empty() {
+            store.deactivate_segments(&fully_deleted_segments)?;
+            store.purge_segments(&fully_deleted_segments)?;
+        }
+
+        // Vacuum segments with many deletions
+        // Merging a segment on its own rewrites it without its deleted documents, this removes them from
+        // the postings lists, stored values and statistics
+        let vacuum_segments = segment_stats.iter()
+            .filter(|&&(_, ref stats)| merge_policy.should_vacuum(stats))
+            .map(|&(segment, _)| segment)
+            .collect::<Vec<_>>();
+
+        for segment in vacuum_segments.iter() {
+            store.merge_segments(&vec![*segment])?;
+            store.purge_segments(&vec![*segment])?;
+        }
+
+        // Reload the statistics if any segments were removed above
+        if fully_deleted_segments.is_empty() && vacuum_segments.is_empty() {
+            Ok(segment_stats)
+        } else {
+            store.get_segment_statistics()
+        }
+    }
+
     /// Run a maintenance task on the index
-    /// This must be run periodically by a background thread. It is not currently thread-safe
+    /// This must be run periodically by a background thread. If a force merge is running on the
+    /// index, this returns straight away and leaves the merging to that
     pub fn run_maintenance_task(&self) -> Result<(), String> {
-        let segment_stats = self.store.get_segment_statistics()?;
+        // Only RocksDB stores merge their segments
+        let store = match self.store.as_rocksdb() {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+
+        let _merge_lock = match self.merge_lock.try_lock() {
+            Ok(lock) => lock,
+            Err(_) => return Ok(()),
+        };
+        let merge_policy = self.metadata.read().unwrap().merge_policy.clone();
 
-        // TODO: Deactivate segments with 100% deletions
-        // TODO: Vacuum segments with many deletions
+        let segment_stats = self.remove_deleted_documents(store, &merge_policy)?;
 
         // Merge segments
+        for segment_ids in merge_policy.find_merges(&segment_stats) {
+            store.merge_segments(&segment_ids)?;
+            store.purge_segments(&segment_ids)?;
+        }
+
+        Ok(())
+    }
+
+    /// Merges segments together until there are no more than max_num_segments of them
+    ///
+    /// This blocks until any running maintenance task has finished. The maximum merged segment size
+    /// of the index's merge policy still applies, so there may be more segments left afterwards.
+    pub fn force_merge(&self, max_num_segments: usize) -> Result<(), String> {
+        let store = match self.store.as_rocksdb() {
+            Some(store) => store,
+            None => return Ok(()),
+        };
 
-        // Firstly we classify each active segment into one of 5 groups (based on the number of
-        // total documents they have):
-        // Group 1: 1 - 9 docs
-        // Group 2: 10 - 99 docs
-        // Group 3: 100 - 999 docs
-        // Group 4: 1000 - 9999 docs
-        // Group 5: 10000 - 65536 docs
-
-        // The group with the most active segments can perform a merge. A merge can be done on
-        // between 5 - 1000 segments at a time. The smallest segments get merged first.
-
-        let mut segments_g1 = Vec::new();
-        let mut segments_g2 = Vec::new();
-        let mut segments_g3 = Vec::new();
-        let mut segments_g4 = Vec::new();
-        let mut segments_g5 = Vec::new();
-
-        for (segment, stats) in segment_stats {
-            match stats.total_docs() {
-                1 ... 9 => segments_g1.push((segment, stats)),
-                10 ... 99 => segments_g2.push((segment, stats)),
-                100 ... 999 => segments_g3.push((segment, stats)),
-                1000 ... 9999 => segments_g4.push((segment, stats)),
-                10000 ... 65536 => segments_g5.push((segment, stats)),
-                _ => {},
-            }
+        let _merge_lock = self.merge_lock.lock().unwrap();
+        let merge_policy = self.metadata.read().unwrap().merge_policy.clone();
+
+        let mut segment_stats = self.remove_deleted_documents(store, &merge_policy)?;
+
+        while let Some(segment_ids) = merge_policy.find_forced_merge(&segment_stats, max_num_segments) {
+            store.merge_segments(&segment_ids)?;
+            store.purge_segments(&segment_ids)?;
+
+            segment_stats = store.get_segment_statistics()?;
         }
 
-        // Now sort the groups by length in ascending order
-        let mut segments_grouped = vec![segments_g1, segments_g2, segments_g3, segments_g4, segments_g5];
-        segments_grouped.sort_by_key(|group| group.len());
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::path::Path;
+
+    use fnv::FnvHashMap;
+    use uuid::Uuid;
+    use search::{Term, Token, Document};
+    use search::schema::{FieldType, FIELD_INDEXED};
+    use search::backends::rocksdb::RocksDBStore;
 
-        // The group with the most segments is our merge candidate. Check that it has above the
-        // minimum number of documents to start a merge and truncate it to be less than the maximum.
-        let mut group_to_merge = segments_grouped.pop().unwrap();
+    use index::Index;
+    use index::metadata::IndexMetadata;
 
-        if group_to_merge.len() < 3 {
-            // No point in merging these
-            return Ok(());
+    fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
+        match remove_dir_all(&path) {
+            Ok(_) => {}
+            Err(_) => {}  // Don't care if this fails
         }
+    }
 
-        // Now we've found a group of segments to merge, we must check that all the docs will fit in a
-        // single segment. If not, we choose the largest sub-group of segments to merge that fills the
-        // quota as much as possible
+    /// Creates an index with a segment for each document
+    fn make_test_index(path: &str, num_docs: usize) -> Index {
+        let mut store = RocksDBStore::create(path).unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        for i in 0..num_docs {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: format!("doc_{}", i),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+            }, None).unwrap();
+        }
 
-        let mut current_doc_count: u32 = 0;
-        let mut segment_ids = Vec::new();
+        Index::new(Uuid::new_v4(), "test".to_string(), IndexMetadata::default(), Box::new(store))
+    }
 
-        // Sort segments total_docs in descending order
-        // TODO: Check that this is descending order
-        group_to_merge.sort_by_key(|&(_, ref stats)| -stats.total_docs());
+    fn num_segments(index: &Index) -> usize {
+        index.store.as_rocksdb().unwrap().get_segment_statistics().unwrap().len()
+    }
 
-        for (segment, stats) in group_to_merge {
-            if current_doc_count + stats.total_docs() as u32 > 65536 {
-                // No space for this segment
-                continue;
-            }
+    #[test]
+    fn test_force_merge() {
+        remove_dir_all_ignore_error("test_indices/test_force_merge");
 
-            segment_ids.push(segment);
-            current_doc_count += stats.total_docs() as u32;
+        let index = make_test_index("test_indices/test_force_merge", 5);
+        assert_eq!(num_segments(&index), 5);
+
+        index.force_merge(3).unwrap();
+        assert_eq!(num_segments(&index), 3);
+
+        index.force_merge(1).unwrap();
+        assert_eq!(num_segments(&index), 1);
+
+        // None of the documents should have been lost
+        let index_reader = index.store.reader();
+        for i in 0..5 {
+            assert!(index_reader.get_document_by_key(&format!("doc_{}", i)).is_some());
         }
+    }
 
-        // Merge segments
-        self.store.merge_segments(&segment_ids)?;
-        self.store.purge_segments(&segment_ids)?;
+    #[test]
+    fn test_force_merge_removes_deleted_documents() {
+        remove_dir_all_ignore_error("test_indices/test_force_merge_removes_deleted_documents");
 
-        Ok(())
+        let index = make_test_index("test_indices/test_force_merge_removes_deleted_documents", 3);
+        index.store.remove_document_by_key("doc_0", None).unwrap();
+
+        // The fully deleted segment is dropped, so there's nothing left to merge
+        index.force_merge(2).unwrap();
+        assert_eq!(num_segments(&index), 2);
+
+        index.force_merge(1).unwrap();
+        let segment_stats = index.store.as_rocksdb().unwrap().get_segment_statistics().unwrap();
+        assert_eq!(segment_stats.len(), 1);
+        assert_eq!(segment_stats[0].1.total_docs(), 2);
+        assert_eq!(segment_stats[0].1.deleted_docs(), 0);
     }
 }
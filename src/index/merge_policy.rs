@@ -0,0 +1,308 @@
+//! Decides which segments the maintenance task should merge together
+//!
+//! The maintenance task and force merges only see the `MergePolicy` trait. `TieredMergePolicy`
+//! is the policy that indexes use, its settings are read from the index metadata.
+//!
+//! Segments are grouped into tiers by the number of live documents they contain and each merge
+//! only takes segments from a single tier. This stops small segments from being repeatedly
+//! merged into much larger ones, which would rewrite the same documents over and over.
+
+use std::cmp::{self, Ordering};
+
+use search::backends::rocksdb::SegmentStatistics;
+
+
+/// Chooses the segments to merge and vacuum
+pub trait MergePolicy {
+    /// Returns true if the segment has enough deleted documents for it to be rewritten on its own
+    fn should_vacuum(&self, stats: &SegmentStatistics) -> bool;
+
+    /// Finds the merges to perform in a maintenance task
+    ///
+    /// Each merge is a list of segment ids to merge into a single new segment, they are run in order.
+    fn find_merges(&self, segment_stats: &[(u32, SegmentStatistics)]) -> Vec<Vec<u32>>;
+
+    /// Finds the next merge that will bring the number of segments down to max_num_segments
+    ///
+    /// This is called repeatedly with the latest statistics until it returns None.
+    fn find_forced_merge(&self, segment_stats: &[(u32, SegmentStatistics)], max_num_segments: usize) -> Option<Vec<u32>>;
+}
+
+
+/// The default merge policy, merges segments that are in the same size tier
+#[derive(Debug, Clone, PartialEq)]
+pub struct TieredMergePolicy {
+    /// Upper bounds (exclusive) of the number of live documents in each tier's segments
+    /// Segments that are larger than the last bound are put in a final tier
+    pub tiers: Vec<u32>,
+
+    /// The number of segments a tier must have before they are merged
+    pub min_segments_per_merge: usize,
+
+    /// The maximum number of segments that can be merged together at once
+    pub max_segments_per_merge: usize,
+
+    /// The maximum number of documents that a merged segment can contain
+    pub max_merged_segment_docs: u32,
+
+    /// How much deleted documents count against the size of a segment when choosing the order
+    /// to merge them in. Higher values prefer merging segments with many deletions
+    pub deletes_weight: f64,
+
+    /// Segments with at least this proportion of their documents deleted are rewritten without them
+    pub vacuum_deleted_ratio: f64,
+
+    /// The maximum number of documents to merge in each maintenance task
+    ///
+    /// This is a cap on the work done by a single task, not a rate limit. Merges that don't fit
+    /// are left for later tasks. The first merge of a task always goes ahead, even if it is
+    /// larger than this. Force merges ignore it.
+    pub max_merged_docs_per_task: u32,
+}
+
+
+impl Default for TieredMergePolicy {
+    fn default() -> TieredMergePolicy {
+        TieredMergePolicy {
+            tiers: vec![10, 100, 1000, 10000, 100000],
+            min_segments_per_merge: 3,
+            max_segments_per_merge: 1000,
+            max_merged_segment_docs: 1000000,
+            deletes_weight: 1.0,
+            vacuum_deleted_ratio: 0.3,
+            max_merged_docs_per_task: 100000,
+        }
+    }
+}
+
+
+impl TieredMergePolicy {
+    fn get_tier(&self, live_docs: i64) -> usize {
+        self.tiers.iter().position(|&bound| live_docs < bound as i64).unwrap_or(self.tiers.len())
+    }
+
+    fn weighted_size(&self, stats: &SegmentStatistics) -> f64 {
+        stats.live_docs() as f64 - stats.deleted_docs() as f64 * self.deletes_weight
+    }
+
+    /// Selects up to max_segments segments to merge together, smallest first
+    /// Returns the segment ids and the number of live documents that will be in the merged segment
+    fn select_segments(&self, mut candidates: Vec<(u32, &SegmentStatistics)>, max_segments: usize) -> (Vec<u32>, i64) {
+        candidates.sort_by(|a, b| {
+            self.weighted_size(a.1).partial_cmp(&self.weighted_size(b.1)).unwrap_or(Ordering::Equal)
+        });
+
+        let mut segments = Vec::new();
+        let mut docs = 0;
+
+        for (segment, stats) in candidates {
+            if segments.len() >= max_segments {
+                break;
+            }
+
+            if docs + stats.live_docs() > self.max_merged_segment_docs as i64 {
+                // No space for this segment
+                continue;
+            }
+
+            segments.push(segment);
+            docs += stats.live_docs();
+        }
+
+        (segments, docs)
+    }
+}
+
+
+impl MergePolicy for TieredMergePolicy {
+    fn should_vacuum(&self, stats: &SegmentStatistics) -> bool {
+        stats.live_docs() > 0 && stats.deleted_docs() as f64 / stats.total_docs() as f64 >= self.vacuum_deleted_ratio
+    }
+
+    /// Tiers with the most segments are merged first, until max_merged_docs_per_task is reached.
+    fn find_merges(&self, segment_stats: &[(u32, SegmentStatistics)]) -> Vec<Vec<u32>> {
+        let mut tiers = (0..self.tiers.len() + 1).map(|_| Vec::new()).collect::<Vec<_>>();
+
+        for &(segment, ref stats) in segment_stats {
+            if stats.live_docs() > 0 {
+                tiers[self.get_tier(stats.live_docs())].push((segment, stats));
+            }
+        }
+
+        tiers.sort_by(|a, b| b.len().cmp(&a.len()));
+
+        let mut merges = Vec::new();
+        let mut merged_docs = 0;
+
+        for tier in tiers {
+            if tier.len() < self.min_segments_per_merge {
+                continue;
+            }
+
+            let (segments, docs) = self.select_segments(tier, self.max_segments_per_merge);
+
+            if segments.len() < self.min_segments_per_merge {
+                // Not enough of these segments fit into a single merged segment
+                continue;
+            }
+
+            if !merges.is_empty() && merged_docs + docs > self.max_merged_docs_per_task as i64 {
+                continue;
+            }
+
+            merges.push(segments);
+            merged_docs += docs;
+        }
+
+        merges
+    }
+
+    /// This ignores the tiers and max_merged_docs_per_task, the smallest segments are merged first.
+    /// Returns None when there are few enough segments, or when no more segments can be merged
+    /// without going over the maximum merged segment size.
+    fn find_forced_merge(&self, segment_stats: &[(u32, SegmentStatistics)], max_num_segments: usize) -> Option<Vec<u32>> {
+        let candidates = segment_stats.iter()
+            .filter(|&&(_, ref stats)| stats.live_docs() > 0)
+            .map(|&(segment, ref stats)| (segment, stats))
+            .collect::<Vec<_>>();
+
+        let max_num_segments = cmp::max(max_num_segments, 1);
+        if candidates.len() <= max_num_segments {
+            return None;
+        }
+
+        // Merging n segments together removes n - 1 of them
+        let max_segments = cmp::min(candidates.len() - max_num_segments + 1, self.max_segments_per_merge);
+        let (segments, _) = self.select_segments(candidates, max_segments);
+
+        if segments.len() < 2 {
+            return None;
+        }
+
+        Some(segments)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::backends::rocksdb::SegmentStatistics;
+
+    use super::{MergePolicy, TieredMergePolicy};
+
+    fn make_segments(sizes: &[(i64, i64)]) -> Vec<(u32, SegmentStatistics)> {
+        sizes.iter().enumerate().map(|(i, &(total_docs, deleted_docs))| {
+            (i as u32 + 1, SegmentStatistics::new(total_docs, deleted_docs))
+        }).collect()
+    }
+
+    #[test]
+    fn test_find_merges() {
+        let policy = TieredMergePolicy::default();
+        let segments = make_segments(&[(5, 0), (50, 0), (7, 0), (60, 0), (3, 0)]);
+
+        assert_eq!(policy.find_merges(&segments), vec![vec![5, 1, 3]]);
+    }
+
+    #[test]
+    fn test_find_merges_not_enough_segments() {
+        let policy = TieredMergePolicy::default();
+        let segments = make_segments(&[(5, 0), (7, 0), (50, 0), (60, 0)]);
+
+        assert_eq!(policy.find_merges(&segments), Vec::<Vec<u32>>::new());
+    }
+
+    #[test]
+    fn test_find_merges_ignores_fully_deleted_segments() {
+        let policy = TieredMergePolicy::default();
+        let segments = make_segments(&[(5, 0), (7, 7), (3, 0)]);
+
+        assert_eq!(policy.find_merges(&segments), Vec::<Vec<u32>>::new());
+    }
+
+    #[test]
+    fn test_find_merges_max_merged_segment_docs() {
+        let policy = TieredMergePolicy {
+            max_merged_segment_docs: 100,
+            min_segments_per_merge: 2,
+            ..TieredMergePolicy::default()
+        };
+        let segments = make_segments(&[(40, 0), (40, 0), (40, 0)]);
+
+        assert_eq!(policy.find_merges(&segments), vec![vec![1, 2]]);
+    }
+
+    #[test]
+    fn test_find_merges_deletes_weight() {
+        let policy = TieredMergePolicy {
+            max_segments_per_merge: 3,
+            ..TieredMergePolicy::default()
+        };
+        let segments = make_segments(&[(5, 0), (5, 0), (5, 0), (9, 4)]);
+
+        // The segment with deletions ranks as smaller than the others
+        assert_eq!(policy.find_merges(&segments), vec![vec![4, 1, 2]]);
+
+        // Without the weighting, it's the largest
+        let policy = TieredMergePolicy {
+            deletes_weight: 0.0,
+            ..policy
+        };
+        assert_eq!(policy.find_merges(&segments), vec![vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn test_find_merges_max_merged_docs_per_task() {
+        let policy = TieredMergePolicy {
+            max_merged_docs_per_task: 20,
+            ..TieredMergePolicy::default()
+        };
+        let segments = make_segments(&[(5, 0), (5, 0), (5, 0), (50, 0), (50, 0), (50, 0), (50, 0)]);
+
+        // The first merge always goes ahead, even though it's over the limit
+        assert_eq!(policy.find_merges(&segments), vec![vec![4, 5, 6, 7]]);
+
+        let policy = TieredMergePolicy {
+            max_merged_docs_per_task: 250,
+            ..policy
+        };
+        assert_eq!(policy.find_merges(&segments), vec![vec![4, 5, 6, 7], vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn test_should_vacuum() {
+        let policy = TieredMergePolicy::default();
+
+        assert!(!policy.should_vacuum(&SegmentStatistics::new(10, 0)));
+        assert!(!policy.should_vacuum(&SegmentStatistics::new(10, 2)));
+        assert!(policy.should_vacuum(&SegmentStatistics::new(10, 3)));
+
+        // Fully deleted segments are deactivated instead
+        assert!(!policy.should_vacuum(&SegmentStatistics::new(10, 10)));
+    }
+
+    #[test]
+    fn test_find_forced_merge() {
+        let policy = TieredMergePolicy::default();
+        let segments = make_segments(&[(500, 0), (5, 0), (50, 0), (5000, 0), (3, 0)]);
+
+        assert_eq!(policy.find_forced_merge(&segments, 1), Some(vec![5, 2, 3, 1, 4]));
+        assert_eq!(policy.find_forced_merge(&segments, 3), Some(vec![5, 2, 3]));
+        assert_eq!(policy.find_forced_merge(&segments, 5), None);
+    }
+
+    #[test]
+    fn test_find_forced_merge_max_merged_segment_docs() {
+        let policy = TieredMergePolicy {
+            max_merged_segment_docs: 100,
+            ..TieredMergePolicy::default()
+        };
+        let segments = make_segments(&[(60, 0), (60, 0), (30, 0)]);
+
+        assert_eq!(policy.find_forced_merge(&segments, 1), Some(vec![3, 1]));
+
+        // Once the segments are too big to merge, stop
+        let segments = make_segments(&[(60, 0), (90, 0)]);
+        assert_eq!(policy.find_forced_merge(&segments, 1), None);
+    }
+}
@@ -10,6 +10,22 @@ use analysis::AnalyzerSpec;
 use analysis::tokenizers::TokenizerSpec;
 use analysis::filters::FilterSpec;
 use mapping::{Mapping, MappingProperty, FieldMapping};
+use index::merge_policy::TieredMergePolicy;
+
+
+/// The default number of documents from a bulk request that are written into each segment
+pub const DEFAULT_BULK_CHUNK_SIZE: usize = 10000;
+
+
+/// Where an index keeps its documents
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StoreType {
+    /// A RocksDB database in the index's directory
+    RocksDB,
+
+    /// Kept in memory only, the documents are lost when the server stops
+    Memory,
+}
 
 
 #[derive(Debug)]
@@ -18,6 +34,11 @@ pub struct IndexMetadata {
     tokenizers: HashMap<String, TokenizerSpec>,
     filters: HashMap<String, FilterSpec>,
     pub mappings: HashMap<String, Mapping>,
+    pub merge_policy: TieredMergePolicy,
+    pub store: StoreType,
+
+    /// The number of documents from a bulk request that are written into each segment
+    pub bulk_chunk_size: usize,
 }
 
 
@@ -28,6 +49,9 @@ impl Default for IndexMetadata {
             tokenizers: HashMap::new(),
             filters: HashMap::new(),
             mappings: HashMap::new(),
+            merge_policy: TieredMergePolicy::default(),
+            store: StoreType::RocksDB,
+            bulk_chunk_size: DEFAULT_BULK_CHUNK_SIZE,
         };
 
         // Builtin tokenizers
@@ -143,13 +167,36 @@ impl Serialize for IndexMetadata {
             mappings_json.insert(name.to_string(), serde_json::to_value(&mapping).unwrap());
         }
 
+        // Merge policy
+        let merge_policy_json = json!({
+            "tiers": self.merge_policy.tiers,
+            "min_segments_per_merge": self.merge_policy.min_segments_per_merge,
+            "max_segments_per_merge": self.merge_policy.max_segments_per_merge,
+            "max_merged_segment_docs": self.merge_policy.max_merged_segment_docs,
+            "deletes_weight": self.merge_policy.deletes_weight,
+            "vacuum_deleted_ratio": self.merge_policy.vacuum_deleted_ratio,
+            "max_merged_docs_per_task": self.merge_policy.max_merged_docs_per_task,
+        });
+
+        let store = match self.store {
+            StoreType::RocksDB => "rocksdb",
+            StoreType::Memory => "memory",
+        };
+
         let json = json!({
             "settings": {
+                "store": store,
                 "analysis": {
                     "tokenizers": tokenizers_json,
                     "filters": filters_json,
                     "analyzers": {},  // TODO
                 },
+                "merge": {
+                    "policy": merge_policy_json,
+                },
+                "bulk": {
+                    "chunk_size": self.bulk_chunk_size,
+                },
             },
             "mappings": mappings_json,
         });
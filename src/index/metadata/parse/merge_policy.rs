@@ -0,0 +1,101 @@
+use serde_json;
+
+use index::merge_policy::TieredMergePolicy;
+
+
+#[derive(Debug, PartialEq)]
+pub enum MergePolicyParseError {
+    ExpectedObject,
+    ExpectedArray,
+    ExpectedPositiveInteger(String),
+    ExpectedNumber(String),
+    UnrecognisedKey(String),
+    TiersNotAscending,
+    InvalidSegmentsPerMerge,
+    MaxMergedSegmentDocsTooLarge,
+    InvalidVacuumDeletedRatio,
+}
+
+
+/// The largest number of documents that can fit in a segment
+const MAX_SEGMENT_DOCS: u64 = 0xFFFFFFFF;
+
+
+fn parse_positive_integer(key: &str, json: &serde_json::Value) -> Result<u64, MergePolicyParseError> {
+    match json.as_u64() {
+        Some(value) if value > 0 => Ok(value),
+        _ => Err(MergePolicyParseError::ExpectedPositiveInteger(key.to_string())),
+    }
+}
+
+
+fn parse_number(key: &str, json: &serde_json::Value) -> Result<f64, MergePolicyParseError> {
+    json.as_f64().ok_or(MergePolicyParseError::ExpectedNumber(key.to_string()))
+}
+
+
+pub fn parse(json: &serde_json::Value) -> Result<TieredMergePolicy, MergePolicyParseError> {
+    let data = json.as_object().ok_or(MergePolicyParseError::ExpectedObject)?;
+    let mut merge_policy = TieredMergePolicy::default();
+
+    for (key, value) in data.iter() {
+        match key.as_str() {
+            "tiers" => {
+                let tiers_json = value.as_array().ok_or(MergePolicyParseError::ExpectedArray)?;
+
+                let mut tiers: Vec<u32> = Vec::new();
+                for tier_json in tiers_json.iter() {
+                    let tier = parse_positive_integer("tiers", tier_json)?;
+
+                    if let Some(&previous) = tiers.last() {
+                        if tier as u32 <= previous {
+                            return Err(MergePolicyParseError::TiersNotAscending);
+                        }
+                    }
+
+                    tiers.push(tier as u32);
+                }
+
+                merge_policy.tiers = tiers;
+            }
+            "min_segments_per_merge" => {
+                merge_policy.min_segments_per_merge = parse_positive_integer(key, value)? as usize;
+            }
+            "max_segments_per_merge" => {
+                merge_policy.max_segments_per_merge = parse_positive_integer(key, value)? as usize;
+            }
+            "max_merged_segment_docs" => {
+                let max_merged_segment_docs = parse_positive_integer(key, value)?;
+
+                if max_merged_segment_docs > MAX_SEGMENT_DOCS {
+                    return Err(MergePolicyParseError::MaxMergedSegmentDocsTooLarge);
+                }
+
+                merge_policy.max_merged_segment_docs = max_merged_segment_docs as u32;
+            }
+            "deletes_weight" => {
+                merge_policy.deletes_weight = parse_number(key, value)?;
+            }
+            "vacuum_deleted_ratio" => {
+                let vacuum_deleted_ratio = parse_number(key, value)?;
+
+                if vacuum_deleted_ratio <= 0.0 || vacuum_deleted_ratio > 1.0 {
+                    return Err(MergePolicyParseError::InvalidVacuumDeletedRatio);
+                }
+
+                merge_policy.vacuum_deleted_ratio = vacuum_deleted_ratio;
+            }
+            "max_merged_docs_per_task" => {
+                merge_policy.max_merged_docs_per_task = parse_positive_integer(key, value)? as u32;
+            }
+            _ => return Err(MergePolicyParseError::UnrecognisedKey(key.clone())),
+        }
+    }
+
+    // A merge needs at least two segments
+    if merge_policy.min_segments_per_merge < 2 || merge_policy.min_segments_per_merge > merge_policy.max_segments_per_merge {
+        return Err(MergePolicyParseError::InvalidSegmentsPerMerge);
+    }
+
+    Ok(merge_policy)
+}
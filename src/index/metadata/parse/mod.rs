@@ -1,24 +1,30 @@
 pub mod analysis_tokenizer;
 pub mod analysis_filter;
 pub mod analysis_analyzer;
+pub mod merge_policy;
 
 use serde_json;
 
-use index::metadata::IndexMetadata;
+use index::metadata::{IndexMetadata, StoreType};
 use mapping::parse::{MappingParseError, parse as parse_mapping};
 
 use self::analysis_tokenizer::{TokenizerParseError, parse as parse_tokenizer};
 use self::analysis_filter::{FilterParseError, parse as parse_filter};
 use self::analysis_analyzer::{AnalyzerParseError, parse as parse_analyzer};
+use self::merge_policy::{MergePolicyParseError, parse as parse_merge_policy};
 
 
 #[derive(Debug, PartialEq)]
 pub enum IndexMetadataParseError {
     ExpectedObject,
+    ExpectedString,
+    ExpectedPositiveInteger,
+    UnrecognisedStoreType(String),
     TokenizerParseError(String, TokenizerParseError),
     FilterParseError(String, FilterParseError),
     AnalyzerParseError(String, AnalyzerParseError),
     MappingParseError(String, MappingParseError),
+    MergePolicyParseError(MergePolicyParseError),
 }
 
 
@@ -36,6 +42,15 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
             None => return Err(IndexMetadataParseError::ExpectedObject),
         };
 
+        if let Some(store) = settings.get("store") {
+            metadata.store = match store.as_str() {
+                Some("rocksdb") => StoreType::RocksDB,
+                Some("memory") => StoreType::Memory,
+                Some(store) => return Err(IndexMetadataParseError::UnrecognisedStoreType(store.to_string())),
+                None => return Err(IndexMetadataParseError::ExpectedString),
+            };
+        }
+
         if let Some(analysis) = settings.get("analysis") {
             let analysis = match analysis.as_object() {
                 Some(object) => object,
@@ -93,6 +108,35 @@ pub fn parse(metadata: &mut IndexMetadata, data: serde_json::Value) -> Result<()
                 }
             }
         }
+
+        if let Some(merge) = settings.get("merge") {
+            let merge = match merge.as_object() {
+                Some(object) => object,
+                None => return Err(IndexMetadataParseError::ExpectedObject),
+            };
+
+            // Merge policy
+            if let Some(merge_policy_data) = merge.get("policy") {
+                metadata.merge_policy = match parse_merge_policy(merge_policy_data) {
+                    Ok(merge_policy) => merge_policy,
+                    Err(e) => return Err(IndexMetadataParseError::MergePolicyParseError(e)),
+                };
+            }
+        }
+
+        if let Some(bulk) = settings.get("bulk") {
+            let bulk = match bulk.as_object() {
+                Some(object) => object,
+                None => return Err(IndexMetadataParseError::ExpectedObject),
+            };
+
+            if let Some(chunk_size) = bulk.get("chunk_size") {
+                metadata.bulk_chunk_size = match chunk_size.as_u64() {
+                    Some(chunk_size) if chunk_size > 0 => chunk_size as usize,
+                    _ => return Err(IndexMetadataParseError::ExpectedPositiveInteger),
+                };
+            }
+        }
     }
 
     if let Some(mappings) = data.get("mappings") {
@@ -124,11 +168,13 @@ mod tests {
     use analysis::filters::FilterSpec;
     use analysis::AnalyzerSpec;
     use mapping::parse::MappingParseError;
-    use index::metadata::IndexMetadata;
+    use index::metadata::{IndexMetadata, StoreType, DEFAULT_BULK_CHUNK_SIZE};
+    use index::merge_policy::TieredMergePolicy;
 
     use super::{parse, IndexMetadataParseError};
     use super::analysis_tokenizer::TokenizerParseError;
     use super::analysis_filter::FilterParseError;
+    use super::merge_policy::MergePolicyParseError;
 
     #[test]
     fn test_default() {
@@ -360,4 +406,122 @@ mod tests {
 
         assert_eq!(error, IndexMetadataParseError::MappingParseError("test_mapping".to_string(), MappingParseError::UnrecognisedKeys(vec!["foo".to_string()])));
     }
+
+    #[test]
+    fn test_merge_policy() {
+        let mut metadata = IndexMetadata::default();
+        parse(&mut metadata, json!({
+            "settings": {
+                "merge": {
+                    "policy": {
+                        "tiers": [100, 10000],
+                        "max_segments_per_merge": 10,
+                        "max_merged_segment_docs": 50000,
+                        "deletes_weight": 0.5,
+                    }
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.merge_policy, TieredMergePolicy {
+            tiers: vec![100, 10000],
+            max_segments_per_merge: 10,
+            max_merged_segment_docs: 50000,
+            deletes_weight: 0.5,
+            ..TieredMergePolicy::default()
+        });
+    }
+
+    #[test]
+    fn test_merge_policy_error() {
+        let mut metadata = IndexMetadata::default();
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "merge": {
+                    "policy": {
+                        "tiers": [100, 10],
+                    }
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::MergePolicyParseError(MergePolicyParseError::TiersNotAscending));
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "merge": {
+                    "policy": {
+                        "max_merged_segment_docs": 5000000000u64,
+                    }
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::MergePolicyParseError(MergePolicyParseError::MaxMergedSegmentDocsTooLarge));
+    }
+
+    #[test]
+    fn test_merge_policy_round_trip() {
+        let mut metadata = IndexMetadata::default();
+        metadata.merge_policy.min_segments_per_merge = 5;
+        metadata.merge_policy.vacuum_deleted_ratio = 0.5;
+
+        let mut loaded_metadata = IndexMetadata::default();
+        parse(&mut loaded_metadata, serde_json::to_value(&metadata).unwrap()).expect("parse() returned an error");
+
+        assert_eq!(loaded_metadata.merge_policy, metadata.merge_policy);
+    }
+
+    #[test]
+    fn test_store() {
+        let mut metadata = IndexMetadata::default();
+        assert_eq!(metadata.store, StoreType::RocksDB);
+
+        parse(&mut metadata, json!({
+            "settings": {
+                "store": "memory"
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.store, StoreType::Memory);
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "store": "foo"
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::UnrecognisedStoreType("foo".to_string()));
+    }
+
+    #[test]
+    fn test_bulk_chunk_size() {
+        let mut metadata = IndexMetadata::default();
+        assert_eq!(metadata.bulk_chunk_size, DEFAULT_BULK_CHUNK_SIZE);
+
+        parse(&mut metadata, json!({
+            "settings": {
+                "bulk": {
+                    "chunk_size": 500
+                }
+            }
+        })).expect("parse() returned an error");
+
+        assert_eq!(metadata.bulk_chunk_size, 500);
+
+        // The setting should be kept when the metadata is saved
+        let mut loaded_metadata = IndexMetadata::default();
+        parse(&mut loaded_metadata, serde_json::to_value(&metadata).unwrap()).expect("parse() returned an error");
+        assert_eq!(loaded_metadata.bulk_chunk_size, 500);
+
+        let error = parse(&mut metadata, json!({
+            "settings": {
+                "bulk": {
+                    "chunk_size": 0
+                }
+            }
+        })).err().expect("parse() was supposed to return an error, but didn't");
+
+        assert_eq!(error, IndexMetadataParseError::ExpectedPositiveInteger);
+    }
 }
@@ -1,10 +1,11 @@
 pub mod maintenance;
+pub mod merge_policy;
 pub mod metadata;
 
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
 use std::path::PathBuf;
 
-use search::backends::rocksdb::RocksDBStore;
+use search::backends::Store;
 use uuid::Uuid;
 
 use index::metadata::IndexMetadata;
@@ -15,17 +16,21 @@ pub struct Index {
     id: Uuid,
     canonical_name: String,
     pub metadata: RwLock<IndexMetadata>,
-    pub store: RocksDBStore,
+    pub store: Box<Store>,
+
+    /// Held while segments are being merged, only one merge can run on an index at a time
+    merge_lock: Mutex<()>,
 }
 
 
 impl Index {
-    pub fn new(id: Uuid, canonical_name: String, metadata: IndexMetadata, store: RocksDBStore) -> Index {
+    pub fn new(id: Uuid, canonical_name: String, metadata: IndexMetadata, store: Box<Store>) -> Index {
         Index {
             id: id,
             canonical_name: canonical_name,
             metadata: RwLock::new(metadata),
             store: store,
+            merge_lock: Mutex::new(()),
         }
     }
 
@@ -37,9 +42,12 @@ impl Index {
         &self.canonical_name
     }
 
-    pub fn metadata_path(&self) -> PathBuf {
-        let mut path = self.store.path().to_path_buf();
-        path.push("metadata.json");
-        path
+    /// Returns the path of the index's metadata file, or None if the index isn't stored on the disk
+    pub fn metadata_path(&self) -> Option<PathBuf> {
+        self.store.path().map(|path| {
+            let mut path = path.to_path_buf();
+            path.push("metadata.json");
+            path
+        })
     }
 }
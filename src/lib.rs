@@ -0,0 +1,44 @@
+extern crate chrono;
+#[macro_use]
+extern crate router;
+extern crate url;
+#[macro_use]
+extern crate slog;
+extern crate slog_term;
+extern crate slog_async;
+#[macro_use]
+extern crate maplit;
+extern crate unicode_segmentation;
+extern crate uuid;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate atomicwrites;
+extern crate fnv;
+#[macro_use]
+extern crate bitflags;
+extern crate roaring;
+extern crate byteorder;
+extern crate rocksdb;
+#[macro_use]
+extern crate lazy_static;
+extern crate num_cpus;
+extern crate rayon;
+
+pub mod search;
+pub mod analysis;
+pub mod query_parser;
+pub mod aggregations;
+pub mod mapping;
+pub mod document;
+pub mod index;
+pub mod cluster;
+pub mod system;
+mod api;
+
+pub use api::api_main;
+
+
+pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
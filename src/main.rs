@@ -1,37 +1,8 @@
-extern crate chrono;
-#[macro_use]
-extern crate router;
-extern crate url;
+extern crate rusticsearch;
 #[macro_use]
 extern crate slog;
 extern crate slog_term;
 extern crate slog_async;
-#[macro_use]
-extern crate maplit;
-extern crate unicode_segmentation;
-extern crate uuid;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-#[macro_use]
-extern crate serde_json;
-extern crate atomicwrites;
-extern crate fnv;
-#[macro_use]
-extern crate bitflags;
-extern crate roaring;
-extern crate byteorder;
-extern crate rocksdb;
-
-pub mod search;
-pub mod analysis;
-pub mod query_parser;
-pub mod mapping;
-pub mod document;
-pub mod index;
-pub mod cluster;
-pub mod system;
-mod api;
 
 use std::path::Path;
 use std::sync::Arc;
@@ -41,10 +12,8 @@ use std::panic;
 
 use slog::Drain;
 
-use system::System;
-
-
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+use rusticsearch::VERSION;
+use rusticsearch::system::System;
 
 
 fn main() {
@@ -84,5 +53,5 @@ fn main() {
     }
 
     info!(system.log, "starting api server");
-    api::api_main(system);
+    rusticsearch::api_main(system);
 }
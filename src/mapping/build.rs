@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use mapping::{Mapping, MappingProperty, FieldMapping, NestedMapping, FieldType, get_standard_analyzer};
+use mapping::{Mapping, MappingProperty, FieldMapping, NestedMapping, FieldType, META_FIELDS, get_standard_analyzer, get_meta_field_mapping};
 use index::metadata::IndexMetadata;
 
 
@@ -175,6 +175,11 @@ impl MappingBuilder {
             ));
         }
 
+        // Insert metadata fields
+        for field_name in META_FIELDS {
+            properties.insert(field_name.to_string(), MappingProperty::Field(get_meta_field_mapping()));
+        }
+
         Mapping {
             properties: properties,
         }
@@ -187,7 +192,7 @@ mod tests {
     use analysis::AnalyzerSpec;
     use analysis::tokenizers::TokenizerSpec;
     use analysis::filters::FilterSpec;
-    use mapping::{Mapping, MappingProperty, FieldMapping, FieldType, get_standard_analyzer};
+    use mapping::{Mapping, MappingProperty, FieldMapping, FieldType, get_standard_analyzer, get_meta_field_mapping};
     use index::metadata::IndexMetadata;
 
     use super::{MappingBuilder, MappingPropertyBuilder, FieldMappingBuilder};
@@ -226,7 +231,10 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
-                })
+                }),
+                "_id".to_string() => MappingProperty::Field(get_meta_field_mapping()),
+                "_type".to_string() => MappingProperty::Field(get_meta_field_mapping()),
+                "_source".to_string() => MappingProperty::Field(get_meta_field_mapping())
             }
         });
     }
@@ -248,7 +256,10 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
-                })
+                }),
+                "_id".to_string() => MappingProperty::Field(get_meta_field_mapping()),
+                "_type".to_string() => MappingProperty::Field(get_meta_field_mapping()),
+                "_source".to_string() => MappingProperty::Field(get_meta_field_mapping())
             }
         });
     }
@@ -278,7 +289,10 @@ mod tests {
                     index_analyzer: Some(get_standard_analyzer()),
                     search_analyzer: Some(get_standard_analyzer()),
                     ..FieldMapping::default()
-                })
+                }),
+                "_id".to_string() => MappingProperty::Field(get_meta_field_mapping()),
+                "_type".to_string() => MappingProperty::Field(get_meta_field_mapping()),
+                "_source".to_string() => MappingProperty::Field(get_meta_field_mapping())
             }
         });
     }
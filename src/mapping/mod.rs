@@ -30,6 +30,24 @@ fn get_standard_analyzer() -> AnalyzerSpec {
 }
 
 
+/// Names of the fields that are added to every mapping to hold the document's metadata
+///
+/// These are stored but not indexed. They allow the original document to be returned from the
+/// GET document API and in search hits.
+pub const META_FIELDS: &'static [&'static str] = &["_id", "_type", "_source"];
+
+
+fn get_meta_field_mapping() -> FieldMapping {
+    FieldMapping {
+        data_type: FieldType::String,
+        is_indexed: false,
+        is_stored: true,
+        is_in_all: false,
+        .. FieldMapping::default()
+    }
+}
+
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FieldType {
     String,
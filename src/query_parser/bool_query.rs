@@ -0,0 +1,602 @@
+//! Parses "bool" queries
+
+use serde_json::Value as Json;
+use search::Query;
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder, parse as parse_query};
+use query_parser::utils::{parse_float, parse_minimum_should_match, MinimumShouldMatch};
+
+
+#[derive(Debug)]
+struct BoolQueryBuilder {
+    must: Vec<Box<QueryBuilder>>,
+    must_not: Vec<Box<QueryBuilder>>,
+    should: Vec<Box<QueryBuilder>>,
+    filter: Vec<Box<QueryBuilder>>,
+    minimum_should_match: Option<MinimumShouldMatch>,
+    boost: f32,
+}
+
+
+fn build_queries(builders: &Vec<Box<QueryBuilder>>, context: &QueryBuildContext, schema: &Schema) -> Vec<Query> {
+    builders.iter().map(|builder| builder.build(context, schema)).collect()
+}
+
+
+/// Joins queries with an AND operator. Doesn't wrap single queries
+/// Used for clauses that don't affect the score
+fn build_conjunction(mut queries: Vec<Query>) -> Query {
+    if queries.len() == 1 {
+        queries.pop().unwrap()
+    } else {
+        Query::Conjunction { queries: queries }
+    }
+}
+
+
+/// Joins queries with an OR operator. Doesn't wrap single queries
+/// Used for clauses that don't affect the score
+fn build_disjunction(mut queries: Vec<Query>) -> Query {
+    if queries.len() == 1 {
+        queries.pop().unwrap()
+    } else {
+        Query::Disjunction { queries: queries }
+    }
+}
+
+
+/// Builds a query that matches documents that match at least "minimum" of the queries
+/// The scores of the queries are added together. Doesn't wrap single queries
+fn build_minimum_match(mut queries: Vec<Query>, minimum: u32) -> Query {
+    if minimum as usize > queries.len() {
+        Query::None
+    } else if queries.len() == 1 {
+        queries.pop().unwrap()
+    } else {
+        Query::MinimumMatch {
+            queries: queries,
+            minimum: minimum,
+        }
+    }
+}
+
+
+/// Joins queries with an AND operator, adding their scores together. Doesn't wrap single queries
+fn build_summed_conjunction(queries: Vec<Query>) -> Query {
+    let minimum = queries.len() as u32;
+    build_minimum_match(queries, minimum)
+}
+
+
+impl QueryBuilder for BoolQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        let filter_context = context.clone().no_score();
+
+        // Work out how many of the should clauses must match
+        // If there is nothing else to match on, at least one should clause must match
+        let mut minimum_should_match = match self.minimum_should_match {
+            Some(ref minimum_should_match) => minimum_should_match.resolve(self.should.len()),
+            None => 0,
+        };
+
+        if self.must.is_empty() && self.filter.is_empty() && !self.should.is_empty() && minimum_should_match == 0 {
+            minimum_should_match = 1;
+        }
+
+        let mut query = if self.should.is_empty() {
+            if self.must.is_empty() {
+                Query::all()
+            } else {
+                build_summed_conjunction(build_queries(&self.must, context, schema))
+            }
+        } else if self.must.is_empty() && minimum_should_match > 0 {
+            // The should clauses decide which documents match
+            build_minimum_match(build_queries(&self.should, context, schema), minimum_should_match)
+        } else {
+            // The should clauses add to the score of documents that match but any of them may
+            // be missing (apart from minimum_should_match). Add up the scores of all clauses that
+            // match and then filter to the documents that match the required clauses.
+            let mut scoring_queries = Vec::with_capacity(self.must.len() + self.should.len() + 1);
+            if self.must.is_empty() {
+                scoring_queries.push(Query::All { score: 0.0f32 });
+            } else {
+                scoring_queries.extend(build_queries(&self.must, context, schema));
+            }
+            scoring_queries.extend(build_queries(&self.should, context, schema));
+            let scoring_query = build_minimum_match(scoring_queries, 1);
+
+            let mut required_queries = Vec::new();
+            if !self.must.is_empty() {
+                required_queries.push(build_conjunction(build_queries(&self.must, &filter_context, schema)));
+            }
+            if minimum_should_match > 0 {
+                required_queries.push(build_minimum_match(build_queries(&self.should, &filter_context, schema), minimum_should_match));
+            }
+
+            if required_queries.is_empty() {
+                scoring_query
+            } else {
+                scoring_query.filter(build_conjunction(required_queries))
+            }
+        };
+
+        // Filter clauses must match but don't affect the score
+        if !self.filter.is_empty() {
+            query = query.filter(build_conjunction(build_queries(&self.filter, &filter_context, schema)));
+        }
+
+        // Remove documents that match any must_not clause
+        if !self.must_not.is_empty() {
+            query = query.exclude(build_disjunction(build_queries(&self.must_not, &filter_context, schema)));
+        }
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+/// Clauses can be given as either a single query or an array of queries
+fn parse_clauses(json: &Json) -> Result<Vec<Box<QueryBuilder>>, QueryParseError> {
+    match *json {
+        Json::Array(ref array) => {
+            let mut queries = Vec::with_capacity(array.len());
+            for item in array.iter() {
+                queries.push(parse_query(item)?);
+            }
+
+            Ok(queries)
+        }
+        Json::Object(_) => Ok(vec![parse_query(json)?]),
+        _ => Err(QueryParseError::ExpectedArray),
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let mut must = Vec::new();
+    let mut must_not = Vec::new();
+    let mut should = Vec::new();
+    let mut filter = Vec::new();
+    let mut minimum_should_match = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in object.iter() {
+        match key.as_ref() {
+            "must" => {
+                must = parse_clauses(value)?;
+            }
+            "must_not" => {
+                must_not = parse_clauses(value)?;
+            }
+            "should" => {
+                should = parse_clauses(value)?;
+            }
+            "filter" => {
+                filter = parse_clauses(value)?;
+            }
+            "minimum_should_match" => {
+                minimum_should_match = Some(parse_minimum_should_match(value)?);
+            }
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(BoolQueryBuilder {
+        must: must,
+        must_not: must_not,
+        should: should,
+        filter: filter,
+        minimum_should_match: minimum_should_match,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FieldId, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    fn term_query(field: FieldId, value: &str) -> Query {
+        Query::Term {
+            field: field,
+            term: Term::from_string(value),
+            scorer: TermScorer::default(),
+        }
+    }
+
+    #[test]
+    fn test_must() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": [
+                {
+                    \"term\": {
+                        \"test\":  \"foo\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"bar\"
+                    }
+                }
+            ]
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // The scores of must clauses are added together
+        assert_eq!(query, Ok(Query::MinimumMatch {
+            queries: vec![
+                term_query(test_field, "foo"),
+                term_query(test_field, "bar"),
+            ],
+            minimum: 2,
+        }))
+    }
+
+    #[test]
+    fn test_single_must_object() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": {
+                \"term\": {
+                    \"test\":  \"foo\"
+                }
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(term_query(test_field, "foo")))
+    }
+
+    #[test]
+    fn test_should() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"should\": [
+                {
+                    \"term\": {
+                        \"test\":  \"foo\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"bar\"
+                    }
+                }
+            ]
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        // The scores of should clauses are added together
+        assert_eq!(query, Ok(Query::MinimumMatch {
+            queries: vec![
+                term_query(test_field, "foo"),
+                term_query(test_field, "bar"),
+            ],
+            minimum: 1,
+        }))
+    }
+
+    #[test]
+    fn test_should_with_minimum_should_match() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"should\": [
+                {
+                    \"term\": {
+                        \"test\":  \"foo\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"bar\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"baz\"
+                    }
+                }
+            ],
+            \"minimum_should_match\": 2
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MinimumMatch {
+            queries: vec![
+                term_query(test_field, "foo"),
+                term_query(test_field, "bar"),
+                term_query(test_field, "baz"),
+            ],
+            minimum: 2,
+        }))
+    }
+
+    #[test]
+    fn test_minimum_should_match_percentage() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // 75% of 3 clauses rounds down to 2
+        let query = parse(&serde_json::from_str("
+        {
+            \"should\": [
+                {
+                    \"term\": {
+                        \"test\":  \"foo\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"bar\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"baz\"
+                    }
+                }
+            ],
+            \"minimum_should_match\": \"75%\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MinimumMatch {
+            queries: vec![
+                term_query(test_field, "foo"),
+                term_query(test_field, "bar"),
+                term_query(test_field, "baz"),
+            ],
+            minimum: 2,
+        }))
+    }
+
+    #[test]
+    fn test_minimum_should_match_negative() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // All but one of the clauses must match
+        let query = parse(&serde_json::from_str("
+        {
+            \"should\": [
+                {
+                    \"term\": {
+                        \"test\":  \"foo\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"bar\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"baz\"
+                    }
+                }
+            ],
+            \"minimum_should_match\": \"-1\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MinimumMatch {
+            queries: vec![
+                term_query(test_field, "foo"),
+                term_query(test_field, "bar"),
+                term_query(test_field, "baz"),
+            ],
+            minimum: 2,
+        }))
+    }
+
+    #[test]
+    fn test_minimum_should_match_too_high() {
+        let mut schema = Schema::new();
+        schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"should\": [
+                {
+                    \"term\": {
+                        \"test\":  \"foo\"
+                    }
+                }
+            ],
+            \"minimum_should_match\": 2
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::None))
+    }
+
+    #[test]
+    fn test_must_and_should() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Should clauses are optional but add to the score
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": {
+                \"term\": {
+                    \"test\":  \"foo\"
+                }
+            },
+            \"should\": {
+                \"term\": {
+                    \"test\":  \"bar\"
+                }
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::MinimumMatch {
+                queries: vec![
+                    term_query(test_field, "foo"),
+                    term_query(test_field, "bar"),
+                ],
+                minimum: 1,
+            }),
+            filter: Box::new(term_query(test_field, "foo")),
+        }))
+    }
+
+    #[test]
+    fn test_filter_and_should() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"filter\": {
+                \"term\": {
+                    \"test\":  \"foo\"
+                }
+            },
+            \"should\": {
+                \"term\": {
+                    \"test\":  \"bar\"
+                }
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Filter {
+            query: Box::new(Query::MinimumMatch {
+                queries: vec![
+                    Query::All { score: 0.0f32 },
+                    term_query(test_field, "bar"),
+                ],
+                minimum: 1,
+            }),
+            filter: Box::new(term_query(test_field, "foo")),
+        }))
+    }
+
+    #[test]
+    fn test_filter_and_must_not() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"filter\": {
+                \"term\": {
+                    \"test\":  \"foo\"
+                }
+            },
+            \"must_not\": [
+                {
+                    \"term\": {
+                        \"test\":  \"bar\"
+                    }
+                },
+                {
+                    \"term\": {
+                        \"test\":  \"baz\"
+                    }
+                }
+            ]
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Exclude {
+            query: Box::new(Query::Filter {
+                query: Box::new(Query::all()),
+                filter: Box::new(term_query(test_field, "foo")),
+            }),
+            exclude: Box::new(Query::Disjunction {
+                queries: vec![
+                    term_query(test_field, "bar"),
+                    term_query(test_field, "baz"),
+                ],
+            }),
+        }))
+    }
+
+    #[test]
+    fn test_with_boost() {
+        let mut schema = Schema::new();
+        let test_field = schema.add_field("test".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": {
+                \"term\": {
+                    \"test\":  \"foo\"
+                }
+            },
+            \"boost\": 2.0
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: test_field,
+            term: Term::from_string("foo"),
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_minimum_should_match() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"should\": [],
+            \"minimum_should_match\": \"lots\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_clause_type() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"must\": \"foo\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedArray));
+    }
+
+    #[test]
+    fn test_gives_error_for_unrecognised_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"hello\": \"world\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+}
@@ -0,0 +1,265 @@
+//! Parses "fuzzy" queries
+
+use serde_json::Value as Json;
+use search::{Term, Query, TermScorer};
+use search::schema::Schema;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_float, parse_positive_integer, parse_fuzziness, FuzzyOptions};
+
+
+#[derive(Debug)]
+struct FuzzyQueryBuilder {
+    field: String,
+    value: String,
+    options: FuzzyOptions,
+    boost: f32,
+}
+
+
+impl QueryBuilder for FuzzyQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let query = self.options.build_query(
+            schema.get_field_by_name(&self.field).unwrap(),
+            Term::from_string(&self.value),
+            TermScorer::default()
+        );
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap();
+
+    // Get configuration
+    let mut value: Option<&Json> = None;
+    let mut options = FuzzyOptions::default();
+    let mut boost = 1.0f32;
+
+    match *object {
+        Json::String(_) => value = Some(object),
+        Json::Object(ref inner_object) => {
+            for (key, val) in inner_object.iter() {
+                match key.as_ref() {
+                    "value" => {
+                        value = Some(val);
+                    }
+                    "fuzziness" => {
+                        options.fuzziness = parse_fuzziness(val)?;
+                    }
+                    "prefix_length" => {
+                        options.prefix_length = parse_positive_integer(val)? as u32;
+                    }
+                    "max_expansions" => {
+                        options.max_expansions = parse_positive_integer(val)? as usize;
+                    }
+                    "transpositions" => {
+                        options.transpositions = val.as_bool().ok_or(QueryParseError::InvalidValue)?;
+                    }
+                    "boost" => {
+                        boost = parse_float(val)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    match value {
+        Some(value) => {
+            if let Json::String(ref string) = *value {
+                Ok(Box::new(FuzzyQueryBuilder {
+                    field: field_name.clone(),
+                    value: string.clone(),
+                    options: options,
+                    boost: boost,
+                }))
+            } else {
+                Err(QueryParseError::ExpectedString)
+            }
+        }
+        None => Err(QueryParseError::ExpectedKey("value"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_fuzzy_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"hello\",
+                \"fuzziness\": 1,
+                \"prefix_length\": 2,
+                \"max_expansions\": 10,
+                \"transpositions\": false
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Fuzzy {
+                term: "hello".to_string(),
+                max_edits: 1,
+                prefix_length: 2,
+                transpositions: false,
+                max_expansions: 10,
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_simple_fuzzy_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Terms with 6 or more characters are allowed two edits by default
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": \"sausage\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Fuzzy {
+                term: "sausage".to_string(),
+                max_edits: 2,
+                prefix_length: 0,
+                transpositions: true,
+                max_expansions: 50,
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_short_term_with_auto_fuzziness() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Terms shorter than 3 characters must match exactly
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"hi\",
+                \"fuzziness\": \"AUTO\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: foo_field,
+            term: Term::from_string("hi"),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_with_boost() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"hello\",
+                \"fuzziness\": \"AUTO:2,4\",
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Fuzzy {
+                term: "hello".to_string(),
+                max_edits: 2,
+                prefix_length: 0,
+                transpositions: true,
+                max_expansions: 50,
+            },
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_fuzziness() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"hello\",
+                \"fuzziness\": 3
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"hello\",
+                \"fuzziness\": \"AUTO:6,3\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_value() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"fuzziness\": 1
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value")));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_inner_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"hello\",
+                \"hello\": \"world\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+}
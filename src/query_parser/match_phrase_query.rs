@@ -0,0 +1,298 @@
+//! Parses "match_phrase" queries
+
+use serde_json::Value as Json;
+use search::{Term, Token, Query, TermScorer};
+use search::schema::Schema;
+
+use mapping::FieldSearchOptions;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_string, parse_float, parse_positive_integer};
+
+
+#[derive(Debug)]
+pub struct MatchPhraseQueryBuilder {
+    pub field: String,
+    pub query: String,
+    pub slop: u32,
+    pub boost: f32,
+}
+
+
+impl QueryBuilder for MatchPhraseQueryBuilder {
+    fn build(&self, context: &QueryBuildContext, schema: &Schema) -> Query {
+        // Get search options for field
+        let field_search_options = match context.index_metadata {
+            Some(index_metadata) => {
+                match index_metadata.get_field_mapping(&self.field) {
+                    Some(field_mapping) => field_mapping.get_search_options(),
+                    None => FieldSearchOptions::default(),  // TODO: error?
+                }
+            }
+            None => FieldSearchOptions::default(),  // TODO: error?
+        };
+
+        // Tokenise query string
+        let tokens = match field_search_options.analyzer {
+            Some(ref analyzer) => {
+                let token_stream = analyzer.initialise(&self.query);
+                token_stream.collect::<Vec<Token>>()
+            }
+            None => {
+                vec![Token {term: Term::from_string(&self.query), position: 1}]
+            }
+        };
+
+        let field = schema.get_field_by_name(&self.field).unwrap();
+
+        // Each term's offset in the phrase is taken from its token's position, so gaps left by
+        // removed tokens (such as stopwords) and tokens that share a position (such as synonyms)
+        // line up with the positions that were indexed
+        let first_position = tokens.first().map(|token| token.position).unwrap_or(0);
+        let mut terms = tokens.into_iter().map(|token| {
+            let offset = token.position.saturating_sub(first_position);
+            (token.term, offset)
+        }).collect::<Vec<(Term, u32)>>();
+
+        // Single term phrases are just term queries
+        let query = match terms.len() {
+            0 => Query::None,
+            1 => {
+                Query::Term {
+                    field: field,
+                    term: terms.pop().unwrap().0,
+                    scorer: TermScorer::default(),
+                }
+            }
+            _ => {
+                Query::Phrase {
+                    field: field,
+                    terms: terms,
+                    slop: self.slop,
+                    scorer: TermScorer::default(),
+                }
+            }
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    // Get configuration
+    let mut query = String::new();
+    let mut slop = 0;
+    let mut boost = 1.0f32;
+
+    match object.get(field_name).unwrap() {
+        s @ &Json::String(_) => query = parse_string(s)?,
+        &Json::Object(ref inner_object) => {
+            let mut has_query_key = false;
+
+            for (key, value) in inner_object.iter() {
+                match key.as_ref() {
+                    "query" => {
+                        has_query_key = true;
+                        query = parse_string(value)?;
+                    }
+                    "slop" => {
+                        slop = parse_positive_integer(value)? as u32;
+                    }
+                    "boost" => {
+                        boost = parse_float(value)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+
+            if !has_query_key {
+                return Err(QueryParseError::ExpectedKey("query"))
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    Ok(Box::new(MatchPhraseQueryBuilder {
+        field: field_name.clone(),
+        query: query,
+        slop: slop,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Term, Query, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_match_phrase_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"new york\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![
+                (Term::from_string("new"), 0),
+                (Term::from_string("york"), 1),
+            ],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }))
+    }
+
+    #[test]
+    fn test_simple_match_phrase_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": \"new york\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![
+                (Term::from_string("new"), 0),
+                (Term::from_string("york"), 1),
+            ],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }))
+    }
+
+    #[test]
+    fn test_single_term() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": \"york\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Term {
+            field: foo_field,
+            term: Term::from_string("york"),
+            scorer: TermScorer::default(),
+        }))
+    }
+
+    #[test]
+    fn test_with_slop() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"new york\",
+                \"slop\": 2
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![
+                (Term::from_string("new"), 0),
+                (Term::from_string("york"), 1),
+            ],
+            slop: 2,
+            scorer: TermScorer::default(),
+        }))
+    }
+
+    #[test]
+    fn test_with_boost() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"new york\",
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![
+                (Term::from_string("new"), 0),
+                (Term::from_string("york"), 1),
+            ],
+            slop: 0,
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_negative_slop() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"new york\",
+                \"slop\": -1
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedPositiveInteger));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_query() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("query")));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_inner_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"new york\",
+                \"hello\": \"world\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+}
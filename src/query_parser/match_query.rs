@@ -7,7 +7,8 @@ use search::schema::Schema;
 use mapping::FieldSearchOptions;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_string, parse_float, Operator, parse_operator};
+use query_parser::utils::{parse_string, parse_float, parse_positive_integer, Operator, parse_operator, Fuzziness, parse_fuzziness, FuzzyOptions};
+use query_parser::match_phrase_query::MatchPhraseQueryBuilder;
 
 
 #[derive(Debug)]
@@ -15,6 +16,7 @@ struct MatchQueryBuilder {
     field: String,
     query: String,
     operator: Operator,
+    fuzzy: Option<FuzzyOptions>,
     boost: f32,
 }
 
@@ -44,12 +46,18 @@ impl QueryBuilder for MatchQueryBuilder {
         };
 
         // Create a term query for each token
+        let field = schema.get_field_by_name(&self.field).unwrap();
         let mut sub_queries = Vec::new();
         for token in tokens {
-            sub_queries.push(Query::Term {
-                field: schema.get_field_by_name(&self.field).unwrap(),
-                term: token.term,
-                scorer: TermScorer::default(),
+            sub_queries.push(match self.fuzzy {
+                Some(ref fuzzy) => fuzzy.build_query(field, token.term, TermScorer::default()),
+                None => {
+                    Query::Term {
+                        field: field,
+                        term: token.term,
+                        scorer: TermScorer::default(),
+                    }
+                }
             });
         }
 
@@ -88,6 +96,10 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let mut query = String::new();
     let mut boost = 1.0f32;
     let mut operator = Operator::Or;
+    let mut is_phrase = false;
+    let mut slop = 0;
+    let mut fuzziness: Option<Fuzziness> = None;
+    let mut fuzzy_options = FuzzyOptions::default();
 
     match object.get(field_name).unwrap() {
         s @ &Json::String(_) => query = parse_string(s)?,
@@ -106,6 +118,28 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
                     "operator" => {
                         operator = parse_operator(value)?;
                     }
+                    "type" => {
+                        is_phrase = match parse_string(value)?.as_ref() {
+                            "boolean" => false,
+                            "phrase" => true,
+                            _ => return Err(QueryParseError::InvalidValue),
+                        };
+                    }
+                    "slop" => {
+                        slop = parse_positive_integer(value)? as u32;
+                    }
+                    "fuzziness" => {
+                        fuzziness = Some(parse_fuzziness(value)?);
+                    }
+                    "prefix_length" => {
+                        fuzzy_options.prefix_length = parse_positive_integer(value)? as u32;
+                    }
+                    "max_expansions" => {
+                        fuzzy_options.max_expansions = parse_positive_integer(value)? as usize;
+                    }
+                    "fuzzy_transpositions" => {
+                        fuzzy_options.transpositions = value.as_bool().ok_or(QueryParseError::InvalidValue)?;
+                    }
                     _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
                 }
             }
@@ -117,10 +151,28 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
         _ => return Err(QueryParseError::ExpectedObjectOrString),
     }
 
+    if is_phrase {
+        return Ok(Box::new(MatchPhraseQueryBuilder {
+            field: field_name.clone(),
+            query: query,
+            slop: slop,
+            boost: boost,
+        }));
+    }
+
+    // Fuzzy matching is only enabled if "fuzziness" is set
+    let fuzzy = fuzziness.map(|fuzziness| {
+        FuzzyOptions {
+            fuzziness: fuzziness,
+            ..fuzzy_options
+        }
+    });
+
     Ok(Box::new(MatchQueryBuilder {
         field: field_name.clone(),
         query: query,
         operator: operator,
+        fuzzy: fuzzy,
         boost: boost,
     }))
 }
@@ -130,7 +182,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 mod tests {
     use serde_json;
 
-    use search::{Term, Query, TermScorer};
+    use search::{Term, Query, MultiTermSelector, TermScorer};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
 
     use query_parser::{QueryBuildContext, QueryParseError};
@@ -213,6 +265,35 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn test_with_fuzziness() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"sausage\",
+                \"fuzziness\": \"AUTO\",
+                \"prefix_length\": 1,
+                \"max_expansions\": 10
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Fuzzy {
+                term: "sausage".to_string(),
+                max_edits: 2,
+                prefix_length: 1,
+                transpositions: true,
+                max_expansions: 10,
+            },
+            scorer: TermScorer::default(),
+        }))
+    }
+
     #[test]
     fn test_with_boost() {
         let mut schema = Schema::new();
@@ -285,6 +366,46 @@ mod tests {
         }))
     }
 
+    #[test]
+    fn test_with_phrase_type() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"bar baz\",
+                \"type\": \"phrase\",
+                \"slop\": 1
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::Phrase {
+            field: foo_field,
+            terms: vec![
+                (Term::from_string("bar"), 0),
+                (Term::from_string("baz"), 1),
+            ],
+            slop: 1,
+            scorer: TermScorer::default(),
+        }))
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_type() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"query\": \"bar baz\",
+                \"type\": \"foo\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
     #[test]
     fn test_gives_error_for_incorrect_type() {
         // Array
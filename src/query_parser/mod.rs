@@ -2,6 +2,7 @@
 
 pub mod utils;
 pub mod match_query;
+pub mod match_phrase_query;
 pub mod multi_match_query;
 pub mod match_all_query;
 pub mod match_none_query;
@@ -9,10 +10,16 @@ pub mod filtered_query;
 pub mod terms_query;
 pub mod term_query;
 pub mod prefix_query;
+pub mod fuzzy_query;
+pub mod wildcard_query;
+pub mod regexp_query;
+pub mod range_query;
+pub mod bool_query;
 pub mod and_query;
 pub mod or_query;
 pub mod not_query;
 pub mod constant_score_query;
+pub mod sort;
 
 use std::fmt::Debug;
 
@@ -56,12 +63,14 @@ impl<'a> QueryBuildContext<'a> {
 pub enum QueryParseError {
     UnrecognisedQueryType(String),
     FieldDoesntExist(String),
+    FieldNotStored(String),
     UnrecognisedKey(String),
     ExpectedKey(&'static str),
     ExpectedObject,
     ExpectedArray,
     ExpectedString,
     ExpectedFloat,
+    ExpectedPositiveInteger,
     ExpectedObjectOrString,
     InvalidValue,
     ExpectedSingleKey,
@@ -77,6 +86,7 @@ pub trait QueryBuilder: Debug {
 fn get_query_parser(query_name: &str) -> Option<fn(&Json) -> Result<Box<QueryBuilder>, QueryParseError>> {
     match query_name {
         "match" => Some(match_query::parse),
+        "match_phrase" => Some(match_phrase_query::parse),
         "multi_match" => Some(multi_match_query::parse),
         "match_all" => Some(match_all_query::parse),
         "match_none" => Some(match_none_query::parse),
@@ -85,6 +95,11 @@ fn get_query_parser(query_name: &str) -> Option<fn(&Json) -> Result<Box<QueryBui
         "in" => Some(terms_query::parse),
         "term" => Some(term_query::parse),
         "prefix" => Some(prefix_query::parse),
+        "fuzzy" => Some(fuzzy_query::parse),
+        "wildcard" => Some(wildcard_query::parse),
+        "regexp" => Some(regexp_query::parse),
+        "range" => Some(range_query::parse),
+        "bool" => Some(bool_query::parse),
         "and" => Some(and_query::parse),
         "or" => Some(or_query::parse),
         "not" => Some(not_query::parse),
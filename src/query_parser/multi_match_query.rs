@@ -7,7 +7,7 @@ use search::schema::Schema;
 use mapping::FieldSearchOptions;
 
 use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
-use query_parser::utils::{parse_string, parse_float, Operator, parse_operator, parse_field_and_boost};
+use query_parser::utils::{parse_string, parse_float, parse_positive_integer, Operator, parse_operator, parse_field_and_boost, Fuzziness, parse_fuzziness, FuzzyOptions};
 
 
 #[derive(Debug)]
@@ -15,6 +15,7 @@ struct MultiMatchQueryBuilder {
     fields: Vec<(String, f32)>,
     query: String,
     operator: Operator,
+    fuzzy: Option<FuzzyOptions>,
     boost: f32,
 }
 
@@ -46,12 +47,18 @@ impl QueryBuilder for MultiMatchQueryBuilder {
                 }
             };
 
+            let field = schema.get_field_by_name(field_name).unwrap();
             let mut term_queries = Vec::new();
             for token in tokens {
-                term_queries.push(Query::Term {
-                    field: schema.get_field_by_name(field_name).unwrap(),
-                    term: token.term,
-                    scorer: TermScorer::default(),
+                term_queries.push(match self.fuzzy {
+                    Some(ref fuzzy) => fuzzy.build_query(field, token.term, TermScorer::default()),
+                    None => {
+                        Query::Term {
+                            field: field,
+                            term: token.term,
+                            scorer: TermScorer::default(),
+                        }
+                    }
                 });
             }
 
@@ -99,6 +106,8 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
     let mut query = String::new();
     let mut boost = 1.0f32;
     let mut operator = Operator::Or;
+    let mut fuzziness: Option<Fuzziness> = None;
+    let mut fuzzy_options = FuzzyOptions::default();
 
     let mut has_fields_key = false;
     let mut has_query_key = false;
@@ -127,6 +136,18 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
             "operator" => {
                 operator = parse_operator(val)?;
             }
+            "fuzziness" => {
+                fuzziness = Some(parse_fuzziness(val)?);
+            }
+            "prefix_length" => {
+                fuzzy_options.prefix_length = parse_positive_integer(val)? as u32;
+            }
+            "max_expansions" => {
+                fuzzy_options.max_expansions = parse_positive_integer(val)? as usize;
+            }
+            "fuzzy_transpositions" => {
+                fuzzy_options.transpositions = val.as_bool().ok_or(QueryParseError::InvalidValue)?;
+            }
             _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
         }
     }
@@ -139,10 +160,19 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
         return Err(QueryParseError::ExpectedKey("query"))
     }
 
+    // Fuzzy matching is only enabled if "fuzziness" is set
+    let fuzzy = fuzziness.map(|fuzziness| {
+        FuzzyOptions {
+            fuzziness: fuzziness,
+            ..fuzzy_options
+        }
+    });
+
     Ok(Box::new(MultiMatchQueryBuilder {
         fields: fields_with_boosts,
         query: query,
         operator: operator,
+        fuzzy: fuzzy,
         boost: boost,
     }))
 }
@@ -152,7 +182,7 @@ pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
 mod tests {
     use serde_json;
 
-    use search::{Term, Query, TermScorer};
+    use search::{Term, Query, MultiTermSelector, TermScorer};
     use search::schema::{Schema, FieldType, FIELD_INDEXED};
 
     use query_parser::{QueryBuildContext, QueryParseError};
@@ -188,6 +218,46 @@ mod tests {
         }));
     }
 
+    #[test]
+    fn test_with_fuzziness() {
+        let mut schema = Schema::new();
+        let bar_field = schema.add_field("bar".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let baz_field = schema.add_field("baz".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"query\": \"hello\",
+            \"fields\": [\"bar\", \"baz\"],
+            \"fuzziness\": 1
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        let fuzzy_selector = || {
+            MultiTermSelector::Fuzzy {
+                term: "hello".to_string(),
+                max_edits: 1,
+                prefix_length: 0,
+                transpositions: true,
+                max_expansions: 50,
+            }
+        };
+
+        assert_eq!(query, Ok(Query::DisjunctionMax {
+            queries: vec![
+                Query::MultiTerm {
+                    field: bar_field,
+                    term_selector: fuzzy_selector(),
+                    scorer: TermScorer::default(),
+                },
+                Query::MultiTerm {
+                    field: baz_field,
+                    term_selector: fuzzy_selector(),
+                    scorer: TermScorer::default(),
+                },
+            ],
+        }))
+    }
+
     #[test]
     fn test_multi_term_multi_match_query() {
         let mut schema = Schema::new();
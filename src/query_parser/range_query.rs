@@ -0,0 +1,278 @@
+//! Parses "range" queries
+
+use std::ops::Bound;
+
+use serde_json::Value as Json;
+use search::{Term, Query, MultiTermSelector, TermScorer};
+use search::schema::{Schema, FieldType};
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::{parse_float, json_value_to_term, parse_datetime};
+
+
+#[derive(Debug)]
+struct RangeQueryBuilder {
+    field: String,
+    gt: Option<Json>,
+    gte: Option<Json>,
+    lt: Option<Json>,
+    lte: Option<Json>,
+    boost: f32,
+}
+
+
+/// Converts a range boundary into a term, using the type of the field to work out how the
+/// value should be encoded
+fn value_to_term(value: &Json, field_type: &FieldType) -> Option<Term> {
+    match *field_type {
+        FieldType::I64 => {
+            match *value {
+                Json::Number(ref number) => number.as_i64().map(Term::from_integer),
+                Json::String(ref string) => string.parse::<i64>().ok().map(Term::from_integer),
+                _ => None,
+            }
+        }
+        FieldType::DateTime => {
+            match *value {
+                Json::String(ref string) => parse_datetime(string).map(|datetime| Term::from_datetime(&datetime)),
+                _ => None,
+            }
+        }
+        FieldType::Text | FieldType::PlainString => {
+            match *value {
+                Json::String(ref string) => Some(Term::from_string(string)),
+                Json::Number(ref number) => Some(Term::from_string(&number.to_string())),
+                _ => None,
+            }
+        }
+        FieldType::Boolean => json_value_to_term(value),
+    }
+}
+
+
+/// Builds one end of the range from its exclusive ("gt"/"lt") and inclusive ("gte"/"lte") values
+/// Returns None if the value couldn't be converted into a term
+fn make_bound(exclusive: &Option<Json>, inclusive: &Option<Json>, field_type: &FieldType) -> Option<Bound<Term>> {
+    match (exclusive, inclusive) {
+        (&Some(ref value), _) => value_to_term(value, field_type).map(Bound::Excluded),
+        (&None, &Some(ref value)) => value_to_term(value, field_type).map(Bound::Included),
+        (&None, &None) => Some(Bound::Unbounded),
+    }
+}
+
+
+impl QueryBuilder for RangeQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let field = schema.get_field_by_name(&self.field).unwrap();
+        let field_type = &schema.get(&field).unwrap().field_type;
+
+        // Convert the boundaries into terms
+        let lower = match make_bound(&self.gt, &self.gte, field_type) {
+            Some(lower) => lower,
+            None => return Query::None,  // TODO: error?
+        };
+
+        let upper = match make_bound(&self.lt, &self.lte, field_type) {
+            Some(upper) => upper,
+            None => return Query::None,  // TODO: error?
+        };
+
+        let query = Query::MultiTerm {
+            field: field,
+            term_selector: MultiTermSelector::Range {
+                lower: lower,
+                upper: upper,
+            },
+            scorer: TermScorer::default(),
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let inner_object = object.get(field_name).unwrap().as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    // Get configuration
+    let mut gt = None;
+    let mut gte = None;
+    let mut lt = None;
+    let mut lte = None;
+    let mut boost = 1.0f32;
+
+    for (key, value) in inner_object.iter() {
+        // Boundaries must be either strings or numbers
+        let boundary = match *value {
+            Json::String(_) | Json::Number(_) => Some(value.clone()),
+            _ => None,
+        };
+
+        match key.as_ref() {
+            "gt" => gt = Some(boundary.ok_or(QueryParseError::InvalidValue)?),
+            "gte" => gte = Some(boundary.ok_or(QueryParseError::InvalidValue)?),
+            "lt" => lt = Some(boundary.ok_or(QueryParseError::InvalidValue)?),
+            "lte" => lte = Some(boundary.ok_or(QueryParseError::InvalidValue)?),
+            "boost" => {
+                boost = parse_float(value)?;
+            }
+            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+        }
+    }
+
+    Ok(Box::new(RangeQueryBuilder {
+        field: field_name.clone(),
+        gt: gt,
+        gte: gte,
+        lt: lt,
+        lte: lte,
+        boost: boost,
+    }))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use serde_json;
+
+    use search::{Term, Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+    use query_parser::utils::parse_datetime;
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_range_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"gte\": 10,
+                \"lt\": 20
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Range {
+                lower: Bound::Included(Term::from_integer(10)),
+                upper: Bound::Excluded(Term::from_integer(20)),
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_open_ended_range_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"gt\": 10
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Range {
+                lower: Bound::Excluded(Term::from_integer(10)),
+                upper: Bound::Unbounded,
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_date_range_query() {
+        let mut schema = Schema::new();
+        let published_field = schema.add_field("published".to_string(), FieldType::DateTime, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"published\": {
+                \"gte\": \"2016-01-01\",
+                \"lte\": \"2016-07-23T16:15:00+01:00\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: published_field,
+            term_selector: MultiTermSelector::Range {
+                lower: Bound::Included(Term::from_datetime(&parse_datetime("2016-01-01T00:00:00Z").unwrap())),
+                upper: Bound::Included(Term::from_datetime(&parse_datetime("2016-07-23T16:15:00+01:00").unwrap())),
+            },
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_with_boost() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"lte\": 10,
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Range {
+                lower: Bound::Unbounded,
+                upper: Bound::Included(Term::from_integer(10)),
+            },
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_boundary() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"gte\": [10]
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_inner_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"gte\": 10,
+                \"hello\": \"world\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+}
@@ -0,0 +1,207 @@
+//! Parses "regexp" queries
+
+use serde_json::Value as Json;
+use search::{Query, MultiTermSelector, TermScorer};
+use search::schema::Schema;
+use search::query::pattern::Pattern;
+
+use query_parser::{QueryBuildContext, QueryParseError, QueryBuilder};
+use query_parser::utils::parse_float;
+
+
+#[derive(Debug)]
+struct RegexpQueryBuilder {
+    field: String,
+    regexp: String,
+    boost: f32,
+}
+
+
+impl QueryBuilder for RegexpQueryBuilder {
+    fn build(&self, _context: &QueryBuildContext, schema: &Schema) -> Query {
+        let query = Query::MultiTerm {
+            field: schema.get_field_by_name(&self.field).unwrap(),
+            term_selector: MultiTermSelector::Regexp(self.regexp.clone()),
+            scorer: TermScorer::default(),
+        };
+
+        // Add boost
+        query.boost(self.boost)
+    }
+}
+
+
+pub fn parse(json: &Json) -> Result<Box<QueryBuilder>, QueryParseError> {
+    let object = json.as_object().ok_or(QueryParseError::ExpectedObject)?;
+
+    let field_name = if object.len() == 1 {
+        object.keys().collect::<Vec<_>>()[0]
+    } else {
+        return Err(QueryParseError::ExpectedSingleKey)
+    };
+
+    let object = object.get(field_name).unwrap();
+
+    // Get configuration
+    let mut value: Option<&Json> = None;
+    let mut boost = 1.0f32;
+
+    match *object {
+        Json::String(_) => value = Some(object),
+        Json::Object(ref inner_object) => {
+            for (key, val) in inner_object.iter() {
+                match key.as_ref() {
+                    "value" => {
+                        value = Some(val);
+                    }
+                    "boost" => {
+                        boost = parse_float(val)?;
+                    }
+                    _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                }
+            }
+        }
+        _ => return Err(QueryParseError::ExpectedObjectOrString),
+    }
+
+    match value {
+        Some(value) => {
+            if let Json::String(ref string) = *value {
+                // Check that the regular expression is valid
+                Pattern::regexp(string).map_err(|_| QueryParseError::InvalidValue)?;
+
+                Ok(Box::new(RegexpQueryBuilder {
+                    field: field_name.clone(),
+                    regexp: string.clone(),
+                    boost: boost,
+                }))
+            } else {
+                Err(QueryParseError::ExpectedString)
+            }
+        }
+        None => Err(QueryParseError::ExpectedKey("value"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::{Query, MultiTermSelector, TermScorer};
+    use search::schema::{Schema, FieldType, FIELD_INDEXED};
+
+    use query_parser::{QueryBuildContext, QueryParseError};
+
+    use super::parse;
+
+    #[test]
+    fn test_regexp_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"ba[rz]+.*\"
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Regexp("ba[rz]+.*".to_string()),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_simple_regexp_query() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": \"ba[rz]+.*\"
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Regexp("ba[rz]+.*".to_string()),
+            scorer: TermScorer::default(),
+        }));
+    }
+
+    #[test]
+    fn test_with_boost() {
+        let mut schema = Schema::new();
+        let foo_field = schema.add_field("foo".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"ba[rz]+.*\",
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap()).and_then(|builder| Ok(builder.build(&QueryBuildContext::new(), &schema)));
+
+        assert_eq!(query, Ok(Query::MultiTerm {
+            field: foo_field,
+            term_selector: MultiTermSelector::Regexp("ba[rz]+.*".to_string()),
+            scorer: TermScorer::default_with_boost(2.0f32),
+        }));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_regexp() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": \"ba(r\"
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_gives_error_for_incorrect_type() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": 123
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedString));
+    }
+
+    #[test]
+    fn test_gives_error_for_missing_value() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"boost\": 2.0
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::ExpectedKey("value")));
+    }
+
+    #[test]
+    fn test_gives_error_for_extra_inner_key() {
+        let query = parse(&serde_json::from_str("
+        {
+            \"foo\": {
+                \"value\": \"ba[rz]+.*\",
+                \"hello\": \"world\"
+            }
+        }
+        ").unwrap());
+
+        assert_eq!(query.err(), Some(QueryParseError::UnrecognisedKey("hello".to_string())));
+    }
+}
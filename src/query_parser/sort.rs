@@ -0,0 +1,292 @@
+//! Parses the "sort" and "search_after" parameters of a search request
+
+use serde_json::Value as Json;
+use search::schema::{Schema, FieldId, FieldType, FIELD_STORED};
+use search::collectors::top_sorted::{SortKey, SortBy, SortOrder, SortMissing, SortValue, needs_tiebreaker};
+
+use query_parser::QueryParseError;
+use query_parser::utils::{parse_string, parse_datetime};
+
+
+fn parse_order(json: &Json) -> Result<SortOrder, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "asc" => Ok(SortOrder::Asc),
+        "desc" => Ok(SortOrder::Desc),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+fn parse_missing(json: &Json) -> Result<SortMissing, QueryParseError> {
+    match parse_string(json)?.as_ref() {
+        "_first" => Ok(SortMissing::First),
+        "_last" => Ok(SortMissing::Last),
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+/// Finds what to sort by, and the order to use if one isn't specified
+fn parse_sort_by(name: &str, schema: &Schema) -> Result<(SortBy, SortOrder), QueryParseError> {
+    match name {
+        "_score" => Ok((SortBy::Score, SortOrder::Desc)),
+        "_doc" => Ok((SortBy::Doc, SortOrder::Asc)),
+        _ => {
+            let field = schema.get_field_by_name(name).ok_or_else(|| QueryParseError::FieldDoesntExist(name.to_string()))?;
+
+            // Values are read from stored fields
+            match schema.get(&field) {
+                Some(field_info) if field_info.field_flags.contains(FIELD_STORED) => Ok((SortBy::Field(field), SortOrder::Asc)),
+                _ => Err(QueryParseError::FieldNotStored(name.to_string())),
+            }
+        }
+    }
+}
+
+
+fn parse_sort_key(json: &Json, schema: &Schema) -> Result<SortKey, QueryParseError> {
+    match *json {
+        Json::String(ref name) => {
+            let (by, order) = parse_sort_by(name, schema)?;
+
+            Ok(SortKey {
+                by: by,
+                order: order,
+                missing: SortMissing::Last,
+            })
+        }
+        Json::Object(ref object) => {
+            let name = if object.len() == 1 {
+                object.keys().collect::<Vec<_>>()[0]
+            } else {
+                return Err(QueryParseError::ExpectedSingleKey)
+            };
+
+            let (by, mut order) = parse_sort_by(name, schema)?;
+            let mut missing = SortMissing::Last;
+
+            match *object.get(name).unwrap() {
+                ref order_json @ Json::String(_) => {
+                    order = parse_order(order_json)?;
+                }
+                Json::Object(ref inner_object) => {
+                    for (key, value) in inner_object.iter() {
+                        match key.as_ref() {
+                            "order" => {
+                                order = parse_order(value)?;
+                            }
+                            "missing" => {
+                                missing = parse_missing(value)?;
+                            }
+                            _ => return Err(QueryParseError::UnrecognisedKey(key.clone()))
+                        }
+                    }
+                }
+                _ => return Err(QueryParseError::ExpectedObjectOrString),
+            }
+
+            Ok(SortKey {
+                by: by,
+                order: order,
+                missing: missing,
+            })
+        }
+        _ => Err(QueryParseError::ExpectedObjectOrString),
+    }
+}
+
+
+/// Parses the "sort" parameter, which may be a single sort key or an array of them
+pub fn parse_sort(json: &Json, schema: &Schema) -> Result<Vec<SortKey>, QueryParseError> {
+    match *json {
+        Json::Array(ref array) => {
+            let mut sort = Vec::with_capacity(array.len());
+
+            for item in array.iter() {
+                sort.push(parse_sort_key(item, schema)?);
+            }
+
+            Ok(sort)
+        }
+        _ => Ok(vec![parse_sort_key(json, schema)?]),
+    }
+}
+
+
+fn parse_field_sort_value(json: &Json, field: FieldId, schema: &Schema) -> Result<SortValue, QueryParseError> {
+    let field_type = &schema.get(&field).unwrap().field_type;
+
+    let value = match (field_type, json) {
+        (&FieldType::I64, &Json::Number(ref number)) => number.as_i64().map(SortValue::Integer),
+        (&FieldType::DateTime, &Json::Number(ref number)) => number.as_i64().map(SortValue::Integer),
+        (&FieldType::DateTime, &Json::String(ref string)) => {
+            parse_datetime(string).map(|datetime| SortValue::Integer(datetime.timestamp() * 1000 + datetime.timestamp_subsec_millis() as i64))
+        }
+        (&FieldType::Boolean, &Json::Bool(value)) => Some(SortValue::Boolean(value)),
+        (&FieldType::Text, &Json::String(ref string)) | (&FieldType::PlainString, &Json::String(ref string)) => Some(SortValue::String(string.clone())),
+        _ => None,
+    };
+
+    value.ok_or(QueryParseError::InvalidValue)
+}
+
+
+/// Parses the "search_after" parameter, this must contain a value for each of the sort keys
+///
+/// This may also have the tiebreaker that was added to the sort values of the previous page
+/// (see "needs_tiebreaker").
+pub fn parse_search_after(json: &Json, sort: &[SortKey], schema: &Schema) -> Result<Vec<SortValue>, QueryParseError> {
+    let array = json.as_array().ok_or(QueryParseError::ExpectedArray)?;
+
+    let has_tiebreaker = needs_tiebreaker(sort) && array.len() == sort.len() + 1;
+    if array.len() != sort.len() && !has_tiebreaker {
+        return Err(QueryParseError::InvalidValue);
+    }
+
+    let mut values = Vec::with_capacity(array.len());
+    for (key, value) in sort.iter().zip(array.iter()) {
+        if value.is_null() {
+            values.push(SortValue::Missing);
+            continue;
+        }
+
+        values.push(match key.by {
+            SortBy::Score => SortValue::Float(value.as_f64().ok_or(QueryParseError::InvalidValue)?),
+            SortBy::Doc => SortValue::Integer(value.as_i64().ok_or(QueryParseError::InvalidValue)?),
+            SortBy::Field(field) => parse_field_sort_value(value, field, schema)?,
+        });
+    }
+
+    if has_tiebreaker {
+        values.push(SortValue::Integer(array[sort.len()].as_i64().ok_or(QueryParseError::InvalidValue)?));
+    }
+
+    Ok(values)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use search::schema::{Schema, FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::collectors::top_sorted::{SortKey, SortBy, SortOrder, SortMissing, SortValue};
+
+    use query_parser::QueryParseError;
+
+    use super::{parse_sort, parse_search_after};
+
+    fn make_test_schema() -> Schema {
+        let mut schema = Schema::new();
+        schema.add_field("price".to_string(), FieldType::I64, FIELD_INDEXED | FIELD_STORED).unwrap();
+        schema.add_field("published".to_string(), FieldType::DateTime, FIELD_INDEXED | FIELD_STORED).unwrap();
+        schema.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        schema
+    }
+
+    #[test]
+    fn test_sort_by_field_name() {
+        let schema = make_test_schema();
+        let sort = parse_sort(&serde_json::from_str("\"price\"").unwrap(), &schema);
+
+        assert_eq!(sort, Ok(vec![
+            SortKey {
+                by: SortBy::Field(schema.get_field_by_name("price").unwrap()),
+                order: SortOrder::Asc,
+                missing: SortMissing::Last,
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_sort_by_multiple_keys() {
+        let schema = make_test_schema();
+        let sort = parse_sort(&serde_json::from_str("
+        [
+            {\"price\": \"desc\"},
+            {\"published\": {\"order\": \"asc\", \"missing\": \"_first\"}},
+            \"_score\",
+            \"_doc\"
+        ]
+        ").unwrap(), &schema);
+
+        assert_eq!(sort, Ok(vec![
+            SortKey {
+                by: SortBy::Field(schema.get_field_by_name("price").unwrap()),
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            },
+            SortKey {
+                by: SortBy::Field(schema.get_field_by_name("published").unwrap()),
+                order: SortOrder::Asc,
+                missing: SortMissing::First,
+            },
+            SortKey {
+                by: SortBy::Score,
+                order: SortOrder::Desc,
+                missing: SortMissing::Last,
+            },
+            SortKey {
+                by: SortBy::Doc,
+                order: SortOrder::Asc,
+                missing: SortMissing::Last,
+            },
+        ]));
+    }
+
+    #[test]
+    fn test_gives_error_for_unstored_field() {
+        let schema = make_test_schema();
+        let sort = parse_sort(&serde_json::from_str("\"body\"").unwrap(), &schema);
+
+        assert_eq!(sort, Err(QueryParseError::FieldNotStored("body".to_string())));
+    }
+
+    #[test]
+    fn test_gives_error_for_invalid_order() {
+        let schema = make_test_schema();
+        let sort = parse_sort(&serde_json::from_str("{\"price\": \"up\"}").unwrap(), &schema);
+
+        assert_eq!(sort, Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_search_after() {
+        let schema = make_test_schema();
+        let sort = parse_sort(&serde_json::from_str("[\"price\", \"published\", \"_doc\"]").unwrap(), &schema).unwrap();
+        let search_after = parse_search_after(&serde_json::from_str("[10, \"2017-01-01\", 5]").unwrap(), &sort, &schema);
+
+        assert_eq!(search_after, Ok(vec![
+            SortValue::Integer(10),
+            SortValue::Integer(1483228800000),
+            SortValue::Integer(5),
+        ]));
+    }
+
+    #[test]
+    fn test_search_after_gives_error_for_wrong_length() {
+        let schema = make_test_schema();
+        let sort = parse_sort(&serde_json::from_str("[\"price\", \"_doc\"]").unwrap(), &schema).unwrap();
+        let search_after = parse_search_after(&serde_json::from_str("[10]").unwrap(), &sort, &schema);
+
+        assert_eq!(search_after, Err(QueryParseError::InvalidValue));
+    }
+
+    #[test]
+    fn test_search_after_with_tiebreaker() {
+        let schema = make_test_schema();
+        let sort = parse_sort(&serde_json::from_str("[\"price\"]").unwrap(), &schema).unwrap();
+        let search_after = parse_search_after(&serde_json::from_str("[10, 4294967301]").unwrap(), &sort, &schema);
+
+        assert_eq!(search_after, Ok(vec![
+            SortValue::Integer(10),
+            SortValue::Integer(4294967301),
+        ]));
+
+        // Sorts with "_doc" don't have a tiebreaker
+        let sort = parse_sort(&serde_json::from_str("[\"price\", \"_doc\"]").unwrap(), &schema).unwrap();
+        let search_after = parse_search_after(&serde_json::from_str("[10, 5, 5]").unwrap(), &sort, &schema);
+
+        assert_eq!(search_after, Err(QueryParseError::InvalidValue));
+    }
+}
@@ -1,5 +1,7 @@
 use serde_json::Value as Json;
-use search::term::Term;
+use chrono::{DateTime, NaiveDate, Utc};
+use search::{Term, Query, MultiTermSelector, TermScorer};
+use search::schema::FieldId;
 
 use query_parser::QueryParseError;
 
@@ -25,6 +27,19 @@ pub fn parse_float(json: &Json) -> Result<f32, QueryParseError> {
 }
 
 
+pub fn parse_positive_integer(json: &Json) -> Result<u64, QueryParseError> {
+    match json {
+        &Json::Number(ref number) => {
+            match number.as_u64() {
+                Some(val) => Ok(val),
+                None => Err(QueryParseError::ExpectedPositiveInteger),
+            }
+        }
+        _ => Err(QueryParseError::ExpectedPositiveInteger),
+    }
+}
+
+
 #[derive(Debug)]
 pub enum Operator {
     Or,
@@ -46,6 +61,231 @@ pub fn parse_operator(json: &Json) -> Result<Operator, QueryParseError> {
 }
 
 
+/// How many of a query's optional clauses must match
+///
+/// This can be either an absolute number of clauses or a percentage of the total number of
+/// clauses. Negative values specify the number of clauses that are allowed to be missing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MinimumShouldMatch {
+    Absolute(i64),
+    Percentage(f32),
+}
+
+
+impl MinimumShouldMatch {
+    /// Works out how many clauses must match, given the total number of clauses
+    pub fn resolve(&self, num_clauses: usize) -> u32 {
+        let num_clauses = num_clauses as i64;
+
+        let required = match *self {
+            MinimumShouldMatch::Absolute(value) => {
+                if value < 0 {
+                    num_clauses + value
+                } else {
+                    value
+                }
+            }
+            MinimumShouldMatch::Percentage(percentage) => {
+                // Percentages are rounded down to a whole number of clauses
+                let clauses = (num_clauses as f32 * percentage.abs() / 100.0).floor() as i64;
+
+                if percentage < 0.0 {
+                    num_clauses - clauses
+                } else {
+                    clauses
+                }
+            }
+        };
+
+        if required < 0 {
+            0
+        } else {
+            required as u32
+        }
+    }
+}
+
+
+pub fn parse_minimum_should_match(json: &Json) -> Result<MinimumShouldMatch, QueryParseError> {
+    match *json {
+        Json::Number(ref number) => {
+            match number.as_i64() {
+                Some(value) => Ok(MinimumShouldMatch::Absolute(value)),
+                None => Err(QueryParseError::InvalidValue),
+            }
+        }
+        Json::String(ref string) => {
+            let string = string.trim();
+
+            if string.ends_with('%') {
+                match string[..string.len() - 1].parse::<f32>() {
+                    Ok(percentage) => Ok(MinimumShouldMatch::Percentage(percentage)),
+                    Err(_) => Err(QueryParseError::InvalidValue),
+                }
+            } else {
+                match string.parse::<i64>() {
+                    Ok(value) => Ok(MinimumShouldMatch::Absolute(value)),
+                    Err(_) => Err(QueryParseError::InvalidValue),
+                }
+            }
+        }
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+/// How many edits a fuzzy term may have
+///
+/// "AUTO" picks the number of edits from the length of the term. Terms shorter than "low"
+/// characters must match exactly, terms shorter than "high" characters may have one edit and
+/// longer terms may have two.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fuzziness {
+    Auto {
+        low: u32,
+        high: u32,
+    },
+    Edits(u32),
+}
+
+
+impl Fuzziness {
+    pub fn max_edits(&self, term: &str) -> u32 {
+        match *self {
+            Fuzziness::Auto{low, high} => {
+                let length = term.chars().count() as u32;
+
+                if length < low {
+                    0
+                } else if length < high {
+                    1
+                } else {
+                    2
+                }
+            }
+            Fuzziness::Edits(edits) => edits,
+        }
+    }
+}
+
+
+impl Default for Fuzziness {
+    fn default() -> Fuzziness {
+        Fuzziness::Auto {
+            low: 3,
+            high: 6,
+        }
+    }
+}
+
+
+pub fn parse_fuzziness(json: &Json) -> Result<Fuzziness, QueryParseError> {
+    // More than two edits would match too many terms to be useful
+    fn check_edits(edits: u64) -> Result<Fuzziness, QueryParseError> {
+        if edits <= 2 {
+            Ok(Fuzziness::Edits(edits as u32))
+        } else {
+            Err(QueryParseError::InvalidValue)
+        }
+    }
+
+    match *json {
+        Json::Number(ref number) => {
+            match number.as_u64() {
+                Some(edits) => check_edits(edits),
+                None => Err(QueryParseError::InvalidValue),
+            }
+        }
+        Json::String(ref string) => {
+            let string = string.trim();
+
+            if string == "AUTO" {
+                return Ok(Fuzziness::default());
+            }
+
+            if string.starts_with("AUTO:") {
+                let distances = string[5..].split(',').map(|distance| distance.trim().parse::<u32>()).collect::<Vec<_>>();
+
+                if distances.len() == 2 {
+                    if let (&Ok(low), &Ok(high)) = (&distances[0], &distances[1]) {
+                        if low <= high {
+                            return Ok(Fuzziness::Auto { low: low, high: high });
+                        }
+                    }
+                }
+
+                return Err(QueryParseError::InvalidValue);
+            }
+
+            match string.parse::<u64>() {
+                Ok(edits) => check_edits(edits),
+                Err(_) => Err(QueryParseError::InvalidValue),
+            }
+        }
+        _ => Err(QueryParseError::InvalidValue),
+    }
+}
+
+
+/// Options for expanding the terms of a query into fuzzy terms
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyOptions {
+    pub fuzziness: Fuzziness,
+
+    /// The number of characters at the start of each term that must match exactly
+    pub prefix_length: u32,
+
+    /// The maximum number of terms each fuzzy term may expand into
+    pub max_expansions: usize,
+
+    /// If true, swapping two adjacent characters counts as a single edit
+    pub transpositions: bool,
+}
+
+
+impl Default for FuzzyOptions {
+    fn default() -> FuzzyOptions {
+        FuzzyOptions {
+            fuzziness: Fuzziness::default(),
+            prefix_length: 0,
+            max_expansions: 50,
+            transpositions: true,
+        }
+    }
+}
+
+
+impl FuzzyOptions {
+    /// Builds a query that matches terms within the allowed number of edits of the given term
+    ///
+    /// If no edits are allowed for the term, this builds a plain term query
+    pub fn build_query(&self, field: FieldId, term: Term, scorer: TermScorer) -> Query {
+        let term_string = String::from_utf8_lossy(term.as_bytes()).into_owned();
+        let max_edits = self.fuzziness.max_edits(&term_string);
+
+        if max_edits == 0 {
+            return Query::Term {
+                field: field,
+                term: term,
+                scorer: scorer,
+            };
+        }
+
+        Query::MultiTerm {
+            field: field,
+            term_selector: MultiTermSelector::Fuzzy {
+                term: term_string,
+                max_edits: max_edits,
+                prefix_length: self.prefix_length,
+                transpositions: self.transpositions,
+                max_expansions: self.max_expansions,
+            },
+            scorer: scorer,
+        }
+    }
+}
+
+
 pub fn parse_field_and_boost(json: &Json) -> Result<(String, f32), QueryParseError> {
     let string = parse_string(json)?;
 
@@ -75,3 +315,17 @@ pub fn json_value_to_term(json: &Json) -> Option<Term> {
         &Json::Object(_) => None,
     }
 }
+
+
+/// Parses a date string, either as a full RFC 3339 datetime ("2016-07-23T16:15:00+01:00")
+/// or just a date ("2016-07-23"), which is interpreted as midnight UTC
+pub fn parse_datetime(string: &str) -> Option<DateTime<Utc>> {
+    if let Ok(datetime) = string.parse::<DateTime<Utc>>() {
+        return Some(datetime);
+    }
+
+    match NaiveDate::parse_from_str(string, "%Y-%m-%d") {
+        Ok(date) => Some(DateTime::from_utc(date.and_hms(0, 0, 0), Utc)),
+        Err(_) => None,
+    }
+}
@@ -0,0 +1,902 @@
+//! A store that keeps all of its documents in memory
+//!
+//! Nothing is written to the disk so the contents are lost when the process exits. This is
+//! useful for tests and for indices that are rebuilt whenever the server starts.
+
+use std::fmt;
+use std::mem;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, BTreeMap};
+
+use roaring::RoaringBitmap;
+use search::{Document, DocId, Term, TermId};
+use search::document::FieldValue;
+use search::query::Query;
+use search::query::multi_term_selector::MultiTermSelector;
+use search::explanation::Explanation;
+use search::collectors::{Collector, FieldValueReader};
+use search::schema::{Schema, FieldType, FieldFlags, FieldId, AddFieldError};
+use search::version::{DocumentVersion, VersionCondition, VersionConflict, next_version, PRIMARY_TERM};
+use search::segment::{Segment, SegmentId, TermImpact};
+use search::segment_builder::{SegmentBuilder, DocumentInsertError};
+use search::doc_values::DocValues;
+use search::term_selection::{MapTermCursor, TerminatingTermCursor, select_terms};
+use search::execution::{self, TermDictionaryReader};
+use search::execution::filter_cache::{FilterCache, FilterCacheStats};
+use fnv::FnvHashMap;
+
+use super::{Store, Reader, DocumentWriteError, StoredFieldReadError, decode_stored_field_value};
+
+
+/// Segments are merged together once there are this many with a similar number of live documents
+///
+/// Segments are grouped into tiers by the number of digits in their live document counts. This
+/// keeps the number of segments logarithmic to the size of the index and means that each document
+/// is only copied once per tier.
+const MERGE_FACTOR: usize = 10;
+
+
+/// The terms that have been indexed into a memory store
+///
+/// Terms are never removed, so this is shared by every version of the index instead of being
+/// copied with it. Readers may see terms that were added after they were created, these aren't in
+/// any of the reader's segments so they don't match anything.
+#[derive(Debug, Default)]
+struct MemoryTermDictionary {
+    terms: HashMap<Term, TermId>,
+
+    /// The terms that have been indexed into each field, in sorted order for multi term queries
+    field_terms: FnvHashMap<FieldId, BTreeMap<Term, TermId>>,
+}
+
+impl MemoryTermDictionary {
+    fn get_or_insert(&mut self, field_id: FieldId, term: &Term) -> TermId {
+        let term_id = match self.terms.get(term) {
+            Some(term_id) => *term_id,
+            None => {
+                let term_id = TermId(self.terms.len() as u32);
+                self.terms.insert(term.clone(), term_id);
+                term_id
+            }
+        };
+
+        let field_terms = self.field_terms.entry(field_id).or_insert_with(BTreeMap::new);
+        if !field_terms.contains_key(term) {
+            field_terms.insert(term.clone(), term_id);
+        }
+
+        term_id
+    }
+}
+
+
+/// A segment of a memory store, along with the keys of the documents in it
+#[derive(Debug)]
+struct MemorySegmentData {
+    builder: SegmentBuilder,
+
+    /// The local id and version of each document in the segment by key
+    ///
+    /// The previous versions of a document are deleted when it is written again, so only one of
+    /// these entries can point to a live document for each key.
+    documents: HashMap<String, (u32, DocumentVersion)>,
+}
+
+
+/// The contents of a memory store
+///
+/// Readers keep hold of the version that was current when they were created. Writers clone the
+/// contents before changing them if any readers are still using them. This only copies the list
+/// of segments and the deleted keys, the segments and their deletion lists are shared until they
+/// are changed.
+#[derive(Debug, Clone)]
+struct MemoryIndex {
+    schema: Schema,
+    term_dictionary: Arc<RwLock<MemoryTermDictionary>>,
+    segments: BTreeMap<u32, Arc<MemorySegmentData>>,
+    deletion_lists: FnvHashMap<u32, Arc<RoaringBitmap>>,
+
+    /// The version of the deletion of each key that has been deleted and not written since
+    deleted_keys: HashMap<String, DocumentVersion>,
+
+    next_segment: u32,
+    next_seq_no: u64,
+}
+
+impl MemoryIndex {
+    /// Finds the live document with the given key
+    ///
+    /// The keys are kept with the segments so each one is checked in turn, merging keeps the
+    /// number of segments small.
+    fn find_document(&self, doc_key: &str) -> Option<(DocId, DocumentVersion)> {
+        for (segment, data) in self.segments.iter() {
+            if let Some(&(doc_local_id, version)) = data.documents.get(doc_key) {
+                let is_deleted = self.deletion_lists.get(segment).map_or(false, |deletion_list| deletion_list.contains(doc_local_id));
+
+                if !is_deleted {
+                    return Some((DocId(SegmentId(*segment), doc_local_id), version));
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Adds a document to a segment, giving any new terms in it an id first
+    fn add_to_segment(&self, builder: &mut SegmentBuilder, doc: &Document) -> Result<u32, DocumentInsertError> {
+        {
+            let mut term_dictionary = self.term_dictionary.write().unwrap();
+
+            for (field_id, tokens) in doc.indexed_fields.iter() {
+                for (term, _positions) in tokens.iter() {
+                    // Terms may already be in the dictionary from another field
+                    let term_id = term_dictionary.get_or_insert(*field_id, term);
+                    builder.term_dictionary.entry(term.clone()).or_insert(term_id);
+                }
+            }
+        }
+
+        builder.add_document(doc, &self.schema)
+    }
+
+    /// Adds a segment and deletes the previous versions of the documents in it
+    fn commit_segment(&mut self, mut builder: SegmentBuilder, docs: Vec<(&Document, u32, DocumentVersion)>) {
+        // The terms were added to the index's term dictionary as the documents were added
+        builder.term_dictionary = HashMap::new();
+
+        if docs.is_empty() {
+            return;
+        }
+
+        let segment = self.next_segment;
+        self.next_segment += 1;
+
+        let mut documents = HashMap::with_capacity(docs.len());
+        for (doc, doc_local_id, version) in docs {
+            // Delete the previous version of the document
+            // This may be earlier in the same segment if the key was written more than once
+            if let Some((previous_local_id, _)) = documents.insert(doc.key.clone(), (doc_local_id, version)) {
+                self.delete_document(DocId(SegmentId(segment), previous_local_id));
+            } else if let Some((previous_doc_id, _)) = self.find_document(&doc.key) {
+                self.delete_document(previous_doc_id);
+            }
+        }
+
+        self.segments.insert(segment, Arc::new(MemorySegmentData {
+            builder: builder,
+            documents: documents,
+        }));
+    }
+
+    fn delete_document(&mut self, doc_id: DocId) {
+        let deletion_list = self.deletion_lists.entry((doc_id.0).0).or_insert_with(|| Arc::new(RoaringBitmap::new()));
+        Arc::make_mut(deletion_list).insert(doc_id.1);
+    }
+
+    fn live_docs(&self, segment: u32) -> u64 {
+        let total_docs = self.segments[&segment].builder.statistics.get(&b"total_docs"[..]).cloned().unwrap_or(0) as u64;
+        let deleted_docs = self.deletion_lists.get(&segment).map_or(0, |deletion_list| deletion_list.len());
+
+        total_docs - deleted_docs
+    }
+
+    /// Removes empty segments and merges segments that have a similar number of live documents
+    ///
+    /// Returns the ids of the segments that were removed
+    fn merge_segments(&mut self) -> Vec<u32> {
+        let mut removed_segments = Vec::new();
+
+        loop {
+            let mut tiers: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+
+            for segment in self.segments.keys().cloned().collect::<Vec<_>>() {
+                let mut live_docs = self.live_docs(segment);
+
+                if live_docs == 0 {
+                    self.segments.remove(&segment);
+                    self.deletion_lists.remove(&segment);
+                    removed_segments.push(segment);
+                    continue;
+                }
+
+                let mut tier = 0;
+                while live_docs >= MERGE_FACTOR as u64 {
+                    live_docs /= MERGE_FACTOR as u64;
+                    tier += 1;
+                }
+
+                tiers.entry(tier).or_insert_with(Vec::new).push(segment);
+            }
+
+            let source_segments = match tiers.into_iter().map(|(_, segments)| segments).find(|segments| segments.len() >= MERGE_FACTOR) {
+                Some(segments) => segments,
+                None => break,
+            };
+
+            let (builder, doc_id_mappings) = {
+                let sources = source_segments.iter().map(|segment| {
+                    (&self.segments[segment].builder, self.deletion_lists.get(segment).map(|deletion_list| &**deletion_list))
+                }).collect::<Vec<_>>();
+
+                match SegmentBuilder::merge(&sources) {
+                    Ok(merged) => merged,
+                    Err(DocumentInsertError::SegmentFull) => break,
+                }
+            };
+
+            // Point the keys of the documents to their new locations
+            let mut documents = HashMap::new();
+            for (segment, doc_id_mapping) in source_segments.iter().zip(doc_id_mappings.iter()) {
+                for (doc_key, &(doc_local_id, version)) in self.segments[segment].documents.iter() {
+                    if let Some(new_local_id) = doc_id_mapping[doc_local_id as usize] {
+                        documents.insert(doc_key.clone(), (new_local_id, version));
+                    }
+                }
+            }
+
+            for segment in source_segments.iter() {
+                self.segments.remove(segment);
+                self.deletion_lists.remove(segment);
+                removed_segments.push(*segment);
+            }
+
+            let segment = self.next_segment;
+            self.next_segment += 1;
+            self.segments.insert(segment, Arc::new(MemorySegmentData {
+                builder: builder,
+                documents: documents,
+            }));
+        }
+
+        removed_segments
+    }
+}
+
+
+pub struct MemoryStore {
+    index: RwLock<Arc<MemoryIndex>>,
+
+    /// Shared with readers, the entries of segments are removed when they are merged
+    filter_cache: Arc<FilterCache>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            index: RwLock::new(Arc::new(MemoryIndex {
+                schema: Schema::new(),
+                term_dictionary: Arc::new(RwLock::new(MemoryTermDictionary::default())),
+                segments: BTreeMap::new(),
+                deletion_lists: FnvHashMap::default(),
+                deleted_keys: HashMap::new(),
+                next_segment: 1,
+                next_seq_no: 0,
+            })),
+            filter_cache: Arc::new(FilterCache::default()),
+        }
+    }
+
+    /// Returns the index, cloning it first if any readers are using it
+    fn index_mut(&mut self) -> &mut MemoryIndex {
+        Arc::make_mut(self.index.get_mut().unwrap())
+    }
+
+    pub fn reader(&self) -> MemoryReader {
+        MemoryReader {
+            index: self.index.read().unwrap().clone(),
+            filter_cache: self.filter_cache.clone(),
+        }
+    }
+
+    /// Merges the segments of the index after it has been written to
+    fn merge_segments(&self, index: &mut MemoryIndex) {
+        let removed_segments = index.merge_segments();
+
+        if !removed_segments.is_empty() {
+            self.filter_cache.remove_segments(&removed_segments);
+        }
+    }
+
+    /// Writes documents that meet their version conditions, returns the new version or the conflict of each one
+    fn write_documents(&self, docs: &[&Document], conditions: &[Option<VersionCondition>]) -> Vec<Result<DocumentVersion, VersionConflict>> {
+        assert_eq!(docs.len(), conditions.len());
+
+        let mut index_lock = self.index.write().unwrap();
+        let index = Arc::make_mut(&mut *index_lock);
+
+        let mut builder = SegmentBuilder::new();
+        let mut segment_docs = Vec::new();
+        let mut results = Vec::with_capacity(docs.len());
+
+        // Keys may be repeated, later documents must check their conditions against the earlier ones
+        let mut new_versions: HashMap<&str, DocumentVersion> = HashMap::new();
+
+        for (doc, condition) in docs.iter().zip(conditions.iter()) {
+            let current_version = match new_versions.get(&doc.key[..]) {
+                Some(version) => Some(*version),
+                None => index.find_document(&doc.key).map(|(_, version)| version),
+            };
+            let deleted = index.deleted_keys.get(&doc.key).cloned();
+
+            let version = match next_version(current_version, deleted, *condition) {
+                Ok(version) => {
+                    DocumentVersion {
+                        version: version,
+                        seq_no: index.next_seq_no,
+                        primary_term: PRIMARY_TERM,
+                    }
+                }
+                Err(conflict) => {
+                    results.push(Err(conflict));
+                    continue;
+                }
+            };
+            index.next_seq_no += 1;
+            index.deleted_keys.remove(&doc.key);
+            new_versions.insert(&doc.key, version);
+            results.push(Ok(version));
+
+            let doc_local_id = match index.add_to_segment(&mut builder, doc) {
+                Ok(doc_local_id) => doc_local_id,
+                Err(DocumentInsertError::SegmentFull) => {
+                    // Add the full segment and start a new one
+                    index.commit_segment(builder, mem::replace(&mut segment_docs, Vec::new()));
+                    builder = SegmentBuilder::new();
+
+                    index.add_to_segment(&mut builder, doc).expect("document doesn't fit in an empty segment")
+                }
+            };
+
+            segment_docs.push((*doc, doc_local_id, version));
+        }
+
+        index.commit_segment(builder, segment_docs);
+        self.merge_segments(index);
+
+        results
+    }
+}
+
+impl fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MemoryStore")
+    }
+}
+
+impl Store for MemoryStore {
+    fn reader<'a>(&'a self) -> Box<Reader + 'a> {
+        Box::new(MemoryStore::reader(self))
+    }
+
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    fn add_field(&mut self, name: String, field_type: FieldType, field_flags: FieldFlags) -> Result<FieldId, AddFieldError> {
+        self.index_mut().schema.add_field(name, field_type, field_flags)
+    }
+
+    fn remove_field(&mut self, field_id: &FieldId) -> bool {
+        self.index_mut().schema.remove_field(field_id)
+    }
+
+    fn insert_or_update_document(&self, doc: &Document, condition: Option<VersionCondition>) -> Result<DocumentVersion, DocumentWriteError> {
+        match self.write_documents(&[doc], &[condition]).pop().unwrap() {
+            Ok(version) => Ok(version),
+            Err(conflict) => Err(DocumentWriteError::VersionConflict(conflict)),
+        }
+    }
+
+    fn insert_or_update_documents(&self, docs: &[Document], conditions: &[Option<VersionCondition>]) -> Result<Vec<Result<DocumentVersion, VersionConflict>>, String> {
+        Ok(self.write_documents(&docs.iter().collect::<Vec<_>>(), conditions))
+    }
+
+    fn remove_document_by_key(&self, doc_key: &str, condition: Option<VersionCondition>) -> Result<Option<DocumentVersion>, DocumentWriteError> {
+        let mut index_lock = self.index.write().unwrap();
+
+        let current_document = index_lock.find_document(doc_key);
+        let deleted = index_lock.deleted_keys.get(doc_key).cloned();
+        let version = try!(next_version(current_document.map(|(_, version)| version), deleted, condition));
+
+        let doc_id = match current_document {
+            Some((doc_id, _)) => doc_id,
+            None => return Ok(None),
+        };
+
+        let index = Arc::make_mut(&mut *index_lock);
+        index.delete_document(doc_id);
+
+        let version = DocumentVersion {
+            version: version,
+            seq_no: index.next_seq_no,
+            primary_term: PRIMARY_TERM,
+        };
+        index.next_seq_no += 1;
+        index.deleted_keys.insert(doc_key.to_string(), version);
+
+        self.merge_segments(index);
+
+        Ok(Some(version))
+    }
+
+    fn filter_cache_stats(&self) -> FilterCacheStats {
+        self.filter_cache.stats()
+    }
+}
+
+
+/// A segment of a memory store
+///
+/// The segments themselves are never changed after they are added, so deletions are kept alongside them.
+struct MemorySegment<'a> {
+    id: u32,
+    builder: &'a SegmentBuilder,
+    deletion_list: Option<&'a RoaringBitmap>,
+}
+
+impl<'a> Segment for MemorySegment<'a> {
+    fn id(&self) -> SegmentId {
+        SegmentId(self.id)
+    }
+
+    fn load_statistic(&self, stat_name: &[u8]) -> Result<Option<i64>, String> {
+        self.builder.load_statistic(stat_name)
+    }
+
+    fn load_stored_field_value_raw(&self, doc_local_id: u32, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        self.builder.load_stored_field_value_raw(doc_local_id, field_id, value_type)
+    }
+
+    fn load_postings_list(&self, field_id: FieldId, term_id: TermId) -> Result<Option<RoaringBitmap>, String> {
+        self.builder.load_postings_list(field_id, term_id)
+    }
+
+    fn load_term_frequencies(&self, field_id: FieldId, term_id: TermId) -> Result<Option<Vec<u32>>, String> {
+        self.builder.load_term_frequencies(field_id, term_id)
+    }
+
+    fn load_field_norms(&self, field_id: FieldId) -> Result<Option<Vec<u8>>, String> {
+        self.builder.load_field_norms(field_id)
+    }
+
+    fn load_term_impact(&self, field_id: FieldId, term_id: TermId) -> Result<Option<TermImpact>, String> {
+        self.builder.load_term_impact(field_id, term_id)
+    }
+
+    fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String> {
+        Ok(self.deletion_list.cloned())
+    }
+
+    fn load_doc_values(&self, field_id: FieldId) -> Result<Option<DocValues>, String> {
+        self.builder.load_doc_values(field_id)
+    }
+
+    fn load_term_positions(&self, doc_local_id: u32, field_id: FieldId, term_id: TermId) -> Result<Option<Vec<u32>>, String> {
+        self.builder.load_term_positions(doc_local_id, field_id, term_id)
+    }
+}
+
+
+pub struct MemoryReader {
+    index: Arc<MemoryIndex>,
+    filter_cache: Arc<FilterCache>,
+}
+
+impl MemoryReader {
+    fn segments(&self) -> Vec<MemorySegment> {
+        self.index.segments.iter().map(|(id, data)| {
+            MemorySegment {
+                id: *id,
+                builder: &data.builder,
+                deletion_list: self.index.deletion_lists.get(id).map(|deletion_list| &**deletion_list),
+            }
+        }).collect()
+    }
+}
+
+impl Reader for MemoryReader {
+    fn schema(&self) -> &Schema {
+        &self.index.schema
+    }
+
+    fn contains_document_key(&self, doc_key: &str) -> bool {
+        self.index.find_document(doc_key).is_some()
+    }
+
+    fn get_document_by_key(&self, doc_key: &str) -> Option<DocId> {
+        self.index.find_document(doc_key).map(|(doc_id, _)| doc_id)
+    }
+
+    fn get_document_version(&self, doc_key: &str) -> Option<DocumentVersion> {
+        self.index.find_document(doc_key).map(|(_, version)| version)
+    }
+
+    fn read_stored_field(&self, field_id: FieldId, doc_id: DocId) -> Result<Option<FieldValue>, StoredFieldReadError> {
+        let field_info = match self.schema().get(&field_id) {
+            Some(field_info) => field_info,
+            None => return Err(StoredFieldReadError::InvalidFieldId(field_id)),
+        };
+
+        let segment = match self.index.segments.get(&(doc_id.0).0) {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+
+        match segment.builder.stored_field_values.get(&(field_id, doc_id.1, b"val".to_vec())) {
+            Some(value) => Ok(Some(try!(decode_stored_field_value(&field_info.field_type, value)))),
+            None => Ok(None),
+        }
+    }
+
+    fn search(&self, collector: &mut Collector, query: &Query) -> Result<(), String> {
+        execution::search(collector, query, self, &self.segments(), &self.filter_cache)
+    }
+
+    fn explain(&self, query: &Query, doc_id: DocId) -> Result<Option<Explanation>, String> {
+        execution::explain(query, doc_id, self, self.schema(), &self.segments())
+    }
+}
+
+impl TermDictionaryReader for MemoryReader {
+    fn get_term_id(&self, term: &Term) -> Option<TermId> {
+        self.index.term_dictionary.read().unwrap().terms.get(term).cloned()
+    }
+
+    fn get_term(&self, field_id: FieldId, term_id: TermId) -> Option<Term> {
+        let term_dictionary = self.index.term_dictionary.read().unwrap();
+
+        term_dictionary.field_terms.get(&field_id).and_then(|terms| {
+            terms.iter().find(|&(_, id)| *id == term_id).map(|(term, _)| term.clone())
+        })
+    }
+
+    fn select_terms_until(&self, field_id: FieldId, term_selector: &MultiTermSelector, should_terminate: &mut FnMut() -> bool) -> Vec<TermId> {
+        let term_dictionary = self.index.term_dictionary.read().unwrap();
+
+        match term_dictionary.field_terms.get(&field_id) {
+            Some(terms) => select_terms(&mut TerminatingTermCursor::new(MapTermCursor::new(terms), should_terminate), term_selector),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl FieldValueReader for MemoryReader {
+    fn read_field_value(&self, field_id: FieldId, doc_id: DocId) -> Option<FieldValue> {
+        // Stored values are already in a hash map so there's nothing to gain from the doc values
+        match self.read_stored_field(field_id, doc_id) {
+            Ok(value) => value,
+            Err(_) => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use fnv::FnvHashMap;
+    use search::{Term, Token, Document};
+    use search::document::FieldValue;
+    use search::schema::{FieldType, FieldId, FIELD_INDEXED, FIELD_STORED};
+    use search::query::Query;
+    use search::query::term_scorer::TermScorer;
+    use search::query::multi_term_selector::MultiTermSelector;
+    use search::collectors::{Collector, DocumentMatch};
+    use search::collectors::total_count::TotalCountCollector;
+    use search::collectors::multi::MultiCollector;
+    use search::collectors::deadline::DeadlineCollector;
+    use search::version::VersionCondition;
+    use search::execution::TermDictionaryReader;
+    use search::backends::{Store, Reader, DocumentWriteError};
+
+    use super::{MemoryStore, MERGE_FACTOR};
+
+    fn make_doc(key: &str, title_field: FieldId, title: &str, pk_field: FieldId, pk: i64) -> Document {
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(
+            title_field,
+            title.split(' ').enumerate().map(|(position, word)| {
+                Token { term: Term::from_string(word), position: position as u32 + 1 }
+            }).collect::<Vec<_>>().into()
+        );
+
+        let mut stored_fields = FnvHashMap::default();
+        stored_fields.insert(pk_field, FieldValue::Integer(pk));
+
+        Document {
+            key: key.to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: stored_fields,
+        }
+    }
+
+    fn count_matches(index_reader: &Reader, query: &Query) -> u64 {
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, query).unwrap();
+        collector.get_total_count()
+    }
+
+    fn term_query(field: FieldId, term: &str) -> Query {
+        Query::Term {
+            field: field,
+            term: Term::from_string(term),
+            scorer: TermScorer::default(),
+        }
+    }
+
+    #[test]
+    fn test_insert_and_search() {
+        let mut store = MemoryStore::new();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let pk_field = store.add_field("pk".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        store.insert_or_update_document(&make_doc("doc_a", title_field, "hello world", pk_field, 1), None).unwrap();
+        store.insert_or_update_documents(&[
+            make_doc("doc_b", title_field, "hello there", pk_field, 2),
+            make_doc("doc_c", title_field, "howdy partner", pk_field, 3),
+        ], &[None; 2]).unwrap();
+
+        let index_reader = store.reader();
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "hello")), 2);
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "partner")), 1);
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "missing")), 0);
+
+        // Terms are shared between segments
+        let doc_a = index_reader.get_document_by_key("doc_a").unwrap();
+        let doc_b = index_reader.get_document_by_key("doc_b").unwrap();
+        assert!(doc_a.0 != doc_b.0);
+        match index_reader.read_stored_field(pk_field, doc_b) {
+            Ok(Some(FieldValue::Integer(pk))) => assert_eq!(pk, 2),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        assert_eq!(count_matches(&index_reader, &Query::MultiTerm {
+            field: title_field,
+            term_selector: MultiTermSelector::Prefix("h".to_string()),
+            scorer: TermScorer::default(),
+        }), 3);
+    }
+
+    #[test]
+    fn test_update_and_delete() {
+        let mut store = MemoryStore::new();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let pk_field = store.add_field("pk".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        store.insert_or_update_documents(&[
+            make_doc("doc_a", title_field, "hello", pk_field, 1),
+            make_doc("doc_b", title_field, "hello", pk_field, 2),
+            make_doc("doc_a", title_field, "world", pk_field, 3),
+        ], &[None; 3]).unwrap();
+
+        let index_reader = store.reader();
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "hello")), 1);
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "world")), 1);
+
+        assert!(store.remove_document_by_key("doc_b", None).unwrap().is_some());
+        assert!(store.remove_document_by_key("doc_b", None).unwrap().is_none());
+
+        // The reader that was created before the delete shouldn't see it
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "hello")), 1);
+        assert!(index_reader.contains_document_key("doc_b"));
+
+        let index_reader = store.reader();
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "hello")), 0);
+        assert!(!index_reader.contains_document_key("doc_b"));
+        assert!(index_reader.contains_document_key("doc_a"));
+    }
+
+    #[test]
+    fn test_document_versions() {
+        let mut store = MemoryStore::new();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let pk_field = store.add_field("pk".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        let version = store.insert_or_update_document(&make_doc("doc_a", title_field, "hello", pk_field, 1), None).unwrap();
+        assert_eq!((version.version, version.seq_no), (1, 0));
+
+        // Documents must be at the version given in the condition
+        match store.insert_or_update_document(&make_doc("doc_a", title_field, "world", pk_field, 1), Some(VersionCondition::Internal(2))) {
+            Err(DocumentWriteError::VersionConflict(conflict)) => assert_eq!(conflict.current, Some(version)),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let version = store.insert_or_update_document(&make_doc("doc_a", title_field, "world", pk_field, 1), Some(VersionCondition::Internal(1))).unwrap();
+        assert_eq!((version.version, version.seq_no), (2, 1));
+
+        // Later documents in a batch see the versions given to earlier ones
+        let results = store.insert_or_update_documents(&[
+            make_doc("doc_b", title_field, "hello", pk_field, 2),
+            make_doc("doc_b", title_field, "hello", pk_field, 2),
+            make_doc("doc_b", title_field, "world", pk_field, 2),
+        ], &[None, Some(VersionCondition::External(5)), Some(VersionCondition::External(5))]).unwrap();
+        assert_eq!(results[0].as_ref().map(|version| version.version), Ok(1));
+        assert_eq!(results[1].as_ref().map(|version| version.version), Ok(5));
+        assert!(results[2].is_err());
+
+        // The document that conflicted wasn't written
+        let index_reader = store.reader();
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "world")), 1);
+        assert_eq!(index_reader.get_document_version("doc_b").map(|version| (version.version, version.seq_no)), Some((5, 3)));
+
+        // Deletes check their condition and give the deletion a version
+        assert!(store.remove_document_by_key("doc_b", Some(VersionCondition::Internal(4))).is_err());
+        let version = store.remove_document_by_key("doc_b", Some(VersionCondition::Internal(5))).unwrap().unwrap();
+        assert_eq!((version.version, version.seq_no), (6, 4));
+        assert!(store.reader().get_document_version("doc_b").is_none());
+
+        // External versions from before the deletion conflict, later writes carry on from it
+        assert!(store.insert_or_update_document(&make_doc("doc_b", title_field, "hello", pk_field, 2), Some(VersionCondition::External(5))).is_err());
+        let version = store.insert_or_update_document(&make_doc("doc_b", title_field, "hello", pk_field, 2), None).unwrap();
+        assert_eq!((version.version, version.seq_no), (7, 5));
+
+        // Only the first of two creates of the same document succeeds
+        let results = store.insert_or_update_documents(&[
+            make_doc("doc_c", title_field, "hello", pk_field, 3),
+            make_doc("doc_c", title_field, "world", pk_field, 3),
+        ], &[Some(VersionCondition::MustNotExist), Some(VersionCondition::MustNotExist)]).unwrap();
+        assert_eq!(results[0].as_ref().map(|version| version.version), Ok(1));
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn test_segments_are_merged() {
+        let mut store = MemoryStore::new();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let pk_field = store.add_field("pk".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        for i in 0..100 {
+            let title = if i % 2 == 0 { "hello even" } else { "hello odd" };
+            store.insert_or_update_document(&make_doc(&format!("doc_{}", i), title_field, title, pk_field, i), None).unwrap();
+        }
+
+        // Overwrite and delete some of the documents, the merges must leave the old copies out
+        for i in 0..10 {
+            store.insert_or_update_document(&make_doc(&format!("doc_{}", i), title_field, "goodbye", pk_field, i + 100), None).unwrap();
+        }
+
+        for i in 10..20 {
+            store.remove_document_by_key(&format!("doc_{}", i), None).unwrap();
+        }
+
+        let index_reader = store.reader();
+        assert!(index_reader.index.segments.len() < MERGE_FACTOR);
+
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "hello")), 80);
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "even")), 40);
+        assert_eq!(count_matches(&index_reader, &term_query(title_field, "goodbye")), 10);
+
+        // Term positions are moved with their documents
+        assert_eq!(count_matches(&index_reader, &Query::Phrase {
+            field: title_field,
+            terms: vec![(Term::from_string("hello"), 0), (Term::from_string("odd"), 1)],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }), 40);
+
+        // Terms that share an offset are synonyms, only one of them needs to be in the document
+        assert_eq!(count_matches(&index_reader, &Query::Phrase {
+            field: title_field,
+            terms: vec![(Term::from_string("hello"), 0), (Term::from_string("hi"), 0), (Term::from_string("odd"), 1)],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }), 40);
+        assert_eq!(count_matches(&index_reader, &Query::Phrase {
+            field: title_field,
+            terms: vec![(Term::from_string("hello"), 0), (Term::from_string("even"), 1), (Term::from_string("odd"), 1)],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }), 80);
+
+        // Keys point to the merged documents
+        assert!(!index_reader.contains_document_key("doc_15"));
+        assert_eq!(index_reader.get_document_version("doc_5").map(|version| version.version), Some(2));
+        for &(key, expected_pk) in [("doc_5", 105), ("doc_50", 50), ("doc_99", 99)].iter() {
+            let doc_id = index_reader.get_document_by_key(key).unwrap();
+            match index_reader.read_stored_field(pk_field, doc_id) {
+                Ok(Some(FieldValue::Integer(pk))) => assert_eq!(pk, expected_pk),
+                result => panic!("unexpected result: {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_readers_share_unchanged_data() {
+        let mut store = MemoryStore::new();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let pk_field = store.add_field("pk".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        store.insert_or_update_document(&make_doc("doc_a", title_field, "hello", pk_field, 1), None).unwrap();
+        let old_reader = store.reader();
+
+        store.insert_or_update_document(&make_doc("doc_b", title_field, "world", pk_field, 2), None).unwrap();
+        let new_reader = store.reader();
+
+        // The write didn't copy the term dictionary or the segment that was already there
+        assert!(Arc::ptr_eq(&old_reader.index.term_dictionary, &new_reader.index.term_dictionary));
+        let segment = (old_reader.get_document_by_key("doc_a").unwrap().0).0;
+        assert!(Arc::ptr_eq(&old_reader.index.segments[&segment], &new_reader.index.segments[&segment]));
+
+        // The old reader can see the new term but not the document that was written with it
+        assert!(old_reader.get_term_id(&Term::from_string("world")).is_some());
+        assert_eq!(count_matches(&old_reader, &term_query(title_field, "world")), 0);
+        assert!(!old_reader.contains_document_key("doc_b"));
+        assert_eq!(count_matches(&new_reader, &term_query(title_field, "world")), 1);
+    }
+
+    /// Counts documents, and asks the search to stop once it has been checked a number of times
+    ///
+    /// This behaves like a deadline that passes part way through the search, without the timing.
+    struct StopAfterChecksCollector {
+        remaining_checks: usize,
+        checks: usize,
+        total_count: u64,
+    }
+
+    impl Collector for StopAfterChecksCollector {
+        fn needs_score(&self) -> bool {
+            false
+        }
+
+        fn collect(&mut self, _doc: DocumentMatch) {
+            self.total_count += 1;
+        }
+
+        fn should_terminate(&mut self) -> bool {
+            self.checks += 1;
+
+            if self.remaining_checks == 0 {
+                return true;
+            }
+
+            self.remaining_checks -= 1;
+            false
+        }
+    }
+
+    #[test]
+    fn test_prefix_query_times_out() {
+        let mut store = MemoryStore::new();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+        let pk_field = store.add_field("pk".to_string(), FieldType::I64, FIELD_STORED).unwrap();
+
+        let docs = (0..1000).map(|i| make_doc(&format!("doc_{}", i), title_field, &format!("term{}", i), pk_field, i)).collect::<Vec<_>>();
+        store.insert_or_update_documents(&docs, &vec![None; docs.len()]).unwrap();
+
+        let query = Query::MultiTerm {
+            field: title_field,
+            term_selector: MultiTermSelector::Prefix("term".to_string()),
+            scorer: TermScorer::default(),
+        };
+
+        let index_reader = store.reader();
+        assert_eq!(count_matches(&index_reader, &query), 1000);
+
+        // The search stops while the prefix is being expanded, instead of after all 1000 terms
+        // have been selected and their postings lists combined
+        let mut collector = StopAfterChecksCollector {
+            remaining_checks: 50,
+            checks: 0,
+            total_count: 0,
+        };
+        index_reader.search(&mut collector, &query).unwrap();
+        assert_eq!(collector.total_count, 0);
+        assert!(collector.checks < 100);
+
+        // Nothing is found once the deadline has passed
+        let mut total_count = TotalCountCollector::new();
+        let mut deadline = DeadlineCollector::new(Instant::now());
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut total_count);
+            collector.add(&mut deadline);
+            index_reader.search(&mut collector, &query).unwrap();
+        }
+
+        assert_eq!(total_count.get_total_count(), 0);
+        assert!(deadline.timed_out());
+    }
+}
@@ -1 +1,156 @@
-pub mod rocksdb;
\ No newline at end of file
+pub mod rocksdb;
+pub mod memory;
+
+use std::str;
+use std::fmt;
+use std::path::Path;
+
+use search::Document;
+use search::document::{DocId, FieldValue};
+use search::schema::{Schema, FieldType, FieldFlags, FieldId, AddFieldError};
+use search::query::Query;
+use search::explanation::Explanation;
+use search::version::{DocumentVersion, VersionCondition, VersionConflict};
+use search::collectors::{Collector, FieldValueReader};
+use search::execution::filter_cache::FilterCacheStats;
+use byteorder::{ByteOrder, LittleEndian};
+use chrono::{NaiveDateTime, DateTime, Utc};
+
+use self::rocksdb::RocksDBStore;
+
+/// Where the documents of an index are kept
+///
+/// Writes go straight to the store, reads go through a Reader so that each request sees
+/// the index as it was when the request started.
+pub trait Store: fmt::Debug + Send + Sync {
+    /// Returns a reader over the current contents of the store
+    fn reader<'a>(&'a self) -> Box<Reader + 'a>;
+
+    /// Returns the directory the store is kept in, or None if the store is only held in memory
+    fn path(&self) -> Option<&Path>;
+
+    fn add_field(&mut self, name: String, field_type: FieldType, field_flags: FieldFlags) -> Result<FieldId, AddFieldError>;
+
+    fn remove_field(&mut self, field_id: &FieldId) -> bool;
+
+    /// Inserts or updates a document, returns its new version
+    fn insert_or_update_document(&self, doc: &Document, condition: Option<VersionCondition>) -> Result<DocumentVersion, DocumentWriteError>;
+
+    /// Inserts or updates many documents at once
+    ///
+    /// Each document may have a condition, at the same position in "conditions". Documents that don't meet
+    /// their condition aren't written. Returns the new version or the conflict of each document, in order.
+    fn insert_or_update_documents(&self, docs: &[Document], conditions: &[Option<VersionCondition>]) -> Result<Vec<Result<DocumentVersion, VersionConflict>>, String>;
+
+    /// Deletes a document, returns the version of the deletion or None if there was no document with the key
+    fn remove_document_by_key(&self, doc_key: &str, condition: Option<VersionCondition>) -> Result<Option<DocumentVersion>, DocumentWriteError>;
+
+    /// Returns the hit and miss counters of the store's filter cache
+    fn filter_cache_stats(&self) -> FilterCacheStats;
+
+    /// Returns the store as a RocksDBStore if it is one
+    ///
+    /// Segments are only merged in RocksDB stores, the maintenance task uses this to find them.
+    fn as_rocksdb(&self) -> Option<&RocksDBStore> {
+        None
+    }
+}
+
+/// Reads documents from a store
+pub trait Reader: FieldValueReader {
+    fn schema(&self) -> &Schema;
+
+    fn contains_document_key(&self, doc_key: &str) -> bool;
+
+    fn get_document_by_key(&self, doc_key: &str) -> Option<DocId>;
+
+    /// Returns the current version of a document, or None if there's no document with the key
+    fn get_document_version(&self, doc_key: &str) -> Option<DocumentVersion>;
+
+    fn read_stored_field(&self, field_id: FieldId, doc_id: DocId) -> Result<Option<FieldValue>, StoredFieldReadError>;
+
+    /// Runs a query, passing each matching document to the collector
+    fn search(&self, collector: &mut Collector, query: &Query) -> Result<(), String>;
+
+    /// Explains how the score of a document was calculated
+    ///
+    /// Returns None if the document doesn't match the query
+    fn explain(&self, query: &Query, doc_id: DocId) -> Result<Option<Explanation>, String>;
+}
+
+#[derive(Debug)]
+pub enum DocumentWriteError {
+    /// The document didn't meet the version condition of the write
+    VersionConflict(VersionConflict),
+
+    /// The store failed to write the document
+    StorageError(String),
+}
+
+impl From<VersionConflict> for DocumentWriteError {
+    fn from(e: VersionConflict) -> DocumentWriteError {
+        DocumentWriteError::VersionConflict(e)
+    }
+}
+
+#[derive(Debug)]
+pub enum StoredFieldReadError {
+    /// The provided FieldId wasn't valid for this index
+    InvalidFieldId(FieldId),
+
+    /// The store failed to read the value
+    StorageError(String),
+
+    /// A UTF-8 decode error occured while reading a Text field
+    TextFieldUTF8DecodeError(Vec<u8>, str::Utf8Error),
+
+    /// A boolean field was read but the value wasn't a boolean
+    BooleanFieldDecodeError(Vec<u8>),
+
+    /// An integer/datetime field was read but the value wasn't 8 bytes
+    IntegerFieldValueSizeError(usize),
+}
+
+/// Decodes a stored value that was encoded with "FieldValue::to_bytes"
+pub fn decode_stored_field_value(field_type: &FieldType, value: &[u8]) -> Result<FieldValue, StoredFieldReadError> {
+    match *field_type {
+        FieldType::Text | FieldType::PlainString => {
+            match str::from_utf8(value) {
+                Ok(value_str) => {
+                    Ok(FieldValue::String(value_str.to_string()))
+                }
+                Err(e) => {
+                    Err(StoredFieldReadError::TextFieldUTF8DecodeError(value.to_vec(), e))
+                }
+            }
+        }
+        FieldType::I64 => {
+            if value.len() != 8 {
+                return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()));
+            }
+
+            Ok(FieldValue::Integer(LittleEndian::read_i64(value)))
+        }
+        FieldType::Boolean => {
+            if value[..] == [b't'] {
+                Ok(FieldValue::Boolean(true))
+            } else if value[..] == [b'f'] {
+                Ok(FieldValue::Boolean(false))
+            } else {
+                Err(StoredFieldReadError::BooleanFieldDecodeError(value.to_vec()))
+            }
+        }
+        FieldType::DateTime => {
+            if value.len() != 8 {
+                return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()))
+            }
+
+            let timestamp_with_micros = LittleEndian::read_i64(value);
+            let timestamp = timestamp_with_micros / 1000000;
+            let micros = timestamp_with_micros % 1000000;
+            let nanos = micros * 1000;
+            let datetime = NaiveDateTime::from_timestamp(timestamp, nanos as u32);
+            Ok(FieldValue::DateTime(DateTime::from_utc(datetime, Utc)))
+        }
+    }
+}
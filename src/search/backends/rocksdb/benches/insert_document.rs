@@ -51,7 +51,7 @@ fn bench_insert_single_document(b: &mut Bencher) {
             key: i.to_string(),
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
-        });
+        }, None);
     });
 }
 
@@ -90,7 +90,7 @@ fn bench_insert_documents_parallel(b: &mut Bencher) {
 
     b.iter(move|| {
         docs.par_iter().for_each(|doc| {
-            store.insert_or_update_document(doc);
+            store.insert_or_update_document(doc, None);
         });
     });
 }
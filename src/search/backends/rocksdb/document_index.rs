@@ -2,33 +2,119 @@ use std::sync::RwLock;
 use std::collections::HashMap;
 use std::io::Cursor;
 
-use rocksdb::{self, DB, WriteBatch};
+use rocksdb::{self, DB, WriteBatch, WriteOptions};
 use roaring::RoaringBitmap;
 use search::document::DocId;
 use search::segment::SegmentId;
+use search::version::{DocumentVersion, VersionCondition, VersionConflict, next_version, PRIMARY_TERM};
+use search::backends::DocumentWriteError;
 use byteorder::{ByteOrder, LittleEndian};
 use fnv::FnvHashMap;
 
 use super::key_builder::KeyBuilder;
 use super::segment_ops::SegmentMergeError;
 
+/// Encodes a document id into the value of a primary key ("k") key
+/// This is the segment id followed by the local document id, both as little endian u32s
+pub fn encode_doc_id(doc_id: DocId) -> [u8; 8] {
+    let mut doc_id_bytes = [0; 8];
+    LittleEndian::write_u32(&mut doc_id_bytes, (doc_id.0).0);
+    LittleEndian::write_u32(&mut doc_id_bytes[4..], doc_id.1);
+    doc_id_bytes
+}
+
+/// Decodes a document id that was encoded by "encode_doc_id"
+pub fn decode_doc_id(doc_id_bytes: &[u8]) -> DocId {
+    let segment = LittleEndian::read_u32(&doc_id_bytes[0..4]);
+    let local_id = LittleEndian::read_u32(&doc_id_bytes[4..8]);
+    DocId(SegmentId(segment), local_id)
+}
+
+/// Encodes the value of a primary key ("k") key
+/// This is the document id (see "encode_doc_id") followed by the version, sequence number and
+/// primary term of the document as little endian u64s
+pub fn encode_primary_key_value(doc_id: DocId, version: DocumentVersion) -> [u8; 32] {
+    let mut value = [0; 32];
+    value[0..8].copy_from_slice(&encode_doc_id(doc_id));
+    LittleEndian::write_u64(&mut value[8..16], version.version);
+    LittleEndian::write_u64(&mut value[16..24], version.seq_no);
+    LittleEndian::write_u64(&mut value[24..32], version.primary_term);
+    value
+}
+
+/// Decodes the value of a primary key that was encoded by "encode_primary_key_value"
+pub fn decode_primary_key_value(value: &[u8]) -> (DocId, DocumentVersion) {
+    let version = DocumentVersion {
+        version: LittleEndian::read_u64(&value[8..16]),
+        seq_no: LittleEndian::read_u64(&value[16..24]),
+        primary_term: LittleEndian::read_u64(&value[24..32]),
+    };
+
+    (decode_doc_id(&value[0..8]), version)
+}
+
+/// Encodes the value of a deleted key ("r") key
+/// This is the version, sequence number and primary term of the deletion as little endian u64s
+pub fn encode_deleted_key_value(version: DocumentVersion) -> [u8; 24] {
+    let mut value = [0; 24];
+    LittleEndian::write_u64(&mut value[0..8], version.version);
+    LittleEndian::write_u64(&mut value[8..16], version.seq_no);
+    LittleEndian::write_u64(&mut value[16..24], version.primary_term);
+    value
+}
+
+/// Decodes the value of a deleted key that was encoded by "encode_deleted_key_value"
+pub fn decode_deleted_key_value(value: &[u8]) -> DocumentVersion {
+    DocumentVersion {
+        version: LittleEndian::read_u64(&value[0..8]),
+        seq_no: LittleEndian::read_u64(&value[8..16]),
+        primary_term: LittleEndian::read_u64(&value[16..24]),
+    }
+}
+
+/// Reads the sequence number that will be given to the next write
+pub fn read_next_seq_no(db: &DB) -> Result<u64, rocksdb::Error> {
+    match try!(db.get(b".next_seq_no")) {
+        Some(next_seq_no) => Ok(next_seq_no.to_utf8().unwrap().parse::<u64>().unwrap()),
+        None => Ok(0),
+    }
+}
+
+/// The primary key index, along with the sequence number of the next write
+/// These are locked together so sequence numbers are assigned in the same order the writes are made
+struct PrimaryKeyIndex {
+    keys: HashMap<Vec<u8>, (DocId, DocumentVersion)>,
+
+    /// The version of the deletion of each key that has been deleted and not written since
+    ///
+    /// These are kept so that the versions of a document carry on from its deletion when it's
+    /// written again, otherwise writes with old external versions would be accepted.
+    deleted_keys: HashMap<Vec<u8>, DocumentVersion>,
+
+    next_seq_no: u64,
+}
+
 /// Manages the index's "document index"
 pub struct DocumentIndexManager {
-    primary_key_index: RwLock<HashMap<Vec<u8>, DocId>>,
+    primary_key_index: RwLock<PrimaryKeyIndex>,
 }
 
 impl DocumentIndexManager {
     /// Generates a new document index
     pub fn new(_db: &DB) -> Result<DocumentIndexManager, rocksdb::Error> {
         Ok(DocumentIndexManager {
-            primary_key_index: RwLock::new(HashMap::new()),
+            primary_key_index: RwLock::new(PrimaryKeyIndex {
+                keys: HashMap::new(),
+                deleted_keys: HashMap::new(),
+                next_seq_no: 0,
+            }),
         })
     }
 
     /// Loads the document index from an index
     pub fn open(db: &DB) -> Result<DocumentIndexManager, rocksdb::Error> {
         // Read primary key index
-        let mut primary_key_index = HashMap::new();
+        let mut keys = HashMap::new();
         let mut iter = db.raw_iterator();
         iter.seek(b"k");
         while iter.valid() {
@@ -38,25 +124,40 @@ impl DocumentIndexManager {
                 break;
             }
 
-            let v = iter.value().unwrap();
-            let segment = LittleEndian::read_u32(&v[0..4]);
-            let ord = LittleEndian::read_u16(&v[4..6]);
-            let doc_id = DocId(SegmentId(segment), ord);
+            keys.insert(k[1..].to_vec(), decode_primary_key_value(&iter.value().unwrap()));
+
+            iter.next();
+        }
+
+        // Read deleted keys
+        let mut deleted_keys = HashMap::new();
+        let mut iter = db.raw_iterator();
+        iter.seek(b"r");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'r' {
+                break;
+            }
 
-            primary_key_index.insert(k[1..].to_vec(), doc_id);
+            deleted_keys.insert(k[1..].to_vec(), decode_deleted_key_value(&iter.value().unwrap()));
 
             iter.next();
         }
 
         Ok(DocumentIndexManager {
-            primary_key_index: RwLock::new(primary_key_index),
+            primary_key_index: RwLock::new(PrimaryKeyIndex {
+                keys: keys,
+                deleted_keys: deleted_keys,
+                next_seq_no: try!(read_next_seq_no(db)),
+            }),
         })
     }
 
     fn delete_document_by_id_unchecked(&self, write_batch: &mut WriteBatch, doc_id: DocId) -> Result<(), rocksdb::Error> {
         let kb = KeyBuilder::segment_del_list((doc_id.0).0);
-        let mut previous_doc_id_bytes = [0; 2];
-        LittleEndian::write_u16(&mut previous_doc_id_bytes, doc_id.1);
+        let mut previous_doc_id_bytes = [0; 4];
+        LittleEndian::write_u32(&mut previous_doc_id_bytes, doc_id.1);
         try!(write_batch.merge(&kb.key(), &previous_doc_id_bytes));
 
         // Increment deleted docs
@@ -68,70 +169,154 @@ impl DocumentIndexManager {
         Ok(())
     }
 
-    pub fn insert_or_replace_key(&self, db: &DB, key: &Vec<u8>, doc_id: DocId) -> Result<Option<DocId>, rocksdb::Error> {
-        // Update primary_key_index
-        let mut write_batch = WriteBatch::default();
-        let previous_doc_id = self.primary_key_index.write().unwrap().insert(key.clone(), doc_id);
+    /// Points each of the keys at their new documents, deleting any documents they previously pointed to
+    ///
+    /// The changes are added to the provided write batch, which is then written. This allows the
+    /// primary keys to be updated atomically with the segment that contains the new documents.
+    ///
+    /// Keys that don't meet their version condition are left alone, and their new documents are deleted.
+    /// Returns the new version or the conflict of each key, in order.
+    pub fn insert_or_replace_keys(&self, db: &DB, mut write_batch: WriteBatch, keys: Vec<(Vec<u8>, DocId, Option<VersionCondition>)>) -> Result<Vec<Result<DocumentVersion, VersionConflict>>, rocksdb::Error> {
+        // Lock the primary key index. This prevents the previous documents being changed by
+        // another thread until our write batch has been written
+        let mut primary_key_index = self.primary_key_index.write().unwrap();
+        let mut next_seq_no = primary_key_index.next_seq_no;
+
+        // Keys may be repeated, in which case the last document wins
+        let mut new_entries: HashMap<Vec<u8>, (DocId, DocumentVersion)> = HashMap::with_capacity(keys.len());
+        let mut results = Vec::with_capacity(keys.len());
+
+        for (key, doc_id, condition) in keys {
+            let previous_entry = match new_entries.get(&key) {
+                Some(previous_entry) => Some(*previous_entry),
+                None => primary_key_index.keys.get(&key).cloned(),
+            };
+
+            // Keys that are written in this batch are no longer deleted
+            let deleted = if new_entries.contains_key(&key) {
+                None
+            } else {
+                primary_key_index.deleted_keys.get(&key).cloned()
+            };
+
+            let version = match next_version(previous_entry.map(|(_, version)| version), deleted, condition) {
+                Ok(version) => version,
+                Err(conflict) => {
+                    // The new document has already been written into the segment, so it must be deleted
+                    try!(self.delete_document_by_id_unchecked(&mut write_batch, doc_id));
+                    results.push(Err(conflict));
+                    continue;
+                }
+            };
 
-        let kb = KeyBuilder::primary_key_index(key);
-        let mut doc_id_bytes = [0; 6];
-        LittleEndian::write_u32(&mut doc_id_bytes, (doc_id.0).0);
-        LittleEndian::write_u16(&mut doc_id_bytes[4..], doc_id.1);
-        try!(write_batch.put(&kb.key(), &doc_id_bytes));
+            let version = DocumentVersion {
+                version: version,
+                seq_no: next_seq_no,
+                primary_term: PRIMARY_TERM,
+            };
+            next_seq_no += 1;
+
+            let kb = KeyBuilder::primary_key_index(&key);
+            try!(write_batch.put(&kb.key(), &encode_primary_key_value(doc_id, version)));
 
-        // If there was a document there previously, delete it
-        if let Some(previous_doc_id) = previous_doc_id {
-            try!(self.delete_document_by_id_unchecked(&mut write_batch, previous_doc_id));
+            if deleted.is_some() {
+                let kb = KeyBuilder::deleted_key(&key);
+                try!(write_batch.delete(&kb.key()));
+            }
+
+            // If there was a document there previously, delete it
+            if let Some((previous_doc_id, _)) = previous_entry {
+                try!(self.delete_document_by_id_unchecked(&mut write_batch, previous_doc_id));
+            }
+
+            new_entries.insert(key, (doc_id, version));
+            results.push(Ok(version));
         }
 
+        try!(write_batch.put(b".next_seq_no", next_seq_no.to_string().as_bytes()));
+
         // Write document data
         try!(db.write(write_batch));
 
-        Ok(previous_doc_id)
+        // Update primary_key_index
+        for (key, entry) in new_entries {
+            primary_key_index.deleted_keys.remove(&key);
+            primary_key_index.keys.insert(key, entry);
+        }
+        primary_key_index.next_seq_no = next_seq_no;
+
+        Ok(results)
     }
 
-    pub fn delete_document_by_key(&self, db: &DB, key: &Vec<u8>) -> Result<Option<DocId>, rocksdb::Error> {
-        // Remove document from index
-        let doc_id = self.primary_key_index.write().unwrap().remove(key);
+    /// Deletes the document with the key, returns the version of the deletion or None if there was no document
+    ///
+    /// The version of the deletion is kept until the key is written again.
+    pub fn delete_document_by_key(&self, db: &DB, key: &Vec<u8>, condition: Option<VersionCondition>) -> Result<Option<DocumentVersion>, DocumentWriteError> {
+        let mut primary_key_index = self.primary_key_index.write().unwrap();
 
-        if let Some(doc_id) = doc_id {
-            let mut write_batch = WriteBatch::default();
+        let entry = primary_key_index.keys.get(key).cloned();
+        let deleted = primary_key_index.deleted_keys.get(key).cloned();
+        let version = try!(next_version(entry.map(|(_, version)| version), deleted, condition));
 
-            try!(self.delete_document_by_id_unchecked(&mut write_batch, doc_id));
+        let doc_id = match entry {
+            Some((doc_id, _)) => doc_id,
+            None => return Ok(None),
+        };
 
-            try!(db.write(write_batch));
-        }
+        let version = DocumentVersion {
+            version: version,
+            seq_no: primary_key_index.next_seq_no,
+            primary_term: PRIMARY_TERM,
+        };
+
+        let mut write_batch = WriteBatch::default();
+        try!(self.delete_document_by_id_unchecked(&mut write_batch, doc_id));
+        try!(write_batch.delete(&KeyBuilder::primary_key_index(key).key()));
+        try!(write_batch.put(&KeyBuilder::deleted_key(key).key(), &encode_deleted_key_value(version)));
+        try!(write_batch.put(b".next_seq_no", (version.seq_no + 1).to_string().as_bytes()));
+        try!(db.write(write_batch));
 
-        Ok(doc_id)
+        // Remove document from index
+        primary_key_index.keys.remove(key);
+        primary_key_index.deleted_keys.insert(key.clone(), version);
+        primary_key_index.next_seq_no += 1;
+
+        Ok(Some(version))
     }
 
     pub fn contains_document_key(&self, key: &Vec<u8>) -> bool {
-        self.primary_key_index.read().unwrap().contains_key(key)
+        self.primary_key_index.read().unwrap().keys.contains_key(key)
+    }
+
+    pub fn get_document_by_key(&self, key: &Vec<u8>) -> Option<DocId> {
+        self.primary_key_index.read().unwrap().keys.get(key).map(|&(doc_id, _)| doc_id)
     }
 
-    pub fn commit_segment_merge(&self, db: &DB, mut write_batch: WriteBatch, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u16>) -> Result<(), SegmentMergeError> {
+    pub fn get_document_version(&self, key: &Vec<u8>) -> Option<DocumentVersion> {
+        self.primary_key_index.read().unwrap().keys.get(key).map(|&(_, version)| version)
+    }
+
+    pub fn commit_segment_merge(&self, db: &DB, mut write_batch: WriteBatch, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u32>) -> Result<(), SegmentMergeError> {
         // Lock the primary key index
         let mut primary_key_index = self.primary_key_index.write().unwrap();
 
         // Update primary keys to point to their new locations
-        let mut keys_to_update: HashMap<Vec<u8>, DocId> = HashMap::with_capacity(doc_id_mapping.len());
-        for (key, doc_id) in primary_key_index.iter() {
+        // Moving a document doesn't change it, so it keeps its version
+        let mut keys_to_update: HashMap<Vec<u8>, (DocId, DocumentVersion)> = HashMap::with_capacity(doc_id_mapping.len());
+        for (key, &(doc_id, version)) in primary_key_index.keys.iter() {
             if doc_id_mapping.contains_key(&doc_id) {
-                keys_to_update.insert(key.clone(), *doc_id);
+                keys_to_update.insert(key.clone(), (doc_id, version));
             }
         }
 
-        for (key, doc_id) in keys_to_update {
+        for (key, (doc_id, version)) in keys_to_update {
             let new_doc_local_id = doc_id_mapping.get(&doc_id).unwrap();
             let new_doc_id = DocId(SegmentId(dest_segment), *new_doc_local_id);
 
             let kb = KeyBuilder::primary_key_index(&key);
-            let mut doc_id_bytes = [0; 6];
-            LittleEndian::write_u32(&mut doc_id_bytes, (new_doc_id.0).0);
-            LittleEndian::write_u16(&mut doc_id_bytes[4..], new_doc_id.1);
-            try!(write_batch.put(&kb.key(), &doc_id_bytes));
+            try!(write_batch.put(&kb.key(), &encode_primary_key_value(new_doc_id, version)));
 
-            primary_key_index.insert(key, new_doc_id);
+            primary_key_index.keys.insert(key, (new_doc_id, version));
         }
 
         // Merge deletion lists
@@ -143,9 +328,11 @@ impl DocumentIndexManager {
                 Some(bitmap) => {
                     let bitmap = RoaringBitmap::deserialize_from(Cursor::new(&bitmap[..])).unwrap();
                     for doc_id in bitmap.iter() {
-                        let doc_id = DocId(SegmentId(*source_segment), doc_id as u16);
-                        let new_doc_id = doc_id_mapping.get(&doc_id).unwrap();
-                        deletion_list.insert(*new_doc_id as u32);
+                        // Documents that were deleted before the merge started weren't copied
+                        let doc_id = DocId(SegmentId(*source_segment), doc_id);
+                        if let Some(new_doc_id) = doc_id_mapping.get(&doc_id) {
+                            deletion_list.insert(*new_doc_id);
+                        }
                     }
                 }
                 None => {},
@@ -156,10 +343,19 @@ impl DocumentIndexManager {
         deletion_list.serialize_into(&mut dl_vec).unwrap();
 
         let kb = KeyBuilder::segment_del_list(dest_segment);
-        try!(db.put(&kb.key(), &dl_vec));
+        try!(write_batch.put(&kb.key(), &dl_vec));
+
+        // Set the number of deleted docs in the new segment
+        let kb = KeyBuilder::segment_stat(dest_segment, b"deleted_docs");
+        let mut deleted_docs_bytes = [0; 8];
+        LittleEndian::write_i64(&mut deleted_docs_bytes, deletion_list.len() as i64);
+        try!(write_batch.put(&kb.key(), &deleted_docs_bytes));
 
         // Commit!
-        try!(db.write_without_wal(write_batch));
+        // This is synced, so the merged data written before it is durable once the segment is active
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(true);
+        try!(db.write_opt(write_batch, &write_options));
 
         Ok(())
     }
@@ -15,7 +15,7 @@ impl KeyBuilder {
         }
     }
 
-    pub fn stored_field_value(segment: u32, doc_local_id: u16, field_id: u32, value_type: &[u8]) -> KeyBuilder {
+    pub fn stored_field_value(segment: u32, doc_local_id: u32, field_id: u32, value_type: &[u8]) -> KeyBuilder {
         let mut kb = KeyBuilder::new();
         kb.push_char(b'v');
         kb.push_string(segment.to_string().as_bytes());
@@ -43,6 +43,14 @@ impl KeyBuilder {
         kb
     }
 
+    /// Records the version of the deletion of the document with the key
+    pub fn deleted_key(key: &[u8]) -> KeyBuilder {
+        let mut kb = KeyBuilder::with_capacity(1 + key.len());
+        kb.push_char(b'r');
+        kb.push_string(key);
+        kb
+    }
+
     pub fn term_dict_mapping(term: &[u8]) -> KeyBuilder {
         let mut kb = KeyBuilder::with_capacity(1 + term.len());
         kb.push_char(b't');
@@ -50,6 +58,21 @@ impl KeyBuilder {
         kb
     }
 
+    pub fn field_term_dict_prefix(field_id: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'f');
+        kb.push_string(field_id.to_string().as_bytes());
+        kb.separator();
+        kb
+    }
+
+    /// The term is the last part of the key so it isn't escaped, this keeps the keys in term order
+    pub fn field_term_dict_mapping(field_id: u32, term: &[u8]) -> KeyBuilder {
+        let mut kb = KeyBuilder::field_term_dict_prefix(field_id);
+        kb.key.extend(term);
+        kb
+    }
+
     pub fn segment_active(segment: u32) -> KeyBuilder {
         let mut kb = KeyBuilder::new();
         kb.push_char(b'a');
@@ -57,6 +80,22 @@ impl KeyBuilder {
         kb
     }
 
+    /// Records that a merge into the segment has started but not yet been committed
+    pub fn segment_merge_intent(segment: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'm');
+        kb.push_string(segment.to_string().as_bytes());
+        kb
+    }
+
+    /// Records that the segment has been deactivated but its data hasn't been purged yet
+    pub fn segment_purge_pending(segment: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'p');
+        kb.push_string(segment.to_string().as_bytes());
+        kb
+    }
+
     pub fn segment_postings_list(segment: u32, field_id: u32, term_id: u32) -> KeyBuilder {
         let mut kb = KeyBuilder::new();
         kb.push_char(b'd');
@@ -68,6 +107,30 @@ impl KeyBuilder {
         kb
     }
 
+    /// Term frequencies are keyed in the same order as postings lists (field/term/segment)
+    pub fn segment_term_frequencies(segment: u32, field_id: u32, term_id: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'q');
+        kb.push_string(field_id.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(term_id.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(segment.to_string().as_bytes());
+        kb
+    }
+
+    /// Term impacts are keyed in the same order as postings lists (field/term/segment)
+    pub fn segment_term_impact(segment: u32, field_id: u32, term_id: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'u');
+        kb.push_string(field_id.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(term_id.to_string().as_bytes());
+        kb.separator();
+        kb.push_string(segment.to_string().as_bytes());
+        kb
+    }
+
     pub fn segment_stat_prefix(segment: u32) -> KeyBuilder {
         let mut kb = KeyBuilder::new();
         kb.push_char(b's');
@@ -82,55 +145,32 @@ impl KeyBuilder {
         kb
     }
 
-    pub fn segment_stat_term_doc_frequency_stat_name(field_id: u32, term_id: u32) -> Vec<u8> {
-        let mut stat_name = Vec::new();
-        for c in b"tdf" {
-            stat_name.push(*c);
-        }
-
-        stat_name.push(b'-');
-
-        for c in field_id.to_string().as_bytes() {
-            stat_name.push(*c);
-        }
-
-        stat_name.push(b'-');
-
-        for c in term_id.to_string().as_bytes() {
-            stat_name.push(*c);
-        }
-
-        stat_name
+    pub fn segment_doc_values_prefix(segment: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'c');
+        kb.push_string(segment.to_string().as_bytes());
+        kb.separator();
+        kb
     }
 
-    pub fn segment_stat_total_field_tokens_stat_name(field_id: u32) -> Vec<u8> {
-        let mut stat_name = Vec::new();
-        for c in b"fttok" {
-            stat_name.push(*c);
-        }
-
-        stat_name.push(b'-');
-
-        for c in field_id.to_string().as_bytes() {
-            stat_name.push(*c);
-        }
-
-        stat_name
+    pub fn segment_doc_values(segment: u32, field_id: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::segment_doc_values_prefix(segment);
+        kb.push_string(field_id.to_string().as_bytes());
+        kb
     }
 
-    pub fn segment_stat_total_field_docs_stat_name(field_id: u32) -> Vec<u8> {
-        let mut stat_name = Vec::new();
-        for c in b"ftdoc" {
-            stat_name.push(*c);
-        }
-
-        stat_name.push(b'-');
-
-        for c in field_id.to_string().as_bytes() {
-            stat_name.push(*c);
-        }
+    pub fn segment_field_norms_prefix(segment: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::new();
+        kb.push_char(b'n');
+        kb.push_string(segment.to_string().as_bytes());
+        kb.separator();
+        kb
+    }
 
-        stat_name
+    pub fn segment_field_norms(segment: u32, field_id: u32) -> KeyBuilder {
+        let mut kb = KeyBuilder::segment_field_norms_prefix(segment);
+        kb.push_string(field_id.to_string().as_bytes());
+        kb
     }
 
     pub fn segment_del_list(segment: u32) -> KeyBuilder {
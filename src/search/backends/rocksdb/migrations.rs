@@ -0,0 +1,1351 @@
+//! Upgrades the on-disk format of indices created by older versions
+//!
+//! The format version of an index is stored in the ".format_version" key. Indices that
+//! don't have this key were created before it was introduced and are treated as version 1.
+
+use std::str;
+use std::mem;
+use std::io::Cursor;
+use std::path::Path;
+use std::collections::HashMap;
+
+use rocksdb::{self, DB, WriteBatch, Options, MergeOperands};
+use roaring::RoaringBitmap;
+use search::{Term, TermId, DocId};
+use search::schema::FieldType;
+use search::segment::{SegmentId, term_doc_frequency_stat_name, sum_doc_frequency_stat_name, encode_term_frequencies, decode_term_frequencies, TermImpact};
+use search::version::{DocumentVersion, PRIMARY_TERM};
+use byteorder::{ByteOrder, LittleEndian};
+use fnv::{FnvHashMap, FnvHashSet};
+
+use super::{RocksDBStore, merge_keys};
+use super::key_builder::KeyBuilder;
+use super::document_index::{encode_doc_id, decode_doc_id, encode_primary_key_value};
+
+/// The format version of indices created by this version of the store
+pub const CURRENT_FORMAT_VERSION: u32 = 7;
+
+/// The maximum number of keys that a chunked migration moves in each write batch
+const MIGRATION_CHUNK_SIZE: usize = 10000;
+
+/// Keys that are being moved by the version 2 migration are kept under this prefix until
+/// all of the old keys have been removed
+const SORTABLE_INTEGER_TERMS_STAGING_PREFIX: &'static [u8] = b".v2_staging/";
+
+/// Written once the version 2 migration has staged all of the old keys
+const SORTABLE_INTEGER_TERMS_STAGED_KEY: &'static [u8] = b".v2_staged";
+
+/// Reads the format version of an index
+pub fn read_format_version(db: &DB) -> Result<u32, String> {
+    match try!(db.get(b".format_version")) {
+        Some(version) => {
+            match version.to_utf8().and_then(|version| version.parse::<u32>().ok()) {
+                Some(version) => Ok(version),
+                None => Err(format!("unable to read index format version: {:?}", String::from_utf8_lossy(&version))),
+            }
+        }
+        None => Ok(1),
+    }
+}
+
+/// Moves values to new keys, committing them in write batches of a bounded size
+///
+/// Each value is deleted from its old key in the same batch as it's written to the new one, so
+/// after a crash the keys that are left at their old locations are the ones that still need moving.
+struct ChunkedMove<'a> {
+    db: &'a DB,
+    write_batch: WriteBatch,
+    moved_keys: usize,
+}
+
+impl<'a> ChunkedMove<'a> {
+    fn new(db: &'a DB) -> ChunkedMove<'a> {
+        ChunkedMove {
+            db: db,
+            write_batch: WriteBatch::default(),
+            moved_keys: 0,
+        }
+    }
+
+    fn move_value(&mut self, old_key: &[u8], new_key: &[u8], value: &[u8]) -> Result<(), rocksdb::Error> {
+        try!(self.write_batch.delete(old_key));
+        try!(self.write_batch.put(new_key, value));
+        self.moved_keys += 1;
+        Ok(())
+    }
+
+    /// Commits the moves so far if the batch is full
+    ///
+    /// This mustn't be called between moves that depend on each other, as only some of them
+    /// would be committed if there's a crash.
+    fn commit_if_full(&mut self) -> Result<(), rocksdb::Error> {
+        if self.moved_keys >= MIGRATION_CHUNK_SIZE {
+            try!(self.commit());
+        }
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), rocksdb::Error> {
+        let write_batch = mem::replace(&mut self.write_batch, WriteBatch::default());
+        self.moved_keys = 0;
+        self.db.write(write_batch)
+    }
+}
+
+/// Reads a deletion list value that was written before version 4
+///
+/// These were sequences of two byte document ids until they started being stored as roaring
+/// bitmaps during version 2 (the format version wasn't bumped at the time), so indices at version
+/// 1 or 2 may have either. A value is only read as a bitmap if it is one from start to end.
+fn decode_legacy_deletion_list(value: &[u8]) -> RoaringBitmap {
+    let mut cursor = Cursor::new(value);
+    if let Ok(deletion_list) = RoaringBitmap::deserialize_from(&mut cursor) {
+        if cursor.position() == value.len() as u64 {
+            return deletion_list;
+        }
+    }
+
+    value.chunks(2).map(|doc_id| LittleEndian::read_u16(doc_id) as u32).collect()
+}
+
+/// The merge operator of indices before version 4
+/// Deletion list operands were sequences of two byte document ids, everything else is the same
+fn merge_keys_v3(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Option<Vec<u8>> {
+    if key[0] != b'x' {
+        return merge_keys(key, existing_val, operands);
+    }
+
+    let mut deletion_list = match existing_val {
+        Some(existing_val) => decode_legacy_deletion_list(existing_val),
+        None => RoaringBitmap::new(),
+    };
+
+    for op in operands {
+        for doc_id in op.chunks(2) {
+            deletion_list.insert(LittleEndian::read_u16(doc_id) as u32);
+        }
+    }
+
+    let mut new_val = Vec::new();
+    deletion_list.serialize_into(&mut new_val).unwrap();
+    Some(new_val)
+}
+
+/// Version 4 (first step): Merges deletion lists that were written by older versions
+///
+/// RocksDB may still be holding deletion list operands that have two byte document ids, which
+/// the current merge operator can't read. This opens the index with the old merge operator and
+/// writes each deletion list back as a single roaring bitmap. It must be run before the index is opened.
+pub fn merge_legacy_deletion_lists<P: AsRef<Path>>(path: P) -> Result<(), String> {
+    let mut opts = Options::default();
+    opts.set_merge_operator("merge operator", merge_keys_v3, None);
+    let db = try!(DB::open(&opts, path));
+
+    if try!(read_format_version(&db)) >= 4 {
+        return Ok(());
+    }
+
+    let mut write_batch = WriteBatch::default();
+    let mut iter = db.raw_iterator();
+    iter.seek(b"x");
+    while iter.valid() {
+        let k = iter.key().unwrap();
+
+        if k[0] != b'x' {
+            break;
+        }
+
+        let deletion_list = decode_legacy_deletion_list(&iter.value().unwrap());
+        let mut value = Vec::new();
+        deletion_list.serialize_into(&mut value).unwrap();
+        try!(write_batch.put(&k, &value));
+
+        iter.next();
+    }
+
+    try!(db.write(write_batch));
+    Ok(())
+}
+
+/// Removes the escaping that KeyBuilder adds before "/" and "\" characters
+fn unescape_key(key: &[u8]) -> Vec<u8> {
+    let mut unescaped = Vec::with_capacity(key.len());
+    let mut escaped = false;
+
+    for c in key.iter() {
+        if !escaped && *c == b'\\' {
+            escaped = true;
+            continue;
+        }
+
+        escaped = false;
+        unescaped.push(*c);
+    }
+
+    unescaped
+}
+
+/// Converts postings list key strings "d1/2/3" into tuples of 3 i32s (1, 2, 3)
+fn parse_postings_list_key(key: &[u8]) -> (u32, u32, u32) {
+    let mut nums_iter = key[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
+    (nums_iter.next().unwrap(), nums_iter.next().unwrap(), nums_iter.next().unwrap())
+}
+
+/// Converts stored value key strings "v1/2/3/v" into tuples of 3 i32s and a Vec<u8> (1, 2, 3, vec![b'v', b'a', b'l'])
+fn parse_stored_value_key(key: &[u8]) -> (u32, u32, u32, Vec<u8>) {
+    let mut parts_iter = key[1..].split(|b| *b == b'/');
+    let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+    let doc_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+    let field_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+    let value_type = parts_iter.next().unwrap().to_vec();
+
+    (segment, doc_id, field_id, value_type)
+}
+
+impl RocksDBStore {
+    /// Builds a mapping from TermIds back to their terms
+    fn read_term_ids(&self) -> HashMap<u32, Term> {
+        let mut terms = HashMap::new();
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"t");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b't' {
+                break;
+            }
+
+            let term_id = str::from_utf8(&iter.value().unwrap()).unwrap().parse::<u32>().unwrap();
+            terms.insert(term_id, Term::from_bytes(&unescape_key(&k[1..])));
+
+            iter.next();
+        }
+
+        terms
+    }
+
+    /// Upgrades the index to the current format version
+    pub fn run_migrations(&self) -> Result<(), String> {
+        let version = try!(read_format_version(&self.db));
+
+        if version > CURRENT_FORMAT_VERSION {
+            return Err(format!("index format version {} is newer than this version of rusticsearch supports ({})", version, CURRENT_FORMAT_VERSION));
+        }
+
+        if version < 2 {
+            try!(self.migrate_sortable_integer_terms());
+        }
+
+        if version < 3 {
+            try!(self.migrate_field_term_dictionaries());
+        }
+
+        if version < 4 {
+            try!(self.migrate_local_doc_ids());
+        }
+
+        if version < 5 {
+            try!(self.migrate_document_versions());
+        }
+
+        if version < 6 {
+            try!(self.migrate_packed_term_frequencies_and_norms());
+        }
+
+        if version < 7 {
+            try!(self.migrate_term_impacts());
+        }
+
+        Ok(())
+    }
+
+    /// Version 2: Integer and datetime terms are now encoded as sign-flipped big endian (was little endian)
+    ///
+    /// As the term dictionary is shared between all fields, we can't just rewrite the old terms
+    /// in place (a text field might be using the same term). Instead, we allocate a new TermId
+    /// for the re-encoded term and move everything that belongs to integer/datetime fields
+    /// over to it. The old terms are left in the dictionary.
+    ///
+    /// A re-encoded term can have the same bytes as another old term (the new encoding of 0 is the
+    /// old encoding of 128), so its TermId may be one that is being moved away from. To stop these
+    /// from overwriting each other, the data is first moved to staging keys and only moved into place
+    /// once none of the old keys are left.
+    ///
+    /// This is committed in chunks so large indices don't have to be rewritten in one batch. If it's
+    /// interrupted, running it again picks up where it left off: the old keys that are left haven't
+    /// been staged yet and the staged keys haven't been moved into place. Once the staged keys start
+    /// being moved into place, the old keys can't be told apart from the new ones, so a marker is
+    /// written first to stop the old keys being looked for again.
+    fn migrate_sortable_integer_terms(&self) -> Result<(), rocksdb::Error> {
+        let numeric_fields = self.schema.iter()
+            .filter(|&(_, field_info)| field_info.field_type == FieldType::I64 || field_info.field_type == FieldType::DateTime)
+            .map(|(field_id, _)| field_id.0)
+            .collect::<FnvHashSet<u32>>();
+
+        let is_staged = try!(self.db.get(SORTABLE_INTEGER_TERMS_STAGED_KEY)).is_some();
+
+        if !numeric_fields.is_empty() && !is_staged {
+            let terms = self.read_term_ids();
+
+            // Find the TermIds of the re-encoded terms
+            // The postings lists are moved last, so they can be used to find the old terms that still
+            // have something left to move if this is picking up from a previous run
+
+            let mut term_id_mapping: FnvHashMap<u32, TermId> = FnvHashMap::default();
+
+            let mut iter = self.db.raw_iterator();
+            iter.seek(b"d");
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if k[0] != b'd' {
+                    break;
+                }
+
+                let (field, term, _) = parse_postings_list_key(&k);
+
+                if numeric_fields.contains(&field) && !term_id_mapping.contains_key(&term) {
+                    match terms.get(&term) {
+                        Some(old_term) if old_term.as_bytes().len() == 8 => {
+                            let new_term = Term::from_integer(LittleEndian::read_i64(old_term.as_bytes()));
+                            let new_term_id = try!(self.term_dictionary.get_or_create(&self.db, &new_term));
+                            term_id_mapping.insert(term, new_term_id);
+                        }
+                        _ => {}
+                    }
+                }
+
+                iter.next();
+            }
+
+            let staging_key = |key: &[u8]| {
+                let mut staging_key = SORTABLE_INTEGER_TERMS_STAGING_PREFIX.to_vec();
+                staging_key.extend_from_slice(key);
+                staging_key
+            };
+
+            let mut chunked_move = ChunkedMove::new(&self.db);
+
+            // Move term frequencies and positions over to the new TermIds
+
+            let mut iter = self.db.raw_iterator();
+            iter.seek(b"v");
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if k[0] != b'v' {
+                    break;
+                }
+
+                let (segment, doc_id, field, value_type) = parse_stored_value_key(&k);
+
+                if numeric_fields.contains(&field) {
+                    let prefix_len = if value_type.starts_with(b"tf") {
+                        Some(2)
+                    } else if value_type.starts_with(b"pos") {
+                        Some(3)
+                    } else {
+                        None
+                    };
+
+                    if let Some(prefix_len) = prefix_len {
+                        let term = str::from_utf8(&value_type[prefix_len..]).unwrap().parse::<u32>().unwrap();
+
+                        if let Some(new_term_id) = term_id_mapping.get(&term) {
+                            let mut new_value_type = value_type[..prefix_len].to_vec();
+                            new_value_type.extend(new_term_id.0.to_string().as_bytes());
+
+                            let kb = KeyBuilder::stored_field_value(segment, doc_id, field, &new_value_type);
+                            try!(chunked_move.move_value(&k, &staging_key(kb.key()), &iter.value().unwrap()));
+                        }
+                    }
+                }
+
+                try!(chunked_move.commit_if_full());
+                iter.next();
+            }
+
+            // Move postings lists and term document frequencies over to the new TermIds
+
+            let mut iter = self.db.raw_iterator();
+            iter.seek(b"d");
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if k[0] != b'd' {
+                    break;
+                }
+
+                let (field, term, segment) = parse_postings_list_key(&k);
+
+                if numeric_fields.contains(&field) {
+                    if let Some(new_term_id) = term_id_mapping.get(&term) {
+                        // Postings list
+                        let kb = KeyBuilder::segment_postings_list(segment, field, new_term_id.0);
+                        try!(chunked_move.move_value(&k, &staging_key(kb.key()), &iter.value().unwrap()));
+
+                        // Term document frequency
+                        // This is found through the postings list, so it must be moved in the same batch
+                        let old_stat_kb = KeyBuilder::segment_stat(segment, &term_doc_frequency_stat_name(field, term));
+                        if let Some(value) = try!(self.db.get(&old_stat_kb.key())) {
+                            let new_stat_kb = KeyBuilder::segment_stat(segment, &term_doc_frequency_stat_name(field, new_term_id.0));
+                            try!(chunked_move.move_value(&old_stat_kb.key(), &staging_key(new_stat_kb.key()), &value));
+                        }
+                    }
+                }
+
+                try!(chunked_move.commit_if_full());
+                iter.next();
+            }
+
+            try!(chunked_move.commit());
+            try!(self.db.put(SORTABLE_INTEGER_TERMS_STAGED_KEY, b""));
+        }
+
+        if !numeric_fields.is_empty() {
+            // None of the old keys are left, so the staged keys can be moved into place
+
+            let mut chunked_move = ChunkedMove::new(&self.db);
+            let mut iter = self.db.raw_iterator();
+            iter.seek(SORTABLE_INTEGER_TERMS_STAGING_PREFIX);
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if !k.starts_with(SORTABLE_INTEGER_TERMS_STAGING_PREFIX) {
+                    break;
+                }
+
+                try!(chunked_move.move_value(&k, &k[SORTABLE_INTEGER_TERMS_STAGING_PREFIX.len()..], &iter.value().unwrap()));
+                try!(chunked_move.commit_if_full());
+                iter.next();
+            }
+
+            try!(chunked_move.commit());
+        }
+
+        // Bump format version
+        let mut write_batch = WriteBatch::default();
+        try!(write_batch.delete(SORTABLE_INTEGER_TERMS_STAGED_KEY));
+        try!(write_batch.put(b".format_version", b"2"));
+
+        self.db.write(write_batch)
+    }
+
+    /// Version 3: Each field has its own term dictionary and a "sum of document frequencies" statistic
+    ///
+    /// The field term dictionaries are built from the postings lists of each field and the new
+    /// statistic is the sum of each segment's term document frequency statistics.
+    fn migrate_field_term_dictionaries(&self) -> Result<(), rocksdb::Error> {
+        let mut write_batch = WriteBatch::default();
+        let terms = self.read_term_ids();
+
+        // Find the terms in each field from the postings lists
+        let mut field_terms = FnvHashSet::default();
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"d");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'd' {
+                break;
+            }
+
+            let (field, term, _segment) = parse_postings_list_key(&k);
+            field_terms.insert((field, term));
+
+            iter.next();
+        }
+
+        for (field, term_id) in field_terms {
+            if let Some(term) = terms.get(&term_id) {
+                let kb = KeyBuilder::field_term_dict_mapping(field, term.as_bytes());
+                try!(write_batch.put(&kb.key(), term_id.to_string().as_bytes()));
+            }
+        }
+
+        // Sum up the term document frequencies of each field in each segment
+        let mut sum_doc_frequencies: FnvHashMap<(u32, u32), i64> = FnvHashMap::default();
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"s");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b's' {
+                break;
+            }
+
+            // Statistic keys look like "s1/tdf-2-3" (segment 1, field 2, term 3)
+            let mut parts_iter = k[1..].splitn(2, |b| *b == b'/');
+            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
+            let stat_name = parts_iter.next().unwrap();
+
+            if stat_name.starts_with(b"tdf-") {
+                let field = str::from_utf8(stat_name[4..].split(|b| *b == b'-').next().unwrap()).unwrap().parse::<u32>().unwrap();
+                let sum_doc_frequency = sum_doc_frequencies.entry((segment, field)).or_insert(0);
+                *sum_doc_frequency += LittleEndian::read_i64(&iter.value().unwrap());
+            }
+
+            iter.next();
+        }
+
+        for ((segment, field), sum_doc_frequency) in sum_doc_frequencies {
+            let kb = KeyBuilder::segment_stat(segment, &sum_doc_frequency_stat_name(field));
+            let mut value_bytes = [0; 8];
+            LittleEndian::write_i64(&mut value_bytes, sum_doc_frequency);
+            try!(write_batch.put(&kb.key(), &value_bytes));
+        }
+
+        // Bump format version
+        try!(write_batch.put(b".format_version", b"3"));
+
+        self.db.write(write_batch)
+    }
+
+    /// Version 4: Local document ids are 32 bits (were 16 bits)
+    ///
+    /// Stored values, postings lists and doc values already have room for larger ids, so only
+    /// the primary key index needs rewriting here. Deletion lists are upgraded by
+    /// "merge_legacy_deletion_lists" before the index is opened.
+    fn migrate_local_doc_ids(&self) -> Result<(), rocksdb::Error> {
+        let mut write_batch = WriteBatch::default();
+
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"k");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'k' {
+                break;
+            }
+
+            // Primary keys were a four byte segment id followed by a two byte local id
+            let v = iter.value().unwrap();
+            if v.len() == 6 {
+                let segment = LittleEndian::read_u32(&v[0..4]);
+                let local_id = LittleEndian::read_u16(&v[4..6]);
+                try!(write_batch.put(&k, &encode_doc_id(DocId(SegmentId(segment), local_id as u32))));
+            }
+
+            iter.next();
+        }
+
+        // Bump format version
+        try!(write_batch.put(b".format_version", b"4"));
+
+        self.db.write(write_batch)
+    }
+
+    /// Version 5: Primary keys hold the version, sequence number and primary term of their document
+    ///
+    /// Existing documents are given version 1 and a sequence number each, in key order.
+    fn migrate_document_versions(&self) -> Result<(), rocksdb::Error> {
+        let mut write_batch = WriteBatch::default();
+        let mut next_seq_no = 0;
+
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"k");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'k' {
+                break;
+            }
+
+            // Primary keys were just the document id
+            let v = iter.value().unwrap();
+            if v.len() == 8 {
+                let version = DocumentVersion {
+                    version: 1,
+                    seq_no: next_seq_no,
+                    primary_term: PRIMARY_TERM,
+                };
+                next_seq_no += 1;
+
+                try!(write_batch.put(&k, &encode_primary_key_value(decode_doc_id(&v), version)));
+            }
+
+            iter.next();
+        }
+
+        try!(write_batch.put(b".next_seq_no", next_seq_no.to_string().as_bytes()));
+
+        // Bump format version
+        try!(write_batch.put(b".format_version", b"5"));
+
+        self.db.write(write_batch)
+    }
+
+    /// Version 6: Term frequencies and field lengths are packed into a list for each segment
+    ///
+    /// These were stored values of each document ("tf{term}" and "len"). The term frequencies were
+    /// written with the segment builder's TermIds rather than the index's, so they can't be trusted
+    /// and are counted from the term positions instead.
+    fn migrate_packed_term_frequencies_and_norms(&self) -> Result<(), rocksdb::Error> {
+        let mut write_batch = WriteBatch::default();
+
+        // Term frequencies over 1, by segment, field and term
+        let mut term_frequencies: FnvHashMap<(u32, u32, u32), FnvHashMap<u32, u32>> = FnvHashMap::default();
+        let mut field_norms: FnvHashMap<(u32, u32), Vec<u8>> = FnvHashMap::default();
+
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"v");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'v' {
+                break;
+            }
+
+            let (segment, doc_id, field, value_type) = parse_stored_value_key(&k);
+
+            if value_type == b"len" {
+                let norms = field_norms.entry((segment, field)).or_insert_with(Vec::new);
+                if norms.len() <= doc_id as usize {
+                    norms.resize(doc_id as usize + 1, 0);
+                }
+
+                norms[doc_id as usize] = iter.value().unwrap()[0];
+                try!(write_batch.delete(&k));
+            } else if value_type.starts_with(b"tf") {
+                try!(write_batch.delete(&k));
+            } else if value_type.starts_with(b"pos") {
+                let term = str::from_utf8(&value_type[3..]).unwrap().parse::<u32>().unwrap();
+                let term_frequency = (iter.value().unwrap().len() / 4) as u32;
+
+                if term_frequency > 1 {
+                    term_frequencies.entry((segment, field, term)).or_insert_with(FnvHashMap::default).insert(doc_id, term_frequency);
+                }
+            }
+
+            iter.next();
+        }
+
+        // Write the term frequencies in the order of their postings lists
+        for ((segment, field, term), doc_term_frequencies) in term_frequencies {
+            let kb = KeyBuilder::segment_postings_list(segment, field, term);
+            let postings = match try!(self.db.get(&kb.key())) {
+                Some(postings) => RoaringBitmap::deserialize_from(Cursor::new(&postings[..])).unwrap(),
+                None => continue,
+            };
+
+            let packed_term_frequencies = postings.iter().map(|doc_id| doc_term_frequencies.get(&doc_id).cloned().unwrap_or(1)).collect::<Vec<u32>>();
+
+            let kb = KeyBuilder::segment_term_frequencies(segment, field, term);
+            try!(write_batch.put(&kb.key(), &encode_term_frequencies(&packed_term_frequencies)));
+        }
+
+        for ((segment, field), norms) in field_norms {
+            let kb = KeyBuilder::segment_field_norms(segment, field);
+            try!(write_batch.put(&kb.key(), &norms));
+        }
+
+        // Bump format version
+        try!(write_batch.put(b".format_version", b"6"));
+
+        self.db.write(write_batch)
+    }
+
+    /// Version 7: Each postings list has a term impact, which holds the highest term frequency and
+    /// shortest field of its documents
+    ///
+    /// These are worked out from the term frequencies and field norms of each segment.
+    fn migrate_term_impacts(&self) -> Result<(), rocksdb::Error> {
+        let mut write_batch = WriteBatch::default();
+
+        // Field norms, by segment and field. Loaded as they are needed
+        let mut field_norms: FnvHashMap<(u32, u32), Option<Vec<u8>>> = FnvHashMap::default();
+
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"d");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'd' {
+                break;
+            }
+
+            let mut nums_iter = k[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
+            let (field, term, segment) = (nums_iter.next().unwrap(), nums_iter.next().unwrap(), nums_iter.next().unwrap());
+
+            if !field_norms.contains_key(&(segment, field)) {
+                let kb = KeyBuilder::segment_field_norms(segment, field);
+                field_norms.insert((segment, field), try!(self.db.get(&kb.key())).map(|norms| norms.to_vec()));
+            }
+            let norms = field_norms[&(segment, field)].as_ref();
+
+            let kb = KeyBuilder::segment_term_frequencies(segment, field, term);
+            let term_frequencies = try!(self.db.get(&kb.key())).map(|term_frequencies| decode_term_frequencies(&term_frequencies));
+
+            let postings = RoaringBitmap::deserialize_from(Cursor::new(iter.value().unwrap())).unwrap();
+            let mut impact: Option<TermImpact> = None;
+            for (i, doc_id) in postings.iter().enumerate() {
+                let term_frequency = term_frequencies.as_ref().and_then(|term_frequencies| term_frequencies.get(i).cloned()).unwrap_or(1);
+                let field_norm = norms.and_then(|norms| norms.get(doc_id as usize)).cloned().unwrap_or(0);
+
+                match impact {
+                    Some(ref mut impact) => impact.add(term_frequency, field_norm),
+                    None => impact = Some(TermImpact::new(term_frequency, field_norm)),
+                }
+            }
+
+            if let Some(impact) = impact {
+                let kb = KeyBuilder::segment_term_impact(segment, field, term);
+                try!(write_batch.put(&kb.key(), &impact.to_bytes()));
+            }
+
+            iter.next();
+        }
+
+        // Bump format version
+        try!(write_batch.put(b".format_version", b"7"));
+
+        self.db.write(write_batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::path::Path;
+    use std::ops::Bound;
+
+    use fnv::FnvHashMap;
+    use search::{Term, Token, Document};
+    use search::schema::{FieldType, FIELD_INDEXED};
+    use search::segment::{sum_doc_frequency_stat_name, encode_field_length, decode_term_frequencies, TermImpact};
+    use search::query::Query;
+    use search::query::multi_term_selector::MultiTermSelector;
+    use search::query::term_scorer::TermScorer;
+    use search::collectors::total_count::TotalCountCollector;
+    use search::backends::Reader;
+    use rocksdb::{DB, Options};
+    use roaring::RoaringBitmap;
+    use byteorder::{ByteOrder, WriteBytesExt, LittleEndian};
+
+    use super::super::RocksDBStore;
+    use super::super::key_builder::KeyBuilder;
+    use super::super::document_index::encode_doc_id;
+    use super::{read_format_version, merge_keys_v3, decode_legacy_deletion_list, SORTABLE_INTEGER_TERMS_STAGING_PREFIX, SORTABLE_INTEGER_TERMS_STAGED_KEY};
+
+    fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
+        match remove_dir_all(&path) {
+            Ok(_) => {}
+            Err(_) => {}  // Don't care if this fails
+        }
+    }
+
+    #[test]
+    fn test_migrate_sortable_integer_terms() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_sortable_integer_terms");
+
+        {
+            let mut store = RocksDBStore::create("test_indices/test_migrate_sortable_integer_terms").unwrap();
+            let number_field = store.add_field("number".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                number_field,
+                vec![
+                    Token { term: Term::from_integer(123), position: 1 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: "test_doc".to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+            }, None).unwrap();
+
+            // Rewrite the index into the old format: integer terms were little endian
+            let term_id = store.term_dictionary.get(&store.db, &Term::from_integer(123)).unwrap().unwrap();
+            let mut old_term = Vec::new();
+            old_term.write_i64::<LittleEndian>(123).unwrap();
+            let old_term_id = store.term_dictionary.get_or_create(&store.db, &Term::from_bytes(&old_term)).unwrap();
+
+            let old_postings_key = KeyBuilder::segment_postings_list(1, number_field.0, term_id.0);
+            let postings = store.db.get(&old_postings_key.key()).unwrap().unwrap().to_vec();
+            store.db.delete(&old_postings_key.key()).unwrap();
+            store.db.put(&KeyBuilder::segment_postings_list(1, number_field.0, old_term_id.0).key(), &postings).unwrap();
+            store.db.delete(b".format_version").unwrap();
+        }
+
+        // Reopen the store, this should run the migration
+        let store = RocksDBStore::open("test_indices/test_migrate_sortable_integer_terms").unwrap();
+        let number_field = store.schema.get_field_by_name("number").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::Term {
+            field: number_field,
+            term: Term::from_integer(123),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    /// Creates a store with a document that has the integers 123 and 456 in its "number" field
+    /// Returns the store and the postings list keys of both integers in the old and new formats
+    ///
+    /// The format version isn't changed, so the migration isn't run until it has been removed.
+    fn make_old_integer_terms_store(path: &str) -> (RocksDBStore, Vec<(Vec<u8>, Vec<u8>)>) {
+        let mut store = RocksDBStore::create(path).unwrap();
+        let number_field = store.add_field("number".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(
+            number_field,
+            vec![
+                Token { term: Term::from_integer(123), position: 1 },
+                Token { term: Term::from_integer(456), position: 2 },
+            ].into()
+        );
+
+        store.insert_or_update_document(&Document {
+            key: "test_doc".to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: FnvHashMap::default(),
+        }, None).unwrap();
+
+        // Rewrite the index into the old format: integer terms were little endian
+        let mut keys = Vec::new();
+        for value in [123, 456].iter() {
+            let term_id = store.term_dictionary.get(&store.db, &Term::from_integer(*value)).unwrap().unwrap();
+            let mut old_term = Vec::new();
+            old_term.write_i64::<LittleEndian>(*value).unwrap();
+            let old_term_id = store.term_dictionary.get_or_create(&store.db, &Term::from_bytes(&old_term)).unwrap();
+
+            let new_postings_key = KeyBuilder::segment_postings_list(1, number_field.0, term_id.0).key().to_vec();
+            let old_postings_key = KeyBuilder::segment_postings_list(1, number_field.0, old_term_id.0).key().to_vec();
+            let postings = store.db.get(&new_postings_key).unwrap().unwrap().to_vec();
+            store.db.delete(&new_postings_key).unwrap();
+            store.db.put(&old_postings_key, &postings).unwrap();
+
+            keys.push((old_postings_key, new_postings_key));
+        }
+
+        (store, keys)
+    }
+
+    /// Moves a postings list key in the same way as migrate_sortable_integer_terms does before it's put into place
+    fn stage_key(store: &RocksDBStore, old_key: &[u8], new_key: &[u8]) {
+        let mut staging_key = SORTABLE_INTEGER_TERMS_STAGING_PREFIX.to_vec();
+        staging_key.extend_from_slice(new_key);
+
+        let value = store.db.get(old_key).unwrap().unwrap().to_vec();
+        store.db.delete(old_key).unwrap();
+        store.db.put(&staging_key, &value).unwrap();
+    }
+
+    fn count_integer_matches(store: &RocksDBStore, value: i64) -> u64 {
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::Term {
+            field: store.schema.get_field_by_name("number").unwrap(),
+            term: Term::from_integer(value),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        collector.get_total_count()
+    }
+
+    #[test]
+    fn test_migrate_sortable_integer_terms_resumes_while_staging() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_sortable_integer_terms_resumes_while_staging");
+
+        {
+            let (store, keys) = make_old_integer_terms_store("test_indices/test_migrate_sortable_integer_terms_resumes_while_staging");
+
+            // Crash after the first term was staged
+            stage_key(&store, &keys[0].0, &keys[0].1);
+            store.db.delete(b".format_version").unwrap();
+        }
+
+        let store = RocksDBStore::open("test_indices/test_migrate_sortable_integer_terms_resumes_while_staging").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+        assert_eq!(count_integer_matches(&store, 123), 1);
+        assert_eq!(count_integer_matches(&store, 456), 1);
+    }
+
+    #[test]
+    fn test_migrate_sortable_integer_terms_resumes_while_moving_into_place() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_sortable_integer_terms_resumes_while_moving_into_place");
+
+        {
+            let (store, keys) = make_old_integer_terms_store("test_indices/test_migrate_sortable_integer_terms_resumes_while_moving_into_place");
+
+            // Crash after both terms were staged and the first was moved into place
+            let postings = store.db.get(&keys[0].0).unwrap().unwrap().to_vec();
+            store.db.delete(&keys[0].0).unwrap();
+            store.db.put(&keys[0].1, &postings).unwrap();
+            stage_key(&store, &keys[1].0, &keys[1].1);
+            store.db.put(SORTABLE_INTEGER_TERMS_STAGED_KEY, b"").unwrap();
+            store.db.delete(b".format_version").unwrap();
+        }
+
+        let store = RocksDBStore::open("test_indices/test_migrate_sortable_integer_terms_resumes_while_moving_into_place").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+        assert_eq!(count_integer_matches(&store, 123), 1);
+        assert_eq!(count_integer_matches(&store, 456), 1);
+
+        // The staging keys are cleaned up
+        assert!(store.db.get(SORTABLE_INTEGER_TERMS_STAGED_KEY).unwrap().is_none());
+        let mut iter = store.db.raw_iterator();
+        iter.seek(SORTABLE_INTEGER_TERMS_STAGING_PREFIX);
+        assert!(!iter.valid() || !iter.key().unwrap().starts_with(SORTABLE_INTEGER_TERMS_STAGING_PREFIX));
+    }
+
+    #[test]
+    fn test_read_invalid_format_version() {
+        remove_dir_all_ignore_error("test_indices/test_read_invalid_format_version");
+
+        {
+            let store = RocksDBStore::create("test_indices/test_read_invalid_format_version").unwrap();
+            store.db.put(b".format_version", b"foo").unwrap();
+            assert!(read_format_version(&store.db).is_err());
+        }
+
+        // Opening the store should fail instead of panicking
+        assert!(RocksDBStore::open("test_indices/test_read_invalid_format_version").is_err());
+    }
+
+    #[test]
+    fn test_migrate_sortable_integer_terms_with_colliding_encodings() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_sortable_integer_terms_with_colliding_encodings");
+
+        {
+            let mut store = RocksDBStore::create("test_indices/test_migrate_sortable_integer_terms_with_colliding_encodings").unwrap();
+            let number_field = store.add_field("number".to_string(), FieldType::I64, FIELD_INDEXED).unwrap();
+
+            let make_doc = |key: &str, value: i64| {
+                let mut indexed_fields = FnvHashMap::default();
+                indexed_fields.insert(
+                    number_field,
+                    vec![
+                        Token { term: Term::from_integer(value), position: 1 },
+                    ].into()
+                );
+
+                Document {
+                    key: key.to_string(),
+                    indexed_fields: indexed_fields,
+                    stored_fields: FnvHashMap::default(),
+                }
+            };
+
+            store.insert_or_update_documents(&[
+                make_doc("zero", 0),
+                make_doc("one_hundred_and_twenty_eight", 128),
+            ], &[None; 2]).unwrap();
+
+            // Rewrite the index into the old format. The old encoding of 128 has the same bytes as
+            // the new encoding of 0, so it's given the TermId that 0 has now
+            let mut old_postings = Vec::new();
+            for value in [0, 128].iter() {
+                let term_id = store.term_dictionary.get(&store.db, &Term::from_integer(*value)).unwrap().unwrap();
+                let postings_key = KeyBuilder::segment_postings_list(1, number_field.0, term_id.0);
+                let postings = store.db.get(&postings_key.key()).unwrap().unwrap().to_vec();
+                store.db.delete(&postings_key.key()).unwrap();
+                old_postings.push((*value, postings));
+            }
+
+            for &(value, ref postings) in old_postings.iter() {
+                let mut old_term = Vec::new();
+                old_term.write_i64::<LittleEndian>(value).unwrap();
+                let old_term_id = store.term_dictionary.get_or_create(&store.db, &Term::from_bytes(&old_term)).unwrap();
+                store.db.put(&KeyBuilder::segment_postings_list(1, number_field.0, old_term_id.0).key(), postings).unwrap();
+            }
+
+            assert_eq!(
+                store.term_dictionary.get(&store.db, &Term::from_integer(0)).unwrap(),
+                store.term_dictionary.get(&store.db, &Term::from_bytes(&[128, 0, 0, 0, 0, 0, 0, 0])).unwrap()
+            );
+
+            store.db.delete(b".format_version").unwrap();
+        }
+
+        // Reopen the store, this should run the migration
+        let store = RocksDBStore::open("test_indices/test_migrate_sortable_integer_terms_with_colliding_encodings").unwrap();
+        let number_field = store.schema.get_field_by_name("number").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+
+        for value in [0, 128].iter() {
+            let mut collector = TotalCountCollector::new();
+            store.reader().search(&mut collector, &Query::Term {
+                field: number_field,
+                term: Term::from_integer(*value),
+                scorer: TermScorer::default(),
+            }).unwrap();
+            assert_eq!(collector.get_total_count(), 1);
+        }
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::MultiTerm {
+            field: number_field,
+            term_selector: MultiTermSelector::Range {
+                lower: Bound::Included(Term::from_integer(0)),
+                upper: Bound::Included(Term::from_integer(128)),
+            },
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 2);
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::MultiTerm {
+            field: number_field,
+            term_selector: MultiTermSelector::Range {
+                lower: Bound::Excluded(Term::from_integer(0)),
+                upper: Bound::Unbounded,
+            },
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_migrate_field_term_dictionaries() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_field_term_dictionaries");
+
+        {
+            let mut store = RocksDBStore::create("test_indices/test_migrate_field_term_dictionaries").unwrap();
+            let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                    Token { term: Term::from_string("help"), position: 2 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: "test_doc".to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+            }, None).unwrap();
+
+            // Rewrite the index into the old format: there were no field term dictionaries or sum of doc frequencies
+            for term in ["hello", "help"].iter() {
+                store.db.delete(&KeyBuilder::field_term_dict_mapping(title_field.0, term.as_bytes()).key()).unwrap();
+            }
+            store.db.delete(&KeyBuilder::segment_stat(1, &sum_doc_frequency_stat_name(title_field.0)).key()).unwrap();
+            store.db.put(b".format_version", b"2").unwrap();
+
+            assert_eq!(store.get_field_statistics(title_field).unwrap().unique_terms(), 0);
+        }
+
+        // Reopen the store, this should run the migration
+        let store = RocksDBStore::open("test_indices/test_migrate_field_term_dictionaries").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+
+        let stats = store.get_field_statistics(title_field).unwrap();
+        assert_eq!(stats.unique_terms(), 2);
+        assert_eq!(stats.sum_doc_freq(), 2);
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::MultiTerm {
+            field: title_field,
+            term_selector: MultiTermSelector::Prefix("hel".to_string()),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_migrate_local_doc_ids() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_local_doc_ids");
+
+        {
+            let mut store = RocksDBStore::create("test_indices/test_migrate_local_doc_ids").unwrap();
+            let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+            let make_doc = |key: &str| {
+                let mut indexed_fields = FnvHashMap::default();
+                indexed_fields.insert(
+                    title_field,
+                    vec![
+                        Token { term: Term::from_string("hello"), position: 1 },
+                    ].into()
+                );
+
+                Document {
+                    key: key.to_string(),
+                    indexed_fields: indexed_fields,
+                    stored_fields: FnvHashMap::default(),
+                }
+            };
+
+            store.insert_or_update_documents(&[
+                make_doc("doc_a"),
+                make_doc("doc_b"),
+                make_doc("doc_c"),
+            ], &[None; 3]).unwrap();
+        }
+
+        {
+            // Rewrite the index into the old format, using the old merge operator: primary keys had a
+            // two byte local id, as did the operands of the deletion lists
+            let mut opts = Options::default();
+            opts.set_merge_operator("merge operator", merge_keys_v3, None);
+            let db = DB::open(&opts, "test_indices/test_migrate_local_doc_ids").unwrap();
+
+            for (local_id, key) in ["doc_a", "doc_b", "doc_c"].iter().enumerate() {
+                let mut doc_id_bytes = [0; 6];
+                LittleEndian::write_u32(&mut doc_id_bytes, 1);
+                LittleEndian::write_u16(&mut doc_id_bytes[4..], local_id as u16);
+                db.put(&KeyBuilder::primary_key_index(key.as_bytes()).key(), &doc_id_bytes).unwrap();
+            }
+
+            // Delete "doc_b"
+            let mut deleted_doc_id_bytes = [0; 2];
+            LittleEndian::write_u16(&mut deleted_doc_id_bytes, 1);
+            db.merge(&KeyBuilder::segment_del_list(1).key(), &deleted_doc_id_bytes).unwrap();
+            db.put(b".format_version", b"3").unwrap();
+        }
+
+        // Reopen the store, this should run the migration
+        let store = RocksDBStore::open("test_indices/test_migrate_local_doc_ids").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+
+        let index_reader = store.reader();
+        assert_eq!(index_reader.get_document_by_key("doc_c").unwrap().1, 2);
+
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 2);
+    }
+
+    #[test]
+    fn test_migrate_unmerged_deletion_lists() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_unmerged_deletion_lists");
+
+        {
+            let mut store = RocksDBStore::create("test_indices/test_migrate_unmerged_deletion_lists").unwrap();
+            let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+            let make_doc = |key: &str| {
+                let mut indexed_fields = FnvHashMap::default();
+                indexed_fields.insert(
+                    title_field,
+                    vec![
+                        Token { term: Term::from_string("hello"), position: 1 },
+                    ].into()
+                );
+
+                Document {
+                    key: key.to_string(),
+                    indexed_fields: indexed_fields,
+                    stored_fields: FnvHashMap::default(),
+                }
+            };
+
+            store.insert_or_update_documents(&[
+                make_doc("doc_a"),
+                make_doc("doc_b"),
+                make_doc("doc_c"),
+            ], &[None; 3]).unwrap();
+        }
+
+        {
+            // Rewrite the index into the format of version 1: the deletion list is a plain sequence
+            // of two byte document ids, rather than a roaring bitmap
+            let mut opts = Options::default();
+            opts.set_merge_operator("merge operator", merge_keys_v3, None);
+            let db = DB::open(&opts, "test_indices/test_migrate_unmerged_deletion_lists").unwrap();
+
+            for (local_id, key) in ["doc_a", "doc_b", "doc_c"].iter().enumerate() {
+                let mut doc_id_bytes = [0; 6];
+                LittleEndian::write_u32(&mut doc_id_bytes, 1);
+                LittleEndian::write_u16(&mut doc_id_bytes[4..], local_id as u16);
+                db.put(&KeyBuilder::primary_key_index(key.as_bytes()).key(), &doc_id_bytes).unwrap();
+            }
+
+            // Delete "doc_a" and "doc_c"
+            let mut deleted_doc_ids_bytes = [0; 4];
+            LittleEndian::write_u16(&mut deleted_doc_ids_bytes[0..2], 0);
+            LittleEndian::write_u16(&mut deleted_doc_ids_bytes[2..4], 2);
+            db.put(&KeyBuilder::segment_del_list(1).key(), &deleted_doc_ids_bytes).unwrap();
+            db.delete(b".format_version").unwrap();
+        }
+
+        // Reopen the store, this should run the migration
+        let store = RocksDBStore::open("test_indices/test_migrate_unmerged_deletion_lists").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+
+        let mut collector = TotalCountCollector::new();
+        store.reader().search(&mut collector, &Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_decode_legacy_deletion_list() {
+        let mut bitmap_bytes = Vec::new();
+        [1, 2, 3].iter().cloned().collect::<RoaringBitmap>().serialize_into(&mut bitmap_bytes).unwrap();
+        assert_eq!(decode_legacy_deletion_list(&bitmap_bytes), [1, 2, 3].iter().cloned().collect::<RoaringBitmap>());
+
+        let mut doc_ids_bytes = [0; 4];
+        LittleEndian::write_u16(&mut doc_ids_bytes[0..2], 1);
+        LittleEndian::write_u16(&mut doc_ids_bytes[2..4], 3);
+        assert_eq!(decode_legacy_deletion_list(&doc_ids_bytes), [1, 3].iter().cloned().collect::<RoaringBitmap>());
+    }
+
+    #[test]
+    fn test_migrate_document_versions() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_document_versions");
+
+        {
+            let mut store = RocksDBStore::create("test_indices/test_migrate_document_versions").unwrap();
+            store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+            let make_doc = |key: &str| {
+                Document {
+                    key: key.to_string(),
+                    indexed_fields: FnvHashMap::default(),
+                    stored_fields: FnvHashMap::default(),
+                }
+            };
+
+            store.insert_or_update_documents(&[
+                make_doc("doc_a"),
+                make_doc("doc_b"),
+            ], &[None; 2]).unwrap();
+
+            // Rewrite the primary keys into the old format, which only had the document id
+            let doc_id = store.reader().get_document_by_key("doc_b").unwrap();
+            store.db.put(&KeyBuilder::primary_key_index(b"doc_b").key(), &encode_doc_id(doc_id)).unwrap();
+            let doc_id = store.reader().get_document_by_key("doc_a").unwrap();
+            store.db.put(&KeyBuilder::primary_key_index(b"doc_a").key(), &encode_doc_id(doc_id)).unwrap();
+            store.db.delete(b".next_seq_no").unwrap();
+            store.db.put(b".format_version", b"4").unwrap();
+        }
+
+        // Reopen the store, this should run the migration
+        let store = RocksDBStore::open("test_indices/test_migrate_document_versions").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+
+        let index_reader = store.reader();
+        let doc_a_version = index_reader.get_document_version("doc_a").unwrap();
+        let doc_b_version = index_reader.get_document_version("doc_b").unwrap();
+        assert_eq!((doc_a_version.version, doc_a_version.seq_no), (1, 0));
+        assert_eq!((doc_b_version.version, doc_b_version.seq_no), (1, 1));
+
+        // New writes carry on after the sequence numbers given out by the migration
+        let version = store.insert_or_update_document(&Document {
+            key: "doc_c".to_string(),
+            indexed_fields: FnvHashMap::default(),
+            stored_fields: FnvHashMap::default(),
+        }, None).unwrap();
+        assert_eq!(version.seq_no, 2);
+    }
+
+    #[test]
+    fn test_migrate_packed_term_frequencies_and_norms() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_packed_term_frequencies_and_norms");
+
+        let (title_field, hello, segment) = {
+            let mut store = RocksDBStore::create("test_indices/test_migrate_packed_term_frequencies_and_norms").unwrap();
+            let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                    Token { term: Term::from_string("hello"), position: 2 },
+                    Token { term: Term::from_string("world"), position: 3 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: "test_doc".to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+            }, None).unwrap();
+
+            let hello = store.term_dictionary.get(&store.db, &Term::from_string("hello")).unwrap().unwrap();
+            let segment = store.get_segment_statistics().unwrap()[0].0;
+
+            // Rewrite the term frequencies and field lengths as stored values
+            // The old term frequency has the wrong TermId, the migration shouldn't use it
+            store.db.delete(&KeyBuilder::segment_term_frequencies(segment, title_field.0, hello.0).key()).unwrap();
+            store.db.delete(&KeyBuilder::segment_field_norms(segment, title_field.0).key()).unwrap();
+            store.db.put(&KeyBuilder::stored_field_value(segment, 0, title_field.0, b"tf1234").key(), &[2, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+            store.db.put(&KeyBuilder::stored_field_value(segment, 0, title_field.0, b"len").key(), &[encode_field_length(3)]).unwrap();
+            store.db.put(b".format_version", b"5").unwrap();
+
+            (title_field, hello, segment)
+        };
+
+        // Reopen the store, this should run the migration
+        let store = RocksDBStore::open("test_indices/test_migrate_packed_term_frequencies_and_norms").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+
+        let kb = KeyBuilder::segment_term_frequencies(segment, title_field.0, hello.0);
+        assert_eq!(decode_term_frequencies(&store.db.get(&kb.key()).unwrap().unwrap()), vec![2]);
+
+        let kb = KeyBuilder::segment_field_norms(segment, title_field.0);
+        assert_eq!(store.db.get(&kb.key()).unwrap().unwrap().to_vec(), vec![encode_field_length(3)]);
+
+        // The old stored values should be removed
+        assert!(store.db.get(&KeyBuilder::stored_field_value(segment, 0, title_field.0, b"tf1234").key()).unwrap().is_none());
+        assert!(store.db.get(&KeyBuilder::stored_field_value(segment, 0, title_field.0, b"len").key()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_migrate_term_impacts() {
+        remove_dir_all_ignore_error("test_indices/test_migrate_term_impacts");
+
+        let (title_field, hello, world, segment) = {
+            let mut store = RocksDBStore::create("test_indices/test_migrate_term_impacts").unwrap();
+            let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string("hello"), position: 1 },
+                    Token { term: Term::from_string("hello"), position: 2 },
+                    Token { term: Term::from_string("world"), position: 3 },
+                    Token { term: Term::from_string("foo"), position: 4 },
+                ].into()
+            );
+
+            store.insert_or_update_document(&Document {
+                key: "test_doc".to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+            }, None).unwrap();
+
+            let hello = store.term_dictionary.get(&store.db, &Term::from_string("hello")).unwrap().unwrap();
+            let world = store.term_dictionary.get(&store.db, &Term::from_string("world")).unwrap().unwrap();
+            let segment = store.get_segment_statistics().unwrap()[0].0;
+
+            // Remove the term impacts, these weren't written before version 7
+            store.db.delete(&KeyBuilder::segment_term_impact(segment, title_field.0, hello.0).key()).unwrap();
+            store.db.delete(&KeyBuilder::segment_term_impact(segment, title_field.0, world.0).key()).unwrap();
+            store.db.put(b".format_version", b"6").unwrap();
+
+            (title_field, hello, world, segment)
+        };
+
+        // Reopen the store, this should run the migration
+        let store = RocksDBStore::open("test_indices/test_migrate_term_impacts").unwrap();
+        assert_eq!(read_format_version(&store.db).unwrap(), 7);
+
+        let kb = KeyBuilder::segment_term_impact(segment, title_field.0, hello.0);
+        assert_eq!(TermImpact::from_bytes(&store.db.get(&kb.key()).unwrap().unwrap()), Some(TermImpact::new(2, encode_field_length(4))));
+
+        let kb = KeyBuilder::segment_term_impact(segment, title_field.0, world.0);
+        assert_eq!(TermImpact::from_bytes(&store.db.get(&kb.key()).unwrap().unwrap()), Some(TermImpact::new(1, encode_field_length(4))));
+    }
+}
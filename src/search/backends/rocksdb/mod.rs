@@ -3,43 +3,75 @@ mod segment;
 mod segment_manager;
 mod segment_ops;
 mod segment_stats;
-mod segment_builder;
 mod term_dictionary;
 mod document_index;
-mod search;
+mod migrations;
 
-use std::str;
 use std::fmt;
+use std::mem;
+use std::ops::Deref;
+use std::io::Cursor;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use rocksdb::{self, DB, WriteBatch, Options, MergeOperands, Snapshot};
-use search::{Document, DocId, TermId};
+use roaring::RoaringBitmap;
+use search::{Document, DocId, Term, TermId};
 use search::document::FieldValue;
+use search::query::Query;
+use search::query::multi_term_selector::MultiTermSelector;
+use search::explanation::Explanation;
+use search::version::{DocumentVersion, VersionCondition, VersionConflict};
+use search::collectors::{Collector, FieldValueReader};
 use search::schema::{Schema, FieldType, FieldFlags, FieldId, AddFieldError};
-use search::segment::SegmentId;
+use search::segment::{Segment, SegmentId, term_positions_value_type, encode_term_positions, encode_term_frequencies};
+use search::segment_builder;
+use search::doc_values::{DocValues, field_type_has_doc_values};
+use search::execution::{self, TermDictionaryReader};
+use search::execution::filter_cache::{FilterCache, FilterCacheStats};
 use byteorder::{ByteOrder, LittleEndian};
-use chrono::{NaiveDateTime, DateTime, Utc};
 use fnv::FnvHashMap;
 use serde_json;
 
+use super::{Store, Reader, DocumentWriteError, StoredFieldReadError, decode_stored_field_value};
+
 use self::key_builder::KeyBuilder;
+use self::segment::RocksDBSegment;
 use self::segment_manager::SegmentManager;
 use self::term_dictionary::TermDictionaryManager;
 use self::document_index::DocumentIndexManager;
 
+pub use self::segment_stats::{SegmentStatistics, FieldStatistics};
+
 fn merge_keys(key: &[u8], existing_val: Option<&[u8]>, operands: &mut MergeOperands) -> Option<Vec<u8>> {
     match key[0] {
-        b'd' | b'x' => {
-            // Sequence of two byte document ids
+        b'x' => {
+            // Deletion list
+            // This is stored as a roaring bitmap. Each operand is a four byte document id to add to it
+            let mut deletion_list = match existing_val {
+                Some(existing_val) => RoaringBitmap::deserialize_from(Cursor::new(existing_val)).unwrap(),
+                None => RoaringBitmap::new(),
+            };
+
+            for op in operands {
+                for doc_id in op.chunks(4) {
+                    deletion_list.insert(LittleEndian::read_u32(doc_id));
+                }
+            }
+
+            let mut new_val = Vec::new();
+            deletion_list.serialize_into(&mut new_val).unwrap();
+            Some(new_val)
+        }
+        b'd' => {
+            // Sequence of four byte document ids
             // d = postings list
-            // x = deletion list
 
             // Allocate vec for new Value
             let new_size = match existing_val {
                 Some(existing_val) => existing_val.len(),
                 None => 0,
-            } + operands.size_hint().0 * 2;
+            } + operands.size_hint().0 * 4;
 
             let mut new_val = Vec::with_capacity(new_size);
 
@@ -89,6 +121,9 @@ pub enum DocumentInsertError {
 
     /// The segment is full
     SegmentFull,
+
+    /// The document didn't meet the version condition of the write
+    VersionConflict(VersionConflict),
 }
 
 impl From<rocksdb::Error> for DocumentInsertError {
@@ -111,6 +146,10 @@ pub struct RocksDBStore {
     term_dictionary: TermDictionaryManager,
     segments: SegmentManager,
     document_index: DocumentIndexManager,
+
+    /// The documents that filters match in each segment
+    /// Entries are removed when their segment is merged or purged
+    filter_cache: FilterCache,
 }
 
 impl RocksDBStore {
@@ -128,6 +167,9 @@ impl RocksDBStore {
         };
         try!(db.put(b".schema", schema_encoded.as_bytes()));
 
+        // Format version
+        try!(db.put(b".format_version", migrations::CURRENT_FORMAT_VERSION.to_string().as_bytes()));
+
         // Segment manager
         let segments = try!(SegmentManager::new(&db));
 
@@ -143,13 +185,18 @@ impl RocksDBStore {
             term_dictionary: term_dictionary,
             segments: segments,
             document_index: document_index,
+            filter_cache: FilterCache::default(),
         })
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> Result<RocksDBStore, String> {
+        // Deletion lists from older versions can't be read by the current merge operator, so these
+        // must be upgraded before the index is opened
+        try!(migrations::merge_legacy_deletion_lists(path.as_ref()));
+
         let mut opts = Options::default();
         opts.set_merge_operator("merge operator", merge_keys, None);
-        let db = try!(DB::open(&opts, path));
+        let db = try!(DB::open(&opts, path.as_ref()));
 
         let schema = match try!(db.get(b".schema")) {
             Some(schema) => {
@@ -169,15 +216,27 @@ impl RocksDBStore {
         let term_dictionary = try!(TermDictionaryManager::open(&db));
 
         // Document index
-        let document_index = try!(DocumentIndexManager::open(&db));
+        // This is loaded after the migrations have run, as older versions encoded document ids differently
+        let document_index = try!(DocumentIndexManager::new(&db));
 
-        Ok(RocksDBStore {
+        let mut store = RocksDBStore {
             schema: Arc::new(schema),
             db: db,
             term_dictionary: term_dictionary,
             segments: segments,
             document_index: document_index,
-        })
+            filter_cache: FilterCache::default(),
+        };
+
+        // Upgrade indices created by older versions
+        try!(store.run_migrations());
+
+        store.document_index = try!(DocumentIndexManager::open(&store.db));
+
+        // Clean up after any merges or purges that were interrupted
+        try!(store.recover_segments());
+
+        Ok(store)
     }
 
     pub fn path(&self) -> &Path {
@@ -209,23 +268,69 @@ impl RocksDBStore {
         field_removed
     }
 
-    pub fn insert_or_update_document(&self, doc: &Document) -> Result<(), DocumentInsertError> {
+    /// Inserts or updates a document, returns its new version
+    pub fn insert_or_update_document(&self, doc: &Document, condition: Option<VersionCondition>) -> Result<DocumentVersion, DocumentInsertError> {
         // Build segment in memory
         let mut builder = segment_builder::SegmentBuilder::new();
-        let doc_key = doc.key.clone();
-        try!(builder.add_document(doc));
+        let doc_local_id = try!(builder.add_document(doc, &self.schema));
+
+        // Write the segment and update the document index
+        match try!(self.commit_segment(&builder, vec![(doc.key.as_bytes().to_vec(), doc_local_id, condition)])).pop().unwrap() {
+            Ok(version) => Ok(version),
+            Err(conflict) => Err(DocumentInsertError::VersionConflict(conflict)),
+        }
+    }
+
+    /// Inserts or updates many documents at once
+    ///
+    /// The documents are packed into as few segments as possible. Each segment is written in the same
+    /// write batch as the primary keys of its documents, so all documents in a segment become visible
+    /// at the same time.
+    ///
+    /// Each document may have a condition, at the same position in "conditions". Returns the new version
+    /// or the conflict of each document, in order.
+    pub fn insert_or_update_documents(&self, docs: &[Document], conditions: &[Option<VersionCondition>]) -> Result<Vec<Result<DocumentVersion, VersionConflict>>, DocumentInsertError> {
+        let mut builder = segment_builder::SegmentBuilder::new();
+        let mut doc_keys = Vec::new();
+        let mut results = Vec::with_capacity(docs.len());
+        assert_eq!(docs.len(), conditions.len());
+
+        for (doc, condition) in docs.iter().zip(conditions.iter()) {
+            let doc_local_id = match builder.add_document(doc, &self.schema) {
+                Ok(doc_local_id) => doc_local_id,
+                Err(segment_builder::DocumentInsertError::SegmentFull) => {
+                    // Write the full segment and start a new one
+                    let full_builder = mem::replace(&mut builder, segment_builder::SegmentBuilder::new());
+                    results.extend(try!(self.commit_segment(&full_builder, mem::replace(&mut doc_keys, Vec::new()))));
+
+                    try!(builder.add_document(doc, &self.schema))
+                }
+            };
 
-        // Write the segment
-        let segment = try!(self.write_segment(&builder));
+            doc_keys.push((doc.key.as_bytes().to_vec(), doc_local_id, *condition));
+        }
 
-        // Update document index
-        let doc_id = DocId(SegmentId(segment), 0);
-        try!(self.document_index.insert_or_replace_key(&self.db, &doc_key.as_bytes().iter().cloned().collect(), doc_id));
+        if !doc_keys.is_empty() {
+            results.extend(try!(self.commit_segment(&builder, doc_keys)));
+        }
 
-        Ok(())
+        Ok(results)
     }
 
-    pub fn write_segment(&self, builder: &segment_builder::SegmentBuilder) -> Result<u32, rocksdb::Error> {
+    /// Writes a segment and points the keys of the documents in it to their new locations
+    /// Returns the new version or the conflict of each document
+    fn commit_segment(&self, builder: &segment_builder::SegmentBuilder, doc_keys: Vec<(Vec<u8>, u32, Option<VersionCondition>)>) -> Result<Vec<Result<DocumentVersion, VersionConflict>>, rocksdb::Error> {
+        let (segment, write_batch) = try!(self.build_segment_write_batch(builder));
+
+        let doc_keys = doc_keys.into_iter()
+            .map(|(doc_key, doc_local_id, condition)| (doc_key, DocId(SegmentId(segment), doc_local_id), condition))
+            .collect();
+        self.document_index.insert_or_replace_keys(&self.db, write_batch, doc_keys)
+    }
+
+    /// Allocates a segment ID and builds a write batch that writes the segment to the disk
+    /// The segment will become active as soon as the write batch is written
+    fn build_segment_write_batch(&self, builder: &segment_builder::SegmentBuilder) -> Result<(u32, WriteBatch), rocksdb::Error> {
         // Allocate a segment ID
         let segment = try!(self.segments.new_segment(&self.db));
 
@@ -240,15 +345,20 @@ impl RocksDBStore {
         // Merge the term dictionary
         // Writes new terms to disk and generates mapping between the builder's term dictionary and the real one
         let mut term_dictionary_map: FnvHashMap<TermId, TermId> = FnvHashMap::default();
+        let mut builder_terms: FnvHashMap<TermId, &Term> = FnvHashMap::default();
         for (term, current_term_id) in builder.term_dictionary.iter() {
             let new_term_id = try!(self.term_dictionary.get_or_create(&self.db, term));
             term_dictionary_map.insert(*current_term_id, new_term_id);
+            builder_terms.insert(*current_term_id, term);
         }
 
         // Write postings lists
         for (&(field_id, term_id), postings) in builder.postings_lists.iter() {
             let new_term_id = term_dictionary_map.get(&term_id).expect("TermId not in term_dictionary_map");
 
+            // Add the term to the field's term dictionary
+            try!(self.term_dictionary.write_field_term(&mut write_batch, field_id, builder_terms[&term_id], *new_term_id));
+
             // Serialise
             let mut postings_bytes = Vec::new();
             postings.serialize_into(&mut postings_bytes).unwrap();
@@ -258,12 +368,54 @@ impl RocksDBStore {
             try!(write_batch.put(&kb.key(), &postings_bytes));
         }
 
+        // Write term frequencies
+        // These are only written for terms that occur more than once in any document, a missing
+        // key is read as a frequency of 1 for every document
+        for (&(field_id, term_id), term_frequencies) in builder.term_frequencies.iter() {
+            if term_frequencies.iter().all(|term_frequency| *term_frequency == 1) {
+                continue;
+            }
+
+            let new_term_id = term_dictionary_map.get(&term_id).expect("TermId not in term_dictionary_map");
+
+            let kb = KeyBuilder::segment_term_frequencies(segment, field_id.0, new_term_id.0);
+            try!(write_batch.put(&kb.key(), &encode_term_frequencies(term_frequencies)));
+        }
+
+        // Write term impacts
+        for (&(field_id, term_id), impact) in builder.term_impacts.iter() {
+            let new_term_id = term_dictionary_map.get(&term_id).expect("TermId not in term_dictionary_map");
+
+            let kb = KeyBuilder::segment_term_impact(segment, field_id.0, new_term_id.0);
+            try!(write_batch.put(&kb.key(), &impact.to_bytes()));
+        }
+
+        // Write field norms
+        for (field_id, norms) in builder.field_norms.iter() {
+            let kb = KeyBuilder::segment_field_norms(segment, field_id.0);
+            try!(write_batch.put(&kb.key(), norms));
+        }
+
         // Write stored fields
         for (&(field_id, doc_id, ref value_type), value) in builder.stored_field_values.iter() {
             let kb = KeyBuilder::stored_field_value(segment, doc_id, field_id.0, value_type);
             try!(write_batch.put(&kb.key(), value));
         }
 
+        // Write term positions
+        for (&(field_id, term_id, doc_id), positions) in builder.term_positions.iter() {
+            let new_term_id = term_dictionary_map.get(&term_id).expect("TermId not in term_dictionary_map");
+
+            let kb = KeyBuilder::stored_field_value(segment, doc_id, field_id.0, &term_positions_value_type(*new_term_id));
+            try!(write_batch.put(&kb.key(), &encode_term_positions(positions)));
+        }
+
+        // Write doc values
+        for (field_id, doc_values) in builder.doc_values.iter() {
+            let kb = KeyBuilder::segment_doc_values(segment, field_id.0);
+            try!(write_batch.put(&kb.key(), &doc_values.build().to_bytes()));
+        }
+
         // Write statistics
         for (name, value) in builder.statistics.iter() {
             let kb = KeyBuilder::segment_stat(segment, name);
@@ -273,23 +425,19 @@ impl RocksDBStore {
             try!(write_batch.put(&kb.key(), &value_bytes));
         }
 
-        // Write data
-        try!(self.db.write(write_batch));
-
-        Ok(segment)
+        Ok((segment, write_batch))
     }
 
-    pub fn remove_document_by_key(&self, doc_key: &str) -> Result<bool, rocksdb::Error> {
-        match try!(self.document_index.delete_document_by_key(&self.db, &doc_key.as_bytes().iter().cloned().collect())) {
-            Some(_doc_id) => Ok(true),
-            None => Ok(false),
-        }
+    /// Deletes a document, returns the version of the deletion or None if there was no document with the key
+    pub fn remove_document_by_key(&self, doc_key: &str, condition: Option<VersionCondition>) -> Result<Option<DocumentVersion>, DocumentWriteError> {
+        self.document_index.delete_document_by_key(&self.db, &doc_key.as_bytes().iter().cloned().collect(), condition)
     }
 
     pub fn reader<'a>(&'a self) -> RocksDBReader<'a> {
         RocksDBReader {
             store: &self,
-            snapshot: self.db.snapshot(),
+            snapshot: SharedSnapshot(self.db.snapshot()),
+            doc_values_cache: Mutex::new(FnvHashMap::default()),
         }
     }
 }
@@ -300,45 +448,133 @@ impl fmt::Debug for RocksDBStore {
     }
 }
 
-pub enum StoredFieldReadError {
-    /// The provided FieldId wasn't valid for this index
-    InvalidFieldId(FieldId),
+impl Store for RocksDBStore {
+    fn reader<'a>(&'a self) -> Box<Reader + 'a> {
+        Box::new(RocksDBStore::reader(self))
+    }
 
-    /// A RocksDB error occurred while reading from the disk
-    RocksDBError(rocksdb::Error),
+    fn path(&self) -> Option<&Path> {
+        Some(RocksDBStore::path(self))
+    }
+
+    fn add_field(&mut self, name: String, field_type: FieldType, field_flags: FieldFlags) -> Result<FieldId, AddFieldError> {
+        RocksDBStore::add_field(self, name, field_type, field_flags)
+    }
+
+    fn remove_field(&mut self, field_id: &FieldId) -> bool {
+        RocksDBStore::remove_field(self, field_id)
+    }
+
+    fn insert_or_update_document(&self, doc: &Document, condition: Option<VersionCondition>) -> Result<DocumentVersion, DocumentWriteError> {
+        match RocksDBStore::insert_or_update_document(self, doc, condition) {
+            Ok(version) => Ok(version),
+            Err(DocumentInsertError::VersionConflict(conflict)) => Err(DocumentWriteError::VersionConflict(conflict)),
+            Err(e) => Err(DocumentWriteError::StorageError(format!("{:?}", e))),
+        }
+    }
 
-    /// A UTF-8 decode error occured while reading a Text field
-    TextFieldUTF8DecodeError(Vec<u8>, str::Utf8Error),
+    fn insert_or_update_documents(&self, docs: &[Document], conditions: &[Option<VersionCondition>]) -> Result<Vec<Result<DocumentVersion, VersionConflict>>, String> {
+        RocksDBStore::insert_or_update_documents(self, docs, conditions).map_err(|e| format!("{:?}", e))
+    }
 
-    /// A boolean field was read but the value wasn't a boolean
-    BooleanFieldDecodeError(Vec<u8>),
+    fn remove_document_by_key(&self, doc_key: &str, condition: Option<VersionCondition>) -> Result<Option<DocumentVersion>, DocumentWriteError> {
+        RocksDBStore::remove_document_by_key(self, doc_key, condition)
+    }
 
-    /// An integer/datetime field was read but the value wasn't 8 bytes
-    IntegerFieldValueSizeError(usize),
+    fn filter_cache_stats(&self) -> FilterCacheStats {
+        self.filter_cache.stats()
+    }
+
+    fn as_rocksdb(&self) -> Option<&RocksDBStore> {
+        Some(self)
+    }
+}
+
+impl From<rocksdb::Error> for DocumentWriteError {
+    fn from(e: rocksdb::Error) -> DocumentWriteError {
+        DocumentWriteError::StorageError(e.into())
+    }
 }
 
 impl From<rocksdb::Error> for StoredFieldReadError {
     fn from(e: rocksdb::Error) -> StoredFieldReadError {
-        StoredFieldReadError::RocksDBError(e)
+        StoredFieldReadError::StorageError(e.into())
+    }
+}
+
+/// A RocksDB snapshot that can be read from several threads at once
+///
+/// Searches read the segments of a reader from several threads, but the snapshot handle holds a
+/// raw pointer so it isn't Sync.
+struct SharedSnapshot<'a>(Snapshot<'a>);
+
+// Safety: sharing a reference to the snapshot between threads is safe for both of its fields:
+//
+//  - "db" is a &DB, which is Sync as the DB handle is Sync (RocksDB allows a database to be read
+//    and written from any number of threads at once).
+//  - "inner" is a *const pointer to a RocksDB snapshot. The snapshot is immutable once it has
+//    been taken, RocksDB only reads the sequence number from it. The &self methods (get and the
+//    iterators) copy the pointer into a new ReadOptions for each read, so no state is shared
+//    between the threads apart from the snapshot itself. The pointer is only released when the
+//    handle is dropped, which needs exclusive access so no other thread can be using it.
+//
+// This isn't Send as well, the snapshot stays with the reader on the thread that created it.
+unsafe impl<'a> Sync for SharedSnapshot<'a> {}
+
+impl<'a> Deref for SharedSnapshot<'a> {
+    type Target = Snapshot<'a>;
+
+    fn deref(&self) -> &Snapshot<'a> {
+        &self.0
     }
 }
 
 pub struct RocksDBReader<'a> {
     store: &'a RocksDBStore,
-    snapshot: Snapshot<'a>
+    snapshot: SharedSnapshot<'a>,
+
+    /// Doc values that have been loaded by this reader, keyed by segment and field
+    /// None is cached for segments that don't have doc values for the field
+    doc_values_cache: Mutex<FnvHashMap<(SegmentId, FieldId), Option<Arc<DocValues>>>>,
 }
 
 impl<'a> RocksDBReader<'a> {
-    pub fn schema(&self) -> &Schema {
+    /// Loads the doc values of a field in a segment, these are cached for the lifetime of the reader
+    pub fn load_doc_values(&self, field_id: FieldId, segment_id: SegmentId) -> Option<Arc<DocValues>> {
+        let mut cache = self.doc_values_cache.lock().unwrap();
+
+        cache.entry((segment_id, field_id)).or_insert_with(|| {
+            let segment = RocksDBSegment::new(self, segment_id.0);
+
+            match segment.load_doc_values(field_id) {
+                Ok(Some(doc_values)) => Some(Arc::new(doc_values)),
+                Ok(None) | Err(_) => None,
+            }
+        }).clone()
+    }
+}
+
+impl<'a> Reader for RocksDBReader<'a> {
+    fn schema(&self) -> &Schema {
         &self.store.schema
     }
 
-    pub fn contains_document_key(&self, doc_key: &str) -> bool {
+    fn contains_document_key(&self, doc_key: &str) -> bool {
         // TODO: use snapshot
         self.store.document_index.contains_document_key(&doc_key.as_bytes().iter().cloned().collect())
     }
 
-    pub fn read_stored_field(&self, field_id: FieldId, doc_id: DocId) -> Result<Option<FieldValue>, StoredFieldReadError> {
+    fn get_document_by_key(&self, doc_key: &str) -> Option<DocId> {
+        // TODO: use snapshot
+        self.store.document_index.get_document_by_key(&doc_key.as_bytes().iter().cloned().collect())
+    }
+
+    fn get_document_version(&self, doc_key: &str) -> Option<DocumentVersion> {
+        // TODO: use snapshot
+        self.store.document_index.get_document_version(&doc_key.as_bytes().iter().cloned().collect())
+    }
+
+    fn read_stored_field(&self, field_id: FieldId, doc_id: DocId) -> Result<Option<FieldValue>, StoredFieldReadError> {
         let field_info = match self.schema().get(&field_id) {
             Some(field_info) => field_info,
             None => return Err(StoredFieldReadError::InvalidFieldId(field_id)),
@@ -347,68 +583,84 @@ impl<'a> RocksDBReader<'a> {
         let kb = KeyBuilder::stored_field_value((doc_id.0).0, doc_id.1, field_id.0, b"val");
 
         match try!(self.snapshot.get(&kb.key())) {
-            Some(value) => {
-                match field_info.field_type {
-                    FieldType::Text | FieldType::PlainString => {
-                        match str::from_utf8(&value) {
-                            Ok(value_str) => {
-                                Ok(Some(FieldValue::String(value_str.to_string())))
-                            }
-                            Err(e) => {
-                                Err(StoredFieldReadError::TextFieldUTF8DecodeError(value.to_vec(), e))
-                            }
-                        }
-                    }
-                    FieldType::I64 => {
-                        if value.len() != 8 {
-                            return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()));
-                        }
-
-                        Ok(Some(FieldValue::Integer(LittleEndian::read_i64(&value))))
-                    }
-                    FieldType::Boolean => {
-                        if value[..] == [b't'] {
-                            Ok(Some(FieldValue::Boolean(true)))
-                        } else if value[..] == [b'f'] {
-                            Ok(Some(FieldValue::Boolean(false)))
-                        } else {
-                            Err(StoredFieldReadError::BooleanFieldDecodeError(value.to_vec()))
-                        }
-                    }
-                    FieldType::DateTime => {
-                        if value.len() != 8 {
-                            return Err(StoredFieldReadError::IntegerFieldValueSizeError(value.len()))
-                        }
-
-                        let timestamp_with_micros = LittleEndian::read_i64(&value);
-                        let timestamp = timestamp_with_micros / 1000000;
-                        let micros = timestamp_with_micros % 1000000;
-                        let nanos = micros * 1000;
-                        let datetime = NaiveDateTime::from_timestamp(timestamp, nanos as u32);
-                        Ok(Some(FieldValue::DateTime(DateTime::from_utc(datetime, Utc))))
-                    }
-                }
-            }
+            Some(value) => Ok(Some(try!(decode_stored_field_value(&field_info.field_type, &value)))),
             None => Ok(None),
         }
     }
+
+    fn search(&self, collector: &mut Collector, query: &Query) -> Result<(), String> {
+        let segments = self.store.segments.iter_active(self).collect::<Vec<_>>();
+        execution::search(collector, query, self, &segments, &self.store.filter_cache)
+    }
+
+    fn explain(&self, query: &Query, doc_id: DocId) -> Result<Option<Explanation>, String> {
+        let segments = self.store.segments.iter_active(self).collect::<Vec<_>>();
+        execution::explain(query, doc_id, self, self.schema(), &segments)
+    }
+}
+
+impl<'a> TermDictionaryReader for RocksDBReader<'a> {
+    fn get_term_id(&self, term: &Term) -> Option<TermId> {
+        self.store.term_dictionary.get(&self.store.db, term).unwrap()
+    }
+
+    fn get_term(&self, field_id: FieldId, term_id: TermId) -> Option<Term> {
+        self.store.term_dictionary.get_term(&self.store.db, field_id, term_id)
+    }
+
+    fn select_terms_until(&self, field_id: FieldId, term_selector: &MultiTermSelector, should_terminate: &mut FnMut() -> bool) -> Vec<TermId> {
+        self.store.term_dictionary.select(&self.store.db, field_id, term_selector, should_terminate)
+    }
+}
+
+impl<'a> FieldValueReader for RocksDBReader<'a> {
+    fn read_field_value(&self, field_id: FieldId, doc_id: DocId) -> Option<FieldValue> {
+        let field_type = match self.schema().get(&field_id) {
+            Some(field_info) => field_info.field_type.clone(),
+            None => return None,
+        };
+
+        // Read from the doc values if we can, this saves a lookup per document
+        if field_type_has_doc_values(&field_type) {
+            if let Some(doc_values) = self.load_doc_values(field_id, doc_id.0) {
+                return doc_values.get_field_value(doc_id.1, &field_type);
+            }
+        }
+
+        // Segments written before doc values were added don't have them, so fall back
+        // to reading the stored value
+        match self.read_stored_field(field_id, doc_id) {
+            Ok(value) => value,
+            Err(_) => None,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::fs::remove_dir_all;
     use std::path::Path;
+    use std::time::{Duration, Instant};
 
     use rocksdb::DB;
     use fnv::FnvHashMap;
     use search::{Term, Token, Document};
     use search::document::FieldValue;
-    use search::schema::{FieldType, FIELD_INDEXED, FIELD_STORED};
+    use search::schema::{FieldType, FieldId, FIELD_INDEXED, FIELD_STORED};
     use search::query::Query;
     use search::query::term_scorer::TermScorer;
     use search::collectors::top_score::TopScoreCollector;
-
-    use super::RocksDBStore;
+    use search::collectors::{Collector, DocumentMatch, FieldValueReader};
+    use search::collectors::total_count::TotalCountCollector;
+    use search::collectors::multi::MultiCollector;
+    use search::collectors::deadline::DeadlineCollector;
+    use search::collectors::terminate_after::TerminateAfterCollector;
+    use search::segment::{encode_field_length, decode_term_frequencies, TermImpact};
+    use search::version::VersionCondition;
+    use search::backends::{Reader, DocumentWriteError};
+
+    use super::{RocksDBStore, DocumentInsertError};
+    use super::key_builder::KeyBuilder;
 
     fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
         match remove_dir_all(&path) {
@@ -441,7 +693,7 @@ mod tests {
         assert!(store.is_ok());
     }
 
-    fn make_test_store(path: &str) -> RocksDBStore {
+    pub fn make_test_store(path: &str) -> RocksDBStore {
         let mut store = RocksDBStore::create(path).unwrap();
         let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
         let body_field = store.add_field("body".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
@@ -475,7 +727,7 @@ mod tests {
             key: "test_doc".to_string(),
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
-        }).unwrap();
+        }, None).unwrap();
 
         let mut indexed_fields = FnvHashMap::default();
         indexed_fields.insert(
@@ -504,7 +756,7 @@ mod tests {
             key: "another_test_doc".to_string(),
             indexed_fields: indexed_fields,
             stored_fields: stored_fields,
-        }).unwrap();
+        }, None).unwrap();
 
         store.merge_segments(&vec![1, 2]).unwrap();
         store.purge_segments(&vec![1, 2]).unwrap();
@@ -579,4 +831,847 @@ mod tests {
         let docs = collector.into_sorted_vec();
         println!("{:?}", docs);
     }
+
+    #[test]
+    fn test_phrase() {
+        remove_dir_all_ignore_error("test_indices/test_phrase");
+
+        make_test_store("test_indices/test_phrase");
+
+        let store = RocksDBStore::open("test_indices/test_phrase").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+
+        let index_reader = store.reader();
+
+        // Terms are next to each other, in the right order
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Phrase {
+            field: title_field,
+            terms: vec![(Term::from_string("hello"), 0), (Term::from_string("world"), 1)],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+
+        // Terms are in the wrong order
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Phrase {
+            field: title_field,
+            terms: vec![(Term::from_string("world"), 0), (Term::from_string("hello"), 1)],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 0);
+
+        // Terms are in different documents
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Phrase {
+            field: title_field,
+            terms: vec![(Term::from_string("hello"), 0), (Term::from_string("partner"), 1)],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 0);
+    }
+
+    #[test]
+    fn test_phrase_positions_survive_merge() {
+        remove_dir_all_ignore_error("test_indices/test_phrase_positions_survive_merge");
+
+        let mut store = RocksDBStore::create("test_indices/test_phrase_positions_survive_merge").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // "new the york", with the stopword removed and a gap left in its place
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(
+            title_field,
+            vec![
+                Token { term: Term::from_string("new"), position: 1 },
+                Token { term: Term::from_string("york"), position: 3 },
+            ].into()
+        );
+        store.insert_or_update_document(&Document {
+            key: "gap".to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: FnvHashMap::default(),
+        }, None).unwrap();
+
+        // "new york"
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(
+            title_field,
+            vec![
+                Token { term: Term::from_string("new"), position: 1 },
+                Token { term: Term::from_string("york"), position: 2 },
+            ].into()
+        );
+        store.insert_or_update_document(&Document {
+            key: "no_gap".to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: FnvHashMap::default(),
+        }, None).unwrap();
+
+        let segments = store.get_segment_statistics().unwrap().iter().map(|&(segment, _)| segment).collect::<Vec<_>>();
+        assert_eq!(segments.len(), 2);
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+        assert_eq!(store.get_segment_statistics().unwrap().len(), 1);
+
+        let index_reader = store.reader();
+
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Phrase {
+            field: title_field,
+            terms: vec![(Term::from_string("new"), 0), (Term::from_string("york"), 2)],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+        assert!(index_reader.get_document_by_key("gap").is_some());
+
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Phrase {
+            field: title_field,
+            terms: vec![(Term::from_string("new"), 0), (Term::from_string("york"), 1)],
+            slop: 0,
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_minimum_match() {
+        remove_dir_all_ignore_error("test_indices/test_minimum_match");
+
+        make_test_store("test_indices/test_minimum_match");
+
+        let store = RocksDBStore::open("test_indices/test_minimum_match").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+
+        let index_reader = store.reader();
+
+        // Only the first document has two of these terms
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::MinimumMatch {
+            queries: vec![
+                Query::term(title_field, Term::from_string("hello")),
+                Query::term(title_field, Term::from_string("world")),
+                Query::term(title_field, Term::from_string("partner")),
+            ],
+            minimum: 2,
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+
+        // Both documents have one of these terms
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::MinimumMatch {
+            queries: vec![
+                Query::term(title_field, Term::from_string("hello")),
+                Query::term(title_field, Term::from_string("world")),
+                Query::term(title_field, Term::from_string("partner")),
+            ],
+            minimum: 1,
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 2);
+    }
+
+    #[test]
+    fn test_combined_scores() {
+        remove_dir_all_ignore_error("test_indices/test_combined_scores");
+
+        make_test_store("test_indices/test_combined_scores");
+
+        let store = RocksDBStore::open("test_indices/test_combined_scores").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+
+        let index_reader = store.reader();
+        let top_score = |query: &Query| {
+            let mut collector = TopScoreCollector::new(1);
+            index_reader.search(&mut collector, query).unwrap();
+            collector.into_sorted_vec()[0].score().unwrap()
+        };
+
+        let queries = || vec![
+            Query::term(title_field, Term::from_string("hello")),
+            Query::term(title_field, Term::from_string("world")),
+        ];
+        let hello_score = top_score(&queries()[0]);
+        let world_score = top_score(&queries()[1]);
+
+        // Conjunction and Disjunction queries average the scores
+        let score = top_score(&Query::Conjunction { queries: queries() });
+        assert!((score - (hello_score + world_score) / 2.0f32).abs() < 0.0001);
+
+        let score = top_score(&Query::Disjunction { queries: queries() });
+        assert!((score - (hello_score + world_score) / 2.0f32).abs() < 0.0001);
+
+        // MinimumMatch queries (used by "bool") add them up
+        let score = top_score(&Query::MinimumMatch { queries: queries(), minimum: 1 });
+        assert!((score - (hello_score + world_score)).abs() < 0.0001);
+
+        let score = top_score(&Query::MinimumMatch { queries: queries(), minimum: 2 });
+        assert!((score - (hello_score + world_score)).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_get_document_by_key() {
+        remove_dir_all_ignore_error("test_indices/test_get_document_by_key");
+
+        make_test_store("test_indices/test_get_document_by_key");
+
+        let store = RocksDBStore::open("test_indices/test_get_document_by_key").unwrap();
+        let pk_field = store.schema.get_field_by_name("pk").unwrap();
+
+        let index_reader = store.reader();
+
+        let doc_id = index_reader.get_document_by_key("another_test_doc").unwrap();
+        match index_reader.read_stored_field(pk_field, doc_id) {
+            Ok(Some(FieldValue::Integer(pk))) => assert_eq!(pk, 2),
+            _ => panic!("expected pk to be stored"),
+        }
+
+        assert!(index_reader.get_document_by_key("missing_doc").is_none());
+    }
+
+    #[test]
+    fn test_explain() {
+        remove_dir_all_ignore_error("test_indices/test_explain");
+
+        make_test_store("test_indices/test_explain");
+
+        let store = RocksDBStore::open("test_indices/test_explain").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+
+        let index_reader = store.reader();
+
+        let query = Query::DisjunctionMax {
+            queries: vec![
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("howdy"),
+                    scorer: TermScorer::default_with_boost(2.0f32),
+                },
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("partner"),
+                    scorer: TermScorer::default(),
+                },
+            ]
+        };
+
+        let mut collector = TopScoreCollector::new(10);
+        index_reader.search(&mut collector, &query).unwrap();
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs.len(), 1);
+
+        // The explanation should give the same score as the search
+        let doc_id = index_reader.get_document_by_key("another_test_doc").unwrap();
+        let explanation = index_reader.explain(&query, doc_id).unwrap().unwrap();
+        assert_eq!(explanation.value, docs[0].score().unwrap());
+        assert_eq!(explanation.description, "max of:");
+        assert_eq!(explanation.details.len(), 2);
+        assert_eq!(explanation.details[0].details[0].value, 2.0f32);
+
+        // Documents that don't match shouldn't have an explanation
+        let doc_id = index_reader.get_document_by_key("test_doc").unwrap();
+        assert!(index_reader.explain(&query, doc_id).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_doc_values() {
+        remove_dir_all_ignore_error("test_indices/test_doc_values");
+
+        make_test_store("test_indices/test_doc_values");
+
+        let store = RocksDBStore::open("test_indices/test_doc_values").unwrap();
+        let pk_field = store.schema.get_field_by_name("pk").unwrap();
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+
+        let index_reader = store.reader();
+
+        // The doc values of both documents should have been remapped into the merged segment
+        let doc_id = index_reader.get_document_by_key("test_doc").unwrap();
+        let another_doc_id = index_reader.get_document_by_key("another_test_doc").unwrap();
+        assert_eq!(doc_id.0, another_doc_id.0);
+
+        let doc_values = index_reader.load_doc_values(pk_field, doc_id.0).unwrap();
+        assert_eq!(doc_values.get_numeric(doc_id.1), Some(1));
+        assert_eq!(doc_values.get_numeric(another_doc_id.1), Some(2));
+
+        match index_reader.read_field_value(pk_field, another_doc_id) {
+            Some(FieldValue::Integer(pk)) => assert_eq!(pk, 2),
+            _ => panic!("expected pk to be read from doc values"),
+        }
+
+        // Text fields don't have doc values
+        assert!(index_reader.load_doc_values(title_field, doc_id.0).is_none());
+    }
+
+    #[test]
+    fn test_insert_or_update_documents() {
+        remove_dir_all_ignore_error("test_indices/test_insert_or_update_documents");
+
+        let mut store = RocksDBStore::create("test_indices/test_insert_or_update_documents").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let make_doc = |key: &str, title: &str| {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                vec![
+                    Token { term: Term::from_string(title), position: 1 },
+                ].into()
+            );
+
+            Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+            }
+        };
+
+        // The second "doc_a" should replace the first one
+        store.insert_or_update_documents(&[
+            make_doc("doc_a", "hello"),
+            make_doc("doc_b", "hello"),
+            make_doc("doc_a", "world"),
+        ], &[None; 3]).unwrap();
+
+        let index_reader = store.reader();
+
+        // All documents should have been written into a single segment
+        let doc_a = index_reader.get_document_by_key("doc_a").unwrap();
+        let doc_b = index_reader.get_document_by_key("doc_b").unwrap();
+        assert_eq!(doc_a.0, doc_b.0);
+        assert_eq!(index_reader.store.segments.iter_active(&index_reader).count(), 1);
+
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Term {
+            field: title_field,
+            term: Term::from_string("world"),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_merge_removes_deleted_documents() {
+        remove_dir_all_ignore_error("test_indices/test_merge_removes_deleted_documents");
+
+        let store = make_test_store("test_indices/test_merge_removes_deleted_documents");
+        let title_field = store.schema.get_field_by_name("title").unwrap();
+        let body_field = store.schema.get_field_by_name("body").unwrap();
+
+        assert!(store.remove_document_by_key("test_doc", None).unwrap().is_some());
+
+        let segment_stats = store.get_segment_statistics().unwrap();
+        assert_eq!(segment_stats.len(), 1);
+        assert_eq!(segment_stats[0].1.total_docs(), 2);
+        assert_eq!(segment_stats[0].1.deleted_docs(), 1);
+
+        // Rewrite the segment on its own, this should drop the deleted document
+        let segments = vec![segment_stats[0].0];
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        let segment_stats = store.get_segment_statistics().unwrap();
+        assert_eq!(segment_stats.len(), 1);
+        assert_eq!(segment_stats[0].1.total_docs(), 1);
+        assert_eq!(segment_stats[0].1.deleted_docs(), 0);
+
+        // The deleted document's tokens shouldn't be counted any more
+        let title_stats = store.get_field_statistics(title_field).unwrap();
+        assert_eq!(title_stats.doc_count(), 1);
+        assert_eq!(title_stats.sum_doc_freq(), 2);
+        assert_eq!(title_stats.sum_total_term_freq(), 2);
+
+        let body_stats = store.get_field_statistics(body_field).unwrap();
+        assert_eq!(body_stats.doc_count(), 1);
+        assert_eq!(body_stats.sum_doc_freq(), 3);
+        assert_eq!(body_stats.sum_total_term_freq(), 3);
+
+        // The remaining document should still be searchable
+        let index_reader = store.reader();
+        assert!(index_reader.get_document_by_key("another_test_doc").is_some());
+        assert!(index_reader.get_document_by_key("test_doc").is_none());
+
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Term {
+            field: title_field,
+            term: Term::from_string("hello"),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 0);
+
+        let mut collector = TotalCountCollector::new();
+        index_reader.search(&mut collector, &Query::Term {
+            field: body_field,
+            term: Term::from_string("lorem"),
+            scorer: TermScorer::default(),
+        }).unwrap();
+        assert_eq!(collector.get_total_count(), 1);
+    }
+
+    #[test]
+    fn test_deactivate_segments() {
+        remove_dir_all_ignore_error("test_indices/test_deactivate_segments");
+
+        let store = make_test_store("test_indices/test_deactivate_segments");
+
+        assert!(store.remove_document_by_key("test_doc", None).unwrap().is_some());
+        assert!(store.remove_document_by_key("another_test_doc", None).unwrap().is_some());
+
+        let segments = store.get_segment_statistics().unwrap().iter().map(|&(segment, _)| segment).collect::<Vec<_>>();
+        store.deactivate_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        assert_eq!(store.get_segment_statistics().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_document_versions() {
+        remove_dir_all_ignore_error("test_indices/test_document_versions");
+
+        {
+            let store = make_test_store("test_indices/test_document_versions");
+            let test_doc_version = store.reader().get_document_version("test_doc").unwrap();
+            assert_eq!(test_doc_version.version, 1);
+            assert_eq!(test_doc_version.seq_no, 0);
+
+            // Update "test_doc" with its current sequence number
+            let doc = Document {
+                key: "test_doc".to_string(),
+                indexed_fields: FnvHashMap::default(),
+                stored_fields: FnvHashMap::default(),
+            };
+
+            let version = store.insert_or_update_document(&doc, Some(VersionCondition::SeqNo { seq_no: 0, primary_term: 1 })).unwrap();
+            assert_eq!(version.version, 2);
+            assert_eq!(version.seq_no, 2);
+
+            // Using the old sequence number again should conflict
+            match store.insert_or_update_document(&doc, Some(VersionCondition::SeqNo { seq_no: 0, primary_term: 1 })) {
+                Err(DocumentInsertError::VersionConflict(conflict)) => assert_eq!(conflict.current, Some(version)),
+                result => panic!("expected a version conflict, got {:?}", result),
+            }
+
+            // External versions must increase
+            assert_eq!(store.insert_or_update_document(&doc, Some(VersionCondition::External(10))).unwrap().version, 10);
+            assert!(store.insert_or_update_document(&doc, Some(VersionCondition::External(10))).is_err());
+
+            // Conflicts in a batch don't stop the other documents being written
+            let results = store.insert_or_update_documents(&[doc.clone(), doc.clone()], &[Some(VersionCondition::Internal(9)), Some(VersionCondition::Internal(10))]).unwrap();
+            assert!(results[0].is_err());
+            assert_eq!(results[1].as_ref().unwrap().version, 11);
+            assert_eq!(store.reader().get_document_version("test_doc").unwrap().version, 11);
+
+            match store.remove_document_by_key("another_test_doc", Some(VersionCondition::Internal(2))) {
+                Err(DocumentWriteError::VersionConflict(_)) => {}
+                result => panic!("expected a version conflict, got {:?}", result),
+            }
+            assert_eq!(store.remove_document_by_key("another_test_doc", Some(VersionCondition::Internal(1))).unwrap().unwrap().version, 2);
+        }
+
+        // Versions and sequence numbers should survive reopening the store
+        let store = RocksDBStore::open("test_indices/test_document_versions").unwrap();
+        assert_eq!(store.reader().get_document_version("test_doc").unwrap().version, 11);
+        assert!(store.reader().get_document_version("another_test_doc").is_none());
+
+        // The versions of a deleted document carry on from its deletion
+        let doc = Document {
+            key: "another_test_doc".to_string(),
+            indexed_fields: FnvHashMap::default(),
+            stored_fields: FnvHashMap::default(),
+        };
+        let version = store.insert_or_update_document(&doc, None).unwrap();
+        assert_eq!(version.version, 3);
+        assert_eq!(version.seq_no, 6);
+    }
+
+    #[test]
+    fn test_deleted_document_versions() {
+        remove_dir_all_ignore_error("test_indices/test_deleted_document_versions");
+
+        let doc = Document {
+            key: "test_doc".to_string(),
+            indexed_fields: FnvHashMap::default(),
+            stored_fields: FnvHashMap::default(),
+        };
+
+        {
+            let store = make_test_store("test_indices/test_deleted_document_versions");
+            assert_eq!(store.remove_document_by_key("test_doc", Some(VersionCondition::External(5))).unwrap().unwrap().version, 5);
+
+            // Indexing the document with an external version from before the deletion should conflict
+            match store.insert_or_update_document(&doc, Some(VersionCondition::External(3))) {
+                Err(DocumentInsertError::VersionConflict(conflict)) => {
+                    assert_eq!(conflict.current, None);
+                    assert_eq!(conflict.deleted.map(|deleted| deleted.version), Some(5));
+                }
+                result => panic!("expected a version conflict, got {:?}", result),
+            }
+            assert!(store.reader().get_document_version("test_doc").is_none());
+        }
+
+        // The deletion should survive reopening the store
+        let store = RocksDBStore::open("test_indices/test_deleted_document_versions").unwrap();
+        assert!(store.insert_or_update_document(&doc, Some(VersionCondition::External(3))).is_err());
+        assert_eq!(store.insert_or_update_document(&doc, Some(VersionCondition::External(6))).unwrap().version, 6);
+
+        // Once the document has been written again, it's no longer deleted
+        assert_eq!(store.insert_or_update_document(&doc, None).unwrap().version, 7);
+    }
+
+    #[test]
+    fn test_term_frequencies_and_norms() {
+        remove_dir_all_ignore_error("test_indices/test_term_frequencies_and_norms");
+
+        let mut store = RocksDBStore::create("test_indices/test_term_frequencies_and_norms").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let make_doc = |key: &str, words: &[&str]| {
+            let mut indexed_fields = FnvHashMap::default();
+            indexed_fields.insert(
+                title_field,
+                words.iter().enumerate().map(|(position, word)| {
+                    Token { term: Term::from_string(word), position: position as u32 + 1 }
+                }).collect::<Vec<_>>().into()
+            );
+
+            Document {
+                key: key.to_string(),
+                indexed_fields: indexed_fields,
+                stored_fields: FnvHashMap::default(),
+            }
+        };
+
+        // Each document goes into its own segment. "world" is the only term in the second
+        // segment, so its TermId in the segment builder is different to the one in the index
+        store.insert_or_update_document(&make_doc("doc_a", &["hello"]), None).unwrap();
+        store.insert_or_update_document(&make_doc("doc_b", &["world", "world"]), None).unwrap();
+
+        let hello = store.term_dictionary.get(&store.db, &Term::from_string("hello")).unwrap().unwrap();
+        let world = store.term_dictionary.get(&store.db, &Term::from_string("world")).unwrap().unwrap();
+        let segments = store.get_segment_statistics().unwrap().iter().map(|&(segment, _)| segment).collect::<Vec<u32>>();
+        assert_eq!(segments.len(), 2);
+
+        // Term frequencies are only written for terms that occur more than once
+        let kb = KeyBuilder::segment_term_frequencies(segments[1], title_field.0, world.0);
+        assert_eq!(decode_term_frequencies(&store.db.get(&kb.key()).unwrap().unwrap()), vec![2]);
+        let kb = KeyBuilder::segment_term_frequencies(segments[0], title_field.0, hello.0);
+        assert!(store.db.get(&kb.key()).unwrap().is_none());
+
+        let kb = KeyBuilder::segment_field_norms(segments[1], title_field.0);
+        assert_eq!(store.db.get(&kb.key()).unwrap().unwrap().to_vec(), vec![encode_field_length(2)]);
+
+        let search_world = |store: &RocksDBStore| {
+            let mut collector = TopScoreCollector::new(10);
+            store.reader().search(&mut collector, &Query::Term {
+                field: title_field,
+                term: Term::from_string("world"),
+                scorer: TermScorer::default(),
+            }).unwrap();
+            collector.into_sorted_vec()[0].score().unwrap()
+        };
+        let score_before_merge = search_world(&store);
+
+        // Merging the segments should keep the term frequencies and norms
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        let merged_segments = store.get_segment_statistics().unwrap();
+        assert_eq!(merged_segments.len(), 1);
+        let kb = KeyBuilder::segment_term_frequencies(merged_segments[0].0, title_field.0, world.0);
+        assert_eq!(decode_term_frequencies(&store.db.get(&kb.key()).unwrap().unwrap()), vec![2]);
+
+        assert_eq!(store.get_field_statistics(title_field).unwrap().sum_total_term_freq(), 3);
+        assert_eq!(search_world(&store), score_before_merge);
+    }
+
+    fn make_text_doc(field: FieldId, key: &str, words: &[&str]) -> Document {
+        let mut indexed_fields = FnvHashMap::default();
+        indexed_fields.insert(
+            field,
+            words.iter().enumerate().map(|(position, word)| {
+                Token { term: Term::from_string(word), position: position as u32 + 1 }
+            }).collect::<Vec<_>>().into()
+        );
+
+        Document {
+            key: key.to_string(),
+            indexed_fields: indexed_fields,
+            stored_fields: FnvHashMap::default(),
+        }
+    }
+
+    #[test]
+    fn test_term_impacts() {
+        remove_dir_all_ignore_error("test_indices/test_term_impacts");
+
+        let mut store = RocksDBStore::create("test_indices/test_term_impacts").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        store.insert_or_update_document(&make_text_doc(title_field, "doc_a", &["hello", "hello", "hello", "world"]), None).unwrap();
+        store.insert_or_update_document(&make_text_doc(title_field, "doc_b", &["hello"]), None).unwrap();
+
+        let hello = store.term_dictionary.get(&store.db, &Term::from_string("hello")).unwrap().unwrap();
+        let segments = store.get_segment_statistics().unwrap().iter().map(|&(segment, _)| segment).collect::<Vec<u32>>();
+        assert_eq!(segments.len(), 2);
+
+        let kb = KeyBuilder::segment_term_impact(segments[0], title_field.0, hello.0);
+        assert_eq!(TermImpact::from_bytes(&store.db.get(&kb.key()).unwrap().unwrap()), Some(TermImpact::new(3, encode_field_length(4))));
+
+        // The impact of a merged segment only covers the documents that weren't deleted
+        store.remove_document_by_key("doc_a", None).unwrap();
+        store.merge_segments(&segments).unwrap();
+        store.purge_segments(&segments).unwrap();
+
+        let merged_segments = store.get_segment_statistics().unwrap();
+        assert_eq!(merged_segments.len(), 1);
+        let kb = KeyBuilder::segment_term_impact(merged_segments[0].0, title_field.0, hello.0);
+        assert_eq!(TermImpact::from_bytes(&store.db.get(&kb.key()).unwrap().unwrap()), Some(TermImpact::new(1, encode_field_length(1))));
+    }
+
+    #[test]
+    fn test_top_score_skips_uncompetitive_documents() {
+        remove_dir_all_ignore_error("test_indices/test_top_score_skips_uncompetitive_documents");
+
+        let mut store = RocksDBStore::create("test_indices/test_top_score_skips_uncompetitive_documents").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Put the documents into a few segments, with the best matches spread between them
+        let words = ["hello", "world", "foo", "bar"];
+        for segment in 0..4 {
+            let mut docs = Vec::new();
+            for i in 0..50 {
+                let doc_words = (0..(i % 7) + 1).map(|j| words[(i * j + segment) % words.len()]).collect::<Vec<_>>();
+                docs.push(make_text_doc(title_field, &format!("doc_{}_{}", segment, i), &doc_words));
+            }
+
+            let conditions = vec![None; docs.len()];
+            store.insert_or_update_documents(&docs, &conditions).unwrap();
+        }
+
+        let query = Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("hello"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("bar"),
+                    scorer: TermScorer::default(),
+                },
+            ],
+        };
+
+        // Documents are only skipped when the top score collector is used on its own
+        let mut top_score = TopScoreCollector::new(5);
+        store.reader().search(&mut top_score, &query).unwrap();
+
+        let mut all_top_score = TopScoreCollector::new(5);
+        let mut total_count = TotalCountCollector::new();
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut all_top_score);
+            collector.add(&mut total_count);
+            store.reader().search(&mut collector, &query).unwrap();
+        }
+
+        // Documents may have the same score, so only compare the scores
+        let scores = top_score.into_sorted_vec().iter().map(|doc| doc.score().unwrap()).collect::<Vec<f32>>();
+        let all_scores = all_top_score.into_sorted_vec().iter().map(|doc| doc.score().unwrap()).collect::<Vec<f32>>();
+        assert_eq!(scores.len(), 5);
+        assert_eq!(scores, all_scores);
+        assert!(total_count.get_total_count() > 5);
+    }
+
+    /// Passes documents to another collector, without allowing the search to be split
+    struct UnsplitCollector<'a>(&'a mut Collector);
+
+    impl<'a> Collector for UnsplitCollector<'a> {
+        fn needs_score(&self) -> bool {
+            self.0.needs_score()
+        }
+
+        fn collect(&mut self, doc: DocumentMatch) {
+            self.0.collect(doc);
+        }
+    }
+
+    #[test]
+    fn test_parallel_search() {
+        remove_dir_all_ignore_error("test_indices/test_parallel_search");
+
+        let mut store = RocksDBStore::create("test_indices/test_parallel_search").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        let words = ["hello", "world", "foo", "bar"];
+        for segment in 0..6 {
+            let mut docs = Vec::new();
+            for i in 0..30 {
+                let doc_words = (0..(i % 5) + 1).map(|j| words[(i + j * segment) % words.len()]).collect::<Vec<_>>();
+                docs.push(make_text_doc(title_field, &format!("doc_{}_{}", segment, i), &doc_words));
+            }
+
+            let conditions = vec![None; docs.len()];
+            store.insert_or_update_documents(&docs, &conditions).unwrap();
+        }
+
+        let query = Query::Disjunction {
+            queries: vec![
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("hello"),
+                    scorer: TermScorer::default(),
+                },
+                Query::Term {
+                    field: title_field,
+                    term: Term::from_string("foo"),
+                    scorer: TermScorer::default(),
+                },
+            ],
+        };
+
+        // These collectors can be split, so the segments may be searched in parallel
+        let mut top_score = TopScoreCollector::new(20);
+        let mut total_count = TotalCountCollector::new();
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut top_score);
+            collector.add(&mut total_count);
+            store.reader().search(&mut collector, &query).unwrap();
+        }
+
+        // These are always searched one segment at a time
+        let mut unsplit_top_score = TopScoreCollector::new(20);
+        let mut unsplit_total_count = TotalCountCollector::new();
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut unsplit_top_score);
+            collector.add(&mut unsplit_total_count);
+            store.reader().search(&mut UnsplitCollector(&mut collector), &query).unwrap();
+        }
+
+        // Documents may have the same score, so only compare the scores
+        let scores = top_score.into_sorted_vec().iter().map(|doc| doc.score().unwrap()).collect::<Vec<f32>>();
+        let unsplit_scores = unsplit_top_score.into_sorted_vec().iter().map(|doc| doc.score().unwrap()).collect::<Vec<f32>>();
+        assert_eq!(scores.len(), 20);
+        assert_eq!(scores, unsplit_scores);
+        assert_eq!(total_count.get_total_count(), unsplit_total_count.get_total_count());
+    }
+
+    #[test]
+    fn test_filter_cache() {
+        remove_dir_all_ignore_error("test_indices/test_filter_cache");
+
+        let mut store = RocksDBStore::create("test_indices/test_filter_cache").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        store.insert_or_update_document(&make_text_doc(title_field, "doc_a", &["hello", "world"]), None).unwrap();
+        store.insert_or_update_document(&make_text_doc(title_field, "doc_b", &["hello", "foo"]), None).unwrap();
+        store.insert_or_update_document(&make_text_doc(title_field, "doc_c", &["bar"]), None).unwrap();
+
+        let query = Query::Filter {
+            query: Box::new(Query::term(title_field, Term::from_string("hello"))),
+            filter: Box::new(Query::Disjunction {
+                queries: vec![
+                    Query::term(title_field, Term::from_string("world")),
+                    Query::term(title_field, Term::from_string("bar")),
+                ],
+            }),
+        };
+
+        let search = |store: &RocksDBStore| {
+            let mut collector = TotalCountCollector::new();
+            store.reader().search(&mut collector, &query).unwrap();
+            collector.get_total_count()
+        };
+
+        // The filter is run on each of the three segments, then reused
+        assert_eq!(search(&store), 1);
+        assert_eq!(store.filter_cache.stats().misses, 3);
+        assert_eq!(store.filter_cache.stats().entries, 3);
+
+        assert_eq!(search(&store), 1);
+        assert_eq!(store.filter_cache.stats().hits, 3);
+
+        // Deletions don't change the cached filters, but are still applied
+        store.remove_document_by_key("doc_a", None).unwrap();
+        assert_eq!(search(&store), 0);
+        assert_eq!(store.filter_cache.stats().hits, 6);
+
+        // The entries of merged segments are removed
+        let segments = store.get_segment_statistics().unwrap().iter().map(|&(segment, _)| segment).collect::<Vec<u32>>();
+        store.merge_segments(&segments).unwrap();
+        assert_eq!(store.filter_cache.stats().entries, 0);
+        store.purge_segments(&segments).unwrap();
+
+        store.insert_or_update_document(&make_text_doc(title_field, "doc_d", &["hello", "bar"]), None).unwrap();
+        assert_eq!(search(&store), 1);
+    }
+
+    #[test]
+    fn test_terminate_early() {
+        remove_dir_all_ignore_error("test_indices/test_terminate_early");
+
+        let mut store = RocksDBStore::create("test_indices/test_terminate_early").unwrap();
+        let title_field = store.add_field("title".to_string(), FieldType::Text, FIELD_INDEXED).unwrap();
+
+        // Put the documents into a few segments, so the search stops between them too
+        for segment in 0..3 {
+            let docs = (0..5).map(|i| make_text_doc(title_field, &format!("doc_{}_{}", segment, i), &["hello"])).collect::<Vec<_>>();
+            let conditions = vec![None; docs.len()];
+            store.insert_or_update_documents(&docs, &conditions).unwrap();
+        }
+
+        let query = Query::term(title_field, Term::from_string("hello"));
+
+        let mut total_count = TotalCountCollector::new();
+        let mut terminate_after = TerminateAfterCollector::new(7);
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut total_count);
+            collector.add(&mut terminate_after);
+            store.reader().search(&mut collector, &query).unwrap();
+        }
+
+        assert_eq!(total_count.get_total_count(), 7);
+        assert!(terminate_after.terminated_early());
+
+        // Nothing is collected once the deadline has passed
+        let mut total_count = TotalCountCollector::new();
+        let mut deadline = DeadlineCollector::new(Instant::now());
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut total_count);
+            collector.add(&mut deadline);
+            store.reader().search(&mut collector, &query).unwrap();
+        }
+
+        assert_eq!(total_count.get_total_count(), 0);
+        assert!(deadline.timed_out());
+
+        let mut total_count = TotalCountCollector::new();
+        let mut deadline = DeadlineCollector::after(Duration::from_secs(3600));
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut total_count);
+            collector.add(&mut deadline);
+            store.reader().search(&mut collector, &query).unwrap();
+        }
+
+        assert_eq!(total_count.get_total_count(), 15);
+        assert!(!deadline.timed_out());
+    }
 }
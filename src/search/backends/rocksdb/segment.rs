@@ -1,8 +1,9 @@
 use std::io::Cursor;
 
-use search::segment::{SegmentId, Segment};
+use search::segment::{SegmentId, Segment, TermImpact, decode_term_frequencies};
 use search::schema::FieldId;
 use search::term::TermId;
+use search::doc_values::DocValues;
 use roaring::RoaringBitmap;
 use byteorder::{ByteOrder, LittleEndian};
 
@@ -34,7 +35,7 @@ impl<'a> Segment for RocksDBSegment<'a> {
         Ok(val)
     }
 
-    fn load_stored_field_value_raw(&self, doc_local_id: u16, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
+    fn load_stored_field_value_raw(&self, doc_local_id: u32, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
         let kb = KeyBuilder::stored_field_value(self.id, doc_local_id, field_id.0, value_type);
         let val = try!(self.reader.snapshot.get(&kb.key()));
         Ok(val.map(|v| v.to_vec()))
@@ -46,9 +47,35 @@ impl<'a> Segment for RocksDBSegment<'a> {
         Ok(doc_id_set)
     }
 
+    fn load_term_frequencies(&self, field_id: FieldId, term_id: TermId) -> Result<Option<Vec<u32>>, String> {
+        let kb = KeyBuilder::segment_term_frequencies(self.id, field_id.0, term_id.0);
+        let term_frequencies = try!(self.reader.snapshot.get(&kb.key())).map(|term_frequencies| decode_term_frequencies(&term_frequencies));
+        Ok(term_frequencies)
+    }
+
+    fn load_field_norms(&self, field_id: FieldId) -> Result<Option<Vec<u8>>, String> {
+        let kb = KeyBuilder::segment_field_norms(self.id, field_id.0);
+        let norms = try!(self.reader.snapshot.get(&kb.key())).map(|norms| norms.to_vec());
+        Ok(norms)
+    }
+
+    fn load_term_impact(&self, field_id: FieldId, term_id: TermId) -> Result<Option<TermImpact>, String> {
+        let kb = KeyBuilder::segment_term_impact(self.id, field_id.0, term_id.0);
+        let impact = try!(self.reader.snapshot.get(&kb.key())).and_then(|impact| TermImpact::from_bytes(&impact));
+        Ok(impact)
+    }
+
     fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String> {
         let kb = KeyBuilder::segment_del_list(self.id);
         let doc_id_set = try!(self.reader.snapshot.get(&kb.key())).map(|doc_id_set| RoaringBitmap::deserialize_from(Cursor::new(&doc_id_set[..])).unwrap());
         Ok(doc_id_set)
     }
+
+    fn load_doc_values(&self, field_id: FieldId) -> Result<Option<DocValues>, String> {
+        let kb = KeyBuilder::segment_doc_values(self.id, field_id.0);
+        match try!(self.reader.snapshot.get(&kb.key())) {
+            Some(doc_values) => Ok(Some(try!(DocValues::from_bytes(&doc_values)))),
+            None => Ok(None),
+        }
+    }
 }
@@ -4,7 +4,8 @@ use std::io::Cursor;
 use rocksdb::{self, WriteBatch, WriteOptions};
 use roaring::RoaringBitmap;
 use search::document::DocId;
-use search::segment::SegmentId;
+use search::segment::{SegmentId, term_doc_frequency_stat_name, total_field_tokens_stat_name, total_field_docs_stat_name, sum_doc_frequency_stat_name, encode_term_frequencies, decode_term_frequencies, TermImpact};
+use search::doc_values::{DocValues, DocValuesBuilder};
 use byteorder::{ByteOrder, LittleEndian};
 use fnv::{FnvHashMap, FnvHashSet};
 
@@ -32,15 +33,172 @@ impl From<SegmentMergeError> for String {
     }
 }
 
+#[derive(Debug)]
+pub enum SegmentPurgeError {
+    /// A key in the segment data couldn't be parsed, so it's not known which segment it belongs to
+    InvalidKey(Vec<u8>),
+    RocksDBError(rocksdb::Error),
+}
+
+impl From<rocksdb::Error> for SegmentPurgeError {
+    fn from(e: rocksdb::Error) -> SegmentPurgeError {
+        SegmentPurgeError::RocksDBError(e)
+    }
+}
+
+impl From<SegmentPurgeError> for String {
+    fn from(e: SegmentPurgeError) -> String {
+        match e {
+            SegmentPurgeError::InvalidKey(key) => format!("Invalid segment key: {}", String::from_utf8_lossy(&key)),
+            SegmentPurgeError::RocksDBError(e) => e.into(),
+        }
+    }
+}
+
+/// Parses a segment, document or field id from a part of a key
+fn parse_key_number(part: &[u8]) -> Option<u32> {
+    str::from_utf8(part).ok().and_then(|part| part.parse::<u32>().ok())
+}
+
+/// Statistics of a merged segment, recalculated from the data that was merged
+///
+/// The statistics of the source segments can't just be added together as they count documents
+/// that have been deleted, which aren't copied into the merged segment.
+#[derive(Debug, Default)]
+struct MergedSegmentStatistics {
+    statistics: FnvHashMap<Vec<u8>, i64>,
+    field_docs: FnvHashMap<u32, RoaringBitmap>,
+    field_tokens: FnvHashMap<u32, i64>,
+}
+
+impl MergedSegmentStatistics {
+    fn add_postings_list(&mut self, field: u32, term: u32, postings: &RoaringBitmap) {
+        // Each document in the postings list contains at least one token of the term, term
+        // frequencies over 1 are added when the term frequencies are merged
+        let doc_frequency = postings.len() as i64;
+        self.statistics.insert(term_doc_frequency_stat_name(field, term), doc_frequency);
+        *self.statistics.entry(sum_doc_frequency_stat_name(field)).or_insert(0) += doc_frequency;
+        *self.field_tokens.entry(field).or_insert(0) += doc_frequency;
+        self.field_docs.entry(field).or_insert_with(RoaringBitmap::new).union_with(postings);
+    }
+
+    fn add_term_frequency(&mut self, field: u32, term_frequency: i64) {
+        *self.field_tokens.entry(field).or_insert(0) += term_frequency - 1;
+    }
+
+    fn build(self, total_docs: i64) -> FnvHashMap<Vec<u8>, i64> {
+        let mut statistics = self.statistics;
+        statistics.insert(b"total_docs".to_vec(), total_docs);
+
+        for (field, docs) in self.field_docs {
+            statistics.insert(total_field_docs_stat_name(field), docs.len() as i64);
+        }
+
+        for (field, tokens) in self.field_tokens {
+            statistics.insert(total_field_tokens_stat_name(field), tokens);
+        }
+
+        statistics
+    }
+}
+
 impl RocksDBStore {
-    fn merge_segment_data(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u16>) -> Result<(), SegmentMergeError> {
+    /// Writes a postings list into a merged segment, skipping it if all of its documents were deleted
+    ///
+    /// "term_frequencies" holds the frequencies of the documents that contain the term more than once.
+    /// "field_norms" are the merged norms of the field, these are used to work out the term's impact.
+    fn write_merged_postings_list(&self, dest_segment: u32, field: u32, term: u32, postings: &RoaringBitmap, term_frequencies: &FnvHashMap<u32, u32>, field_norms: Option<&Vec<u8>>, statistics: &mut MergedSegmentStatistics, write_options: &WriteOptions) -> Result<(), rocksdb::Error> {
+        if postings.is_empty() {
+            return Ok(());
+        }
+
+        let mut postings_vec = Vec::new();
+        postings.serialize_into(&mut postings_vec).unwrap();
+
+        let kb = KeyBuilder::segment_postings_list(dest_segment, field, term);
+        try!(self.db.put_opt(&kb.key(), &postings_vec, write_options));
+
+        statistics.add_postings_list(field, term, postings);
+
+        // Write the term frequencies, in the order of the new postings list
+        if !term_frequencies.is_empty() {
+            let term_frequencies_vec = postings.iter().map(|doc_id| term_frequencies.get(&doc_id).cloned().unwrap_or(1)).collect::<Vec<u32>>();
+
+            let kb = KeyBuilder::segment_term_frequencies(dest_segment, field, term);
+            try!(self.db.put_opt(&kb.key(), &encode_term_frequencies(&term_frequencies_vec), write_options));
+
+            for term_frequency in term_frequencies.values() {
+                statistics.add_term_frequency(field, *term_frequency as i64);
+            }
+        }
+
+        // Work out the term's impact from the remaining documents
+        // This can't be taken from the source segments as their impacts may come from deleted documents
+        let mut impact: Option<TermImpact> = None;
+        for doc_id in postings.iter() {
+            let term_frequency = term_frequencies.get(&doc_id).cloned().unwrap_or(1);
+            let field_norm = field_norms.and_then(|field_norms| field_norms.get(doc_id as usize)).cloned().unwrap_or(0);
+
+            match impact {
+                Some(ref mut impact) => impact.add(term_frequency, field_norm),
+                None => impact = Some(TermImpact::new(term_frequency, field_norm)),
+            }
+        }
+
+        if let Some(impact) = impact {
+            let kb = KeyBuilder::segment_term_impact(dest_segment, field, term);
+            try!(self.db.put_opt(&kb.key(), &impact.to_bytes(), write_options));
+        }
+
+        Ok(())
+    }
+
+    fn merge_segment_data(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u32>) -> Result<(), SegmentMergeError> {
         // Put source_segments in a FnvHashSet as this is much faster for performing contains queries against
         let source_segments_btree = source_segments.iter().collect::<FnvHashSet<_>>();
 
-        // Since we're merging existing data, there's no need to recover if it crashes half way through
+        // The data is written to the WAL without syncing it. The commit is synced, which syncs
+        // everything that was written before it too. If we crash before then, the merge's intent
+        // is used to clean up the segment when the index is next opened
         let mut write_options = WriteOptions::default();
         write_options.set_sync(false);
-        write_options.disable_wal(true);
+
+        // Merge the field norms
+        // Like the doc values, there is one list per field in each segment. These are merged
+        // first as the term impacts are worked out from them when the postings lists are merged
+
+        let mut merged_norms: FnvHashMap<u32, Vec<u8>> = FnvHashMap::default();
+
+        for source_segment in source_segments.iter() {
+            let kb = KeyBuilder::segment_field_norms_prefix(*source_segment);
+            let mut iter = self.db.raw_iterator();
+            iter.seek(&kb.key());
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if !k.starts_with(&kb.key()) {
+                    // Segment finished
+                    break;
+                }
+
+                let field = str::from_utf8(&k[kb.key().len()..]).unwrap().parse::<u32>().unwrap();
+                let field_norms = merged_norms.entry(field).or_insert_with(Vec::new);
+
+                for (doc_id, norm) in iter.value().unwrap().iter().enumerate() {
+                    let doc_id = DocId(SegmentId(*source_segment), doc_id as u32);
+                    if let Some(new_doc_id) = doc_id_mapping.get(&doc_id) {
+                        let new_doc_id = *new_doc_id as usize;
+                        if field_norms.len() <= new_doc_id {
+                            field_norms.resize(new_doc_id + 1, 0);
+                        }
+
+                        field_norms[new_doc_id] = *norm;
+                    }
+                }
+
+                iter.next();
+            }
+        }
 
         // Merge the term directories
         // The postings lists keys are ordered to be most convenient for retrieving all the segments
@@ -54,8 +212,11 @@ impl RocksDBStore {
             (nums_iter.next().unwrap(), nums_iter.next().unwrap(), nums_iter.next().unwrap())
         }
 
+        let mut statistics = MergedSegmentStatistics::default();
+
         let mut current_td_key: Option<(u32, u32)> = None;
         let mut current_td = RoaringBitmap::new();
+        let mut current_tfs: FnvHashMap<u32, u32> = FnvHashMap::default();
 
         let mut iter = self.db.raw_iterator();
         iter.seek(b"d");
@@ -73,23 +234,31 @@ impl RocksDBStore {
                 if current_td_key != Some((field, term)) {
                     // Finished current postings list. Write it to the DB and start the next one
                     if let Some((field, term)) = current_td_key {
-                        let mut current_td_vec = Vec::new();
-                        current_td.serialize_into(&mut current_td_vec).unwrap();
-
-                        let kb = KeyBuilder::segment_postings_list(dest_segment, field, term);
-                        try!(self.db.put_opt(&kb.key(), &current_td_vec, &write_options));
+                        try!(self.write_merged_postings_list(dest_segment, field, term, &current_td, &current_tfs, merged_norms.get(&field), &mut statistics, &write_options));
                         current_td.clear();
+                        current_tfs.clear();
                     }
 
                     current_td_key = Some((field, term));
                 }
 
+                // The term frequencies are in the same order as the postings list
+                let kb = KeyBuilder::segment_term_frequencies(segment, field, term);
+                let term_frequencies = try!(self.db.get(&kb.key())).map(|term_frequencies| decode_term_frequencies(&term_frequencies));
+
                 // Merge postings list into the new one (and remap the doc ids)
+                // Documents that aren't in the mapping have been deleted
                 let bitmap = RoaringBitmap::deserialize_from(Cursor::new(iter.value().unwrap())).unwrap();
-                for doc_id in bitmap.iter() {
-                    let doc_id = DocId(SegmentId(segment), doc_id as u16);
-                    let new_doc_id = doc_id_mapping.get(&doc_id).unwrap();
-                    current_td.insert(*new_doc_id as u32);
+                for (i, doc_id) in bitmap.iter().enumerate() {
+                    let doc_id = DocId(SegmentId(segment), doc_id);
+                    if let Some(new_doc_id) = doc_id_mapping.get(&doc_id) {
+                        current_td.insert(*new_doc_id);
+
+                        let term_frequency = term_frequencies.as_ref().and_then(|term_frequencies| term_frequencies.get(i).cloned()).unwrap_or(1);
+                        if term_frequency != 1 {
+                            current_tfs.insert(*new_doc_id, term_frequency);
+                        }
+                    }
                 }
             }
 
@@ -98,12 +267,9 @@ impl RocksDBStore {
 
         // All done, write the last postings list
         if let Some((field, term)) = current_td_key {
-            let mut current_td_vec = Vec::new();
-            current_td.serialize_into(&mut current_td_vec).unwrap();
-
-            let kb = KeyBuilder::segment_postings_list(dest_segment, field, term);
-            try!(self.db.put_opt(&kb.key(), &current_td_vec, &write_options));
+            try!(self.write_merged_postings_list(dest_segment, field, term, &current_td, &current_tfs, merged_norms.get(&field), &mut statistics, &write_options));
             current_td.clear();
+            current_tfs.clear();
         }
 
         // Merge the stored values
@@ -143,8 +309,15 @@ impl RocksDBStore {
                 }
 
                 // Remap doc id
-                let doc_id = DocId(SegmentId(segment), doc_id as u16);
-                let new_doc_id = doc_id_mapping.get(&doc_id).unwrap();
+                let doc_id = DocId(SegmentId(segment), doc_id);
+                let new_doc_id = match doc_id_mapping.get(&doc_id) {
+                    Some(new_doc_id) => new_doc_id,
+                    None => {
+                        // Document has been deleted
+                        iter.next();
+                        continue;
+                    }
+                };
 
                 // Write value into new segment
                 let kb = KeyBuilder::stored_field_value(dest_segment, *new_doc_id, field, &value_type);
@@ -154,51 +327,73 @@ impl RocksDBStore {
             }
         }
 
-        // Merge the statistics
-        // Like stored values, these start with segment ids. But instead of just rewriting the
-        // key, we need to sum up all the statistics across the segments being merged.
+        // Merge the doc values
+        // There is one column per field in each segment. The values in these are copied into
+        // a new column for each field, in the order of the remapped doc ids.
 
-        let mut statistics = FnvHashMap::default();
-
-        /// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
-        fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
-            let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let statistic_name = parts_iter.next().unwrap().to_vec();
+        let mut doc_values_builders: FnvHashMap<u32, DocValuesBuilder> = FnvHashMap::default();
 
-            (segment, statistic_name)
+        /// Converts doc values key strings "c1/2" into tuples of 2 i32s (1, 2)
+        fn parse_doc_values_key(key: &[u8]) -> (u32, u32) {
+            let mut nums_iter = key[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
+            (nums_iter.next().unwrap(), nums_iter.next().unwrap())
         }
 
-        // Fetch and merge statistics
         for source_segment in source_segments.iter() {
-            let kb = KeyBuilder::segment_stat_prefix(*source_segment);
+            let kb = KeyBuilder::segment_doc_values_prefix(*source_segment);
             let mut iter = self.db.raw_iterator();
             iter.seek(&kb.key());
             while iter.valid() {
                 let k = iter.key().unwrap();
 
-                if k[0] != b's' {
-                    // No more statistics to merge
+                if k[0] != b'c' {
+                    // No more doc values to merge
                     break;
                 }
 
-                let (segment, statistic_name) = parse_statistic_key(&k);
+                let (segment, field) = parse_doc_values_key(&k);
 
                 if segment != *source_segment {
                     // Segment finished
                     break;
                 }
 
+                let doc_values = match DocValues::from_bytes(unsafe { &iter.value_inner().unwrap() }) {
+                    Ok(doc_values) => doc_values,
+                    Err(_) => {
+                        // Skip corrupted columns, readers fall back to the stored values
+                        iter.next();
+                        continue;
+                    }
+                };
 
-                let stat = statistics.entry(statistic_name).or_insert(0);
-                *stat += LittleEndian::read_i64(unsafe { &iter.value_inner().unwrap() });
+                let builder = doc_values_builders.entry(field).or_insert_with(|| DocValuesBuilder::like(&doc_values));
+                for doc_id in 0..doc_values.len() {
+                    let doc_id = DocId(SegmentId(segment), doc_id as u32);
+                    if let Some(new_doc_id) = doc_id_mapping.get(&doc_id) {
+                        builder.insert_from(*new_doc_id, &doc_values, doc_id.1);
+                    }
+                }
 
                 iter.next();
             }
         }
 
-        // Write merged statistics to new segment
-        for (stat_name, stat_value) in statistics {
+        for (field, builder) in doc_values_builders {
+            let kb = KeyBuilder::segment_doc_values(dest_segment, field);
+            try!(self.db.put_opt(&kb.key(), &builder.build().to_bytes(), &write_options));
+        }
+
+        // Write the field norms
+        for (field, field_norms) in merged_norms.iter() {
+            let kb = KeyBuilder::segment_field_norms(dest_segment, *field);
+            try!(self.db.put_opt(&kb.key(), field_norms, &write_options));
+        }
+
+        // Write the statistics
+        // The number of deleted docs is written when the merge is committed, as more documents may
+        // be deleted before then.
+        for (stat_name, stat_value) in statistics.build(doc_id_mapping.len() as i64) {
             let kb = KeyBuilder::segment_stat(dest_segment, &stat_name);
             let mut val_bytes = [0; 8];
             LittleEndian::write_i64(&mut val_bytes, stat_value);
@@ -213,18 +408,25 @@ impl RocksDBStore {
         Ok(())
     }
 
-    fn commit_segment_merge(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u16>) -> Result<(), SegmentMergeError> {
+    fn commit_segment_merge(&self, source_segments: &Vec<u32>, dest_segment: u32, doc_id_mapping: &FnvHashMap<DocId, u32>) -> Result<(), SegmentMergeError> {
         let mut write_batch = WriteBatch::default();
 
         // Activate new segment
         let kb = KeyBuilder::segment_active(dest_segment);
         try!(write_batch.put(&kb.key(), b""));
 
+        // The merge is complete, so there's nothing to clean up if we crash after this
+        let kb = KeyBuilder::segment_merge_intent(dest_segment);
+        try!(write_batch.delete(&kb.key()));
+
         // Deactivate old segments
+        // These are marked for purging, in case we crash before purge_segments is finished
         for source_segment in source_segments.iter() {
-            // Activate new segment
             let kb = KeyBuilder::segment_active(*source_segment);
             try!(write_batch.delete(&kb.key()));
+
+            let kb = KeyBuilder::segment_purge_pending(*source_segment);
+            try!(write_batch.put(&kb.key(), b""));
         }
 
         // Update document index and commit
@@ -234,17 +436,35 @@ impl RocksDBStore {
         Ok(())
     }
 
-    pub fn merge_segments(&self, source_segments: &Vec<u32>) -> Result<u32, SegmentMergeError> {
+    /// Allocates the segment that a merge will write into and records the merge's intent
+    ///
+    /// The intent is removed when the merge is committed. If it's still there when the index is
+    /// opened, the merge was interrupted and its segment is purged by recover_segments.
+    fn begin_segment_merge(&self) -> Result<u32, rocksdb::Error> {
         let dest_segment = try!(self.segments.new_segment(&self.db));
 
+        let mut write_options = WriteOptions::default();
+        write_options.set_sync(true);
+
+        let kb = KeyBuilder::segment_merge_intent(dest_segment);
+        try!(self.db.put_opt(&kb.key(), b"", &write_options));
+
+        Ok(dest_segment)
+    }
+
+    fn build_doc_id_mapping(&self, source_segments: &Vec<u32>) -> Result<FnvHashMap<DocId, u32>, SegmentMergeError> {
         // Generate a mapping between the ids of the documents in the old segments to the new one
         // This packs the id spaces of the old segments together:
         // For example, say we have to merge 3 segments with 100 documents each:
         //  - The first segment's ids will be the same as before
         //  - The second segment's ids will be remapped to 100 - 199
         //  - The third segment's ids will be remapped to 200 - 299
+        //
+        // Documents that have already been deleted are left out of the mapping, so they are
+        // dropped from the new segment. Documents deleted while the merge is running are still
+        // copied, their deletions are moved over to the new segment when the merge is committed.
 
-        let mut doc_id_mapping: FnvHashMap<DocId, u16> = FnvHashMap::default();
+        let mut doc_id_mapping: FnvHashMap<DocId, u32> = FnvHashMap::default();
         let mut current_doc_id: u32 = 0;
 
         for source_segment in source_segments.iter() {
@@ -256,24 +476,37 @@ impl RocksDBStore {
                 None => continue,
             };
 
+            let kb = KeyBuilder::segment_del_list(*source_segment);
+            let deletion_list = match try!(self.db.get(&kb.key())) {
+                Some(bitmap) => RoaringBitmap::deserialize_from(Cursor::new(&bitmap[..])).unwrap(),
+                None => RoaringBitmap::new(),
+            };
+
             for source_doc_id in 0..total_docs {
-                if current_doc_id >= 65536 {
-                    return Err(SegmentMergeError::TooManyDocs);
+                if deletion_list.contains(source_doc_id as u32) {
+                    continue;
                 }
 
-                let from = DocId(SegmentId(*source_segment), source_doc_id as u16);
-                doc_id_mapping.insert(from, current_doc_id as u16);
-                current_doc_id += 1;
+                let from = DocId(SegmentId(*source_segment), source_doc_id as u32);
+                doc_id_mapping.insert(from, current_doc_id);
+                current_doc_id = try!(current_doc_id.checked_add(1).ok_or(SegmentMergeError::TooManyDocs));
             }
         }
 
+        Ok(doc_id_mapping)
+    }
+
+    pub fn merge_segments(&self, source_segments: &Vec<u32>) -> Result<u32, SegmentMergeError> {
+        let doc_id_mapping = try!(self.build_doc_id_mapping(source_segments));
+        let dest_segment = try!(self.begin_segment_merge());
+
         // Merge segment data
         // Most of the heavy lifting happens here. This merges all the immutable parts of
         // the segment (which is everything but the deletion list). It does not activate the
         // segment.
         // This means that nothing bad will happen if it crashes half way through -- the
-        // worst that could happen is we're left with a partially-written segment that we
-        // have to clean up.
+        // worst that could happen is we're left with a partially-written segment, which is
+        // cleaned up by recover_segments as its merge intent was recorded.
         try!(self.merge_segment_data(&source_segments, dest_segment, &doc_id_mapping));
 
         // Commit the merge
@@ -285,23 +518,49 @@ impl RocksDBStore {
         // the new segment).
         try!(self.commit_segment_merge(&source_segments, dest_segment, &doc_id_mapping));
 
+        // The source segments won't be searched again, so their cached filters can be dropped
+        self.filter_cache.remove_segments(source_segments);
+
         Ok(dest_segment)
     }
 
-    pub fn purge_segments(&self, segments: &Vec<u32>) -> Result<(), rocksdb::Error> {
+    /// Deactivates segments that have had all of their documents deleted
+    ///
+    /// None of the documents in these segments are pointed to by a key any more, so unlike merges
+    /// this doesn't need to lock the document index. The segments should be purged afterwards.
+    pub fn deactivate_segments(&self, segments: &Vec<u32>) -> Result<(), rocksdb::Error> {
+        let mut write_batch = WriteBatch::default();
+
+        for segment in segments.iter() {
+            let kb = KeyBuilder::segment_active(*segment);
+            try!(write_batch.delete(&kb.key()));
+
+            let kb = KeyBuilder::segment_purge_pending(*segment);
+            try!(write_batch.put(&kb.key(), b""));
+        }
+
+        self.db.write(write_batch)
+    }
+
+    pub fn purge_segments(&self, segments: &Vec<u32>) -> Result<(), SegmentPurgeError> {
+        // Readers that were opened before the segments were deactivated may have cached filters
+        // for them since they were merged
+        self.filter_cache.remove_segments(segments);
+
         // Put segments in a FnvHashSet as this is much faster for performing contains queries against
         let segments_btree = segments.iter().collect::<FnvHashSet<_>>();
 
+        // Deletes go into the WAL so that, if we crash, they can't be lost while the removal of
+        // the purge markers at the end is kept
         let mut write_options = WriteOptions::default();
         write_options.set_sync(false);
-        write_options.disable_wal(true);
 
         // Purge term directories
 
         /// Converts postings list key strings "d1/2/3" into tuples of 3 i32s (1, 2, 3)
-        fn parse_postings_list_key(key: &[u8]) -> (u32, u32, u32) {
-            let mut nums_iter = key[1..].split(|b| *b == b'/').map(|s| str::from_utf8(s).unwrap().parse::<u32>().unwrap());
-            (nums_iter.next().unwrap(), nums_iter.next().unwrap(), nums_iter.next().unwrap())
+        fn parse_postings_list_key(key: &[u8]) -> Option<(u32, u32, u32)> {
+            let mut nums_iter = key[1..].split(|b| *b == b'/').map(parse_key_number);
+            Some((nums_iter.next()??, nums_iter.next()??, nums_iter.next()??))
         }
 
         let mut iter = self.db.raw_iterator();
@@ -314,10 +573,52 @@ impl RocksDBStore {
                 break;
             }
 
-            let (_, _, segment) = parse_postings_list_key(&k);
+            let (_, _, segment) = parse_postings_list_key(&k).ok_or_else(|| SegmentPurgeError::InvalidKey(k.clone()))?;
+
+            if segments_btree.contains(&segment) {
+                try!(self.db.delete_opt(&k, &write_options));
+            }
+
+            iter.next();
+        }
+
+        // Purge the term frequencies
+        // These are keyed in the same way as the postings lists
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"q");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'q' {
+                // No more term frequencies to delete
+                break;
+            }
+
+            let (_, _, segment) = parse_postings_list_key(&k).ok_or_else(|| SegmentPurgeError::InvalidKey(k.clone()))?;
+
+            if segments_btree.contains(&segment) {
+                try!(self.db.delete_opt(&k, &write_options));
+            }
+
+            iter.next();
+        }
+
+        // Purge the term impacts
+        // These are keyed in the same way as the postings lists
+        let mut iter = self.db.raw_iterator();
+        iter.seek(b"u");
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != b'u' {
+                // No more term impacts to delete
+                break;
+            }
+
+            let (_, _, segment) = parse_postings_list_key(&k).ok_or_else(|| SegmentPurgeError::InvalidKey(k.clone()))?;
 
             if segments_btree.contains(&segment) {
-                try!(self.db.delete(&k));
+                try!(self.db.delete_opt(&k, &write_options));
             }
 
             iter.next();
@@ -326,14 +627,14 @@ impl RocksDBStore {
         // Purge the stored values
 
         /// Converts stored value key strings "v1/2/3/v" into tuples of 3 i32s and a Vec<u8> (1, 2, 3, vec![b'v', b'a', b'l'])
-        fn parse_stored_value_key(key: &[u8]) -> (u32, u32, u32, Vec<u8>) {
+        fn parse_stored_value_key(key: &[u8]) -> Option<(u32, u32, u32, Vec<u8>)> {
             let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let doc_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let field_id = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let value_type = parts_iter.next().unwrap().to_vec();
+            let segment = parse_key_number(parts_iter.next()?)?;
+            let doc_id = parse_key_number(parts_iter.next()?)?;
+            let field_id = parse_key_number(parts_iter.next()?)?;
+            let value_type = parts_iter.next()?.to_vec();
 
-            (segment, doc_id, field_id, value_type)
+            Some((segment, doc_id, field_id, value_type))
         }
 
         for source_segment in segments.iter() {
@@ -348,7 +649,7 @@ impl RocksDBStore {
                     break;
                 }
 
-                let (segment, _, _, _) = parse_stored_value_key(&k);
+                let (segment, _, _, _) = parse_stored_value_key(&k).ok_or_else(|| SegmentPurgeError::InvalidKey(k.clone()))?;
 
                 if segment != *source_segment {
                     // Segment finished
@@ -364,12 +665,12 @@ impl RocksDBStore {
         // Purge the statistics
 
         /// Converts statistic key strings "s1/total_docs" into tuples of 1 i32 and a Vec<u8> (1, ['t', 'o', 't', ...])
-        fn parse_statistic_key(key: &[u8]) -> (u32, Vec<u8>) {
+        fn parse_statistic_key(key: &[u8]) -> Option<(u32, Vec<u8>)> {
             let mut parts_iter = key[1..].split(|b| *b == b'/');
-            let segment = str::from_utf8(parts_iter.next().unwrap()).unwrap().parse::<u32>().unwrap();
-            let statistic_name = parts_iter.next().unwrap().to_vec();
+            let segment = parse_key_number(parts_iter.next()?)?;
+            let statistic_name = parts_iter.next()?.to_vec();
 
-            (segment, statistic_name)
+            Some((segment, statistic_name))
         }
 
         for source_segment in segments.iter() {
@@ -384,7 +685,7 @@ impl RocksDBStore {
                     break;
                 }
 
-                let (segment, _) = parse_statistic_key(&k);
+                let (segment, _) = parse_statistic_key(&k).ok_or_else(|| SegmentPurgeError::InvalidKey(k.clone()))?;
 
                 if segment != *source_segment {
                     // Segment finished
@@ -397,12 +698,302 @@ impl RocksDBStore {
             }
         }
 
+        // Purge the doc values
+        for source_segment in segments.iter() {
+            let kb = KeyBuilder::segment_doc_values_prefix(*source_segment);
+            let mut iter = self.db.raw_iterator();
+            iter.seek(&kb.key());
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if !k.starts_with(&kb.key()) {
+                    // Segment finished
+                    break;
+                }
+
+                try!(self.db.delete_opt(&k, &write_options));
+
+                iter.next();
+            }
+        }
+
+        // Purge the field norms
+        for source_segment in segments.iter() {
+            let kb = KeyBuilder::segment_field_norms_prefix(*source_segment);
+            let mut iter = self.db.raw_iterator();
+            iter.seek(&kb.key());
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if !k.starts_with(&kb.key()) {
+                    // Segment finished
+                    break;
+                }
+
+                try!(self.db.delete_opt(&k, &write_options));
+
+                iter.next();
+            }
+        }
+
         // Purge the deletion lists
         for source_segment in segments.iter() {
             let kb = KeyBuilder::segment_del_list(*source_segment);
             try!(self.db.delete_opt(&kb.key(), &write_options));
         }
 
+        // All done, remove the purge markers
+        let mut write_batch = WriteBatch::default();
+        for source_segment in segments.iter() {
+            let kb = KeyBuilder::segment_purge_pending(*source_segment);
+            try!(write_batch.delete(&kb.key()));
+        }
+
+        try!(self.db.write(write_batch));
+
+        Ok(())
+    }
+
+    /// Reads the segment ids from all the markers with the given key prefix
+    fn read_segment_markers(&self, prefix: u8) -> Result<Vec<u32>, SegmentPurgeError> {
+        let mut segments = Vec::new();
+        let mut iter = self.db.raw_iterator();
+        iter.seek(&[prefix]);
+        while iter.valid() {
+            let k = iter.key().unwrap();
+
+            if k[0] != prefix {
+                break;
+            }
+
+            let segment = parse_key_number(&k[1..]).ok_or_else(|| SegmentPurgeError::InvalidKey(k.clone()))?;
+            segments.push(segment);
+
+            iter.next();
+        }
+
+        Ok(segments)
+    }
+
+    /// Cleans up after merges and purges that were interrupted by a crash
+    ///
+    /// This must be run when the index is opened, before any new merges can start.
+    pub fn recover_segments(&self) -> Result<(), SegmentPurgeError> {
+        // Merges that were never committed
+        // Their segments were never activated, so everything that was written into them can be thrown away
+        let abandoned_segments = try!(self.read_segment_markers(b'm'));
+
+        if !abandoned_segments.is_empty() {
+            try!(self.purge_segments(&abandoned_segments));
+
+            let mut write_batch = WriteBatch::default();
+            for segment in abandoned_segments.iter() {
+                let kb = KeyBuilder::segment_merge_intent(*segment);
+                try!(write_batch.delete(&kb.key()));
+            }
+            try!(self.db.write(write_batch));
+        }
+
+        // Segments that were deactivated but not purged
+        let unpurged_segments = try!(self.read_segment_markers(b'p'));
+
+        if !unpurged_segments.is_empty() {
+            try!(self.purge_segments(&unpurged_segments));
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+    use std::fs::remove_dir_all;
+    use std::path::Path;
+
+    use search::segment::SegmentId;
+    use search::backends::Reader;
+
+    use super::super::RocksDBStore;
+    use super::super::key_builder::KeyBuilder;
+    use super::super::tests::make_test_store;
+
+    fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
+        match remove_dir_all(&path) {
+            Ok(_) => {}
+            Err(_) => {}  // Don't care if this fails
+        }
+    }
+
+    /// Returns true if any postings lists, term frequencies, term impacts, stored values, statistics, doc values or field norms belong to the segment
+    fn segment_has_data(store: &RocksDBStore, segment: u32) -> bool {
+        let prefixes = vec![
+            KeyBuilder::segment_stored_values_prefix(segment),
+            KeyBuilder::segment_stat_prefix(segment),
+            KeyBuilder::segment_doc_values_prefix(segment),
+            KeyBuilder::segment_field_norms_prefix(segment),
+        ];
+
+        for prefix in prefixes.iter() {
+            let mut iter = store.db.raw_iterator();
+            iter.seek(prefix.key());
+            if iter.valid() && iter.key().unwrap().starts_with(prefix.key()) {
+                return true;
+            }
+        }
+
+        // Postings list, term frequency and term impact keys end with the segment id
+        for prefix in [b'd', b'q', b'u'].iter() {
+            let mut iter = store.db.raw_iterator();
+            iter.seek(&[*prefix]);
+            while iter.valid() {
+                let k = iter.key().unwrap();
+
+                if k[0] != *prefix {
+                    break;
+                }
+
+                if str::from_utf8(k.split(|b| *b == b'/').last().unwrap()).unwrap().parse::<u32>().unwrap() == segment {
+                    return true;
+                }
+
+                iter.next();
+            }
+        }
+
+        false
+    }
+
+    fn get_active_segments(store: &RocksDBStore) -> Vec<u32> {
+        store.get_segment_statistics().unwrap().iter().map(|&(segment, _)| segment).collect()
+    }
+
+    #[test]
+    fn test_recover_from_crash_during_merge() {
+        remove_dir_all_ignore_error("test_indices/test_recover_from_crash_during_merge");
+
+        let (source_segment, dest_segment) = {
+            let store = make_test_store("test_indices/test_recover_from_crash_during_merge");
+            let source_segments = get_active_segments(&store);
+
+            // Crash after the data has been written, but before the merge is committed
+            let doc_id_mapping = store.build_doc_id_mapping(&source_segments).unwrap();
+            let dest_segment = store.begin_segment_merge().unwrap();
+            store.merge_segment_data(&source_segments, dest_segment, &doc_id_mapping).unwrap();
+            assert!(segment_has_data(&store, dest_segment));
+
+            (source_segments[0], dest_segment)
+        };
+
+        // Reopen the store, the half-merged segment should be removed
+        let store = RocksDBStore::open("test_indices/test_recover_from_crash_during_merge").unwrap();
+        assert!(!segment_has_data(&store, dest_segment));
+        assert!(store.db.get(&KeyBuilder::segment_merge_intent(dest_segment).key()).unwrap().is_none());
+
+        // The source segment should be untouched
+        assert_eq!(get_active_segments(&store), vec![source_segment]);
+        assert!(segment_has_data(&store, source_segment));
+        assert_eq!(store.reader().get_document_by_key("test_doc").unwrap().0, SegmentId(source_segment));
+    }
+
+    #[test]
+    fn test_recover_from_crash_before_purge() {
+        remove_dir_all_ignore_error("test_indices/test_recover_from_crash_before_purge");
+
+        let (source_segment, dest_segment) = {
+            let store = make_test_store("test_indices/test_recover_from_crash_before_purge");
+            let source_segments = get_active_segments(&store);
+
+            // Crash after the merge is committed, but before the source segment is purged
+            let dest_segment = store.merge_segments(&source_segments).unwrap();
+            assert!(segment_has_data(&store, source_segments[0]));
+
+            (source_segments[0], dest_segment)
+        };
+
+        // Reopen the store, the source segment should be purged
+        let store = RocksDBStore::open("test_indices/test_recover_from_crash_before_purge").unwrap();
+        assert!(!segment_has_data(&store, source_segment));
+        assert!(store.db.get(&KeyBuilder::segment_purge_pending(source_segment).key()).unwrap().is_none());
+
+        // The merged segment should be active
+        assert_eq!(get_active_segments(&store), vec![dest_segment]);
+        assert!(segment_has_data(&store, dest_segment));
+        assert_eq!(store.reader().get_document_by_key("test_doc").unwrap().0, SegmentId(dest_segment));
+    }
+
+    #[test]
+    fn test_recover_from_crash_during_purge() {
+        remove_dir_all_ignore_error("test_indices/test_recover_from_crash_during_purge");
+
+        let (source_segment, dest_segment) = {
+            let store = make_test_store("test_indices/test_recover_from_crash_during_purge");
+            let source_segments = get_active_segments(&store);
+            let dest_segment = store.merge_segments(&source_segments).unwrap();
+
+            // Crash after the statistics have been purged, but not the rest of the data
+            let prefix = KeyBuilder::segment_stat_prefix(source_segments[0]);
+            let mut iter = store.db.raw_iterator();
+            iter.seek(prefix.key());
+            while iter.valid() && iter.key().unwrap().starts_with(prefix.key()) {
+                store.db.delete(&iter.key().unwrap()).unwrap();
+                iter.next();
+            }
+            assert!(segment_has_data(&store, source_segments[0]));
+
+            (source_segments[0], dest_segment)
+        };
+
+        // Reopen the store, the rest of the source segment should be purged
+        let store = RocksDBStore::open("test_indices/test_recover_from_crash_during_purge").unwrap();
+        assert!(!segment_has_data(&store, source_segment));
+        assert!(store.db.get(&KeyBuilder::segment_purge_pending(source_segment).key()).unwrap().is_none());
+        assert_eq!(get_active_segments(&store), vec![dest_segment]);
+    }
+
+    #[test]
+    fn test_recover_from_crash_after_deactivation() {
+        remove_dir_all_ignore_error("test_indices/test_recover_from_crash_after_deactivation");
+
+        let segment = {
+            let store = make_test_store("test_indices/test_recover_from_crash_after_deactivation");
+            let segments = get_active_segments(&store);
+
+            // Crash after the segment has been deactivated, but before it is purged
+            assert!(store.remove_document_by_key("test_doc", None).unwrap().is_some());
+            assert!(store.remove_document_by_key("another_test_doc", None).unwrap().is_some());
+            store.deactivate_segments(&segments).unwrap();
+
+            segments[0]
+        };
+
+        // Reopen the store, the segment should be purged
+        let store = RocksDBStore::open("test_indices/test_recover_from_crash_after_deactivation").unwrap();
+        assert!(!segment_has_data(&store, segment));
+        assert_eq!(get_active_segments(&store), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_purge_with_invalid_key() {
+        remove_dir_all_ignore_error("test_indices/test_purge_with_invalid_key");
+
+        let store = make_test_store("test_indices/test_purge_with_invalid_key");
+        let segments = get_active_segments(&store);
+        store.db.put(b"dfoo/bar", b"").unwrap();
+
+        assert!(store.purge_segments(&segments).is_err());
+    }
+
+    #[test]
+    fn test_recover_with_invalid_purge_marker() {
+        remove_dir_all_ignore_error("test_indices/test_recover_with_invalid_purge_marker");
+
+        {
+            let store = make_test_store("test_indices/test_recover_with_invalid_purge_marker");
+            store.db.put(b"pfoo", b"").unwrap();
+        }
+
+        // Opening the store should fail instead of panicking
+        assert!(RocksDBStore::open("test_indices/test_recover_with_invalid_purge_marker").is_err());
+    }
+}
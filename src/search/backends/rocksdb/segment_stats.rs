@@ -1,4 +1,5 @@
-use search::segment::Segment;
+use search::segment::{Segment, total_field_docs_stat_name, total_field_tokens_stat_name, sum_doc_frequency_stat_name};
+use search::schema::FieldId;
 
 use super::RocksDBStore;
 
@@ -9,6 +10,13 @@ pub struct SegmentStatistics {
 }
 
 impl SegmentStatistics {
+    pub fn new(total_docs: i64, deleted_docs: i64) -> SegmentStatistics {
+        SegmentStatistics {
+            total_docs: total_docs,
+            deleted_docs: deleted_docs,
+        }
+    }
+
     fn read<S: Segment>(segment: &S) -> Result<SegmentStatistics, String> {
         let total_docs = try!(segment.load_statistic(b"total_docs")).unwrap_or(0);
         let deleted_docs = try!(segment.load_statistic(b"deleted_docs")).unwrap_or(0);
@@ -28,6 +36,46 @@ impl SegmentStatistics {
     pub fn deleted_docs(&self) -> i64 {
         self.deleted_docs
     }
+
+    /// The number of documents that haven't been deleted
+    #[inline]
+    pub fn live_docs(&self) -> i64 {
+        self.total_docs - self.deleted_docs
+    }
+}
+
+#[derive(Debug)]
+pub struct FieldStatistics {
+    unique_terms: usize,
+    doc_count: i64,
+    sum_doc_freq: i64,
+    sum_total_term_freq: i64,
+}
+
+impl FieldStatistics {
+    /// The number of unique terms that have been indexed in the field
+    #[inline]
+    pub fn unique_terms(&self) -> usize {
+        self.unique_terms
+    }
+
+    /// The number of documents that have a value in the field
+    #[inline]
+    pub fn doc_count(&self) -> i64 {
+        self.doc_count
+    }
+
+    /// The sum of the document frequencies of all terms in the field
+    #[inline]
+    pub fn sum_doc_freq(&self) -> i64 {
+        self.sum_doc_freq
+    }
+
+    /// The total number of tokens indexed in the field
+    #[inline]
+    pub fn sum_total_term_freq(&self) -> i64 {
+        self.sum_total_term_freq
+    }
 }
 
 impl RocksDBStore {
@@ -42,4 +90,33 @@ impl RocksDBStore {
 
         Ok(segment_stats)
     }
+
+    /// Reads the statistics of a field across all active segments
+    ///
+    /// Like the statistics used for scoring, these include documents that have been deleted
+    /// but not yet merged away.
+    pub fn get_field_statistics(&self, field_id: FieldId) -> Result<FieldStatistics, String> {
+        let reader = self.reader();
+
+        let doc_count_stat_name = total_field_docs_stat_name(field_id.0);
+        let sum_doc_freq_stat_name = sum_doc_frequency_stat_name(field_id.0);
+        let sum_total_term_freq_stat_name = total_field_tokens_stat_name(field_id.0);
+
+        let mut doc_count = 0;
+        let mut sum_doc_freq = 0;
+        let mut sum_total_term_freq = 0;
+
+        for segment in self.segments.iter_active(&reader) {
+            doc_count += try!(segment.load_statistic(&doc_count_stat_name)).unwrap_or(0);
+            sum_doc_freq += try!(segment.load_statistic(&sum_doc_freq_stat_name)).unwrap_or(0);
+            sum_total_term_freq += try!(segment.load_statistic(&sum_total_term_freq_stat_name)).unwrap_or(0);
+        }
+
+        Ok(FieldStatistics {
+            unique_terms: self.term_dictionary.count_field_terms(&self.db, field_id),
+            doc_count: doc_count,
+            sum_doc_freq: sum_doc_freq,
+            sum_total_term_freq: sum_total_term_freq,
+        })
+    }
 }
@@ -1,11 +1,12 @@
 use std::str;
-use std::sync::{Mutex, RwLock};
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::collections::HashMap;
 
-use rocksdb::{self, DB};
+use rocksdb::{self, DB, DBRawIterator, WriteBatch};
 use search::{Term, TermId};
+use search::schema::FieldId;
 use search::query::multi_term_selector::MultiTermSelector;
+use search::term_selection::{TermCursor, TerminatingTermCursor, select_terms};
 
 use super::key_builder::KeyBuilder;
 
@@ -15,10 +16,15 @@ use super::key_builder::KeyBuilder;
 /// keys. We generate a unique number for each one to use instead.
 ///
 /// The term dictionary is a mapping between terms and their internal IDs
-/// (aka. TermId). It is entirely held in memory and persisted to the disk.
+/// (aka. TermId). It is stored in RocksDB under "t{term}" keys, which RocksDB
+/// keeps sorted and block-compressed, so no terms are held in memory.
+///
+/// TermIds are shared between fields, but each field also has its own dictionary
+/// of the terms that have been indexed in it ("f{field}/{term}" keys). Multi-term
+/// queries walk the dictionary of the field being queried so they only select
+/// terms that are present in that field.
 pub struct TermDictionaryManager {
     next_term_id: AtomicUsize,
-    terms: RwLock<HashMap<Term, TermId>>,
     write_lock: Mutex<i32>,
 }
 
@@ -31,7 +37,6 @@ impl TermDictionaryManager {
 
         Ok(TermDictionaryManager {
             next_term_id: AtomicUsize::new(1),
-            terms: RwLock::new(HashMap::new()),
             write_lock: Mutex::new(0),
         })
     }
@@ -45,49 +50,51 @@ impl TermDictionaryManager {
             None => 1,  // TODO: error
         };
 
-        // Read dictionary
-        let mut terms = HashMap::new();
-        let mut iter = db.raw_iterator();
-        iter.seek(b"t");
-        while iter.valid() {
-            let k = iter.key().unwrap();
-
-            if k[0] != b't' {
-                break;
-            }
-
-            let term_id = TermId(str::from_utf8(unsafe { &iter.value_inner().unwrap() }).unwrap().parse::<u32>().unwrap());
-            terms.insert(Term::from_bytes(&k[1..]), term_id);
-
-            iter.next();
-        }
-
         Ok(TermDictionaryManager {
             next_term_id: AtomicUsize::new(next_term_id as usize),
-            terms: RwLock::new(terms),
             write_lock: Mutex::new(0),
         })
     }
 
     /// Retrieves the TermId for the given term
-    pub fn get(&self, term: &Term) -> Option<TermId> {
-        self.terms.read().unwrap().get(term).cloned()
+    pub fn get(&self, db: &DB, term: &Term) -> Result<Option<TermId>, rocksdb::Error> {
+        let kb = KeyBuilder::term_dict_mapping(term.as_bytes());
+
+        match try!(db.get(&kb.key())) {
+            Some(value) => Ok(Some(parse_term_id(&value))),
+            None => Ok(None),
+        }
     }
 
-    /// Iterates over terms in the dictionary which match the selector
-    pub fn select(&self, term_selector: &MultiTermSelector) -> Vec<TermId> {
-        self.terms.read().unwrap().iter()
-            .filter(|&(term, _term_id)| {
-                term_selector.matches(term)
-            })
-            .map(|(_term, term_id)| *term_id)
-            .collect()
+    /// Finds the term with the given TermId in a field
+    ///
+    /// This scans the field's dictionary so it should only be used for debugging (such as explaining scores)
+    pub fn get_term(&self, db: &DB, field_id: FieldId, term_id: TermId) -> Option<Term> {
+        let mut cursor = DBTermCursor::new(db, field_id);
+        cursor.seek(b"");
+
+        while let Some((term, current_term_id)) = cursor.current() {
+            if current_term_id == term_id {
+                return Some(term);
+            }
+
+            cursor.next();
+        }
+
+        None
+    }
+
+    /// Iterates over terms in a field's dictionary which match the selector
+    ///
+    /// Stops early, returning the terms found so far, once "should_terminate" returns true
+    pub fn select(&self, db: &DB, field_id: FieldId, term_selector: &MultiTermSelector, should_terminate: &mut FnMut() -> bool) -> Vec<TermId> {
+        select_terms(&mut TerminatingTermCursor::new(DBTermCursor::new(db, field_id), should_terminate), term_selector)
     }
 
     /// Retrieves the TermId for the given term, adding the term to the
     /// dictionary if it doesn't exist
     pub fn get_or_create(&self, db: &DB, term: &Term) -> Result<TermId, rocksdb::Error> {
-        if let Some(term_id) = self.get(term) {
+        if let Some(term_id) = try!(self.get(db, term)) {
             return Ok(term_id);
         }
 
@@ -101,25 +108,165 @@ impl TermDictionaryManager {
         let term_id = TermId(next_term_id);
 
         // Get write lock
-        // Note: We have a separate lock so we don't need to keep an exclusive
-        // lock on the in-memory term dictionary while writing to disk, as this
-        // blocks readers.
         let _guard = self.write_lock.lock().unwrap();
 
         // It's possible that another thread has written the term to the dictionary
         // since we checked earlier. If this is the case, We should forget about
         // writing our TermId and use the one that has been inserted already.
-        if let Some(term_id) = self.terms.read().unwrap().get(term) {
-            return Ok(*term_id);
+        if let Some(term_id) = try!(self.get(db, term)) {
+            return Ok(term_id);
         }
 
         // Write it to the on-disk term dictionary
         let kb = KeyBuilder::term_dict_mapping(term.as_bytes());
         try!(db.put(kb.key(), next_term_id.to_string().as_bytes()));
 
-        // Write it to the term dictionary
-        self.terms.write().unwrap().insert(term.clone(), term_id);;
-
         Ok(term_id)
     }
+
+    /// Adds a term to a field's dictionary
+    ///
+    /// This should be written in the same batch as the postings lists of the term so the
+    /// term can't be selected before there is anything to find.
+    pub fn write_field_term(&self, write_batch: &mut WriteBatch, field_id: FieldId, term: &Term, term_id: TermId) -> Result<(), rocksdb::Error> {
+        let kb = KeyBuilder::field_term_dict_mapping(field_id.0, term.as_bytes());
+        write_batch.put(kb.key(), term_id.0.to_string().as_bytes())
+    }
+
+    /// Counts the unique terms that have been indexed in a field
+    ///
+    /// Terms stay in the dictionary after all documents containing them have been deleted.
+    pub fn count_field_terms(&self, db: &DB, field_id: FieldId) -> usize {
+        let mut cursor = DBTermCursor::new(db, field_id);
+        let mut count = 0;
+
+        cursor.seek(b"");
+        while cursor.current().is_some() {
+            count += 1;
+            cursor.next();
+        }
+
+        count
+    }
+}
+
+
+fn parse_term_id(value: &[u8]) -> TermId {
+    TermId(str::from_utf8(value).unwrap().parse::<u32>().unwrap())
+}
+
+
+/// Walks over a field's on-disk term dictionary
+struct DBTermCursor {
+    iter: DBRawIterator,
+    field_id: FieldId,
+
+    /// The prefix of all keys in the field's dictionary
+    prefix: Vec<u8>,
+}
+
+impl DBTermCursor {
+    fn new(db: &DB, field_id: FieldId) -> DBTermCursor {
+        DBTermCursor {
+            iter: db.raw_iterator(),
+            field_id: field_id,
+            prefix: KeyBuilder::field_term_dict_prefix(field_id.0).key().to_vec(),
+        }
+    }
+}
+
+impl TermCursor for DBTermCursor {
+    fn seek(&mut self, term: &[u8]) {
+        let kb = KeyBuilder::field_term_dict_mapping(self.field_id.0, term);
+        self.iter.seek(&kb.key());
+    }
+
+    fn next(&mut self) {
+        self.iter.next();
+    }
+
+    fn current(&self) -> Option<(Term, TermId)> {
+        if !self.iter.valid() {
+            return None;
+        }
+
+        let k = self.iter.key().unwrap();
+        if !k.starts_with(&self.prefix) {
+            return None;
+        }
+
+        Some((Term::from_bytes(&k[self.prefix.len()..]), parse_term_id(&self.iter.value().unwrap())))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::remove_dir_all;
+    use std::path::Path;
+
+    use rocksdb::{DB, Options, WriteBatch};
+    use search::Term;
+    use search::schema::FieldId;
+    use search::query::multi_term_selector::MultiTermSelector;
+
+    use super::TermDictionaryManager;
+
+    fn remove_dir_all_ignore_error<P: AsRef<Path>>(path: P) {
+        match remove_dir_all(&path) {
+            Ok(_) => {}
+            Err(_) => {}  // Don't care if this fails
+        }
+    }
+
+    #[test]
+    fn test_on_disk_dictionary() {
+        remove_dir_all_ignore_error("test_indices/test_term_dictionary");
+
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, "test_indices/test_term_dictionary").unwrap();
+
+        let hello_id = {
+            let term_dictionary = TermDictionaryManager::new(&db).unwrap();
+            let mut write_batch = WriteBatch::default();
+
+            // "hello", "help" and "world" are in the first field, "help" is also in the second field
+            for term in ["hello", "help", "world"].iter() {
+                let term = Term::from_string(term);
+                let term_id = term_dictionary.get_or_create(&db, &term).unwrap();
+                term_dictionary.write_field_term(&mut write_batch, FieldId(1), &term, term_id).unwrap();
+            }
+
+            let help_id = term_dictionary.get_or_create(&db, &Term::from_string("help")).unwrap();
+            term_dictionary.write_field_term(&mut write_batch, FieldId(2), &Term::from_string("help"), help_id).unwrap();
+            db.write(write_batch).unwrap();
+
+            // Creating an existing term gives the same id
+            let hello_id = term_dictionary.get(&db, &Term::from_string("hello")).unwrap().unwrap();
+            assert_eq!(term_dictionary.get_or_create(&db, &Term::from_string("hello")).unwrap(), hello_id);
+
+            hello_id
+        };
+
+        // Nothing is loaded into memory when the dictionary is opened, all reads go to the disk
+        let term_dictionary = TermDictionaryManager::open(&db).unwrap();
+
+        assert_eq!(term_dictionary.get(&db, &Term::from_string("hello")).unwrap(), Some(hello_id));
+        assert_eq!(term_dictionary.get(&db, &Term::from_string("foo")).unwrap(), None);
+        assert_eq!(term_dictionary.get_term(&db, FieldId(1), hello_id), Some(Term::from_string("hello")));
+        assert_eq!(term_dictionary.get_term(&db, FieldId(2), hello_id), None);
+
+        // Terms are only selected from the field being queried
+        assert_eq!(term_dictionary.select(&db, FieldId(1), &MultiTermSelector::Prefix("hel".to_string()), &mut || false).len(), 2);
+        assert_eq!(term_dictionary.select(&db, FieldId(2), &MultiTermSelector::Prefix("hel".to_string()), &mut || false).len(), 1);
+        assert_eq!(term_dictionary.select(&db, FieldId(1), &MultiTermSelector::Wildcard("*o*".to_string()), &mut || false).len(), 2);
+        assert_eq!(term_dictionary.count_field_terms(&db, FieldId(1)), 3);
+        assert_eq!(term_dictionary.count_field_terms(&db, FieldId(2)), 1);
+        assert_eq!(term_dictionary.count_field_terms(&db, FieldId(3)), 0);
+
+        // New terms don't reuse ids
+        let foo_id = term_dictionary.get_or_create(&db, &Term::from_string("foo")).unwrap();
+        assert!(foo_id.0 > hello_id.0);
+    }
 }
@@ -0,0 +1,104 @@
+use std::time::{Duration, Instant};
+
+use search::collectors::{Collector, SplitCollector, DocumentMatch};
+
+/// Stops a search once a deadline has passed
+///
+/// This doesn't keep any documents, it's added to a MultiCollector alongside the collectors that
+/// do. Those are left with the documents that were found before the deadline.
+#[derive(Debug)]
+pub struct DeadlineCollector {
+    /// None if the deadline is too far away to be represented, it never passes
+    deadline: Option<Instant>,
+
+    /// Set when the search is told to stop, the deadline may have passed after the search finished
+    timed_out: bool,
+}
+
+impl DeadlineCollector {
+    pub fn new(deadline: Instant) -> DeadlineCollector {
+        DeadlineCollector {
+            deadline: Some(deadline),
+            timed_out: false,
+        }
+    }
+
+    /// Creates a collector with a deadline of "timeout" from now
+    pub fn after(timeout: Duration) -> DeadlineCollector {
+        DeadlineCollector {
+            deadline: Instant::now().checked_add(timeout),
+            timed_out: false,
+        }
+    }
+
+    /// Returns true if the search was stopped because the deadline passed
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+}
+
+impl Collector for DeadlineCollector {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, _doc: DocumentMatch) {}
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        // None of the documents make a difference to this collector
+        Some(f32::INFINITY)
+    }
+
+    fn should_terminate(&mut self) -> bool {
+        if let Some(deadline) = self.deadline {
+            if !self.timed_out && Instant::now() >= deadline {
+                self.timed_out = true;
+            }
+        }
+
+        self.timed_out
+    }
+
+    fn split(&self) -> Option<Box<SplitCollector>> {
+        Some(Box::new(DeadlineCollector {
+            deadline: self.deadline,
+            timed_out: false,
+        }))
+    }
+
+    fn merge(&mut self, other: Box<SplitCollector>) {
+        let other = other.into_any().downcast::<DeadlineCollector>().expect("DeadlineCollector: can only merge collectors that it was split into");
+        self.timed_out |= other.timed_out;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use search::collectors::Collector;
+    use super::DeadlineCollector;
+
+    #[test]
+    fn test_deadline_collector_should_terminate() {
+        let mut collector = DeadlineCollector::after(Duration::from_secs(3600));
+        assert_eq!(collector.should_terminate(), false);
+        assert_eq!(collector.timed_out(), false);
+
+        let mut collector = DeadlineCollector::new(Instant::now());
+        assert_eq!(collector.should_terminate(), true);
+        assert_eq!(collector.timed_out(), true);
+    }
+
+    #[test]
+    fn test_deadline_collector_merge() {
+        let mut collector = DeadlineCollector::new(Instant::now());
+
+        // The search stopped on one of the other threads
+        let mut split = collector.split().unwrap();
+        assert_eq!(split.should_terminate(), true);
+        collector.merge(split);
+
+        assert_eq!(collector.timed_out(), true);
+    }
+}
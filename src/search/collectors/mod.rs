@@ -1,7 +1,16 @@
 pub mod total_count;
 pub mod top_score;
+pub mod multi;
+pub mod top_sorted;
+pub mod deadline;
+pub mod terminate_after;
 
-#[derive(Debug)]
+use std::any::Any;
+
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+
+#[derive(Debug, Clone)]
 pub struct DocumentMatch {
     id: u64,
     score: Option<f32>,
@@ -36,4 +45,53 @@ impl DocumentMatch {
 pub trait Collector {
     fn needs_score(&self) -> bool;
     fn collect(&mut self, doc: DocumentMatch);
+
+    /// Returns the score that a document must reach to make any difference to the collector
+    ///
+    /// Documents that can't reach this score may be skipped without being scored or collected.
+    /// Collectors that need to see every matching document (for example, to count them) must return None.
+    fn min_competitive_score(&self) -> Option<f32> {
+        None
+    }
+
+    /// Returns true once the collector doesn't want any more documents
+    ///
+    /// This is checked before each segment and before each document is scored. The search stops
+    /// early when it returns true, so the collectors only see some of the matching documents.
+    fn should_terminate(&mut self) -> bool {
+        false
+    }
+
+    /// Creates an empty collector that collects documents in the same way as this one
+    ///
+    /// Segments are searched on several threads at once when the collector can be split. The
+    /// documents of each segment go into their own split collector, these are merged back into
+    /// this collector in the order of the segments when the search finishes.
+    ///
+    /// Returns None if the collector can't be split, the segments are then searched one at a time.
+    fn split(&self) -> Option<Box<SplitCollector>> {
+        None
+    }
+
+    /// Adds the documents of a collector that was created by "split"
+    fn merge(&mut self, _other: Box<SplitCollector>) {
+        panic!("collector can't merge as it can't be split");
+    }
+}
+
+/// A collector that was split off from another one, so it can collect documents on another thread
+pub trait SplitCollector: Collector + Send {
+    /// Converts the collector into "Any", so it can be turned back into its concrete type when merged
+    fn into_any(self: Box<Self>) -> Box<Any + Send>;
+}
+
+impl<C: Collector + Send + 'static> SplitCollector for C {
+    fn into_any(self: Box<Self>) -> Box<Any + Send> {
+        self
+    }
+}
+
+/// Reads the values of fields, for collectors that look at more than the score
+pub trait FieldValueReader {
+    fn read_field_value(&self, field: FieldId, doc_id: DocId) -> Option<FieldValue>;
 }
@@ -0,0 +1,218 @@
+use search::collectors::{Collector, SplitCollector, DocumentMatch};
+
+/// Passes each document on to several other collectors
+///
+/// This allows a single search to fill more than one collector (for example, to find the top
+/// documents and run aggregations at the same time)
+pub struct MultiCollector<'a> {
+    collectors: Vec<&'a mut Collector>,
+}
+
+impl<'a> MultiCollector<'a> {
+    pub fn new() -> MultiCollector<'a> {
+        MultiCollector {
+            collectors: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, collector: &'a mut Collector) {
+        self.collectors.push(collector);
+    }
+}
+
+/// Documents can only be skipped if none of the collectors want them
+fn lowest_min_competitive_score<I: Iterator<Item = Option<f32>>>(scores: I) -> Option<f32> {
+    let mut min_score: Option<f32> = None;
+    for score in scores {
+        let score = match score {
+            Some(score) => score,
+            None => return None,
+        };
+
+        min_score = match min_score {
+            Some(min_score) if min_score < score => Some(min_score),
+            _ => Some(score),
+        };
+    }
+
+    min_score
+}
+
+impl<'a> Collector for MultiCollector<'a> {
+    fn needs_score(&self) -> bool {
+        self.collectors.iter().any(|collector| collector.needs_score())
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        for collector in self.collectors.iter_mut() {
+            collector.collect(doc.clone());
+        }
+    }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        lowest_min_competitive_score(self.collectors.iter().map(|collector| collector.min_competitive_score()))
+    }
+
+    fn should_terminate(&mut self) -> bool {
+        // The search stops if any of the collectors asks it to
+        self.collectors.iter_mut().any(|collector| collector.should_terminate())
+    }
+
+    fn split(&self) -> Option<Box<SplitCollector>> {
+        // All of the collectors must be split
+        let mut collectors = Vec::with_capacity(self.collectors.len());
+        for collector in self.collectors.iter() {
+            collectors.push(match collector.split() {
+                Some(collector) => collector,
+                None => return None,
+            });
+        }
+
+        Some(Box::new(SplitMultiCollector {
+            collectors: collectors,
+        }))
+    }
+
+    fn merge(&mut self, other: Box<SplitCollector>) {
+        let other = other.into_any().downcast::<SplitMultiCollector>().expect("MultiCollector: can only merge collectors that it was split into");
+
+        for (collector, other_collector) in self.collectors.iter_mut().zip(other.collectors.into_iter()) {
+            collector.merge(other_collector);
+        }
+    }
+}
+
+/// A MultiCollector that has been split, this owns the collectors that its collectors were split into
+struct SplitMultiCollector {
+    collectors: Vec<Box<SplitCollector>>,
+}
+
+impl Collector for SplitMultiCollector {
+    fn needs_score(&self) -> bool {
+        self.collectors.iter().any(|collector| collector.needs_score())
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        for collector in self.collectors.iter_mut() {
+            collector.collect(doc.clone());
+        }
+    }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        lowest_min_competitive_score(self.collectors.iter().map(|collector| collector.min_competitive_score()))
+    }
+
+    fn should_terminate(&mut self) -> bool {
+        // The search stops if any of the collectors asks it to
+        self.collectors.iter_mut().any(|collector| collector.should_terminate())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::collectors::{Collector, DocumentMatch};
+    use search::collectors::top_score::TopScoreCollector;
+    use search::collectors::total_count::TotalCountCollector;
+    use search::collectors::terminate_after::TerminateAfterCollector;
+    use super::MultiCollector;
+
+    #[test]
+    fn test_multi_collector_needs_score() {
+        let mut total_count = TotalCountCollector::new();
+        let mut top_score = TopScoreCollector::new(10);
+
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut total_count);
+            assert_eq!(collector.needs_score(), false);
+        }
+
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut total_count);
+            collector.add(&mut top_score);
+            assert_eq!(collector.needs_score(), true);
+        }
+    }
+
+    #[test]
+    fn test_multi_collector_collect() {
+        let mut total_count = TotalCountCollector::new();
+        let mut top_score = TopScoreCollector::new(10);
+
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut total_count);
+            collector.add(&mut top_score);
+
+            collector.collect(DocumentMatch::new_scored(0, 1.0f32));
+            collector.collect(DocumentMatch::new_scored(1, 2.0f32));
+        }
+
+        assert_eq!(total_count.get_total_count(), 2);
+
+        let docs = top_score.into_sorted_vec();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].doc_id(), 1);
+    }
+
+    #[test]
+    fn test_multi_collector_min_competitive_score() {
+        let mut total_count = TotalCountCollector::new();
+        let mut top_score = TopScoreCollector::new(1);
+        top_score.collect(DocumentMatch::new_scored(0, 1.0f32));
+
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut top_score);
+            assert_eq!(collector.min_competitive_score(), Some(1.0f32));
+        }
+
+        {
+            // The total count needs to see every document
+            let mut collector = MultiCollector::new();
+            collector.add(&mut top_score);
+            collector.add(&mut total_count);
+            assert_eq!(collector.min_competitive_score(), None);
+        }
+    }
+
+    #[test]
+    fn test_multi_collector_should_terminate() {
+        let mut total_count = TotalCountCollector::new();
+        let mut terminate_after = TerminateAfterCollector::new(1);
+
+        let mut collector = MultiCollector::new();
+        collector.add(&mut total_count);
+        collector.add(&mut terminate_after);
+        assert_eq!(collector.should_terminate(), false);
+
+        // The search stops when any of the collectors asks it to
+        collector.collect(DocumentMatch::new_unscored(0));
+        assert_eq!(collector.should_terminate(), true);
+    }
+
+    #[test]
+    fn test_multi_collector_merge() {
+        let mut total_count = TotalCountCollector::new();
+        let mut top_score = TopScoreCollector::new(10);
+
+        {
+            let mut collector = MultiCollector::new();
+            collector.add(&mut total_count);
+            collector.add(&mut top_score);
+
+            collector.collect(DocumentMatch::new_scored(0, 1.0f32));
+
+            let mut split = collector.split().unwrap();
+            split.collect(DocumentMatch::new_scored(1, 2.0f32));
+            collector.merge(split);
+        }
+
+        assert_eq!(total_count.get_total_count(), 2);
+
+        let docs = top_score.into_sorted_vec();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].doc_id(), 1);
+    }
+}
@@ -0,0 +1,58 @@
+use search::collectors::{Collector, DocumentMatch};
+
+/// Stops a search once a number of documents have been collected
+///
+/// Like DeadlineCollector, this is added to a MultiCollector alongside the collectors that keep
+/// the documents. The documents are counted across all segments so this can't be split, searches
+/// that use it search one segment at a time.
+#[derive(Debug)]
+pub struct TerminateAfterCollector {
+    max_docs: u64,
+    collected: u64,
+}
+
+impl TerminateAfterCollector {
+    pub fn new(max_docs: u64) -> TerminateAfterCollector {
+        TerminateAfterCollector {
+            max_docs: max_docs,
+            collected: 0,
+        }
+    }
+
+    /// Returns true if the search was stopped because enough documents were collected
+    pub fn terminated_early(&self) -> bool {
+        self.collected >= self.max_docs
+    }
+}
+
+impl Collector for TerminateAfterCollector {
+    fn needs_score(&self) -> bool {
+        false
+    }
+
+    fn collect(&mut self, _doc: DocumentMatch) {
+        self.collected += 1;
+    }
+
+    fn should_terminate(&mut self) -> bool {
+        self.collected >= self.max_docs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::collectors::{Collector, DocumentMatch};
+    use super::TerminateAfterCollector;
+
+    #[test]
+    fn test_terminate_after_collector() {
+        let mut collector = TerminateAfterCollector::new(2);
+
+        collector.collect(DocumentMatch::new_unscored(0));
+        assert_eq!(collector.should_terminate(), false);
+
+        collector.collect(DocumentMatch::new_unscored(1));
+        assert_eq!(collector.should_terminate(), true);
+        assert_eq!(collector.terminated_early(), true);
+    }
+}
@@ -1,7 +1,7 @@
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
 
-use search::collectors::{Collector, DocumentMatch};
+use search::collectors::{Collector, SplitCollector, DocumentMatch};
 
 /// An f32 that cannot be NaN.
 /// We need to order documents by score but NaN cannot be ordered, so we convert all scores into
@@ -59,6 +59,15 @@ impl TopScoreCollector {
         }
     }
 
+    fn push(&mut self, scored_document: ScoredDocument) {
+        self.heap.push(scored_document);
+
+        // Now reduce the heap size if it's too big
+        if self.heap.len() > self.max_docs {
+            self.heap.pop();
+        }
+    }
+
     pub fn into_sorted_vec(self) -> Vec<DocumentMatch> {
         self.heap.into_sorted_vec().iter()
             .map(|scored_document| {
@@ -100,11 +109,27 @@ impl Collector for TopScoreCollector {
         };
 
         // Now insert the document into the heap
-        self.heap.push(scored_document);
+        self.push(scored_document);
+    }
 
-        // Now reduce the heap size if it's too big
-        if self.heap.len() > self.max_docs {
-            self.heap.pop();
+    fn min_competitive_score(&self) -> Option<f32> {
+        // Once the heap is full, documents must beat the lowest score in it to get in
+        if self.max_docs == 0 || self.heap.len() < self.max_docs {
+            return None;
+        }
+
+        self.heap.peek().map(|scored_document| -scored_document.score.0)
+    }
+
+    fn split(&self) -> Option<Box<SplitCollector>> {
+        Some(Box::new(TopScoreCollector::new(self.max_docs)))
+    }
+
+    fn merge(&mut self, other: Box<SplitCollector>) {
+        let other = other.into_any().downcast::<TopScoreCollector>().expect("TopScoreCollector: can only merge collectors that it was split into");
+
+        for scored_document in other.heap.into_iter() {
+            self.push(scored_document);
         }
     }
 }
@@ -159,4 +184,35 @@ mod tests {
         assert_eq!(docs[0].id, 2);
         assert_eq!(docs[1].id, 0);
     }
+
+    #[test]
+    fn test_top_score_collector_min_competitive_score() {
+        let mut collector = TopScoreCollector::new(2);
+        assert_eq!(collector.min_competitive_score(), None);
+
+        collector.collect(DocumentMatch::new_scored(0, 1.0f32));
+        assert_eq!(collector.min_competitive_score(), None);
+
+        collector.collect(DocumentMatch::new_scored(1, 0.5f32));
+        assert_eq!(collector.min_competitive_score(), Some(0.5f32));
+
+        collector.collect(DocumentMatch::new_scored(2, 2.0f32));
+        assert_eq!(collector.min_competitive_score(), Some(1.0f32));
+    }
+
+    #[test]
+    fn test_top_score_collector_merge() {
+        let mut collector = TopScoreCollector::new(2);
+        collector.collect(DocumentMatch::new_scored(0, 1.0f32));
+
+        let mut split = collector.split().unwrap();
+        split.collect(DocumentMatch::new_scored(1, 0.5f32));
+        split.collect(DocumentMatch::new_scored(2, 2.0f32));
+        collector.merge(split);
+
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].id, 2);
+        assert_eq!(docs[1].id, 0);
+    }
 }
@@ -0,0 +1,431 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use chrono::Timelike;
+use search::document::{DocId, FieldValue};
+use search::schema::FieldId;
+use search::collectors::{Collector, DocumentMatch, FieldValueReader};
+
+/// What to sort documents by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortBy {
+    Score,
+
+    /// Index order
+    Doc,
+
+    Field(FieldId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Where to put documents that don't have a value to sort by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortMissing {
+    First,
+    Last,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub by: SortBy,
+    pub order: SortOrder,
+    pub missing: SortMissing,
+}
+
+/// The value a document is sorted by for one of the sort keys
+///
+/// Datetimes are converted into milliseconds since the epoch
+#[derive(Debug, Clone, PartialEq)]
+pub enum SortValue {
+    Missing,
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl SortValue {
+    pub fn from_field_value(value: &FieldValue) -> SortValue {
+        match *value {
+            FieldValue::String(ref string) => SortValue::String(string.clone()),
+            FieldValue::Integer(value) => SortValue::Integer(value),
+            FieldValue::Boolean(value) => SortValue::Boolean(value),
+            FieldValue::DateTime(ref value) => SortValue::Integer(value.timestamp() * 1000 + (value.nanosecond() / 1000000) as i64),
+        }
+    }
+
+    /// Compares two values, ignoring the sort order
+    ///
+    /// Values of different types shouldn't be compared, but if they are they are ordered by type
+    fn compare(&self, other: &SortValue) -> Ordering {
+        match (self, other) {
+            (&SortValue::Boolean(a), &SortValue::Boolean(b)) => a.cmp(&b),
+            (&SortValue::Integer(a), &SortValue::Integer(b)) => a.cmp(&b),
+            (&SortValue::Float(a), &SortValue::Float(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            (&SortValue::String(ref a), &SortValue::String(ref b)) => a.cmp(b),
+            (a, b) => a.type_rank().cmp(&b.type_rank()),
+        }
+    }
+
+    fn type_rank(&self) -> u8 {
+        match *self {
+            SortValue::Missing => 0,
+            SortValue::Boolean(_) => 1,
+            SortValue::Integer(_) => 2,
+            SortValue::Float(_) => 3,
+            SortValue::String(_) => 4,
+        }
+    }
+}
+
+/// Returns true if documents that are equal on all of the sort keys need a tiebreaker
+///
+/// The tiebreaker is the document's id. This is added to the end of the sort values of each
+/// document, so the values of the last document on a page can be passed to "search_after" to
+/// find the next page without skipping documents that have the same values as it.
+/// Sorts that include the "_doc" key already have a unique value for each document.
+pub fn needs_tiebreaker(sort: &[SortKey]) -> bool {
+    !sort.iter().any(|key| key.by == SortBy::Doc)
+}
+
+/// Compares two documents' sort values
+///
+/// Documents that should be returned first are "less" than the others
+fn compare_sort_values(sort: &[SortKey], a: &[SortValue], b: &[SortValue]) -> Ordering {
+    for (key, (a, b)) in sort.iter().zip(a.iter().zip(b.iter())) {
+        let ordering = match (a, b) {
+            (&SortValue::Missing, &SortValue::Missing) => Ordering::Equal,
+            (&SortValue::Missing, _) => {
+                match key.missing {
+                    SortMissing::First => Ordering::Less,
+                    SortMissing::Last => Ordering::Greater,
+                }
+            }
+            (_, &SortValue::Missing) => {
+                match key.missing {
+                    SortMissing::First => Ordering::Greater,
+                    SortMissing::Last => Ordering::Less,
+                }
+            }
+            (a, b) => {
+                match key.order {
+                    SortOrder::Asc => a.compare(b),
+                    SortOrder::Desc => b.compare(a),
+                }
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    Ordering::Equal
+}
+
+#[derive(Debug)]
+struct SortedDocument<'a> {
+    sort: &'a [SortKey],
+    id: u64,
+    score: Option<f32>,
+    values: Vec<SortValue>,
+}
+
+impl<'a> PartialEq for SortedDocument<'a> {
+    fn eq(&self, other: &SortedDocument<'a>) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<'a> Eq for SortedDocument<'a> {}
+
+impl<'a> Ord for SortedDocument<'a> {
+    fn cmp(&self, other: &SortedDocument<'a>) -> Ordering {
+        // Documents with the same values are kept in index order
+        match compare_sort_values(self.sort, &self.values, &other.values) {
+            Ordering::Equal => self.id.cmp(&other.id),
+            ordering => ordering,
+        }
+    }
+}
+
+impl<'a> PartialOrd for SortedDocument<'a> {
+    fn partial_cmp(&self, other: &SortedDocument<'a>) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Finds the first documents in the order given by a list of sort keys
+///
+/// If "search_after" is set, only documents that sort after those values are collected. As this
+/// collector only keeps the top documents, this allows deep pagination without the cost of
+/// collecting every document before the requested page.
+///
+/// If the sort needs a tiebreaker (see "needs_tiebreaker"), the id of each document is added to
+/// its sort values. "search_after" may include this to continue from the middle of a group of
+/// documents with the same values.
+pub struct TopSortedCollector<'a, R: FieldValueReader + ?Sized + 'a> {
+    sort: &'a [SortKey],
+    tiebreaker: bool,
+    search_after: Option<&'a [SortValue]>,
+    reader: &'a R,
+    max_docs: usize,
+    heap: BinaryHeap<SortedDocument<'a>>,
+}
+
+impl<'a, R: FieldValueReader + ?Sized + 'a> TopSortedCollector<'a, R> {
+    pub fn new(sort: &'a [SortKey], search_after: Option<&'a [SortValue]>, reader: &'a R, max_docs: usize) -> TopSortedCollector<'a, R> {
+        TopSortedCollector {
+            sort: sort,
+            tiebreaker: needs_tiebreaker(sort),
+            search_after: search_after,
+            reader: reader,
+            max_docs: max_docs,
+            heap: BinaryHeap::with_capacity(max_docs + 1),
+        }
+    }
+
+    /// Returns the documents in order, along with the values they were sorted by
+    pub fn into_sorted_vec(self) -> Vec<(DocumentMatch, Vec<SortValue>)> {
+        self.heap.into_sorted_vec().into_iter()
+            .map(|sorted_document| {
+                let doc = match sorted_document.score {
+                    Some(score) => DocumentMatch::new_scored(sorted_document.id, score),
+                    None => DocumentMatch::new_unscored(sorted_document.id),
+                };
+
+                (doc, sorted_document.values)
+            })
+            .collect()
+    }
+}
+
+impl<'a, R: FieldValueReader + ?Sized + 'a> Collector for TopSortedCollector<'a, R> {
+    fn needs_score(&self) -> bool {
+        self.sort.iter().any(|key| key.by == SortBy::Score)
+    }
+
+    fn collect(&mut self, doc: DocumentMatch) {
+        let doc_id = doc.doc_id();
+
+        let mut values = self.sort.iter().map(|key| {
+            match key.by {
+                SortBy::Score => {
+                    match doc.score() {
+                        Some(score) => SortValue::Float(score as f64),
+                        None => SortValue::Missing,
+                    }
+                }
+                SortBy::Doc => SortValue::Integer(doc_id as i64),
+                SortBy::Field(field) => {
+                    match self.reader.read_field_value(field, DocId::from_u64(doc_id)) {
+                        Some(value) => SortValue::from_field_value(&value),
+                        None => SortValue::Missing,
+                    }
+                }
+            }
+        }).collect::<Vec<SortValue>>();
+
+        if self.tiebreaker {
+            values.push(SortValue::Integer(doc_id as i64));
+        }
+
+        // Skip documents that were on previous pages
+        if let Some(search_after) = self.search_after {
+            match compare_sort_values(self.sort, &values, search_after) {
+                Ordering::Greater => {}
+                Ordering::Less => return,
+                Ordering::Equal => {
+                    // Documents with the same values are in index order, only skip the ones
+                    // before the tiebreaker. If there isn't one, all of them were on previous pages
+                    let is_after = match (values.get(self.sort.len()), search_after.get(self.sort.len())) {
+                        (Some(&SortValue::Integer(doc)), Some(&SortValue::Integer(after_doc))) => doc > after_doc,
+                        _ => false,
+                    };
+
+                    if !is_after {
+                        return;
+                    }
+                }
+            }
+        }
+
+        // Now insert the document into the heap
+        self.heap.push(SortedDocument {
+            sort: self.sort,
+            id: doc_id,
+            score: doc.score(),
+            values: values,
+        });
+
+        // Now reduce the heap size if it's too big
+        if self.heap.len() > self.max_docs {
+            self.heap.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use search::document::{DocId, FieldValue};
+    use search::schema::FieldId;
+    use search::collectors::{Collector, DocumentMatch, FieldValueReader};
+    use super::{TopSortedCollector, SortKey, SortBy, SortOrder, SortMissing, SortValue};
+
+    const PRICE: FieldId = FieldId(1);
+
+    struct TestReader {
+        values: HashMap<(FieldId, DocId), FieldValue>,
+    }
+
+    impl FieldValueReader for TestReader {
+        fn read_field_value(&self, field: FieldId, doc_id: DocId) -> Option<FieldValue> {
+            self.values.get(&(field, doc_id)).cloned()
+        }
+    }
+
+    fn make_test_reader() -> TestReader {
+        let mut values = HashMap::new();
+        values.insert((PRICE, DocId::from_u64(0)), FieldValue::Integer(20));
+        values.insert((PRICE, DocId::from_u64(1)), FieldValue::Integer(10));
+        values.insert((PRICE, DocId::from_u64(2)), FieldValue::Integer(30));
+        values.insert((PRICE, DocId::from_u64(4)), FieldValue::Integer(10));
+
+        // Document 3 doesn't have a price
+        TestReader {
+            values: values,
+        }
+    }
+
+    fn collect_doc_ids<R: FieldValueReader>(mut collector: TopSortedCollector<R>) -> Vec<u64> {
+        for doc_id in 0..5 {
+            collector.collect(DocumentMatch::new_scored(doc_id, doc_id as f32));
+        }
+
+        collector.into_sorted_vec().iter().map(|&(ref doc, _)| doc.doc_id()).collect()
+    }
+
+    #[test]
+    fn test_sort_by_field_asc() {
+        let reader = make_test_reader();
+        let sort = vec![
+            SortKey { by: SortBy::Field(PRICE), order: SortOrder::Asc, missing: SortMissing::Last },
+        ];
+
+        let collector = TopSortedCollector::new(&sort, None, &reader, 10);
+
+        // Documents with the same price should be in index order
+        assert_eq!(collect_doc_ids(collector), vec![1, 4, 0, 2, 3]);
+    }
+
+    #[test]
+    fn test_sort_by_field_desc_missing_first() {
+        let reader = make_test_reader();
+        let sort = vec![
+            SortKey { by: SortBy::Field(PRICE), order: SortOrder::Desc, missing: SortMissing::First },
+        ];
+
+        let collector = TopSortedCollector::new(&sort, None, &reader, 10);
+
+        assert_eq!(collect_doc_ids(collector), vec![3, 2, 0, 1, 4]);
+    }
+
+    #[test]
+    fn test_sort_by_multiple_keys() {
+        let reader = make_test_reader();
+        let sort = vec![
+            SortKey { by: SortBy::Field(PRICE), order: SortOrder::Asc, missing: SortMissing::Last },
+            SortKey { by: SortBy::Score, order: SortOrder::Desc, missing: SortMissing::Last },
+        ];
+
+        let collector = TopSortedCollector::new(&sort, None, &reader, 10);
+        assert_eq!(collector.needs_score(), true);
+
+        assert_eq!(collect_doc_ids(collector), vec![4, 1, 0, 2, 3]);
+    }
+
+    #[test]
+    fn test_max_docs() {
+        let reader = make_test_reader();
+        let sort = vec![
+            SortKey { by: SortBy::Field(PRICE), order: SortOrder::Asc, missing: SortMissing::Last },
+        ];
+
+        let collector = TopSortedCollector::new(&sort, None, &reader, 2);
+
+        assert_eq!(collect_doc_ids(collector), vec![1, 4]);
+    }
+
+    #[test]
+    fn test_search_after() {
+        let reader = make_test_reader();
+        let sort = vec![
+            SortKey { by: SortBy::Field(PRICE), order: SortOrder::Asc, missing: SortMissing::Last },
+            SortKey { by: SortBy::Doc, order: SortOrder::Asc, missing: SortMissing::Last },
+        ];
+        let search_after = vec![SortValue::Integer(10), SortValue::Integer(4)];
+
+        let collector = TopSortedCollector::new(&sort, Some(&search_after[..]), &reader, 2);
+
+        assert_eq!(collect_doc_ids(collector), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_sort_values() {
+        let reader = make_test_reader();
+        let sort = vec![
+            SortKey { by: SortBy::Field(PRICE), order: SortOrder::Asc, missing: SortMissing::Last },
+        ];
+
+        let mut collector = TopSortedCollector::new(&sort, None, &reader, 10);
+        collector.collect(DocumentMatch::new_unscored(0));
+        collector.collect(DocumentMatch::new_unscored(3));
+
+        let docs = collector.into_sorted_vec();
+        assert_eq!(docs[0].1, vec![SortValue::Integer(20), SortValue::Integer(0)]);
+        assert_eq!(docs[1].1, vec![SortValue::Missing, SortValue::Integer(3)]);
+    }
+
+    #[test]
+    fn test_search_after_with_ties() {
+        let reader = make_test_reader();
+        let sort = vec![
+            SortKey { by: SortBy::Field(PRICE), order: SortOrder::Asc, missing: SortMissing::Last },
+        ];
+
+        // Documents 1 and 4 both have a price of 10, the first page ends between them
+        let mut collector = TopSortedCollector::new(&sort, None, &reader, 1);
+        for doc_id in 0..5 {
+            collector.collect(DocumentMatch::new_unscored(doc_id));
+        }
+        let first_page = collector.into_sorted_vec();
+        assert_eq!(first_page.len(), 1);
+        assert_eq!(first_page[0].0.doc_id(), 1);
+        assert_eq!(first_page[0].1, vec![SortValue::Integer(10), SortValue::Integer(1)]);
+
+        // The next page starts with the other document with the same price
+        let search_after = first_page[0].1.clone();
+        let collector = TopSortedCollector::new(&sort, Some(&search_after[..]), &reader, 2);
+        assert_eq!(collect_doc_ids(collector), vec![4, 0]);
+    }
+
+    #[test]
+    fn test_search_after_without_tiebreaker() {
+        let reader = make_test_reader();
+        let sort = vec![
+            SortKey { by: SortBy::Field(PRICE), order: SortOrder::Asc, missing: SortMissing::Last },
+        ];
+        let search_after = vec![SortValue::Integer(10)];
+
+        // Without the tiebreaker, all documents with the same values are skipped
+        let collector = TopSortedCollector::new(&sort, Some(&search_after[..]), &reader, 10);
+        assert_eq!(collect_doc_ids(collector), vec![0, 2, 3]);
+    }
+}
@@ -1,4 +1,4 @@
-use search::collectors::{Collector, DocumentMatch};
+use search::collectors::{Collector, SplitCollector, DocumentMatch};
 
 #[derive(Debug)]
 pub struct TotalCountCollector {
@@ -25,6 +25,15 @@ impl Collector for TotalCountCollector {
     fn collect(&mut self, _doc: DocumentMatch) {
         self.total_count += 1;
     }
+
+    fn split(&self) -> Option<Box<SplitCollector>> {
+        Some(Box::new(TotalCountCollector::new()))
+    }
+
+    fn merge(&mut self, other: Box<SplitCollector>) {
+        let other = other.into_any().downcast::<TotalCountCollector>().expect("TotalCountCollector: can only merge collectors that it was split into");
+        self.total_count += other.total_count;
+    }
 }
 
 #[cfg(test)]
@@ -56,4 +65,17 @@ mod tests {
 
         assert_eq!(collector.get_total_count(), 3);
     }
+
+    #[test]
+    fn test_total_count_collector_merge() {
+        let mut collector = TotalCountCollector::new();
+        collector.collect(DocumentMatch::new_unscored(0));
+
+        let mut split = collector.split().unwrap();
+        split.collect(DocumentMatch::new_unscored(1));
+        split.collect(DocumentMatch::new_unscored(2));
+        collector.merge(split);
+
+        assert_eq!(collector.get_total_count(), 3);
+    }
 }
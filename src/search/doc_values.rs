@@ -0,0 +1,412 @@
+//! Doc values are a column of per-document values for a field in a segment
+//!
+//! Stored field values are kept under a key per document. This is fine for fetching the values
+//! of a page of search results, but sorting and aggregating needs a value for every matching
+//! document which is far too slow to do with a lookup per document. Doc values keep all the
+//! values of a field in a segment together so they can be loaded in one go.
+
+use std::u32;
+
+use chrono::{NaiveDateTime, DateTime, Utc, Timelike};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+use search::document::FieldValue;
+use search::schema::FieldType;
+
+/// Returns true if doc values are kept for fields of this type
+///
+/// Text fields are excluded as their values are usually long and aren't useful to sort by
+pub fn field_type_has_doc_values(field_type: &FieldType) -> bool {
+    match *field_type {
+        FieldType::Text => false,
+        FieldType::PlainString | FieldType::I64 | FieldType::Boolean | FieldType::DateTime => true,
+    }
+}
+
+/// Converts a value into the integer that is stored in a numeric column
+///
+/// Booleans are stored as 0/1 and datetimes as microseconds since the epoch. Strings can't
+/// be stored in numeric columns.
+fn field_value_to_numeric(value: &FieldValue) -> Option<i64> {
+    match *value {
+        FieldValue::Integer(value) => Some(value),
+        FieldValue::Boolean(value) => Some(if value { 1 } else { 0 }),
+        FieldValue::DateTime(ref value) => Some(value.timestamp() * 1000000 + (value.nanosecond() / 1000) as i64),
+        FieldValue::String(_) => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DocValues {
+    /// Values of integer, boolean and datetime fields, indexed by local document id
+    Numeric(Vec<Option<i64>>),
+
+    /// Values of keyword fields
+    ///
+    /// Each distinct value is kept once in "terms", which is sorted. Documents refer to their
+    /// value by its position in that list (the ordinal). So, within a segment, comparing the
+    /// ordinals of two documents gives the same result as comparing their values.
+    Keyword {
+        terms: Vec<String>,
+        ordinals: Vec<Option<u32>>,
+    },
+}
+
+impl DocValues {
+    /// Returns the number of documents in the column (including ones that don't have a value)
+    pub fn len(&self) -> usize {
+        match *self {
+            DocValues::Numeric(ref values) => values.len(),
+            DocValues::Keyword { ref ordinals, .. } => ordinals.len(),
+        }
+    }
+
+    pub fn get_numeric(&self, local_id: u32) -> Option<i64> {
+        match *self {
+            DocValues::Numeric(ref values) => values.get(local_id as usize).cloned().unwrap_or(None),
+            DocValues::Keyword { .. } => None,
+        }
+    }
+
+    pub fn get_ordinal(&self, local_id: u32) -> Option<u32> {
+        match *self {
+            DocValues::Numeric(_) => None,
+            DocValues::Keyword { ref ordinals, .. } => ordinals.get(local_id as usize).cloned().unwrap_or(None),
+        }
+    }
+
+    pub fn get_keyword(&self, local_id: u32) -> Option<&str> {
+        match *self {
+            DocValues::Numeric(_) => None,
+            DocValues::Keyword { ref terms, .. } => {
+                self.get_ordinal(local_id).map(|ordinal| &terms[ordinal as usize][..])
+            }
+        }
+    }
+
+    /// Reads the value of a document, converting it back into the type of the field
+    pub fn get_field_value(&self, local_id: u32, field_type: &FieldType) -> Option<FieldValue> {
+        match *field_type {
+            FieldType::Text | FieldType::PlainString => {
+                self.get_keyword(local_id).map(|value| FieldValue::String(value.to_string()))
+            }
+            FieldType::I64 => {
+                self.get_numeric(local_id).map(FieldValue::Integer)
+            }
+            FieldType::Boolean => {
+                self.get_numeric(local_id).map(|value| FieldValue::Boolean(value != 0))
+            }
+            FieldType::DateTime => {
+                self.get_numeric(local_id).map(|timestamp_with_micros| {
+                    let timestamp = timestamp_with_micros / 1000000;
+                    let micros = timestamp_with_micros % 1000000;
+                    let datetime = NaiveDateTime::from_timestamp(timestamp, (micros * 1000) as u32);
+                    FieldValue::DateTime(DateTime::from_utc(datetime, Utc))
+                })
+            }
+        }
+    }
+
+    /// Packs the column into bytes
+    ///
+    /// Numeric columns are written as a "n" byte, the number of documents (u32), a bitmap of
+    /// which documents have a value followed by the value of each document (i64). Keyword
+    /// columns are written as a "k" byte, the number of terms (u32), each term (a u32 length
+    /// followed by the UTF-8 bytes), the number of documents (u32) then the ordinal of each
+    /// document (u32, u32::MAX if the document doesn't have a value). All integers are little
+    /// endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        match *self {
+            DocValues::Numeric(ref values) => {
+                bytes.push(b'n');
+                bytes.write_u32::<LittleEndian>(values.len() as u32).unwrap();
+
+                let mut present = vec![0u8; (values.len() + 7) / 8];
+                for (i, value) in values.iter().enumerate() {
+                    if value.is_some() {
+                        present[i / 8] |= 1 << (i % 8);
+                    }
+                }
+                bytes.extend(present);
+
+                for value in values.iter() {
+                    bytes.write_i64::<LittleEndian>(value.unwrap_or(0)).unwrap();
+                }
+            }
+            DocValues::Keyword { ref terms, ref ordinals } => {
+                bytes.push(b'k');
+                bytes.write_u32::<LittleEndian>(terms.len() as u32).unwrap();
+
+                for term in terms.iter() {
+                    bytes.write_u32::<LittleEndian>(term.len() as u32).unwrap();
+                    bytes.extend(term.as_bytes());
+                }
+
+                bytes.write_u32::<LittleEndian>(ordinals.len() as u32).unwrap();
+
+                for ordinal in ordinals.iter() {
+                    bytes.write_u32::<LittleEndian>(ordinal.unwrap_or(u32::MAX)).unwrap();
+                }
+            }
+        }
+
+        bytes
+    }
+
+    /// Unpacks a column that was packed by "to_bytes"
+    pub fn from_bytes(bytes: &[u8]) -> Result<DocValues, String> {
+        fn read_u32(bytes: &[u8], position: &mut usize) -> Result<u32, String> {
+            if bytes.len() < *position + 4 {
+                return Err("unexpected end of doc values".to_string());
+            }
+
+            let value = LittleEndian::read_u32(&bytes[*position..]);
+            *position += 4;
+            Ok(value)
+        }
+
+        if bytes.is_empty() {
+            return Err("empty doc values".to_string());
+        }
+
+        let mut position = 1;
+
+        match bytes[0] {
+            b'n' => {
+                let num_docs = try!(read_u32(bytes, &mut position)) as usize;
+                let present_len = (num_docs + 7) / 8;
+
+                if bytes.len() != position + present_len + num_docs * 8 {
+                    return Err("numeric doc values have the wrong length".to_string());
+                }
+
+                let present = &bytes[position..position + present_len];
+                let values_bytes = &bytes[position + present_len..];
+
+                let values = (0..num_docs).map(|i| {
+                    if present[i / 8] & (1 << (i % 8)) != 0 {
+                        Some(LittleEndian::read_i64(&values_bytes[i * 8..]))
+                    } else {
+                        None
+                    }
+                }).collect();
+
+                Ok(DocValues::Numeric(values))
+            }
+            b'k' => {
+                let num_terms = try!(read_u32(bytes, &mut position)) as usize;
+                let mut terms = Vec::with_capacity(num_terms);
+
+                for _ in 0..num_terms {
+                    let term_len = try!(read_u32(bytes, &mut position)) as usize;
+
+                    if bytes.len() < position + term_len {
+                        return Err("unexpected end of doc values".to_string());
+                    }
+
+                    match String::from_utf8(bytes[position..position + term_len].to_vec()) {
+                        Ok(term) => terms.push(term),
+                        Err(_) => return Err("keyword doc value is not valid UTF-8".to_string()),
+                    }
+
+                    position += term_len;
+                }
+
+                let num_docs = try!(read_u32(bytes, &mut position)) as usize;
+                let mut ordinals = Vec::with_capacity(num_docs);
+
+                for _ in 0..num_docs {
+                    let ordinal = try!(read_u32(bytes, &mut position));
+
+                    if ordinal == u32::MAX {
+                        ordinals.push(None);
+                    } else if (ordinal as usize) < terms.len() {
+                        ordinals.push(Some(ordinal));
+                    } else {
+                        return Err("keyword doc value ordinal out of range".to_string());
+                    }
+                }
+
+                Ok(DocValues::Keyword {
+                    terms: terms,
+                    ordinals: ordinals,
+                })
+            }
+            _ => Err("unrecognised doc values type".to_string()),
+        }
+    }
+}
+
+/// Builds the doc values of a field, values can be inserted in any order
+#[derive(Debug)]
+pub enum DocValuesBuilder {
+    Numeric(Vec<Option<i64>>),
+    Keyword(Vec<Option<String>>),
+}
+
+impl DocValuesBuilder {
+    /// Creates a builder for a field, or None if doc values aren't kept for its type
+    pub fn for_field_type(field_type: &FieldType) -> Option<DocValuesBuilder> {
+        if !field_type_has_doc_values(field_type) {
+            return None;
+        }
+
+        match *field_type {
+            FieldType::PlainString => Some(DocValuesBuilder::Keyword(Vec::new())),
+            _ => Some(DocValuesBuilder::Numeric(Vec::new())),
+        }
+    }
+
+    /// Creates a builder of the same type as an existing column
+    pub fn like(doc_values: &DocValues) -> DocValuesBuilder {
+        match *doc_values {
+            DocValues::Numeric(_) => DocValuesBuilder::Numeric(Vec::new()),
+            DocValues::Keyword { .. } => DocValuesBuilder::Keyword(Vec::new()),
+        }
+    }
+
+    fn set<T: Clone>(values: &mut Vec<Option<T>>, local_id: u32, value: T) {
+        let local_id = local_id as usize;
+
+        if values.len() <= local_id {
+            values.resize(local_id + 1, None);
+        }
+
+        values[local_id] = Some(value);
+    }
+
+    /// Sets the value of a document, values of the wrong type are ignored
+    pub fn insert(&mut self, local_id: u32, value: &FieldValue) {
+        match *self {
+            DocValuesBuilder::Numeric(ref mut values) => {
+                if let Some(value) = field_value_to_numeric(value) {
+                    DocValuesBuilder::set(values, local_id, value);
+                }
+            }
+            DocValuesBuilder::Keyword(ref mut values) => {
+                if let FieldValue::String(ref value) = *value {
+                    DocValuesBuilder::set(values, local_id, value.clone());
+                }
+            }
+        }
+    }
+
+    /// Copies the value of a document in another column into this one
+    pub fn insert_from(&mut self, local_id: u32, source: &DocValues, source_local_id: u32) {
+        match *self {
+            DocValuesBuilder::Numeric(ref mut values) => {
+                if let Some(value) = source.get_numeric(source_local_id) {
+                    DocValuesBuilder::set(values, local_id, value);
+                }
+            }
+            DocValuesBuilder::Keyword(ref mut values) => {
+                if let Some(value) = source.get_keyword(source_local_id) {
+                    DocValuesBuilder::set(values, local_id, value.to_string());
+                }
+            }
+        }
+    }
+
+    pub fn build(&self) -> DocValues {
+        match *self {
+            DocValuesBuilder::Numeric(ref values) => DocValues::Numeric(values.clone()),
+            DocValuesBuilder::Keyword(ref values) => {
+                let mut terms = values.iter().filter_map(|value| value.clone()).collect::<Vec<String>>();
+                terms.sort();
+                terms.dedup();
+
+                let ordinals = values.iter().map(|value| {
+                    value.as_ref().map(|value| terms.binary_search(value).unwrap() as u32)
+                }).collect();
+
+                DocValues::Keyword {
+                    terms: terms,
+                    ordinals: ordinals,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use search::document::FieldValue;
+    use search::schema::FieldType;
+
+    use super::{DocValues, DocValuesBuilder};
+
+    #[test]
+    fn test_build_numeric() {
+        let mut builder = DocValuesBuilder::for_field_type(&FieldType::I64).unwrap();
+        builder.insert(2, &FieldValue::Integer(10));
+        builder.insert(0, &FieldValue::Integer(-5));
+
+        assert_eq!(builder.build(), DocValues::Numeric(vec![Some(-5), None, Some(10)]));
+    }
+
+    #[test]
+    fn test_build_keyword() {
+        let mut builder = DocValuesBuilder::for_field_type(&FieldType::PlainString).unwrap();
+        builder.insert(0, &FieldValue::String("foo".to_string()));
+        builder.insert(1, &FieldValue::String("bar".to_string()));
+        builder.insert(3, &FieldValue::String("foo".to_string()));
+
+        let doc_values = builder.build();
+
+        assert_eq!(doc_values, DocValues::Keyword {
+            terms: vec!["bar".to_string(), "foo".to_string()],
+            ordinals: vec![Some(1), Some(0), None, Some(1)],
+        });
+        assert_eq!(doc_values.get_keyword(3), Some("foo"));
+        assert_eq!(doc_values.get_keyword(2), None);
+    }
+
+    #[test]
+    fn test_no_doc_values_for_text_fields() {
+        assert!(DocValuesBuilder::for_field_type(&FieldType::Text).is_none());
+    }
+
+    #[test]
+    fn test_get_field_value() {
+        let datetime = "2017-01-01T12:30:00.5Z".parse::<DateTime<Utc>>().unwrap();
+
+        let mut builder = DocValuesBuilder::for_field_type(&FieldType::DateTime).unwrap();
+        builder.insert(0, &FieldValue::DateTime(datetime));
+        let doc_values = builder.build();
+
+        match doc_values.get_field_value(0, &FieldType::DateTime) {
+            Some(FieldValue::DateTime(value)) => assert_eq!(value, datetime),
+            value => panic!("expected a datetime, got {:?}", value),
+        }
+        assert!(doc_values.get_field_value(1, &FieldType::DateTime).is_none());
+    }
+
+    #[test]
+    fn test_encode_decode_numeric() {
+        let doc_values = DocValues::Numeric(vec![Some(1), None, Some(-300), None, None, None, None, None, Some(0)]);
+
+        assert_eq!(DocValues::from_bytes(&doc_values.to_bytes()), Ok(doc_values));
+    }
+
+    #[test]
+    fn test_encode_decode_keyword() {
+        let doc_values = DocValues::Keyword {
+            terms: vec!["bar".to_string(), "foo".to_string()],
+            ordinals: vec![Some(1), None, Some(0)],
+        };
+
+        assert_eq!(DocValues::from_bytes(&doc_values.to_bytes()), Ok(doc_values));
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let doc_values = DocValues::Numeric(vec![Some(1), Some(2)]);
+        let bytes = doc_values.to_bytes();
+
+        assert!(DocValues::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
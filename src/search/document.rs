@@ -7,17 +7,17 @@ use search::schema::FieldId;
 use search::segment::SegmentId;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
-pub struct DocId(pub SegmentId, pub u16);
+pub struct DocId(pub SegmentId, pub u32);
 
 impl DocId {
     pub fn as_u64(&self) -> u64 {
-        ((self.0).0 as u64) << 16 | (self.1 as u64)
+        ((self.0).0 as u64) << 32 | (self.1 as u64)
     }
 
     pub fn from_u64(val: u64) -> DocId {
-        let segment = (val >> 16) & 0xFFFFFFFF;
-        let local_id = val & 0xFFFF;
-        DocId(SegmentId(segment as u32), local_id as u16)
+        let segment = (val >> 32) & 0xFFFFFFFF;
+        let local_id = val & 0xFFFFFFFF;
+        DocId(SegmentId(segment as u32), local_id as u32)
     }
 }
 
@@ -0,0 +1,278 @@
+//! Caches the documents that filters match in each segment
+//!
+//! Filters don't affect the score, so all a search needs from them is the set of documents they
+//! match. Segments never change after they are written, so this set can be reused by later
+//! searches until the segment is merged or purged. Deleted documents are removed from the results
+//! of the whole query, so the cached sets don't need to change when documents are deleted.
+
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+
+use fnv::{FnvHashMap, FnvHashSet};
+use roaring::RoaringBitmap;
+
+use search::segment::SegmentId;
+use search::execution::planner::boolean_query::BooleanQueryOp;
+
+/// The default limit on the memory used by the filter cache of each index
+pub const DEFAULT_FILTER_CACHE_SIZE: usize = 32 * 1024 * 1024;
+
+/// Filters are identified by their planned boolean query, so filters that are written
+/// differently but plan to the same operations share an entry
+type FilterKey = (SegmentId, Arc<Vec<BooleanQueryOp>>);
+
+#[derive(Debug)]
+struct FilterCacheEntry {
+    matches: Arc<RoaringBitmap>,
+    size: usize,
+    last_used: u64,
+}
+
+#[derive(Debug, Default)]
+struct FilterCacheEntries {
+    entries: FnvHashMap<FilterKey, FilterCacheEntry>,
+
+    /// The key of each entry, ordered by when it was last used
+    recently_used: BTreeMap<u64, FilterKey>,
+
+    next_use: u64,
+    memory_size: usize,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl FilterCacheEntries {
+    fn remove(&mut self, key: &FilterKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recently_used.remove(&entry.last_used);
+            self.memory_size -= entry.size;
+        }
+    }
+}
+
+/// The counters of a filter cache, for the stats API
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub memory_size: usize,
+}
+
+/// A least recently used cache of the documents that filters match in each segment
+///
+/// The least recently used entries are evicted when the estimated size of the cached sets goes
+/// over the limit.
+#[derive(Debug)]
+pub struct FilterCache {
+    max_size: usize,
+    entries: Mutex<FilterCacheEntries>,
+}
+
+impl FilterCache {
+    pub fn new(max_size: usize) -> FilterCache {
+        FilterCache {
+            max_size: max_size,
+            entries: Mutex::new(FilterCacheEntries::default()),
+        }
+    }
+
+    /// Returns the documents that a filter matches in a segment, if they have been cached
+    pub fn get(&self, segment: SegmentId, filter: &Arc<Vec<BooleanQueryOp>>) -> Option<Arc<RoaringBitmap>> {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+        let key = (segment, filter.clone());
+
+        let use_id = entries.next_use;
+        let matches = match entries.entries.get_mut(&key) {
+            Some(entry) => {
+                entries.recently_used.remove(&entry.last_used);
+                entry.last_used = use_id;
+                entry.matches.clone()
+            }
+            None => {
+                entries.misses += 1;
+                return None;
+            }
+        };
+
+        entries.recently_used.insert(use_id, key);
+        entries.next_use += 1;
+        entries.hits += 1;
+
+        Some(matches)
+    }
+
+    /// Adds the documents that a filter matches in a segment, evicting old entries to make room
+    ///
+    /// Nothing is cached if the entry alone is bigger than the cache.
+    pub fn insert(&self, segment: SegmentId, filter: Arc<Vec<BooleanQueryOp>>, matches: Arc<RoaringBitmap>) {
+        let size = estimate_size(&filter, &matches);
+        if size > self.max_size {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let key = (segment, filter);
+
+        // Another thread may have cached the same filter since we looked
+        entries.remove(&key);
+
+        while entries.memory_size + size > self.max_size {
+            let oldest = match entries.recently_used.keys().next() {
+                Some(oldest) => *oldest,
+                None => break,
+            };
+
+            if let Some(oldest_key) = entries.recently_used.get(&oldest).cloned() {
+                entries.remove(&oldest_key);
+                entries.evictions += 1;
+            }
+        }
+
+        let use_id = entries.next_use;
+        entries.next_use += 1;
+        entries.memory_size += size;
+        entries.recently_used.insert(use_id, key.clone());
+        entries.entries.insert(key, FilterCacheEntry {
+            matches: matches,
+            size: size,
+            last_used: use_id,
+        });
+    }
+
+    /// Removes the entries of segments that have been merged or purged
+    pub fn remove_segments(&self, segments: &[u32]) {
+        let segments = segments.iter().map(|segment| SegmentId(*segment)).collect::<FnvHashSet<_>>();
+        let mut entries = self.entries.lock().unwrap();
+
+        let keys = entries.entries.keys().filter(|key| segments.contains(&key.0)).cloned().collect::<Vec<_>>();
+        for key in keys.iter() {
+            entries.remove(key);
+        }
+    }
+
+    pub fn stats(&self) -> FilterCacheStats {
+        let entries = self.entries.lock().unwrap();
+
+        FilterCacheStats {
+            hits: entries.hits,
+            misses: entries.misses,
+            evictions: entries.evictions,
+            entries: entries.entries.len(),
+            memory_size: entries.memory_size,
+        }
+    }
+}
+
+impl Default for FilterCache {
+    fn default() -> FilterCache {
+        FilterCache::new(DEFAULT_FILTER_CACHE_SIZE)
+    }
+}
+
+/// Estimates the memory used by a cache entry, from the serialised size of the bitmap
+fn estimate_size(filter: &[BooleanQueryOp], matches: &RoaringBitmap) -> usize {
+    let mut matches_bytes = Vec::new();
+    matches.serialize_into(&mut matches_bytes).unwrap();
+
+    matches_bytes.len() + filter.len() * mem::size_of::<BooleanQueryOp>()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use roaring::RoaringBitmap;
+
+    use search::schema::FieldId;
+    use search::term::TermId;
+    use search::segment::SegmentId;
+    use search::execution::planner::boolean_query::BooleanQueryOp;
+
+    use super::{FilterCache, estimate_size};
+
+    fn make_filter(term_id: u32) -> Arc<Vec<BooleanQueryOp>> {
+        Arc::new(vec![
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(term_id)),
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(term_id + 1)),
+            BooleanQueryOp::Or,
+        ])
+    }
+
+    fn make_matches(docs: &[u32]) -> Arc<RoaringBitmap> {
+        Arc::new(docs.iter().cloned().collect())
+    }
+
+    #[test]
+    fn test_get_and_insert() {
+        let cache = FilterCache::default();
+
+        assert!(cache.get(SegmentId(1), &make_filter(1)).is_none());
+        cache.insert(SegmentId(1), make_filter(1), make_matches(&[1, 2, 3]));
+
+        // Filters with the same operations share an entry
+        assert_eq!(cache.get(SegmentId(1), &make_filter(1)), Some(make_matches(&[1, 2, 3])));
+
+        // Entries are separate for each segment
+        assert!(cache.get(SegmentId(2), &make_filter(1)).is_none());
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.memory_size, estimate_size(&make_filter(1), &make_matches(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let entry_size = estimate_size(&make_filter(1), &make_matches(&[1]));
+        let cache = FilterCache::new(entry_size * 2);
+
+        cache.insert(SegmentId(1), make_filter(1), make_matches(&[1]));
+        cache.insert(SegmentId(1), make_filter(2), make_matches(&[2]));
+
+        // Use the first filter, so the second one is evicted when the third is added
+        assert!(cache.get(SegmentId(1), &make_filter(1)).is_some());
+        cache.insert(SegmentId(1), make_filter(3), make_matches(&[3]));
+
+        assert!(cache.get(SegmentId(1), &make_filter(1)).is_some());
+        assert!(cache.get(SegmentId(1), &make_filter(2)).is_none());
+        assert!(cache.get(SegmentId(1), &make_filter(3)).is_some());
+
+        let stats = cache.stats();
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.entries, 2);
+        assert!(stats.memory_size <= entry_size * 2);
+    }
+
+    #[test]
+    fn test_entries_bigger_than_cache_are_not_cached() {
+        let cache = FilterCache::new(1);
+
+        cache.insert(SegmentId(1), make_filter(1), make_matches(&[1, 2, 3]));
+
+        assert!(cache.get(SegmentId(1), &make_filter(1)).is_none());
+        assert_eq!(cache.stats().memory_size, 0);
+    }
+
+    #[test]
+    fn test_remove_segments() {
+        let cache = FilterCache::default();
+
+        cache.insert(SegmentId(1), make_filter(1), make_matches(&[1]));
+        cache.insert(SegmentId(2), make_filter(1), make_matches(&[1]));
+        cache.insert(SegmentId(3), make_filter(1), make_matches(&[1]));
+
+        cache.remove_segments(&[1, 3]);
+
+        assert!(cache.get(SegmentId(1), &make_filter(1)).is_none());
+        assert!(cache.get(SegmentId(2), &make_filter(1)).is_some());
+        assert!(cache.get(SegmentId(3), &make_filter(1)).is_none());
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(cache.stats().memory_size, estimate_size(&make_filter(1), &make_matches(&[1])));
+    }
+}
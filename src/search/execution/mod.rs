@@ -0,0 +1,617 @@
+//! Runs queries against segments
+//!
+//! This is shared by all storage backends. Queries are first planned against the index's term
+//! dictionary, then the plan is run on each segment.
+
+pub mod statistics;
+pub mod planner;
+pub mod scorer;
+pub mod pool;
+pub mod filter_cache;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use roaring::RoaringBitmap;
+use search::{Term, TermId};
+use search::schema::{Schema, FieldId};
+use search::document::DocId;
+use search::segment::Segment;
+use search::query::Query;
+use search::query::term_scorer::TermScorer;
+use search::query::multi_term_selector::MultiTermSelector;
+use search::explanation::Explanation;
+use search::collectors::{Collector, SplitCollector, DocumentMatch};
+use fnv::FnvHashMap;
+
+use self::statistics::{StatisticsReader, SegmentsStatisticsReader};
+use self::planner::{SearchPlan, plan_query};
+use self::planner::boolean_query::BooleanQueryOp;
+use self::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
+use self::scorer::{SegmentScorer, TermPostings, FieldNorms};
+use self::pool::SearchPool;
+use self::filter_cache::FilterCache;
+
+/// Looks up terms in an index's term dictionary
+///
+/// Queries are planned against this, so each backend must provide one.
+pub trait TermDictionaryReader {
+    /// Retrieves the TermId of a term, returns None if the term has never been indexed
+    fn get_term_id(&self, term: &Term) -> Option<TermId>;
+
+    /// Finds the term with the given TermId in a field
+    ///
+    /// This is only used for describing terms when explaining scores, so it may be slow.
+    fn get_term(&self, field_id: FieldId, term_id: TermId) -> Option<Term>;
+
+    /// Finds the terms in a field's dictionary which match the selector
+    fn select_terms(&self, field_id: FieldId, term_selector: &MultiTermSelector) -> Vec<TermId> {
+        self.select_terms_until(field_id, term_selector, &mut || false)
+    }
+
+    /// Finds the terms in a field's dictionary which match the selector, stopping early once
+    /// "should_terminate" returns true
+    ///
+    /// This is checked before each term is read. The terms that were selected before then are returned.
+    fn select_terms_until(&self, field_id: FieldId, term_selector: &MultiTermSelector, should_terminate: &mut FnMut() -> bool) -> Vec<TermId>;
+}
+
+/// Checks if a document contains a phrase, given the offset of each of the phrase's terms and
+/// their positions in the document
+///
+/// Each term's positions are shifted back by that term's offset in the phrase so a perfect
+/// match puts every term at the same position. The document matches if we can pick a position
+/// for each term where the distance between the lowest and highest is within the slop.
+///
+/// Terms that share an offset (synonyms) are alternatives, only one of them needs to match.
+fn phrase_matches(term_positions: &Vec<(u32, Vec<u32>)>, slop: u32) -> bool {
+    if term_positions.is_empty() {
+        return false;
+    }
+
+    // Merge the positions of terms that share an offset
+    let mut offset_positions: Vec<(u32, Vec<u32>)> = Vec::new();
+    for &(term_offset, ref positions) in term_positions.iter() {
+        match offset_positions.iter().position(|&(offset, _)| offset == term_offset) {
+            Some(i) => {
+                let merged_positions = &mut offset_positions[i].1;
+                merged_positions.extend(positions.iter().cloned());
+                merged_positions.sort();
+                merged_positions.dedup();
+            }
+            None => offset_positions.push((term_offset, positions.clone())),
+        }
+    }
+
+    // Walk through the positions of all terms at once, always advancing the term that is
+    // furthest behind. This finds the smallest window containing one position of each term.
+    let mut cursors = vec![0; offset_positions.len()];
+    loop {
+        let mut min_offset = i64::max_value();
+        let mut max_offset = i64::min_value();
+        let mut min_term = 0;
+
+        for (i, &(term_offset, ref positions)) in offset_positions.iter().enumerate() {
+            let position = match positions.get(cursors[i]) {
+                Some(position) => *position,
+                None => return false,
+            };
+
+            let offset = position as i64 - term_offset as i64;
+
+            if offset < min_offset {
+                min_offset = offset;
+                min_term = i;
+            }
+
+            if offset > max_offset {
+                max_offset = offset;
+            }
+        }
+
+        if max_offset - min_offset <= slop as i64 {
+            return true;
+        }
+
+        cursors[min_term] += 1;
+    }
+}
+
+fn run_phrase_query<S: Segment>(field_id: FieldId, term_ids: &Vec<(TermId, u32)>, slop: u32, segment: &S) -> Result<RoaringBitmap, String> {
+    // Find documents that contain a term at every offset
+    // Terms that share an offset are synonyms, so documents only need to contain one of them
+    let mut offset_postings: Vec<(u32, RoaringBitmap)> = Vec::new();
+    for &(term_id, term_offset) in term_ids.iter() {
+        let postings = match try!(segment.load_postings_list(field_id, term_id)) {
+            Some(postings) => postings,
+            None => RoaringBitmap::new(),
+        };
+
+        match offset_postings.iter().position(|&(offset, _)| offset == term_offset) {
+            Some(i) => offset_postings[i].1.union_with(&postings),
+            None => offset_postings.push((term_offset, postings)),
+        }
+    }
+
+    let mut candidates: Option<RoaringBitmap> = None;
+    for (_, postings) in offset_postings {
+        candidates = match candidates {
+            Some(mut candidates) => {
+                candidates.intersect_with(&postings);
+                Some(candidates)
+            }
+            None => Some(postings),
+        };
+    }
+
+    // Check the positions of each candidate
+    let mut matches = RoaringBitmap::new();
+    for doc in candidates.unwrap_or_else(RoaringBitmap::new).iter() {
+        let mut term_positions = Vec::with_capacity(term_ids.len());
+        for &(term_id, offset) in term_ids.iter() {
+            // Synonyms of the other terms at this offset may not be in the document
+            let positions = try!(segment.load_term_positions(doc, field_id, term_id)).unwrap_or_else(Vec::new);
+            term_positions.push((offset, positions));
+        }
+
+        if phrase_matches(&term_positions, slop) {
+            matches.insert(doc);
+        }
+    }
+
+    Ok(matches)
+}
+
+/// Runs a filter on a segment, using the cached result if there is one
+///
+/// Returns None if the search was told to stop before the filter finished, nothing is cached then.
+fn run_filter<S: Segment>(filter: &Arc<Vec<BooleanQueryOp>>, segment: &S, filter_cache: Option<&FilterCache>, should_terminate: &mut FnMut() -> bool) -> Result<Option<RoaringBitmap>, String> {
+    let filter_cache = match filter_cache {
+        Some(filter_cache) => filter_cache,
+        None => return run_boolean_query(filter, false, segment, None, should_terminate),
+    };
+
+    if let Some(matches) = filter_cache.get(segment.id(), filter) {
+        return Ok(Some((*matches).clone()));
+    }
+
+    let matches = match try!(run_boolean_query(filter, false, segment, Some(filter_cache), should_terminate)) {
+        Some(matches) => matches,
+        None => return Ok(None),
+    };
+    filter_cache.insert(segment.id(), filter.clone(), Arc::new(matches.clone()));
+
+    Ok(Some(matches))
+}
+
+/// Finds the documents in a segment that match a boolean query
+///
+/// "should_terminate" is checked before each operation, as unions of the postings lists of many
+/// terms (from prefix queries for example) can take a long time. Returns None if it returned true.
+fn run_boolean_query<S: Segment>(boolean_query: &Vec<BooleanQueryOp>, is_negated: bool, segment: &S, filter_cache: Option<&FilterCache>, should_terminate: &mut FnMut() -> bool) -> Result<Option<RoaringBitmap>, String> {
+    // Execute boolean query
+    let mut stack = Vec::new();
+    for op in boolean_query.iter() {
+        if should_terminate() {
+            return Ok(None);
+        }
+
+        match *op {
+            BooleanQueryOp::PushEmpty => {
+                stack.push(RoaringBitmap::new());
+            }
+            BooleanQueryOp::PushPostingsList(field_id, term_id) => {
+                match try!(segment.load_postings_list(field_id, term_id)) {
+                    Some(doc_id_set) => stack.push(doc_id_set),
+                    None => stack.push(RoaringBitmap::new()),
+                }
+            }
+            BooleanQueryOp::PushPhrase(field_id, ref term_ids, slop) => {
+                stack.push(try!(run_phrase_query(field_id, term_ids, slop, segment)));
+            }
+            BooleanQueryOp::PushFilter(ref filter) => {
+                match try!(run_filter(filter, segment, filter_cache, should_terminate)) {
+                    Some(matches) => stack.push(matches),
+                    None => return Ok(None),
+                }
+            }
+            BooleanQueryOp::PushDeletionList => {
+                    match try!(segment.load_deletion_list()) {
+                    Some(doc_id_set) => stack.push(doc_id_set),
+                    None => stack.push(RoaringBitmap::new()),
+                }
+            }
+            BooleanQueryOp::And => {
+                let b = stack.pop().expect("boolean query executor: stack underflow");
+                let a = stack.last_mut().expect("boolean query executor: stack underflow");
+
+                a.intersect_with(&b);
+            }
+            BooleanQueryOp::Or => {
+                let b = stack.pop().expect("boolean query executor: stack underflow");
+                let a = stack.last_mut().expect("boolean query executor: stack underflow");
+
+                a.union_with(&b);
+            }
+            BooleanQueryOp::AndNot => {
+                let b = stack.pop().expect("boolean query executor: stack underflow");
+                let a = stack.last_mut().expect("boolean query executor: stack underflow");
+
+                a.difference_with(&b);
+            }
+            BooleanQueryOp::Not => {
+                let a = stack.last_mut().expect("boolean query executor: stack underflow");
+
+                let total_docs = try!(segment.load_statistic(b"total_docs")).unwrap_or(0);
+                let mut all_docs = RoaringBitmap::new();
+                for doc_id in 0..total_docs {
+                    all_docs.insert(doc_id as u32);
+                }
+
+                all_docs.difference_with(a);
+                *a = all_docs;
+            }
+            BooleanQueryOp::AtLeast(num_operands, minimum) => {
+                let stack_len = stack.len();
+                if stack_len < num_operands as usize {
+                    panic!("boolean query executor: stack underflow");
+                }
+
+                // Count the number of operands each document appears in
+                let mut match_counts: FnvHashMap<u32, u32> = FnvHashMap::default();
+                for doc_id_set in stack.drain(stack_len - num_operands as usize..) {
+                    for doc_id in doc_id_set.iter() {
+                        *match_counts.entry(doc_id).or_insert(0) += 1;
+                    }
+                }
+
+                let mut result = RoaringBitmap::new();
+                for (doc_id, count) in match_counts {
+                    if count >= minimum {
+                        result.insert(doc_id);
+                    }
+                }
+
+                stack.push(result);
+            }
+        }
+    }
+
+    if !stack.len() == 1 {
+        // This shouldn't be possible unless there's a bug in the planner
+        panic!("boolean query executor: stack size too big ({})", stack.len());
+    }
+
+    let mut matches = stack.pop().unwrap();
+
+    if is_negated {
+        // Query returns a negated result so we need to correct this by inverting the returned bitmap
+        let total_docs = try!(segment.load_statistic(b"total_docs")).unwrap_or(0);
+        let mut all_docs = RoaringBitmap::new();
+        for doc_id in 0..total_docs {
+            all_docs.insert(doc_id as u32);
+        }
+
+        all_docs.difference_with(&matches);
+        matches = all_docs;
+    }
+
+    Ok(Some(matches))
+}
+
+/// Loads the term frequency and field length of a term in a single document
+///
+/// Returns None if the document doesn't contain the term
+fn load_term_frequency_and_length<S: Segment>(doc_id: u32, field_id: FieldId, term_id: TermId, segment: &S) -> Result<Option<(u32, f32)>, String> {
+    let term_frequency = match try!(TermPostings::load(segment, field_id, term_id)).advance_to(doc_id) {
+        Some(term_frequency) => term_frequency,
+        None => return Ok(None),
+    };
+
+    let field_length = try!(FieldNorms::load(segment, field_id)).field_length(doc_id);
+
+    Ok(Some((term_frequency, field_length)))
+}
+
+/// Describes a term in an explanation, eg "title:hello"
+fn describe_term<D: TermDictionaryReader>(term_dictionary: &D, schema: &Schema, field_id: FieldId, term_id: TermId) -> String {
+    let field_name = match schema.get(&field_id) {
+        Some(field_info) => field_info.name().to_string(),
+        None => format!("#{}", field_id.0),
+    };
+
+    match term_dictionary.get_term(field_id, term_id) {
+        Some(term) => format!("{}:{}", field_name, String::from_utf8_lossy(term.as_bytes())),
+        None => format!("{}:#{}", field_name, term_id.0),
+    }
+}
+
+fn explain_term_score(description: String, doc_id: u32, similarity: Explanation, scorer: &TermScorer) -> Explanation {
+    Explanation::with_details(similarity.value * scorer.boost, format!("weight({} in {}), product of:", description, doc_id), vec![
+        Explanation::new(scorer.boost, "boost"),
+        similarity,
+    ])
+}
+
+/// Runs the score function in the same way as "SegmentScorer" but builds an explanation of each step
+fn explain_doc<D: TermDictionaryReader, S: Segment, R: StatisticsReader>(term_dictionary: &D, schema: &Schema, doc_id: u32, score_function: &Vec<ScoreFunctionOp>, segment: &S, stats: &R) -> Result<Explanation, String> {
+    let mut stack = Vec::new();
+    for op in score_function.iter() {
+        match *op {
+            ScoreFunctionOp::Literal(val) => stack.push(Explanation::new(val, "constant score")),
+            ScoreFunctionOp::TermScorer(field_id, term_id, ref scorer) => {
+                let description = describe_term(term_dictionary, schema, field_id, term_id);
+
+                match try!(load_term_frequency_and_length(doc_id, field_id, term_id, segment)) {
+                    Some((term_frequency, field_length)) => {
+                        let similarity = scorer.similarity_model.explain(term_frequency, field_length, try!(stats.total_tokens(field_id)) as u64, try!(stats.total_docs(field_id)) as u64, try!(stats.term_document_frequency(field_id, term_id)) as u64);
+                        stack.push(explain_term_score(description, doc_id, similarity, scorer));
+                    }
+                    None => stack.push(Explanation::new(0.0f32, format!("no matching term for {}", description))),
+                }
+            }
+            ScoreFunctionOp::CombinatorScorer(num_vals, ref scorer) => {
+                let mut details = Vec::with_capacity(num_vals as usize);
+                for _ in 0..num_vals {
+                    details.push(stack.pop().expect("document explainer: stack underflow"));
+                }
+
+                // Values are combined in the same order as "SegmentScorer" so the result is identical
+                let (score, description) = match *scorer {
+                    CombinatorScorer::Avg => {
+                        let total_score = details.iter().fold(0.0f32, |total_score, detail| total_score + detail.value);
+                        (total_score / num_vals as f32, "avg of:")
+                    }
+                    CombinatorScorer::Max => {
+                        let max_score = details.iter().fold(0.0f32, |max_score, detail| if detail.value > max_score { detail.value } else { max_score });
+                        (max_score, "max of:")
+                    }
+                    CombinatorScorer::Sum => {
+                        let total_score = details.iter().fold(0.0f32, |total_score, detail| total_score + detail.value);
+                        (total_score, "sum of:")
+                    }
+                };
+
+                // Put the details back into the order of the queries
+                details.reverse();
+
+                stack.push(Explanation::with_details(score, description, details));
+            }
+        }
+    }
+
+    Ok(stack.pop().expect("document explainer: stack underflow"))
+}
+
+fn search_segment<C: Collector + ?Sized, S: Segment, R: StatisticsReader>(collector: &mut C, plan: &SearchPlan, segment: &S, stats: &R, filter_cache: &FilterCache) -> Result<(), String> {
+    // This is checked before each segment, so the search stops between segments too
+    if collector.should_terminate() {
+        return Ok(());
+    }
+
+    let matches = match try!(run_boolean_query(&plan.boolean_query, plan.boolean_query_is_negated, segment, Some(filter_cache), &mut || collector.should_terminate())) {
+        Some(matches) => matches,
+        None => return Ok(()),
+    };
+
+    if matches.is_empty() {
+        return Ok(());
+    }
+
+    // Score documents and pass to collector
+    // The postings lists, term frequencies and field norms are loaded once for the whole segment
+    let mut scorer = try!(SegmentScorer::new(&plan.score_function, segment, stats));
+    for doc in matches.iter() {
+        // Stop if the collector has all it needs or the search has run out of time
+        if collector.should_terminate() {
+            break;
+        }
+
+        // Skip documents that can't score highly enough to make a difference to the collector
+        // Each document's upper bound is worked out from the terms it contains, which is much
+        // quicker than scoring it
+        if let Some(min_score) = collector.min_competitive_score() {
+            if scorer.max_score() < min_score {
+                // Nothing else in the segment can
+                break;
+            }
+
+            if scorer.upper_bound(doc) < min_score {
+                continue;
+            }
+        }
+
+        let score = scorer.score(doc);
+
+        let doc_id = segment.doc_id(doc);
+        let doc_match = DocumentMatch::new_scored(doc_id.as_u64(), score);
+        collector.collect(doc_match);
+    }
+
+    Ok(())
+}
+
+/// Splits a collector into one collector for each segment, returns None if the collector can't be split
+fn split_collector<C: Collector + ?Sized>(collector: &C, segments: usize) -> Option<Vec<Box<SplitCollector>>> {
+    let mut split_collectors = Vec::with_capacity(segments);
+    for _ in 0..segments {
+        split_collectors.push(match collector.split() {
+            Some(split_collector) => split_collector,
+            None => return None,
+        });
+    }
+
+    Some(split_collectors)
+}
+
+/// Searches segments on several of the search pool's workers at once
+///
+/// Each segment is searched into its own split collector. These are merged back into the
+/// collector in the order of the segments, so the results are the same as searching the
+/// segments one at a time.
+fn search_segments_in_parallel<C: Collector + ?Sized, S: Segment + Sync, R: StatisticsReader>(collector: &mut C, split_collectors: Vec<Box<SplitCollector>>, plan: &SearchPlan, segments: &[S], stats: &R, filter_cache: &FilterCache) -> Result<(), String> {
+    let split_collectors = Mutex::new(split_collectors.into_iter().map(Some).collect::<Vec<_>>());
+    let searched_segments = Mutex::new(Vec::with_capacity(segments.len()));
+    let error = Mutex::new(None);
+    let next_segment = AtomicUsize::new(0);
+
+    // Each thread keeps taking the next segment that hasn't been searched yet, so threads that
+    // get small segments go on to search more of them
+    SearchPool::global().run(segments.len(), &|| {
+        loop {
+            let segment = next_segment.fetch_add(1, Ordering::SeqCst);
+            if segment >= segments.len() {
+                return;
+            }
+
+            let mut split_collector = split_collectors.lock().unwrap()[segment].take().expect("parallel search: segment was searched twice");
+            if let Err(e) = search_segment(&mut *split_collector, plan, &segments[segment], stats, filter_cache) {
+                // Stop the other threads from starting any more segments
+                next_segment.store(segments.len(), Ordering::SeqCst);
+                *error.lock().unwrap() = Some(e);
+                return;
+            }
+
+            searched_segments.lock().unwrap().push((segment, split_collector));
+        }
+    });
+
+    if let Some(e) = error.into_inner().unwrap() {
+        return Err(e);
+    }
+
+    let mut searched_segments = searched_segments.into_inner().unwrap();
+    searched_segments.sort_by_key(|&(segment, _)| segment);
+    for (_, split_collector) in searched_segments {
+        collector.merge(split_collector);
+    }
+
+    Ok(())
+}
+
+/// Runs a query on a set of segments, passing each matching document to the collector
+///
+/// The segments must be all of the active segments in the index as they are also used to
+/// calculate the statistics for scoring.
+///
+/// The matches of filters in each segment are kept in the filter cache, for later searches to reuse.
+///
+/// The search stops early if the collector asks it to, the collector is left with the documents
+/// that were found before then.
+///
+/// If the collector can be split, the segments are searched on several of the global search
+/// pool's workers at once.
+pub fn search<C: Collector + ?Sized, D: TermDictionaryReader, S: Segment + Sync>(collector: &mut C, query: &Query, term_dictionary: &D, segments: &[S], filter_cache: &FilterCache) -> Result<(), String> {
+    // Plan query
+    // Multi term queries stop selecting terms if the collector asks the search to stop
+    let needs_score = collector.needs_score();
+    let plan = plan_query(term_dictionary, query, needs_score, &mut || collector.should_terminate());
+
+    if collector.should_terminate() {
+        return Ok(());
+    }
+
+    // Initialise statistics reader
+    // This is shared by all threads, so each statistic is only read once
+    let stats = SegmentsStatisticsReader::new(segments);
+
+    // Search the segments in parallel
+    if segments.len() > 1 {
+        if let Some(split_collectors) = split_collector(collector, segments.len()) {
+            return search_segments_in_parallel(collector, split_collectors, &plan, segments, &stats, filter_cache);
+        }
+    }
+
+    // Run query on each segment
+    for segment in segments.iter() {
+        try!(search_segment(collector, &plan, segment, &stats, filter_cache));
+    }
+
+    Ok(())
+}
+
+/// Explains how the score of a document was calculated
+///
+/// Returns None if the document doesn't match the query, or isn't in any of the segments
+pub fn explain<D: TermDictionaryReader, S: Segment + Sync>(query: &Query, doc_id: DocId, term_dictionary: &D, schema: &Schema, segments: &[S]) -> Result<Option<Explanation>, String> {
+    let segment = match segments.iter().find(|segment| segment.id() == doc_id.0) {
+        Some(segment) => segment,
+        None => return Ok(None),
+    };
+
+    // Plan query
+    let plan = plan_query(term_dictionary, query, true, &mut || false);
+
+    // Initialise statistics reader
+    let stats = SegmentsStatisticsReader::new(segments);
+
+    // Check that the document matches
+    // Explanations are rare, so they don't use or fill the filter cache
+    let matches = try!(run_boolean_query(&plan.boolean_query, plan.boolean_query_is_negated, segment, None, &mut || false));
+    if !matches.map_or(false, |matches| matches.contains(doc_id.1)) {
+        return Ok(None);
+    }
+
+    Ok(Some(try!(explain_doc(term_dictionary, schema, doc_id.1, &plan.score_function, segment, &stats))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::phrase_matches;
+
+    #[test]
+    fn test_phrase_matches_exact() {
+        // "new york" in "i love new york"
+        assert!(phrase_matches(&vec![(0, vec![3]), (1, vec![4])], 0));
+    }
+
+    #[test]
+    fn test_phrase_matches_wrong_order() {
+        // "new york" in "york is not new"
+        assert!(!phrase_matches(&vec![(0, vec![4]), (1, vec![1])], 0));
+    }
+
+    #[test]
+    fn test_phrase_matches_gap() {
+        // "new york" in "new and old york"
+        assert!(!phrase_matches(&vec![(0, vec![1]), (1, vec![4])], 0));
+        assert!(!phrase_matches(&vec![(0, vec![1]), (1, vec![4])], 1));
+        assert!(phrase_matches(&vec![(0, vec![1]), (1, vec![4])], 2));
+    }
+
+    #[test]
+    fn test_phrase_matches_multiple_positions() {
+        // "new york" in "new jersey and new york"
+        assert!(phrase_matches(&vec![(0, vec![1, 4]), (1, vec![5])], 0));
+    }
+
+    #[test]
+    fn test_phrase_matches_empty() {
+        assert!(!phrase_matches(&vec![], 0));
+        assert!(!phrase_matches(&vec![(0, vec![1]), (1, vec![])], 10));
+    }
+
+    #[test]
+    fn test_phrase_matches_offsets() {
+        // "new the york" in "i love new the york", the stopword "the" was removed from both
+        // and left a gap
+        assert!(phrase_matches(&vec![(0, vec![3]), (2, vec![5])], 0));
+
+        // "new the york" in "i love new york"
+        assert!(!phrase_matches(&vec![(0, vec![3]), (2, vec![4])], 0));
+
+        // "ny" and "new" share a position, as synonyms
+        assert!(phrase_matches(&vec![(0, vec![1]), (0, vec![1]), (1, vec![2])], 0));
+
+        // Synonyms are alternatives, "new york" matches without "ny" being in the document
+        assert!(phrase_matches(&vec![(0, vec![1]), (0, vec![]), (1, vec![2])], 0));
+        assert!(phrase_matches(&vec![(0, vec![]), (0, vec![1]), (1, vec![2])], 0));
+
+        // Either synonym can be the one next to "york"
+        assert!(phrase_matches(&vec![(0, vec![6]), (0, vec![1]), (1, vec![2])], 0));
+
+        // But one of them must be there
+        assert!(!phrase_matches(&vec![(0, vec![]), (0, vec![]), (1, vec![2])], 0));
+        assert!(!phrase_matches(&vec![(0, vec![5]), (0, vec![6]), (1, vec![2])], 0));
+    }
+}
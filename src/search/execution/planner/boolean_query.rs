@@ -1,19 +1,26 @@
 use std::rc::Rc;
+use std::sync::Arc;
 
 use search::schema::FieldId;
 use search::term::TermId;
 use search::Query;
+use search::execution::TermDictionaryReader;
 
-use super::super::RocksDBReader;
-
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum BooleanQueryOp {
     PushEmpty,
     PushPostingsList(FieldId, TermId),
+    PushPhrase(FieldId, Vec<(TermId, u32)>, u32),
     PushDeletionList,
+
+    /// Pushes the result of a filter, which is cached separately for each segment
+    PushFilter(Arc<Vec<BooleanQueryOp>>),
+
     And,
     Or,
     AndNot,
+    Not,
+    AtLeast(u32, u32),
 }
 
 #[derive(Clone, Copy, PartialEq)]
@@ -35,6 +42,11 @@ enum BooleanQueryBlock {
         child_a: Rc<BooleanQueryBlock>,
         child_b: Rc<BooleanQueryBlock>,
         return_type: BooleanQueryBlockReturnType,
+    },
+    MultiCombinator {
+        op: BooleanQueryOp,
+        children: Vec<Rc<BooleanQueryBlock>>,
+        return_type: BooleanQueryBlockReturnType,
     }
 }
 
@@ -45,6 +57,7 @@ impl BooleanQueryBlock {
         match *self {
             Leaf{return_type, ..} => return_type,
             Combinator{return_type, ..} => return_type,
+            MultiCombinator{return_type, ..} => return_type,
         }
     }
 
@@ -54,6 +67,7 @@ impl BooleanQueryBlock {
         match *self {
             Leaf{ref mut return_type, ..} => *return_type = new_type,
             Combinator{ref mut return_type, ..} => *return_type = new_type,
+            MultiCombinator{ref mut return_type, ..} => *return_type = new_type,
         }
     }
 
@@ -69,6 +83,12 @@ impl BooleanQueryBlock {
                 child_b.build(boolean_query);
                 boolean_query.push(op.clone());
             }
+            MultiCombinator{ref op, ref children, ..} => {
+                for child in children.iter() {
+                    child.build(boolean_query);
+                }
+                boolean_query.push(op.clone());
+            }
         }
     }
 }
@@ -117,6 +137,18 @@ impl BooleanQueryBuilder {
         }));
     }
 
+    /// Pushes the documents that contain a phrase, "terms" are the term ids of the phrase with their offsets
+    pub fn push_phrase(&mut self, field_id: FieldId, terms: Vec<(TermId, u32)>, slop: u32) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        self.stack.push(Rc::new(Leaf{
+            op: PushPhrase(field_id, terms, slop),
+            return_type: Sparse,
+        }));
+    }
+
     pub fn push_deletion_list(&mut self) {
         use self::BooleanQueryOp::*;
         use self::BooleanQueryBlock::*;
@@ -128,6 +160,38 @@ impl BooleanQueryBuilder {
         }));
     }
 
+    /// Pushes the result of a filter that was planned with another builder
+    ///
+    /// Filters don't affect the score, so their matches in each segment are cached. Filters that
+    /// are cheap to run (a single postings list) or that match all or none of the documents are
+    /// added to this builder directly instead.
+    pub fn push_filter(&mut self, filter: BooleanQueryBuilder) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        let block = filter.stack.last().expect("stack underflow").clone();
+
+        if let Leaf{op: PushPostingsList(..), ..} = *block {
+            self.stack.push(block);
+            return;
+        }
+
+        match block.return_type() {
+            Full | Empty => self.stack.push(block),
+            Sparse | NegatedSparse => {
+                // The cached result is the sparse set of documents, so the filter keeps its return type
+                let mut boolean_query = Vec::new();
+                block.build(&mut boolean_query);
+
+                self.stack.push(Rc::new(Leaf{
+                    op: PushFilter(Arc::new(boolean_query)),
+                    return_type: block.return_type(),
+                }));
+            }
+        }
+    }
+
     pub fn and_combinator(&mut self) {
         use self::BooleanQueryOp::*;
         use self::BooleanQueryBlock::*;
@@ -320,6 +384,68 @@ impl BooleanQueryBuilder {
         }
     }
 
+    /// Combines the top "num_operands" blocks into a block that matches documents which
+    /// appear in at least "minimum" of them
+    pub fn at_least_combinator(&mut self, num_operands: usize, minimum: u32) {
+        use self::BooleanQueryOp::*;
+        use self::BooleanQueryBlock::*;
+        use self::BooleanQueryBlockReturnType::*;
+
+        let stack_len = self.stack.len();
+        let operands = self.stack.split_off(stack_len.checked_sub(num_operands).expect("stack underflow"));
+
+        let mut minimum = minimum;
+        let mut children = Vec::with_capacity(operands.len());
+        for operand in operands {
+            match operand.return_type() {
+                // Full blocks match every document so they can be removed from the minimum
+                Full => minimum = minimum.saturating_sub(1),
+
+                // Empty blocks never match so they can be removed entirely
+                Empty => {},
+
+                Sparse => children.push(operand),
+
+                // Documents need to be counted individually so negated blocks must be inverted
+                NegatedSparse => {
+                    children.push(Rc::new(MultiCombinator{
+                        op: Not,
+                        children: vec![operand],
+                        return_type: Sparse,
+                    }));
+                }
+            }
+        }
+
+        if minimum == 0 {
+            // Every document matches
+            self.push_full();
+        } else if children.len() < minimum as usize {
+            // Not enough operands left to reach the minimum
+            self.push_empty();
+        } else if minimum == 1 {
+            // Same as an OR
+            let num_children = children.len();
+            self.stack.extend(children);
+            for _ in 1..num_children {
+                self.or_combinator();
+            }
+        } else if children.len() == minimum as usize {
+            // Same as an AND
+            let num_children = children.len();
+            self.stack.extend(children);
+            for _ in 1..num_children {
+                self.and_combinator();
+            }
+        } else {
+            self.stack.push(Rc::new(MultiCombinator{
+                op: AtLeast(children.len() as u32, minimum),
+                children: children,
+                return_type: Sparse,
+            }));
+        }
+    }
+
     pub fn build(&self) -> (Vec<BooleanQueryOp>, bool) {
         use self::BooleanQueryBlockReturnType::*;
 
@@ -333,18 +459,18 @@ impl BooleanQueryBuilder {
     }
 }
 
-fn plan_boolean_query_combinator<J: Fn(&mut BooleanQueryBuilder) -> ()> (index_reader: &RocksDBReader, mut builder: &mut BooleanQueryBuilder, queries: &Vec<Query>, join_cb: J) {
+fn plan_boolean_query_combinator<D: TermDictionaryReader, J: Fn(&mut BooleanQueryBuilder) -> ()> (term_dictionary: &D, mut builder: &mut BooleanQueryBuilder, queries: &Vec<Query>, join_cb: J) {
     match queries.len() {
         0 => {
             builder.push_empty();
         }
-        1 =>  plan_boolean_query(index_reader, &mut builder, &queries[0]),
+        1 =>  plan_boolean_query(term_dictionary, &mut builder, &queries[0]),
         _ => {
             let mut query_iter = queries.iter();
-            plan_boolean_query(index_reader, &mut builder, query_iter.next().unwrap());
+            plan_boolean_query(term_dictionary, &mut builder, query_iter.next().unwrap());
 
             for query in query_iter {
-                plan_boolean_query(index_reader, &mut builder, query);
+                plan_boolean_query(term_dictionary, &mut builder, query);
 
                 // Add the join operation
                 join_cb(&mut builder);
@@ -353,7 +479,14 @@ fn plan_boolean_query_combinator<J: Fn(&mut BooleanQueryBuilder) -> ()> (index_r
     }
 }
 
-pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut BooleanQueryBuilder, query: &Query) {
+/// Plans a query that doesn't affect the score, so its matches can be cached
+fn plan_filter<D: TermDictionaryReader>(term_dictionary: &D, builder: &mut BooleanQueryBuilder, filter: &Query) {
+    let mut filter_builder = BooleanQueryBuilder::new();
+    plan_boolean_query(term_dictionary, &mut filter_builder, filter);
+    builder.push_filter(filter_builder);
+}
+
+pub fn plan_boolean_query<D: TermDictionaryReader>(term_dictionary: &D, mut builder: &mut BooleanQueryBuilder, query: &Query) {
     match *query {
         Query::All{..} => {
             builder.push_full();
@@ -363,7 +496,7 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
         }
         Query::Term{field, ref term, ..} => {
             // Get term
-            let term_id = match index_reader.store.term_dictionary.get(term) {
+            let term_id = match term_dictionary.get_term_id(term) {
                 Some(term_id) => term_id,
                 None => {
                     // Term doesn't exist, so will never match
@@ -377,28 +510,53 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
         Query::MultiTerm{field, ref term_selector, ..} => {
             // Get terms
             builder.push_empty();
-            for term_id in index_reader.store.term_dictionary.select(term_selector) {
+            for term_id in term_dictionary.select_terms(field, term_selector) {
                 builder.push_postings_list(field, term_id);
                 builder.or_combinator();
             }
         }
+        Query::Phrase{field, ref terms, slop, ..} => {
+            // Get terms
+            let mut term_ids = Vec::with_capacity(terms.len());
+            for &(ref term, offset) in terms.iter() {
+                if let Some(term_id) = term_dictionary.get_term_id(term) {
+                    term_ids.push((term_id, offset));
+                }
+            }
+
+            // Terms that share an offset are synonyms, only one of them needs to exist. If none of
+            // the terms at an offset exist, the phrase will never match
+            if !terms.iter().all(|&(_, offset)| term_ids.iter().any(|&(_, term_offset)| term_offset == offset)) {
+                builder.push_empty();
+                return
+            }
+
+            builder.push_phrase(field, term_ids, slop);
+        }
         Query::Conjunction{ref queries} => {
-            plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.and_combinator());
+            plan_boolean_query_combinator(term_dictionary, &mut builder, queries, |builder| builder.and_combinator());
         }
         Query::Disjunction{ref queries} => {
-            plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
+            plan_boolean_query_combinator(term_dictionary, &mut builder, queries, |builder| builder.or_combinator());
         }
         Query::DisjunctionMax{ref queries} => {
-            plan_boolean_query_combinator(index_reader, &mut builder, queries, |builder| builder.or_combinator());
+            plan_boolean_query_combinator(term_dictionary, &mut builder, queries, |builder| builder.or_combinator());
+        }
+        Query::MinimumMatch{ref queries, minimum} => {
+            for query in queries.iter() {
+                plan_boolean_query(term_dictionary, &mut builder, query);
+            }
+
+            builder.at_least_combinator(queries.len(), minimum);
         }
         Query::Filter{ref query, ref filter} => {
-            plan_boolean_query(index_reader, &mut builder, query);
-            plan_boolean_query(index_reader, &mut builder, filter);
+            plan_boolean_query(term_dictionary, &mut builder, query);
+            plan_filter(term_dictionary, &mut builder, filter);
             builder.and_combinator();
         }
         Query::Exclude{ref query, ref exclude} => {
-            plan_boolean_query(index_reader, &mut builder, query);
-            plan_boolean_query(index_reader, &mut builder, exclude);
+            plan_boolean_query(term_dictionary, &mut builder, query);
+            plan_filter(term_dictionary, &mut builder, exclude);
             builder.andnot_combinator();
         }
     }
@@ -406,6 +564,8 @@ pub fn plan_boolean_query(index_reader: &RocksDBReader, mut builder: &mut Boolea
 
 #[cfg(test)]
 mod builder_tests {
+    use std::sync::Arc;
+
     use search::schema::FieldId;
     use search::term::TermId;
 
@@ -454,6 +614,20 @@ mod builder_tests {
         assert_eq!(negated, false);
     }
 
+    #[test]
+    fn test_push_phrase() {
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_phrase(FieldId(1), vec![(TermId(1), 0), (TermId(2), 1)], 0);
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPhrase(FieldId(1), vec![(TermId(1), 0), (TermId(2), 1)], 0),
+        ]);
+        assert_eq!(negated, false);
+    }
+
     #[test]
     fn test_push_deletion_list() {
         let mut builder = BooleanQueryBuilder::new();
@@ -662,6 +836,176 @@ mod builder_tests {
         assert_eq!(negated, false);
     }
 
+    #[test]
+    fn test_at_least_combinator() {
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_postings_list(FieldId(1), TermId(1));
+        builder.push_postings_list(FieldId(1), TermId(2));
+        builder.push_postings_list(FieldId(1), TermId(3));
+        builder.at_least_combinator(3, 2);
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(1)),
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(2)),
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(3)),
+            BooleanQueryOp::AtLeast(3, 2),
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_at_least_combinator_minimum_one() {
+        // A minimum of one is the same as an or combinator
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_postings_list(FieldId(1), TermId(1));
+        builder.push_postings_list(FieldId(1), TermId(2));
+        builder.at_least_combinator(2, 1);
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(1)),
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(2)),
+            BooleanQueryOp::Or,
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_at_least_combinator_with_full_and_empty() {
+        // Full operands count towards the minimum and empty operands are removed
+        // This leaves a minimum of one, which is the same as an or combinator
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_full();
+        builder.push_empty();
+        builder.push_postings_list(FieldId(1), TermId(1));
+        builder.push_postings_list(FieldId(1), TermId(2));
+        builder.at_least_combinator(4, 2);
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(1)),
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(2)),
+            BooleanQueryOp::Or,
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_at_least_combinator_with_negated_operand() {
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_full();
+        builder.push_postings_list(FieldId(1), TermId(1));
+        builder.andnot_combinator();
+        builder.push_postings_list(FieldId(1), TermId(2));
+        builder.push_postings_list(FieldId(1), TermId(3));
+        builder.at_least_combinator(3, 2);
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(1)),
+            BooleanQueryOp::Not,
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(2)),
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(3)),
+            BooleanQueryOp::AtLeast(3, 2),
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_at_least_combinator_minimum_too_high() {
+        let mut builder = BooleanQueryBuilder::new();
+
+        builder.push_postings_list(FieldId(1), TermId(1));
+        builder.push_empty();
+        builder.at_least_combinator(2, 2);
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushEmpty,
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_push_filter() {
+        let mut filter = BooleanQueryBuilder::new();
+        filter.push_postings_list(FieldId(1), TermId(1));
+        filter.push_postings_list(FieldId(1), TermId(2));
+        filter.or_combinator();
+
+        let mut builder = BooleanQueryBuilder::new();
+        builder.push_postings_list(FieldId(1), TermId(3));
+        builder.push_filter(filter);
+        builder.and_combinator();
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(3)),
+            BooleanQueryOp::PushFilter(Arc::new(vec![
+                BooleanQueryOp::PushPostingsList(FieldId(1), TermId(1)),
+                BooleanQueryOp::PushPostingsList(FieldId(1), TermId(2)),
+                BooleanQueryOp::Or,
+            ])),
+            BooleanQueryOp::And,
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_push_negated_filter() {
+        // The filter keeps its negation, so it can still be turned into an exclusion
+        let mut filter = BooleanQueryBuilder::new();
+        filter.push_full();
+        filter.push_phrase(FieldId(1), vec![(TermId(1), 0), (TermId(2), 1)], 0);
+        filter.andnot_combinator();
+
+        let mut builder = BooleanQueryBuilder::new();
+        builder.push_postings_list(FieldId(1), TermId(3));
+        builder.push_filter(filter);
+        builder.and_combinator();
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(3)),
+            BooleanQueryOp::PushFilter(Arc::new(vec![
+                BooleanQueryOp::PushPhrase(FieldId(1), vec![(TermId(1), 0), (TermId(2), 1)], 0),
+            ])),
+            BooleanQueryOp::AndNot,
+        ]);
+        assert_eq!(negated, false);
+    }
+
+    #[test]
+    fn test_push_cheap_filter() {
+        // Single postings lists aren't cached
+        let mut filter = BooleanQueryBuilder::new();
+        filter.push_postings_list(FieldId(1), TermId(1));
+
+        let mut builder = BooleanQueryBuilder::new();
+        builder.push_full();
+        builder.push_filter(filter);
+        builder.and_combinator();
+
+        let (query, negated) = builder.build();
+
+        assert_eq!(query, vec![
+            BooleanQueryOp::PushPostingsList(FieldId(1), TermId(1)),
+        ]);
+        assert_eq!(negated, false);
+    }
+
     #[test]
     fn test_complex_query() {
         // There's a lot going on here. This checks that a complex query gets optimised as much as possible
@@ -0,0 +1,87 @@
+pub mod boolean_query;
+pub mod score_function;
+
+use std::cell::RefCell;
+
+use search::{Query, Term, TermId};
+use search::schema::FieldId;
+use search::query::multi_term_selector::MultiTermSelector;
+use search::execution::TermDictionaryReader;
+
+use self::boolean_query::{BooleanQueryOp, BooleanQueryBuilder, plan_boolean_query};
+use self::score_function::{ScoreFunctionOp, plan_score_function};
+
+#[derive(Debug)]
+pub struct SearchPlan {
+    pub boolean_query: Vec<BooleanQueryOp>,
+    pub boolean_query_is_negated: bool,
+    pub score_function: Vec<ScoreFunctionOp>,
+}
+
+impl SearchPlan {
+    pub fn new() -> SearchPlan {
+        SearchPlan {
+            boolean_query: Vec::new(),
+            boolean_query_is_negated: false,
+            score_function: Vec::new(),
+        }
+    }
+}
+
+/// Gives multi term queries the search's termination check while the query is planned
+///
+/// Prefix and wildcard queries can match a huge number of terms, this lets the search stop part
+/// way through selecting them.
+struct TerminatingTermDictionary<'a, D: TermDictionaryReader + 'a> {
+    term_dictionary: &'a D,
+    should_terminate: RefCell<&'a mut FnMut() -> bool>,
+}
+
+impl<'a, D: TermDictionaryReader> TermDictionaryReader for TerminatingTermDictionary<'a, D> {
+    fn get_term_id(&self, term: &Term) -> Option<TermId> {
+        self.term_dictionary.get_term_id(term)
+    }
+
+    fn get_term(&self, field_id: FieldId, term_id: TermId) -> Option<Term> {
+        self.term_dictionary.get_term(field_id, term_id)
+    }
+
+    fn select_terms_until(&self, field_id: FieldId, term_selector: &MultiTermSelector, should_terminate: &mut FnMut() -> bool) -> Vec<TermId> {
+        let mut search_should_terminate = self.should_terminate.borrow_mut();
+        self.term_dictionary.select_terms_until(field_id, term_selector, &mut || should_terminate() || (*search_should_terminate)())
+    }
+}
+
+/// Plans how a query is run against the segments of an index
+///
+/// "should_terminate" is checked while the terms of multi term queries are selected. If it
+/// returns true the plan is left incomplete, so the search should stop.
+pub fn plan_query<D: TermDictionaryReader>(term_dictionary: &D, query: &Query, score: bool, should_terminate: &mut FnMut() -> bool) -> SearchPlan {
+    let term_dictionary = TerminatingTermDictionary {
+        term_dictionary: term_dictionary,
+        should_terminate: RefCell::new(should_terminate),
+    };
+
+    let mut plan = SearchPlan::new();
+
+    // Plan boolean query
+    let mut builder = BooleanQueryBuilder::new();
+    plan_boolean_query(&term_dictionary, &mut builder, query);
+
+    // Add operations to exclude deleted documents to boolean query
+    builder.push_deletion_list();
+    builder.andnot_combinator();
+
+    let (boolean_query, boolean_query_is_negated) = builder.build();
+    plan.boolean_query = boolean_query;
+    plan.boolean_query_is_negated = boolean_query_is_negated;
+
+    // Plan score function
+    if score {
+        plan_score_function(&term_dictionary, &mut plan.score_function, query);
+    } else {
+        plan.score_function.push(ScoreFunctionOp::Literal(0.0f32));
+    }
+
+    plan
+}
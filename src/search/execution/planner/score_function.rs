@@ -2,13 +2,13 @@ use search::schema::FieldId;
 use search::term::TermId;
 use search::Query;
 use search::query::term_scorer::TermScorer;
-
-use super::super::RocksDBReader;
+use search::execution::TermDictionaryReader;
 
 #[derive(Debug, Clone)]
 pub enum CombinatorScorer {
     Avg,
     Max,
+    Sum,
 }
 
 #[derive(Debug, Clone)]
@@ -18,18 +18,18 @@ pub enum ScoreFunctionOp {
     CombinatorScorer(u32, CombinatorScorer),
 }
 
-fn plan_score_function_combinator(index_reader: &RocksDBReader, mut score_function: &mut Vec<ScoreFunctionOp>, queries: &Vec<Query>, scorer: CombinatorScorer) {
+fn plan_score_function_combinator<D: TermDictionaryReader>(term_dictionary: &D, mut score_function: &mut Vec<ScoreFunctionOp>, queries: &Vec<Query>, scorer: CombinatorScorer) {
     match queries.len() {
         0 => {
             score_function.push(ScoreFunctionOp::Literal(0.0f32));
         }
-        1 =>  plan_score_function(index_reader, &mut score_function, &queries[0]),
+        1 =>  plan_score_function(term_dictionary, &mut score_function, &queries[0]),
         _ => {
             let mut query_iter = queries.iter();
-            plan_score_function(index_reader, &mut score_function, query_iter.next().unwrap());
+            plan_score_function(term_dictionary, &mut score_function, query_iter.next().unwrap());
 
             for query in query_iter {
-                plan_score_function(index_reader, &mut score_function, query);
+                plan_score_function(term_dictionary, &mut score_function, query);
             }
         }
     }
@@ -37,7 +37,7 @@ fn plan_score_function_combinator(index_reader: &RocksDBReader, mut score_functi
     score_function.push(ScoreFunctionOp::CombinatorScorer(queries.len() as u32, scorer));
 }
 
-pub fn plan_score_function(index_reader: &RocksDBReader, mut score_function: &mut Vec<ScoreFunctionOp>, query: &Query) {
+pub fn plan_score_function<D: TermDictionaryReader>(term_dictionary: &D, mut score_function: &mut Vec<ScoreFunctionOp>, query: &Query) {
     match *query {
         Query::All{ref score} => {
             score_function.push(ScoreFunctionOp::Literal(*score));
@@ -47,7 +47,7 @@ pub fn plan_score_function(index_reader: &RocksDBReader, mut score_function: &mu
         }
         Query::Term{field, ref term, ref scorer} => {
             // Get term
-            let term_id = match index_reader.store.term_dictionary.get(term) {
+            let term_id = match term_dictionary.get_term_id(term) {
                 Some(term_id) => term_id,
                 None => {
                     // Term doesn't exist, so will never match
@@ -61,7 +61,7 @@ pub fn plan_score_function(index_reader: &RocksDBReader, mut score_function: &mu
         Query::MultiTerm{field, ref term_selector, ref scorer} => {
             // Get terms
             let mut total_terms = 0;
-            for term_id in index_reader.store.term_dictionary.select(term_selector) {
+            for term_id in term_dictionary.select_terms(field, term_selector) {
                 score_function.push(ScoreFunctionOp::TermScorer(field, term_id, scorer.clone()));
                 total_terms += 1;
             }
@@ -77,20 +77,43 @@ pub fn plan_score_function(index_reader: &RocksDBReader, mut score_function: &mu
                 _ => score_function.push(ScoreFunctionOp::CombinatorScorer(total_terms, CombinatorScorer::Avg)),
             }
         }
+        Query::Phrase{field, ref terms, ref scorer, ..} => {
+            // Score each term of the phrase individually
+            // The boolean query has already checked that the terms appear together
+            let mut total_terms = 0;
+            for &(ref term, _) in terms.iter() {
+                match term_dictionary.get_term_id(term) {
+                    Some(term_id) => {
+                        score_function.push(ScoreFunctionOp::TermScorer(field, term_id, scorer.clone()));
+                        total_terms += 1;
+                    }
+                    None => {}
+                }
+            }
+
+            match total_terms {
+                0 => score_function.push(ScoreFunctionOp::Literal(0.0f32)),
+                1 => {},
+                _ => score_function.push(ScoreFunctionOp::CombinatorScorer(total_terms, CombinatorScorer::Avg)),
+            }
+        }
         Query::Conjunction{ref queries} => {
-            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
+            plan_score_function_combinator(term_dictionary, &mut score_function, queries, CombinatorScorer::Avg);
         }
         Query::Disjunction{ref queries} => {
-            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Avg);
+            plan_score_function_combinator(term_dictionary, &mut score_function, queries, CombinatorScorer::Avg);
         }
         Query::DisjunctionMax{ref queries} => {
-            plan_score_function_combinator(index_reader, &mut score_function, queries, CombinatorScorer::Max);
+            plan_score_function_combinator(term_dictionary, &mut score_function, queries, CombinatorScorer::Max);
+        }
+        Query::MinimumMatch{ref queries, ..} => {
+            plan_score_function_combinator(term_dictionary, &mut score_function, queries, CombinatorScorer::Sum);
         }
         Query::Filter{ref query, ..} => {
-            plan_score_function(index_reader, &mut score_function, query);
+            plan_score_function(term_dictionary, &mut score_function, query);
         }
         Query::Exclude{ref query, ..} => {
-            plan_score_function(index_reader, &mut score_function, query);
+            plan_score_function(term_dictionary, &mut score_function, query);
         }
     }
 }
@@ -0,0 +1,147 @@
+//! Runs the segments of searches on a set of long-lived threads
+//!
+//! Searches run the segments of an index on several threads at once. Instead of starting new
+//! threads for each search, the work is given to a fixed set of worker threads that is shared
+//! by the whole process, so a burst of searches can't start more threads than there are cores.
+//!
+//! The workers are a rayon thread pool. Tasks are run in a rayon scope, which doesn't return
+//! until all of them have finished, so they can borrow from the stack of the search.
+
+use std::cmp;
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use num_cpus;
+
+
+lazy_static! {
+    /// The pool that is shared by all searches, this has one worker for every core
+    static ref GLOBAL_POOL: SearchPool = SearchPool::new(num_cpus::get());
+}
+
+
+/// A fixed set of threads that run searches
+pub struct SearchPool {
+    pool: ThreadPool,
+}
+
+impl SearchPool {
+    /// Starts a pool with the given number of worker threads
+    ///
+    /// The workers live as long as the pool. Pools other than the global one are only used
+    /// in tests.
+    pub fn new(workers: usize) -> SearchPool {
+        assert!(workers > 0, "search pool: must have at least one worker");
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(workers)
+            .thread_name(|_| "search worker".to_string())
+            .build()
+            .expect("search pool: failed to start worker threads");
+
+        SearchPool {
+            pool: pool,
+        }
+    }
+
+    /// Returns the pool that is shared by all searches
+    pub fn global() -> &'static SearchPool {
+        &GLOBAL_POOL
+    }
+
+    /// Runs up to "copies" copies of a task on the pool's workers at the same time
+    ///
+    /// The task is expected to share out its work through the data it borrows, so it is fine for
+    /// some copies of it to find nothing left to do. The copies are started from a worker, which
+    /// runs any of them that the other workers haven't taken by the time it finishes its own, so
+    /// this never waits for workers that are busy with other searches. Returns once every copy has
+    /// finished, if any of them panicked the panic is passed on to the caller.
+    pub fn run<F: Fn() + Sync>(&self, copies: usize, task: &F) {
+        let copies = cmp::min(copies, self.pool.current_num_threads());
+
+        self.pool.scope(|scope| {
+            for _ in 1..copies {
+                scope.spawn(move |_| task());
+            }
+
+            task();
+        });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::SearchPool;
+
+    #[test]
+    fn test_run() {
+        let pool = SearchPool::new(3);
+
+        // Each copy of the task takes items until there are none left
+        let items = (0..100).collect::<Vec<usize>>();
+        let next_item = AtomicUsize::new(0);
+        let taken_items = Mutex::new(Vec::new());
+        let copies = AtomicUsize::new(0);
+
+        pool.run(10, &|| {
+            copies.fetch_add(1, Ordering::SeqCst);
+
+            loop {
+                let item = next_item.fetch_add(1, Ordering::SeqCst);
+                if item >= items.len() {
+                    return;
+                }
+
+                thread::sleep(Duration::from_millis(1));
+                taken_items.lock().unwrap().push(items[item]);
+            }
+        });
+
+        let mut taken_items = taken_items.into_inner().unwrap();
+        taken_items.sort();
+        assert_eq!(taken_items, items);
+
+        // There is no point running more copies than there are workers
+        assert_eq!(copies.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_run_with_one_worker() {
+        let pool = SearchPool::new(1);
+        let copies = AtomicUsize::new(0);
+
+        pool.run(3, &|| {
+            copies.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert_eq!(copies.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_run_on_workers() {
+        let pool = SearchPool::new(2);
+
+        pool.run(2, &|| {
+            assert_eq!(thread::current().name(), Some("search worker"));
+        });
+    }
+
+    #[test]
+    #[should_panic(expected = "copy panicked")]
+    fn test_run_passes_on_panics() {
+        let pool = SearchPool::new(2);
+        let copies = AtomicUsize::new(0);
+
+        pool.run(2, &|| {
+            // Only the second copy to start panics
+            if copies.fetch_add(1, Ordering::SeqCst) == 1 {
+                panic!("copy panicked");
+            }
+        });
+    }
+}
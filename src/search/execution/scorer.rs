@@ -0,0 +1,441 @@
+//! Scores the documents that match a query in a segment
+//!
+//! Everything that the score function reads from the segment is loaded once, when the scorer is
+//! created. Scoring each document then only walks through lists that are already in memory.
+
+use std::f32;
+
+use search::schema::FieldId;
+use search::term::TermId;
+use search::segment::{Segment, decode_field_length};
+use search::query::term_scorer::TermScorer;
+use fnv::FnvHashMap;
+
+use super::statistics::StatisticsReader;
+use super::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
+
+/// The documents in a segment that contain a term, with the frequency of the term in each one
+pub struct TermPostings {
+    docs: Vec<u32>,
+
+    /// The frequency of the term in each document, None if it occurs once in all of them
+    term_frequencies: Option<Vec<u32>>,
+
+    position: usize,
+}
+
+impl TermPostings {
+    pub fn load<S: Segment>(segment: &S, field_id: FieldId, term_id: TermId) -> Result<TermPostings, String> {
+        let docs = match try!(segment.load_postings_list(field_id, term_id)) {
+            Some(postings) => postings.iter().collect::<Vec<u32>>(),
+            None => Vec::new(),
+        };
+
+        let term_frequencies = if docs.is_empty() {
+            None
+        } else {
+            try!(segment.load_term_frequencies(field_id, term_id))
+        };
+
+        Ok(TermPostings {
+            docs: docs,
+            term_frequencies: term_frequencies,
+            position: 0,
+        })
+    }
+
+    /// Moves forward to a document and returns the frequency of the term in it
+    ///
+    /// Returns None if the document doesn't contain the term. Documents must be visited in ascending order.
+    pub fn advance_to(&mut self, doc: u32) -> Option<u32> {
+        while self.position < self.docs.len() && self.docs[self.position] < doc {
+            self.position += 1;
+        }
+
+        if self.docs.get(self.position) != Some(&doc) {
+            return None;
+        }
+
+        match self.term_frequencies {
+            Some(ref term_frequencies) => Some(term_frequencies.get(self.position).cloned().unwrap_or(1)),
+            None => Some(1),
+        }
+    }
+}
+
+/// The lengths of a field in each document of a segment
+pub struct FieldNorms {
+    norms: Option<Vec<u8>>,
+}
+
+impl FieldNorms {
+    pub fn load<S: Segment>(segment: &S, field_id: FieldId) -> Result<FieldNorms, String> {
+        Ok(FieldNorms {
+            norms: try!(segment.load_field_norms(field_id)),
+        })
+    }
+
+    /// Returns the length of the field in a document
+    pub fn field_length(&self, doc: u32) -> f32 {
+        let norm = self.norms.as_ref().and_then(|norms| norms.get(doc as usize)).cloned().unwrap_or(0);
+        decode_field_length(norm)
+    }
+}
+
+/// A term in the score function, with the statistics that are used to score it
+struct ScoredTerm {
+    field_id: FieldId,
+    postings: TermPostings,
+    total_tokens: u64,
+    total_docs: u64,
+    total_docs_with_term: u64,
+
+    /// The highest score that the term can give any document in the segment
+    upper_bound: f32,
+}
+
+/// Runs a score function, taking the value of each TermScorer from "term_score"
+///
+/// "term_score" is called with the position of the term in the score function and its scorer.
+fn run_score_function<F: FnMut(usize, &TermScorer) -> f32>(score_function: &[ScoreFunctionOp], stack: &mut Vec<f32>, mut term_score: F) -> f32 {
+    stack.clear();
+    let mut term = 0;
+
+    for op in score_function.iter() {
+        match *op {
+            ScoreFunctionOp::Literal(val) => stack.push(val),
+            ScoreFunctionOp::TermScorer(_, _, ref scorer) => {
+                stack.push(term_score(term, scorer));
+                term += 1;
+            }
+            ScoreFunctionOp::CombinatorScorer(num_vals, ref scorer) => {
+                let score = match *scorer {
+                    CombinatorScorer::Avg => {
+                        let mut total_score = 0.0f32;
+
+                        for _ in 0..num_vals {
+                            total_score += stack.pop().expect("document scorer: stack underflow");
+                        }
+
+                        total_score / num_vals as f32
+                    }
+                    CombinatorScorer::Max => {
+                        let mut max_score = 0.0f32;
+
+                        for _ in 0..num_vals {
+                            let score = stack.pop().expect("document scorer: stack underflow");
+                            if score > max_score {
+                                max_score = score
+                            }
+                        }
+
+                        max_score
+                    }
+                    CombinatorScorer::Sum => {
+                        let mut total_score = 0.0f32;
+
+                        for _ in 0..num_vals {
+                            total_score += stack.pop().expect("document scorer: stack underflow");
+                        }
+
+                        total_score
+                    }
+                };
+
+                stack.push(score);
+            }
+        }
+    }
+
+    if stack.len() > 1 {
+        // This shouldn't be possible unless there's a bug in the planner
+        panic!("document scorer: stack size too big ({})", stack.len());
+    }
+
+    stack.pop().expect("document scorer: stack underflow")
+}
+
+/// Runs a score function on the documents of a segment
+///
+/// As well as scoring documents, this can work out the highest score that a document could get
+/// from the terms it contains. Collectors that only want the top scoring documents use this to
+/// skip documents without scoring them (see "Collector::min_competitive_score").
+pub struct SegmentScorer<'a> {
+    score_function: &'a [ScoreFunctionOp],
+
+    /// The terms of each TermScorer in the score function, in order
+    terms: Vec<ScoredTerm>,
+
+    field_norms: FnvHashMap<FieldId, FieldNorms>,
+    stack: Vec<f32>,
+
+    /// The highest score that any document in the segment could get
+    max_score: f32,
+}
+
+impl<'a> SegmentScorer<'a> {
+    /// Loads the postings lists, term frequencies and field norms that the score function needs from the segment
+    pub fn new<S: Segment, R: StatisticsReader>(score_function: &'a [ScoreFunctionOp], segment: &S, stats: &R) -> Result<SegmentScorer<'a>, String> {
+        let mut terms = Vec::new();
+        let mut field_norms = FnvHashMap::default();
+
+        for op in score_function.iter() {
+            if let ScoreFunctionOp::TermScorer(field_id, term_id, ref scorer) = *op {
+                if !field_norms.contains_key(&field_id) {
+                    field_norms.insert(field_id, try!(FieldNorms::load(segment, field_id)));
+                }
+
+                let postings = try!(TermPostings::load(segment, field_id, term_id));
+                let total_tokens = try!(stats.total_tokens(field_id)) as u64;
+                let total_docs = try!(stats.total_docs(field_id)) as u64;
+                let total_docs_with_term = try!(stats.term_document_frequency(field_id, term_id)) as u64;
+
+                // Score the term's impact, this scores at least as highly as any document with the term.
+                // Segments written before impacts were kept can't be skipped
+                let upper_bound = if postings.docs.is_empty() {
+                    0.0f32
+                } else {
+                    match try!(segment.load_term_impact(field_id, term_id)) {
+                        Some(impact) => {
+                            let score = scorer.similarity_model.score(impact.max_term_frequency, decode_field_length(impact.min_field_norm), total_tokens, total_docs, total_docs_with_term);
+                            (score * scorer.boost).max(0.0f32)
+                        }
+                        None => f32::INFINITY,
+                    }
+                };
+
+                terms.push(ScoredTerm {
+                    field_id: field_id,
+                    postings: postings,
+                    total_tokens: total_tokens,
+                    total_docs: total_docs,
+                    total_docs_with_term: total_docs_with_term,
+                    upper_bound: upper_bound,
+                });
+            }
+        }
+
+        // The combinators never lower the score when one of their values goes up, so running the
+        // score function on the upper bounds of every term gives the highest possible score
+        let mut stack = Vec::new();
+        let max_score = run_score_function(score_function, &mut stack, |term, _| terms[term].upper_bound);
+
+        Ok(SegmentScorer {
+            score_function: score_function,
+            terms: terms,
+            field_norms: field_norms,
+            stack: stack,
+            max_score: max_score,
+        })
+    }
+
+    /// Returns the highest score that any document in the segment could get
+    pub fn max_score(&self) -> f32 {
+        self.max_score
+    }
+
+    /// Returns the highest score that a document could get from the terms it contains
+    ///
+    /// This is much quicker than scoring the document. Documents must be visited in ascending order,
+    /// the same document can then be scored.
+    pub fn upper_bound(&mut self, doc: u32) -> f32 {
+        let terms = &mut self.terms;
+
+        run_score_function(self.score_function, &mut self.stack, |term, _| {
+            let term = &mut terms[term];
+
+            match term.postings.advance_to(doc) {
+                Some(_) => term.upper_bound,
+                None => 0.0f32,
+            }
+        })
+    }
+
+    /// Scores a document, documents must be scored in ascending order
+    pub fn score(&mut self, doc: u32) -> f32 {
+        let terms = &mut self.terms;
+        let field_norms = &self.field_norms;
+
+        run_score_function(self.score_function, &mut self.stack, |term, scorer| {
+            let term = &mut terms[term];
+
+            match term.postings.advance_to(doc) {
+                Some(term_frequency) => {
+                    let field_length = field_norms[&term.field_id].field_length(doc);
+                    let score = scorer.similarity_model.score(term_frequency, field_length, term.total_tokens, term.total_docs, term.total_docs_with_term);
+                    score * scorer.boost
+                }
+                None => 0.0f32,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32;
+
+    use roaring::RoaringBitmap;
+    use search::schema::FieldId;
+    use search::term::TermId;
+    use search::segment::{Segment, SegmentId, TermImpact};
+    use search::doc_values::DocValues;
+    use search::query::term_scorer::TermScorer;
+
+    use super::super::statistics::SegmentsStatisticsReader;
+    use super::super::planner::score_function::{CombinatorScorer, ScoreFunctionOp};
+    use super::{TermPostings, FieldNorms, SegmentScorer};
+
+    /// A segment with a single term, that only implements what the scorer reads
+    struct TestSegment {
+        postings: Vec<u32>,
+        term_frequencies: Option<Vec<u32>>,
+        norms: Vec<u8>,
+        impact: Option<TermImpact>,
+    }
+
+    impl Segment for TestSegment {
+        fn id(&self) -> SegmentId {
+            SegmentId(1)
+        }
+
+        fn load_statistic(&self, _stat_name: &[u8]) -> Result<Option<i64>, String> {
+            Ok(None)
+        }
+
+        fn load_stored_field_value_raw(&self, _doc_local_id: u32, _field_id: FieldId, _value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
+            Ok(None)
+        }
+
+        fn load_postings_list(&self, _field_id: FieldId, _term_id: TermId) -> Result<Option<RoaringBitmap>, String> {
+            Ok(Some(self.postings.iter().cloned().collect()))
+        }
+
+        fn load_term_frequencies(&self, _field_id: FieldId, _term_id: TermId) -> Result<Option<Vec<u32>>, String> {
+            Ok(self.term_frequencies.clone())
+        }
+
+        fn load_field_norms(&self, _field_id: FieldId) -> Result<Option<Vec<u8>>, String> {
+            Ok(Some(self.norms.clone()))
+        }
+
+        fn load_term_impact(&self, _field_id: FieldId, _term_id: TermId) -> Result<Option<TermImpact>, String> {
+            Ok(self.impact)
+        }
+
+        fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String> {
+            Ok(None)
+        }
+
+        fn load_doc_values(&self, _field_id: FieldId) -> Result<Option<DocValues>, String> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_term_postings() {
+        let segment = TestSegment {
+            postings: vec![1, 5, 9],
+            term_frequencies: Some(vec![2, 1, 7]),
+            norms: vec![],
+            impact: None,
+        };
+
+        let mut postings = TermPostings::load(&segment, FieldId(1), TermId(1)).unwrap();
+        assert_eq!(postings.advance_to(0), None);
+        assert_eq!(postings.advance_to(1), Some(2));
+        assert_eq!(postings.advance_to(4), None);
+        assert_eq!(postings.advance_to(9), Some(7));
+        assert_eq!(postings.advance_to(10), None);
+    }
+
+    #[test]
+    fn test_term_postings_without_frequencies() {
+        let segment = TestSegment {
+            postings: vec![3, 4],
+            term_frequencies: None,
+            norms: vec![],
+            impact: None,
+        };
+
+        let mut postings = TermPostings::load(&segment, FieldId(1), TermId(1)).unwrap();
+        assert_eq!(postings.advance_to(3), Some(1));
+        assert_eq!(postings.advance_to(4), Some(1));
+    }
+
+    #[test]
+    fn test_field_norms() {
+        let segment = TestSegment {
+            postings: vec![],
+            term_frequencies: None,
+            norms: vec![3, 0, 27],
+            impact: None,
+        };
+
+        let norms = FieldNorms::load(&segment, FieldId(1)).unwrap();
+        assert_eq!(norms.field_length(0), 4.0);
+        assert_eq!(norms.field_length(1), 1.0);
+        assert_eq!(norms.field_length(2), 100.0);
+
+        // Documents past the end of the norms don't have the field
+        assert_eq!(norms.field_length(3), 1.0);
+    }
+
+    /// Scores a term and adds a constant score of 1 to every document
+    fn score_function() -> Vec<ScoreFunctionOp> {
+        vec![
+            ScoreFunctionOp::TermScorer(FieldId(1), TermId(1), TermScorer::default()),
+            ScoreFunctionOp::Literal(1.0f32),
+            ScoreFunctionOp::CombinatorScorer(2, CombinatorScorer::Sum),
+        ]
+    }
+
+    #[test]
+    fn test_upper_bound() {
+        let segments = vec![TestSegment {
+            postings: vec![1, 5, 9],
+            term_frequencies: Some(vec![2, 1, 7]),
+            norms: vec![0, 3, 0, 0, 0, 9, 0, 0, 0, 3],
+            impact: Some(TermImpact::new(7, 3)),
+        }];
+        let score_function = score_function();
+        let stats = SegmentsStatisticsReader::new(&segments);
+        let mut scorer = SegmentScorer::new(&score_function, &segments[0], &stats).unwrap();
+
+        for doc in 0..10 {
+            let upper_bound = scorer.upper_bound(doc);
+            let score = scorer.score(doc);
+
+            assert!(score <= upper_bound);
+            assert!(upper_bound <= scorer.max_score());
+
+            if !segments[0].postings.contains(&doc) {
+                // Documents without the term only get the constant score
+                assert_eq!(upper_bound, 1.0f32);
+            }
+
+            if doc == 9 {
+                // This has the highest term frequency and the shortest field, so reaches the maximum score
+                assert_eq!(score, scorer.max_score());
+            }
+        }
+    }
+
+    #[test]
+    fn test_upper_bound_without_impact() {
+        let segments = vec![TestSegment {
+            postings: vec![1],
+            term_frequencies: None,
+            norms: vec![],
+            impact: None,
+        }];
+        let score_function = score_function();
+        let stats = SegmentsStatisticsReader::new(&segments);
+        let mut scorer = SegmentScorer::new(&score_function, &segments[0], &stats).unwrap();
+
+        // Without an impact, there's no limit on the score of documents with the term
+        assert_eq!(scorer.max_score(), f32::INFINITY);
+        assert_eq!(scorer.upper_bound(0), 1.0f32);
+        assert_eq!(scorer.upper_bound(1), f32::INFINITY);
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Mutex;
+
+use fnv::FnvHashMap;
+
+use search::schema::FieldId;
+use search::term::TermId;
+use search::segment::{Segment, total_field_docs_stat_name, total_field_tokens_stat_name, term_doc_frequency_stat_name};
+
+/// Reads the statistics that are used for scoring
+///
+/// Segments may be searched on several threads at once, so statistics readers must be able to
+/// be shared between them.
+pub trait StatisticsReader: Sync {
+    fn total_docs(&self, field_id: FieldId) -> Result<i64, String>;
+    fn total_tokens(&self, field_id: FieldId) -> Result<i64, String>;
+    fn term_document_frequency(&self, field_id: FieldId, term_id: TermId) -> Result<i64, String>;
+}
+
+/// Reads statistics by summing them across a set of segments
+///
+/// Each statistic is only summed once, the result is then shared by every thread that asks for it.
+pub struct SegmentsStatisticsReader<'a, S: Segment + Sync + 'a> {
+    segments: &'a [S],
+    total_docs: Mutex<FnvHashMap<FieldId, i64>>,
+    total_tokens: Mutex<FnvHashMap<FieldId, i64>>,
+    term_document_frequencies: Mutex<FnvHashMap<(FieldId, TermId), i64>>,
+}
+
+impl<'a, S: Segment + Sync + 'a> SegmentsStatisticsReader<'a, S> {
+    pub fn new(segments: &'a [S]) -> SegmentsStatisticsReader<'a, S> {
+        SegmentsStatisticsReader {
+            segments: segments,
+            total_docs: Mutex::new(FnvHashMap::default()),
+            total_tokens: Mutex::new(FnvHashMap::default()),
+            term_document_frequencies: Mutex::new(FnvHashMap::default()),
+        }
+    }
+
+    fn get_statistic(&self, name: &[u8]) -> Result<i64, String> {
+        let mut val = 0;
+
+        for segment in self.segments.iter() {
+            if let Some(new_val) = try!(segment.load_statistic(name)) {
+                val += new_val;
+            }
+        }
+
+        Ok(val)
+    }
+}
+
+impl<'a, S: Segment + Sync + 'a> StatisticsReader for SegmentsStatisticsReader<'a, S> {
+    fn total_docs(&self, field_id: FieldId) -> Result<i64, String> {
+        if let Some(val) = self.total_docs.lock().unwrap().get(&field_id) {
+            return Ok(*val);
+        }
+
+        // The lock isn't held while summing, another thread may sum the same statistic at the
+        // same time but they will both get the same result
+        let stat_name = total_field_docs_stat_name(field_id.0);
+        let val = try!(self.get_statistic(&stat_name));
+        self.total_docs.lock().unwrap().insert(field_id, val);
+        Ok(val)
+    }
+
+    fn total_tokens(&self, field_id: FieldId) -> Result<i64, String> {
+        if let Some(val) = self.total_tokens.lock().unwrap().get(&field_id) {
+            return Ok(*val);
+        }
+
+        let stat_name = total_field_tokens_stat_name(field_id.0);
+        let val = try!(self.get_statistic(&stat_name));
+        self.total_tokens.lock().unwrap().insert(field_id, val);
+        Ok(val)
+    }
+
+    fn term_document_frequency(&self, field_id: FieldId, term_id: TermId) -> Result<i64, String> {
+        if let Some(val) = self.term_document_frequencies.lock().unwrap().get(&(field_id, term_id)) {
+            return Ok(*val);
+        }
+
+        let stat_name = term_doc_frequency_stat_name(field_id.0, term_id.0);
+        let val = try!(self.get_statistic(&stat_name));
+        self.term_document_frequencies.lock().unwrap().insert((field_id, term_id), val);
+        Ok(val)
+    }
+}
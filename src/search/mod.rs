@@ -4,9 +4,15 @@ pub mod term_vector;
 pub mod schema;
 pub mod document;
 pub mod segment;
+pub mod segment_builder;
+pub mod version;
+pub mod doc_values;
 pub mod similarity;
+pub mod explanation;
 pub mod query;
 pub mod collectors;
+pub mod term_selection;
+pub mod execution;
 pub mod backends;
 
 pub use search::term::{Term, TermId};
@@ -0,0 +1,36 @@
+//! A common interface for the automata used to select terms
+//!
+//! Automata are fed the characters of a term one at a time. This allows the term dictionary
+//! to walk its terms in order, sharing states between terms with a common prefix and skipping
+//! over every term that starts with a prefix the automaton can't match.
+
+pub trait Automaton {
+    type State: Clone;
+
+    /// The state before any characters have been fed
+    fn start(&self) -> Self::State;
+
+    /// Feeds a character into the automaton, returning the next state
+    fn step(&self, state: &Self::State, c: char) -> Self::State;
+
+    /// Returns true if the characters fed so far are accepted
+    fn is_match(&self, state: &Self::State) -> bool;
+
+    /// Returns false if no more characters could make the state match
+    fn can_match(&self, state: &Self::State) -> bool;
+
+    /// Feeds a whole string into the automaton, returning true if it is accepted
+    fn accepts(&self, string: &str) -> bool {
+        let mut state = self.start();
+
+        for c in string.chars() {
+            state = self.step(&state, c);
+
+            if !self.can_match(&state) {
+                return false;
+            }
+        }
+
+        self.is_match(&state)
+    }
+}
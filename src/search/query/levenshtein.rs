@@ -0,0 +1,189 @@
+//! A Levenshtein automaton for finding terms within an edit distance of a query
+//!
+//! The automaton is fed the characters of a term one at a time. Its state is a row of the
+//! edit distance matrix, so feeding it the characters of a term gives the same result as
+//! computing the edit distance between the query and the term. But as the state only depends
+//! on the characters fed so far, terms that share a prefix can share states. And once a state
+//! can't match, none of the terms starting with that prefix can match so they can all be skipped.
+
+use std::cmp;
+
+use search::query::automaton::Automaton;
+
+#[derive(Debug)]
+pub struct LevenshteinAutomaton {
+    query: Vec<char>,
+    max_edits: u32,
+
+    /// If true, swapping two adjacent characters counts as a single edit
+    transpositions: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct LevenshteinState {
+    /// The edit distance between the characters fed so far and each prefix of the query
+    row: Vec<u32>,
+
+    /// The row and character before the last one, used for transpositions
+    previous_row: Option<Vec<u32>>,
+    last_char: Option<char>,
+}
+
+impl LevenshteinAutomaton {
+    pub fn new(query: &str, max_edits: u32, transpositions: bool) -> LevenshteinAutomaton {
+        LevenshteinAutomaton {
+            query: query.chars().collect(),
+            max_edits: max_edits,
+            transpositions: transpositions,
+        }
+    }
+
+    /// Returns the edit distance if the characters fed so far match the query
+    pub fn distance(&self, state: &LevenshteinState) -> Option<u32> {
+        let distance = *state.row.last().unwrap();
+
+        if distance <= self.max_edits {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the edit distance between the query and a string, if it is within max_edits
+    pub fn eval(&self, string: &str) -> Option<u32> {
+        let mut state = self.start();
+
+        for c in string.chars() {
+            state = self.step(&state, c);
+
+            if !self.can_match(&state) {
+                return None;
+            }
+        }
+
+        self.distance(&state)
+    }
+}
+
+impl Automaton for LevenshteinAutomaton {
+    type State = LevenshteinState;
+
+    fn start(&self) -> LevenshteinState {
+        LevenshteinState {
+            row: (0..self.query.len() as u32 + 1).collect(),
+            previous_row: None,
+            last_char: None,
+        }
+    }
+
+    fn step(&self, state: &LevenshteinState, c: char) -> LevenshteinState {
+        let mut row = Vec::with_capacity(state.row.len());
+        row.push(state.row[0] + 1);
+
+        for (i, query_char) in self.query.iter().enumerate() {
+            let cost = if *query_char == c { 0 } else { 1 };
+
+            let mut distance = cmp::min(
+                cmp::min(row[i] + 1, state.row[i + 1] + 1),
+                state.row[i] + cost,
+            );
+
+            if self.transpositions && i > 0 {
+                if let (Some(previous_row), Some(last_char)) = (state.previous_row.as_ref(), state.last_char) {
+                    if *query_char == last_char && self.query[i - 1] == c {
+                        distance = cmp::min(distance, previous_row[i - 1] + 1);
+                    }
+                }
+            }
+
+            row.push(distance);
+        }
+
+        LevenshteinState {
+            row: row,
+            previous_row: Some(state.row.clone()),
+            last_char: Some(c),
+        }
+    }
+
+    fn is_match(&self, state: &LevenshteinState) -> bool {
+        self.distance(state).is_some()
+    }
+
+    /// Returns false if no more characters could make the state match the query
+    fn can_match(&self, state: &LevenshteinState) -> bool {
+        if state.row.iter().any(|distance| *distance <= self.max_edits) {
+            return true;
+        }
+
+        // A transposition can reach back to the previous row
+        if self.transpositions {
+            if let Some(ref previous_row) = state.previous_row {
+                return previous_row.iter().any(|distance| *distance < self.max_edits);
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::query::automaton::Automaton;
+
+    use super::LevenshteinAutomaton;
+
+    #[test]
+    fn test_exact_match() {
+        let automaton = LevenshteinAutomaton::new("hello", 0, false);
+
+        assert_eq!(automaton.eval("hello"), Some(0));
+        assert_eq!(automaton.eval("hellp"), None);
+    }
+
+    #[test]
+    fn test_edits() {
+        let automaton = LevenshteinAutomaton::new("hello", 1, false);
+
+        // Substitution
+        assert_eq!(automaton.eval("hallo"), Some(1));
+
+        // Insertion
+        assert_eq!(automaton.eval("helllo"), Some(1));
+
+        // Deletion
+        assert_eq!(automaton.eval("helo"), Some(1));
+
+        // Too many edits
+        assert_eq!(automaton.eval("hallp"), None);
+        assert_eq!(automaton.eval("he"), None);
+    }
+
+    #[test]
+    fn test_transpositions() {
+        let automaton = LevenshteinAutomaton::new("hello", 1, true);
+        assert_eq!(automaton.eval("hlelo"), Some(1));
+
+        // Without transpositions, this is two substitutions
+        let automaton = LevenshteinAutomaton::new("hello", 1, false);
+        assert_eq!(automaton.eval("hlelo"), None);
+    }
+
+    #[test]
+    fn test_can_match() {
+        let automaton = LevenshteinAutomaton::new("hello", 1, false);
+
+        let state = automaton.step(&automaton.start(), 'h');
+        assert!(automaton.can_match(&state));
+
+        let state = automaton.step(&automaton.step(&automaton.start(), 'x'), 'y');
+        assert!(!automaton.can_match(&state));
+    }
+
+    #[test]
+    fn test_unicode() {
+        let automaton = LevenshteinAutomaton::new("café", 1, false);
+
+        assert_eq!(automaton.eval("cafe"), Some(1));
+    }
+}
@@ -1,4 +1,7 @@
 pub mod multi_term_selector;
+pub mod automaton;
+pub mod levenshtein;
+pub mod pattern;
 pub mod term_scorer;
 
 use search::term::Term;
@@ -42,6 +45,23 @@ pub enum Query {
         scorer: TermScorer,
     },
 
+    /// Matches documents that contain the specified terms next to each other in the specified field
+    Phrase {
+        /// The field being searched
+        field: FieldId,
+
+        /// The terms to search for, each with the offset of its position from the first term's
+        /// Terms may share an offset, and offsets may be skipped where the analyzer left a gap
+        terms: Vec<(Term, u32)>,
+
+        /// The maximum number of positions the terms may be moved by and still match
+        /// A slop of 0 requires the terms to be exactly next to each other
+        slop: u32,
+
+        /// The method of scoring each match
+        scorer: TermScorer,
+    },
+
     /// Joins two queries with an AND operator
     /// This intersects the results of the queries. The scores are combined by average
     Conjunction {
@@ -61,6 +81,13 @@ pub enum Query {
         queries: Vec<Query>,
     },
 
+    /// Matches documents that match at least "minimum" of the queries
+    /// Used for "bool" queries. Unlike Conjunction and Disjunction queries, the scores are combined by sum
+    MinimumMatch {
+        queries: Vec<Query>,
+        minimum: u32,
+    },
+
     /// Removes documents that do not match the "filter" query from the results
     /// Basically the same as a Conjunction query except that the "filter" query does not affect the score
     Filter {
@@ -133,6 +160,9 @@ impl Query {
             Query::MultiTerm{ref mut scorer, ..} => {
                 scorer.boost *= add_boost;
             }
+            Query::Phrase{ref mut scorer, ..} => {
+                scorer.boost *= add_boost;
+            }
             Query::Conjunction{ref mut queries} => {
                 for query in queries {
                     query.add_boost(add_boost);
@@ -148,6 +178,11 @@ impl Query {
                     query.add_boost(add_boost);
                 }
             }
+            Query::MinimumMatch{ref mut queries, ..} => {
+                for query in queries {
+                    query.add_boost(add_boost);
+                }
+            }
             Query::Filter{ref mut query, ..} => {
                 query.add_boost(add_boost);
             }
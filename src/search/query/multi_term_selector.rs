@@ -1,8 +1,50 @@
+use std::str;
+use std::ops::Bound;
+
 use search::term::Term;
+use search::query::automaton::Automaton;
+use search::query::levenshtein::LevenshteinAutomaton;
+use search::query::pattern::Pattern;
 
 #[derive(Debug, PartialEq)]
 pub enum MultiTermSelector {
     Prefix(String),
+
+    /// Selects all terms that fall between the two bounds
+    /// Terms are compared by their bytes, integer and datetime terms are encoded so this
+    /// matches their numeric order
+    Range {
+        lower: Bound<Term>,
+        upper: Bound<Term>,
+    },
+
+    /// Selects terms that are within "max_edits" edits of "term"
+    ///
+    /// The first "prefix_length" characters must match exactly. At most "max_expansions"
+    /// terms are selected, preferring the closest ones.
+    Fuzzy {
+        term: String,
+        max_edits: u32,
+        prefix_length: u32,
+        transpositions: bool,
+        max_expansions: usize,
+    },
+
+    /// Selects terms that match a wildcard pattern, "*" matches any sequence of characters
+    /// and "?" matches any single character
+    Wildcard(String),
+
+    /// Selects terms that match a regular expression
+    /// See the pattern module for the supported syntax
+    Regexp(String),
+}
+
+/// Splits a string after the given number of characters
+pub fn split_at_char(string: &str, chars: usize) -> (&str, &str) {
+    match string.char_indices().nth(chars) {
+        Some((index, _)) => string.split_at(index),
+        None => (string, ""),
+    }
 }
 
 impl MultiTermSelector {
@@ -11,6 +53,144 @@ impl MultiTermSelector {
             MultiTermSelector::Prefix(ref prefix) => {
                 return term.as_bytes().starts_with(prefix.as_bytes());
             }
+            MultiTermSelector::Range{ref lower, ref upper} => {
+                let above_lower = match *lower {
+                    Bound::Included(ref lower) => term >= lower,
+                    Bound::Excluded(ref lower) => term > lower,
+                    Bound::Unbounded => true,
+                };
+
+                let below_upper = match *upper {
+                    Bound::Included(ref upper) => term <= upper,
+                    Bound::Excluded(ref upper) => term < upper,
+                    Bound::Unbounded => true,
+                };
+
+                above_lower && below_upper
+            }
+            MultiTermSelector::Fuzzy{term: ref fuzzy_term, max_edits, prefix_length, transpositions, ..} => {
+                let candidate = match str::from_utf8(term.as_bytes()) {
+                    Ok(candidate) => candidate,
+                    Err(_) => return false,
+                };
+
+                let (prefix, suffix) = split_at_char(fuzzy_term, prefix_length as usize);
+                if !candidate.starts_with(prefix) {
+                    return false;
+                }
+
+                let automaton = LevenshteinAutomaton::new(suffix, max_edits, transpositions);
+                automaton.eval(&candidate[prefix.len()..]).is_some()
+            }
+            MultiTermSelector::Wildcard(ref wildcard) => {
+                match str::from_utf8(term.as_bytes()) {
+                    Ok(candidate) => Pattern::wildcard(wildcard).accepts(candidate),
+                    Err(_) => false,
+                }
+            }
+            MultiTermSelector::Regexp(ref regexp) => {
+                match (str::from_utf8(term.as_bytes()), Pattern::regexp(regexp)) {
+                    (Ok(candidate), Ok(pattern)) => pattern.accepts(candidate),
+                    _ => false,
+                }
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use search::term::Term;
+
+    use super::MultiTermSelector;
+
+    #[test]
+    fn test_prefix() {
+        let selector = MultiTermSelector::Prefix("foo".to_string());
+
+        assert!(selector.matches(&Term::from_string("foo")));
+        assert!(selector.matches(&Term::from_string("foobar")));
+        assert!(!selector.matches(&Term::from_string("fo")));
+        assert!(!selector.matches(&Term::from_string("bar")));
+    }
+
+    #[test]
+    fn test_fuzzy() {
+        let selector = MultiTermSelector::Fuzzy {
+            term: "hello".to_string(),
+            max_edits: 1,
+            prefix_length: 1,
+            transpositions: true,
+            max_expansions: 50,
+        };
+
+        assert!(selector.matches(&Term::from_string("hello")));
+        assert!(selector.matches(&Term::from_string("hallo")));
+        assert!(selector.matches(&Term::from_string("hlelo")));
+        assert!(!selector.matches(&Term::from_string("hallp")));
+
+        // The first character is in the prefix so can't be edited
+        assert!(!selector.matches(&Term::from_string("jello")));
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let selector = MultiTermSelector::Wildcard("f?o*".to_string());
+
+        assert!(selector.matches(&Term::from_string("foo")));
+        assert!(selector.matches(&Term::from_string("fxobar")));
+        assert!(!selector.matches(&Term::from_string("fo")));
+        assert!(!selector.matches(&Term::from_string("bar")));
+    }
+
+    #[test]
+    fn test_regexp() {
+        let selector = MultiTermSelector::Regexp("fo+(bar)?".to_string());
+
+        assert!(selector.matches(&Term::from_string("foo")));
+        assert!(selector.matches(&Term::from_string("foobar")));
+        assert!(!selector.matches(&Term::from_string("foob")));
+        assert!(!selector.matches(&Term::from_string("bar")));
+    }
+
+    #[test]
+    fn test_range_inclusive() {
+        let selector = MultiTermSelector::Range {
+            lower: Bound::Included(Term::from_integer(-10)),
+            upper: Bound::Included(Term::from_integer(10)),
+        };
+
+        assert!(!selector.matches(&Term::from_integer(-11)));
+        assert!(selector.matches(&Term::from_integer(-10)));
+        assert!(selector.matches(&Term::from_integer(0)));
+        assert!(selector.matches(&Term::from_integer(10)));
+        assert!(!selector.matches(&Term::from_integer(11)));
+    }
+
+    #[test]
+    fn test_range_exclusive() {
+        let selector = MultiTermSelector::Range {
+            lower: Bound::Excluded(Term::from_integer(-10)),
+            upper: Bound::Excluded(Term::from_integer(10)),
+        };
+
+        assert!(!selector.matches(&Term::from_integer(-10)));
+        assert!(selector.matches(&Term::from_integer(-9)));
+        assert!(selector.matches(&Term::from_integer(9)));
+        assert!(!selector.matches(&Term::from_integer(10)));
+    }
+
+    #[test]
+    fn test_range_unbounded() {
+        let selector = MultiTermSelector::Range {
+            lower: Bound::Included(Term::from_integer(1000)),
+            upper: Bound::Unbounded,
+        };
+
+        assert!(!selector.matches(&Term::from_integer(999)));
+        assert!(selector.matches(&Term::from_integer(1000)));
+        assert!(selector.matches(&Term::from_integer(i64::max_value())));
+    }
+}
@@ -0,0 +1,518 @@
+//! Wildcard and regular expression patterns for selecting terms
+//!
+//! Patterns are compiled into a small NFA (a list of instructions) which implements the
+//! Automaton trait. The state of the automaton is the set of instructions that could
+//! match the next character.
+//!
+//! Wildcard patterns support "*" (any sequence of characters) and "?" (any single
+//! character). Regular expressions support a subset of Lucene's syntax: literals, ".",
+//! "*", "+", "?", "{n}", "{n,}", "{n,m}", "|", groups and character classes such as
+//! "[a-z]" and "[^0-9]". "\" escapes the next character in both. Like in Lucene, a
+//! pattern must match the whole term.
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use search::query::automaton::Automaton;
+
+/// The maximum number of repetitions that can be given in a "{n,m}" quantifier
+const MAX_REPETITIONS: u32 = 100;
+
+#[derive(Debug, PartialEq)]
+pub enum PatternParseError {
+    UnexpectedEnd,
+    UnmatchedParenthesis,
+    UnmatchedBracket,
+    NothingToRepeat,
+    InvalidRepetition,
+    InvalidRange,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CharMatcher {
+    Literal(char),
+    Any,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl CharMatcher {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            CharMatcher::Literal(literal) => c == literal,
+            CharMatcher::Any => true,
+            CharMatcher::Class{ref ranges, negated} => {
+                ranges.iter().any(|&(low, high)| c >= low && c <= high) != negated
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Char(CharMatcher),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+impl Node {
+    /// Returns the literal characters that every match must start with
+    fn literal_prefix(&self) -> String {
+        let mut prefix = String::new();
+
+        match *self {
+            Node::Char(CharMatcher::Literal(c)) => prefix.push(c),
+            Node::Concat(ref nodes) => {
+                for node in nodes.iter() {
+                    if let Node::Char(CharMatcher::Literal(c)) = *node {
+                        prefix.push(c);
+                    } else {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        prefix
+    }
+}
+
+
+struct RegexpParser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> RegexpParser<'a> {
+    fn parse_alternate(&mut self) -> Result<Node, PatternParseError> {
+        let mut alternatives = vec![self.parse_concat()?];
+
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            alternatives.push(self.parse_concat()?);
+        }
+
+        if alternatives.len() == 1 {
+            Ok(alternatives.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(alternatives))
+        }
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, PatternParseError> {
+        let mut nodes = Vec::new();
+
+        loop {
+            match self.chars.peek().cloned() {
+                None | Some('|') | Some(')') => break,
+                _ => {}
+            }
+
+            let mut node = self.parse_atom()?;
+
+            // Quantifiers
+            loop {
+                let (min, max) = match self.chars.peek().cloned() {
+                    Some('*') => (0, None),
+                    Some('+') => (1, None),
+                    Some('?') => (0, Some(1)),
+                    Some('{') => {
+                        self.chars.next();
+                        let (min, max) = self.parse_repetition()?;
+                        node = Node::Repeat { node: Box::new(node), min: min, max: max };
+                        continue;
+                    }
+                    _ => break,
+                };
+
+                self.chars.next();
+                node = Node::Repeat { node: Box::new(node), min: min, max: max };
+            }
+
+            nodes.push(node);
+        }
+
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, PatternParseError> {
+        match self.chars.next() {
+            Some('(') => {
+                let node = self.parse_alternate()?;
+
+                match self.chars.next() {
+                    Some(')') => Ok(node),
+                    _ => Err(PatternParseError::UnmatchedParenthesis),
+                }
+            }
+            Some('[') => self.parse_class(),
+            Some('.') => Ok(Node::Char(CharMatcher::Any)),
+            Some('\\') => {
+                let c = self.chars.next().ok_or(PatternParseError::UnexpectedEnd)?;
+                Ok(Node::Char(CharMatcher::Literal(c)))
+            }
+            Some('*') | Some('+') | Some('?') | Some('{') => Err(PatternParseError::NothingToRepeat),
+            Some(c) => Ok(Node::Char(CharMatcher::Literal(c))),
+            None => Err(PatternParseError::UnexpectedEnd),
+        }
+    }
+
+    /// Parses a character class, after the opening "["
+    fn parse_class(&mut self) -> Result<Node, PatternParseError> {
+        let negated = if self.chars.peek() == Some(&'^') {
+            self.chars.next();
+            true
+        } else {
+            false
+        };
+
+        let mut ranges = Vec::new();
+        loop {
+            let low = match self.chars.next() {
+                Some(']') if !ranges.is_empty() => break,
+                Some('\\') => self.chars.next().ok_or(PatternParseError::UnmatchedBracket)?,
+                Some(c) => c,
+                None => return Err(PatternParseError::UnmatchedBracket),
+            };
+
+            let high = if self.chars.peek() == Some(&'-') {
+                self.chars.next();
+
+                match self.chars.next() {
+                    Some(']') => {
+                        // A "-" at the end of the class is a literal
+                        ranges.push((low, low));
+                        ranges.push(('-', '-'));
+                        break;
+                    }
+                    Some('\\') => self.chars.next().ok_or(PatternParseError::UnmatchedBracket)?,
+                    Some(c) => c,
+                    None => return Err(PatternParseError::UnmatchedBracket),
+                }
+            } else {
+                low
+            };
+
+            if high < low {
+                return Err(PatternParseError::InvalidRange);
+            }
+
+            ranges.push((low, high));
+        }
+
+        Ok(Node::Char(CharMatcher::Class { ranges: ranges, negated: negated }))
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let mut number = String::new();
+
+        while let Some(c) = self.chars.peek().cloned() {
+            if !c.is_digit(10) {
+                break;
+            }
+
+            number.push(c);
+            self.chars.next();
+        }
+
+        number.parse().ok()
+    }
+
+    /// Parses a "{n}", "{n,}" or "{n,m}" quantifier, after the opening "{"
+    fn parse_repetition(&mut self) -> Result<(u32, Option<u32>), PatternParseError> {
+        let min = self.parse_number().ok_or(PatternParseError::InvalidRepetition)?;
+
+        let max = match self.chars.next() {
+            Some('}') => Some(min),
+            Some(',') => {
+                let max = self.parse_number();
+
+                match self.chars.next() {
+                    Some('}') => max,
+                    _ => return Err(PatternParseError::InvalidRepetition),
+                }
+            }
+            _ => return Err(PatternParseError::InvalidRepetition),
+        };
+
+        if min > MAX_REPETITIONS || max.map_or(false, |max| max < min || max > MAX_REPETITIONS) {
+            return Err(PatternParseError::InvalidRepetition);
+        }
+
+        Ok((min, max))
+    }
+}
+
+
+fn parse_regexp(regexp: &str) -> Result<Node, PatternParseError> {
+    let mut parser = RegexpParser {
+        chars: regexp.chars().peekable(),
+    };
+
+    let node = parser.parse_alternate()?;
+
+    // The only way to stop before the end is an unopened parenthesis
+    if parser.chars.next().is_some() {
+        return Err(PatternParseError::UnmatchedParenthesis);
+    }
+
+    Ok(node)
+}
+
+
+fn parse_wildcard(wildcard: &str) -> Node {
+    let mut nodes = Vec::new();
+    let mut chars = wildcard.chars();
+
+    while let Some(c) = chars.next() {
+        nodes.push(match c {
+            '*' => Node::Repeat { node: Box::new(Node::Char(CharMatcher::Any)), min: 0, max: None },
+            '?' => Node::Char(CharMatcher::Any),
+            '\\' => Node::Char(CharMatcher::Literal(chars.next().unwrap_or('\\'))),
+            c => Node::Char(CharMatcher::Literal(c)),
+        });
+    }
+
+    Node::Concat(nodes)
+}
+
+
+#[derive(Debug, Clone)]
+enum Instruction {
+    Char(CharMatcher, usize),
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// Compiles a node so that it continues to the instruction at "next", returning the first instruction
+fn compile_node(node: &Node, next: usize, program: &mut Vec<Instruction>) -> usize {
+    match *node {
+        Node::Empty => next,
+        Node::Char(ref matcher) => {
+            program.push(Instruction::Char(matcher.clone(), next));
+            program.len() - 1
+        }
+        Node::Concat(ref nodes) => {
+            nodes.iter().rev().fold(next, |next, node| compile_node(node, next, program))
+        }
+        Node::Alternate(ref nodes) => {
+            let mut starts = nodes.iter().map(|node| compile_node(node, next, program)).collect::<Vec<usize>>();
+            let mut start = starts.pop().unwrap();
+
+            while let Some(other) = starts.pop() {
+                program.push(Instruction::Split(other, start));
+                start = program.len() - 1;
+            }
+
+            start
+        }
+        Node::Repeat{node: ref inner, min, max} => {
+            let mut start = match max {
+                None => {
+                    // Loop back to a split which either runs the node again or continues
+                    program.push(Instruction::Jump(next));
+                    let split = program.len() - 1;
+                    let body = compile_node(inner, split, program);
+                    program[split] = Instruction::Split(body, next);
+                    split
+                }
+                Some(max) => {
+                    // Each optional copy can skip straight to the end
+                    let mut start = next;
+                    for _ in min..max {
+                        let body = compile_node(inner, start, program);
+                        program.push(Instruction::Split(body, next));
+                        start = program.len() - 1;
+                    }
+                    start
+                }
+            };
+
+            for _ in 0..min {
+                start = compile_node(inner, start, program);
+            }
+
+            start
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    program: Vec<Instruction>,
+    start: usize,
+    literal_prefix: String,
+}
+
+/// The instructions that could match the next character, in order
+pub type PatternState = Vec<usize>;
+
+impl Pattern {
+    fn compile(node: Node) -> Pattern {
+        let mut program = vec![Instruction::Match];
+        let start = compile_node(&node, 0, &mut program);
+
+        Pattern {
+            program: program,
+            start: start,
+            literal_prefix: node.literal_prefix(),
+        }
+    }
+
+    pub fn wildcard(wildcard: &str) -> Pattern {
+        Pattern::compile(parse_wildcard(wildcard))
+    }
+
+    pub fn regexp(regexp: &str) -> Result<Pattern, PatternParseError> {
+        Ok(Pattern::compile(parse_regexp(regexp)?))
+    }
+
+    /// The literal characters that every term matching the pattern starts with
+    pub fn literal_prefix(&self) -> &str {
+        &self.literal_prefix
+    }
+
+    /// Adds an instruction to the state, following any splits and jumps
+    fn add_to_state(&self, pc: usize, state: &mut PatternState, visited: &mut Vec<bool>) {
+        if visited[pc] {
+            return;
+        }
+        visited[pc] = true;
+
+        match self.program[pc] {
+            Instruction::Split(a, b) => {
+                self.add_to_state(a, state, visited);
+                self.add_to_state(b, state, visited);
+            }
+            Instruction::Jump(next) => self.add_to_state(next, state, visited),
+            Instruction::Char(..) | Instruction::Match => state.push(pc),
+        }
+    }
+}
+
+impl Automaton for Pattern {
+    type State = PatternState;
+
+    fn start(&self) -> PatternState {
+        let mut state = Vec::new();
+        self.add_to_state(self.start, &mut state, &mut vec![false; self.program.len()]);
+        state
+    }
+
+    fn step(&self, state: &PatternState, c: char) -> PatternState {
+        let mut next_state = Vec::new();
+        let mut visited = vec![false; self.program.len()];
+
+        for pc in state.iter() {
+            if let Instruction::Char(ref matcher, next) = self.program[*pc] {
+                if matcher.matches(c) {
+                    self.add_to_state(next, &mut next_state, &mut visited);
+                }
+            }
+        }
+
+        next_state
+    }
+
+    fn is_match(&self, state: &PatternState) -> bool {
+        state.iter().any(|pc| *pc == 0)
+    }
+
+    fn can_match(&self, state: &PatternState) -> bool {
+        !state.is_empty()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use search::query::automaton::Automaton;
+
+    use super::{Pattern, PatternParseError};
+
+    #[test]
+    fn test_wildcard() {
+        let pattern = Pattern::wildcard("he*o?");
+
+        assert!(pattern.accepts("hello!"));
+        assert!(pattern.accepts("heo!"));
+        assert!(!pattern.accepts("hello"));
+        assert!(!pattern.accepts("ahello!"));
+        assert_eq!(pattern.literal_prefix(), "he");
+    }
+
+    #[test]
+    fn test_wildcard_escape() {
+        let pattern = Pattern::wildcard("a\\*b");
+
+        assert!(pattern.accepts("a*b"));
+        assert!(!pattern.accepts("axb"));
+        assert_eq!(pattern.literal_prefix(), "a*b");
+    }
+
+    #[test]
+    fn test_regexp() {
+        let pattern = Pattern::regexp("ab+c?(de|fg)*.").unwrap();
+
+        assert!(pattern.accepts("abx"));
+        assert!(pattern.accepts("abbbcdefgx"));
+        assert!(!pattern.accepts("ax"));
+        assert!(!pattern.accepts("abcdx"));
+        assert_eq!(pattern.literal_prefix(), "a");
+    }
+
+    #[test]
+    fn test_regexp_alternation() {
+        let pattern = Pattern::regexp("foo|bar").unwrap();
+
+        assert!(pattern.accepts("foo"));
+        assert!(pattern.accepts("bar"));
+        assert!(!pattern.accepts("foobar"));
+        assert_eq!(pattern.literal_prefix(), "");
+    }
+
+    #[test]
+    fn test_regexp_character_class() {
+        let pattern = Pattern::regexp("[a-c_]+[^0-9]").unwrap();
+
+        assert!(pattern.accepts("ab_cx"));
+        assert!(!pattern.accepts("ab_c1"));
+        assert!(!pattern.accepts("dx"));
+    }
+
+    #[test]
+    fn test_regexp_repetition() {
+        let pattern = Pattern::regexp("a{2,3}b{2}c{1,}").unwrap();
+
+        assert!(pattern.accepts("aabbc"));
+        assert!(pattern.accepts("aaabbccc"));
+        assert!(!pattern.accepts("abbc"));
+        assert!(!pattern.accepts("aaaabbc"));
+        assert!(!pattern.accepts("aabbbc"));
+        assert!(!pattern.accepts("aabb"));
+    }
+
+    #[test]
+    fn test_regexp_errors() {
+        assert_eq!(Pattern::regexp("(ab").err(), Some(PatternParseError::UnmatchedParenthesis));
+        assert_eq!(Pattern::regexp("ab)").err(), Some(PatternParseError::UnmatchedParenthesis));
+        assert_eq!(Pattern::regexp("[ab").err(), Some(PatternParseError::UnmatchedBracket));
+        assert_eq!(Pattern::regexp("*a").err(), Some(PatternParseError::NothingToRepeat));
+        assert_eq!(Pattern::regexp("a{3,2}").err(), Some(PatternParseError::InvalidRepetition));
+        assert_eq!(Pattern::regexp("[z-a]").err(), Some(PatternParseError::InvalidRange));
+    }
+}
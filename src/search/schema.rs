@@ -92,6 +92,10 @@ impl FieldInfo {
             field_flags: field_flags,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
@@ -1,20 +1,255 @@
 use roaring::RoaringBitmap;
+use byteorder::{ByteOrder, LittleEndian};
 
 use search::schema::FieldId;
 use search::term::TermId;
 use search::document::DocId;
+use search::doc_values::DocValues;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub struct SegmentId(pub u32);
 
+/// Generates the stored value type that holds the positions of a term in a field
+/// For example, the positions of TermId 12 are stored in the "pos12" value
+pub fn term_positions_value_type(term_id: TermId) -> Vec<u8> {
+    let mut value_type = vec![b'p', b'o', b's'];
+    value_type.extend(term_id.0.to_string().as_bytes());
+    value_type
+}
+
+/// Names the statistic that counts the documents that contain a term in a field
+pub fn term_doc_frequency_stat_name(field_id: u32, term_id: u32) -> Vec<u8> {
+    format!("tdf-{}-{}", field_id, term_id).into_bytes()
+}
+
+/// Names the statistic that counts the tokens indexed in a field
+pub fn total_field_tokens_stat_name(field_id: u32) -> Vec<u8> {
+    format!("fttok-{}", field_id).into_bytes()
+}
+
+/// Names the statistic that counts the documents that have a value in a field
+pub fn total_field_docs_stat_name(field_id: u32) -> Vec<u8> {
+    format!("ftdoc-{}", field_id).into_bytes()
+}
+
+/// Names the statistic that sums the document frequencies of every term in a field
+pub fn sum_doc_frequency_stat_name(field_id: u32) -> Vec<u8> {
+    format!("fsdf-{}", field_id).into_bytes()
+}
+
+/// Packs a list of token positions into bytes (a sequence of little endian u32s)
+pub fn encode_term_positions(positions: &[u32]) -> Vec<u8> {
+    let mut bytes = vec![0; positions.len() * 4];
+
+    for (i, position) in positions.iter().enumerate() {
+        LittleEndian::write_u32(&mut bytes[i * 4..], *position);
+    }
+
+    bytes
+}
+
+/// Unpacks a list of token positions that was packed by "encode_term_positions"
+pub fn decode_term_positions(bytes: &[u8]) -> Vec<u32> {
+    bytes.chunks(4).map(LittleEndian::read_u32).collect()
+}
+
+/// Packs a list of term frequencies into bytes (a sequence of little endian u32s)
+pub fn encode_term_frequencies(frequencies: &[u32]) -> Vec<u8> {
+    let mut bytes = vec![0; frequencies.len() * 4];
+
+    for (i, frequency) in frequencies.iter().enumerate() {
+        LittleEndian::write_u32(&mut bytes[i * 4..], *frequency);
+    }
+
+    bytes
+}
+
+/// Unpacks a list of term frequencies that was packed by "encode_term_frequencies"
+pub fn decode_term_frequencies(bytes: &[u8]) -> Vec<u32> {
+    bytes.chunks(4).map(LittleEndian::read_u32).collect()
+}
+
+/// Packs the number of tokens in a field into the single byte that is kept in the field's norms
+///
+/// Precision is lost for longer fields, which is fine as the length only slightly affects the score.
+pub fn encode_field_length(token_count: usize) -> u8 {
+    let length = ((token_count as f32).sqrt() - 1.0) * 3.0;
+
+    if length > 255.0 {
+        255
+    } else if length > 0.0 {
+        length as u8
+    } else {
+        0
+    }
+}
+
+/// Unpacks a field length that was packed by "encode_field_length"
+///
+/// Documents that don't have a norm for the field are read as 0, which gives a length of 1.
+pub fn decode_field_length(norm: u8) -> f32 {
+    let length_sqrt = (norm as f32) / 3.0 + 1.0;
+    length_sqrt * length_sqrt
+}
+
+/// The highest term frequency and the shortest field of the documents in a segment that contain a term
+///
+/// Scores go up with the term frequency and down with the length of the field, so scoring these
+/// together gives an upper bound for the score of the term in any document of the segment. The
+/// two values may come from different documents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermImpact {
+    pub max_term_frequency: u32,
+
+    /// The lowest packed field length (see "encode_field_length")
+    pub min_field_norm: u8,
+}
+
+impl TermImpact {
+    pub fn new(term_frequency: u32, field_norm: u8) -> TermImpact {
+        TermImpact {
+            max_term_frequency: term_frequency,
+            min_field_norm: field_norm,
+        }
+    }
+
+    /// Widens the impact to cover another document
+    pub fn add(&mut self, term_frequency: u32, field_norm: u8) {
+        if term_frequency > self.max_term_frequency {
+            self.max_term_frequency = term_frequency;
+        }
+
+        if field_norm < self.min_field_norm {
+            self.min_field_norm = field_norm;
+        }
+    }
+
+    /// Packs the impact into bytes (a little endian u32 followed by the norm)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; 5];
+        LittleEndian::write_u32(&mut bytes, self.max_term_frequency);
+        bytes[4] = self.min_field_norm;
+        bytes
+    }
+
+    /// Unpacks an impact that was packed by "to_bytes", returns None if the bytes are the wrong length
+    pub fn from_bytes(bytes: &[u8]) -> Option<TermImpact> {
+        if bytes.len() != 5 {
+            return None;
+        }
+
+        Some(TermImpact::new(LittleEndian::read_u32(bytes), bytes[4]))
+    }
+}
+
 pub trait Segment {
     fn load_statistic(&self, stat_name: &[u8]) -> Result<Option<i64>, String>;
-    fn load_stored_field_value_raw(&self, doc_local_id: u16, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String>;
+    fn load_stored_field_value_raw(&self, doc_local_id: u32, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String>;
     fn load_postings_list(&self, field_id: FieldId, term_id: TermId) -> Result<Option<RoaringBitmap>, String>;
+
+    /// Loads the frequency of a term in each document of its postings list, in the same order as the postings list
+    ///
+    /// Returns None if the term occurs once in every document, which is by far the most common case.
+    fn load_term_frequencies(&self, field_id: FieldId, term_id: TermId) -> Result<Option<Vec<u32>>, String>;
+
+    /// Loads the packed length of a field in each document, indexed by local id (see "encode_field_length")
+    ///
+    /// The list stops at the last document that has a value in the field.
+    fn load_field_norms(&self, field_id: FieldId) -> Result<Option<Vec<u8>>, String>;
+
+    /// Loads the impact of a term, which is used to skip documents that can't score highly enough
+    ///
+    /// Returns None if the term isn't in the segment, or if the segment was written before impacts were kept.
+    fn load_term_impact(&self, field_id: FieldId, term_id: TermId) -> Result<Option<TermImpact>, String>;
+
     fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String>;
+
+    /// Loads the doc values of a field. Returns None if none of the documents in the segment have a value for the field
+    fn load_doc_values(&self, field_id: FieldId) -> Result<Option<DocValues>, String>;
+
     fn id(&self) -> SegmentId;
 
-    fn doc_id(&self, local_id: u16) -> DocId {
+    fn doc_id(&self, local_id: u32) -> DocId {
         DocId(self.id(), local_id)
     }
+
+    /// Loads the positions of a term in a document's field, in ascending order
+    fn load_term_positions(&self, doc_local_id: u32, field_id: FieldId, term_id: TermId) -> Result<Option<Vec<u32>>, String> {
+        let value_type = term_positions_value_type(term_id);
+        let positions = try!(self.load_stored_field_value_raw(doc_local_id, field_id, &value_type));
+        Ok(positions.map(|positions| decode_term_positions(&positions)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use search::term::TermId;
+
+    use super::{term_positions_value_type, encode_term_positions, decode_term_positions};
+    use super::{encode_term_frequencies, decode_term_frequencies, encode_field_length, decode_field_length};
+    use super::{term_doc_frequency_stat_name, total_field_tokens_stat_name, total_field_docs_stat_name, sum_doc_frequency_stat_name};
+    use super::TermImpact;
+
+    #[test]
+    fn test_term_positions_value_type() {
+        assert_eq!(term_positions_value_type(TermId(12)), b"pos12".to_vec());
+    }
+
+    #[test]
+    fn test_stat_names() {
+        // These are stored on the disk, so they must not change
+        assert_eq!(term_doc_frequency_stat_name(1, 23), b"tdf-1-23".to_vec());
+        assert_eq!(total_field_tokens_stat_name(1), b"fttok-1".to_vec());
+        assert_eq!(total_field_docs_stat_name(1), b"ftdoc-1".to_vec());
+        assert_eq!(sum_doc_frequency_stat_name(1), b"fsdf-1".to_vec());
+    }
+
+    #[test]
+    fn test_encode_term_positions() {
+        assert_eq!(encode_term_positions(&[1, 256]), vec![1, 0, 0, 0, 0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_term_positions() {
+        assert_eq!(decode_term_positions(&encode_term_positions(&[1, 5, 70000])), vec![1, 5, 70000]);
+    }
+
+    #[test]
+    fn test_encode_term_frequencies() {
+        assert_eq!(decode_term_frequencies(&encode_term_frequencies(&[1, 2, 300])), vec![1, 2, 300]);
+    }
+
+    #[test]
+    fn test_encode_field_length() {
+        // These are stored on the disk, so they must not change
+        assert_eq!(encode_field_length(0), 0);
+        assert_eq!(encode_field_length(1), 0);
+        assert_eq!(encode_field_length(4), 3);
+        assert_eq!(encode_field_length(100), 27);
+        assert_eq!(encode_field_length(1000000), 255);
+    }
+
+    #[test]
+    fn test_decode_field_length() {
+        assert_eq!(decode_field_length(0), 1.0);
+        assert_eq!(decode_field_length(3), 4.0);
+        assert_eq!(decode_field_length(27), 100.0);
+    }
+
+    #[test]
+    fn test_term_impact_add() {
+        let mut impact = TermImpact::new(2, 10);
+        impact.add(1, 4);
+        impact.add(5, 12);
+
+        assert_eq!(impact, TermImpact::new(5, 4));
+    }
+
+    #[test]
+    fn test_encode_term_impact() {
+        let impact = TermImpact::new(300, 27);
+
+        assert_eq!(TermImpact::from_bytes(&impact.to_bytes()), Some(impact));
+        assert_eq!(TermImpact::from_bytes(&[1, 2, 3]), None);
+    }
 }
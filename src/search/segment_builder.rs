@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+
+use search::{Document, Term, TermId};
+use search::schema::{Schema, FieldId};
+use search::segment::{SegmentId, Segment, term_doc_frequency_stat_name, total_field_tokens_stat_name, total_field_docs_stat_name, sum_doc_frequency_stat_name, encode_field_length, TermImpact};
+use search::doc_values::{DocValues, DocValuesBuilder};
+use roaring::RoaringBitmap;
+use fnv::FnvHashMap;
+
+#[derive(Debug)]
+pub struct SegmentBuilder {
+    current_doc: u32,
+    pub term_dictionary: HashMap<Term, TermId>,
+    current_term_id: u32,
+    pub postings_lists: FnvHashMap<(FieldId, TermId), RoaringBitmap>,
+
+    /// The frequency of each term in the documents of its postings list, in the same order
+    pub term_frequencies: FnvHashMap<(FieldId, TermId), Vec<u32>>,
+
+    /// The packed length of each field, indexed by local document id
+    pub field_norms: FnvHashMap<FieldId, Vec<u8>>,
+
+    /// The highest term frequency and shortest field of the documents that contain each term
+    pub term_impacts: FnvHashMap<(FieldId, TermId), TermImpact>,
+
+    pub statistics: FnvHashMap<Vec<u8>, i64>,
+    pub stored_field_values: FnvHashMap<(FieldId, u32, Vec<u8>), Vec<u8>>,
+    pub term_positions: FnvHashMap<(FieldId, TermId, u32), Vec<u32>>,
+    pub doc_values: FnvHashMap<FieldId, DocValuesBuilder>,
+}
+
+#[derive(Debug)]
+pub enum DocumentInsertError {
+    /// Segment couldn't hold any more docs
+    SegmentFull,
+}
+
+impl SegmentBuilder {
+    pub fn new() -> SegmentBuilder {
+        SegmentBuilder {
+            current_doc: 0,
+            term_dictionary: HashMap::new(),
+            current_term_id: 0,
+            postings_lists: FnvHashMap::default(),
+            term_frequencies: FnvHashMap::default(),
+            field_norms: FnvHashMap::default(),
+            term_impacts: FnvHashMap::default(),
+            statistics: FnvHashMap::default(),
+            stored_field_values: FnvHashMap::default(),
+            term_positions: FnvHashMap::default(),
+            doc_values: FnvHashMap::default(),
+        }
+    }
+
+    fn get_term_id(&mut self, term: &Term) -> TermId {
+        if let Some(term_id) = self.term_dictionary.get(term) {
+            return *term_id;
+        }
+
+        // Add the term to the dictionary
+        let term_id = TermId(self.current_term_id);
+        self.current_term_id += 1;
+        self.term_dictionary.insert(term.clone(), term_id);
+
+        term_id
+    }
+
+    pub fn add_document(&mut self, doc: &Document, schema: &Schema) -> Result<u32, DocumentInsertError> {
+        // Get document ord
+        let doc_id = self.current_doc;
+        self.current_doc += 1;
+        try!(self.current_doc.checked_add(1).ok_or(DocumentInsertError::SegmentFull));
+
+        // Insert indexed fields
+        let mut term_frequencies = FnvHashMap::default();
+        for (field_id, tokens) in doc.indexed_fields.iter() {
+            let mut field_token_count = 0;
+            let mut field_term_frequencies = Vec::new();
+
+            for (term, positions) in tokens.iter() {
+                let frequency = positions.len();
+                field_token_count += frequency;
+
+                // Get term id
+                let term_id = self.get_term_id(term);
+
+                // Term frequency
+                let term_frequency = term_frequencies.entry(term_id).or_insert(0);
+                *term_frequency += frequency;
+
+                // Write postings list
+                self.postings_lists.entry((*field_id, term_id)).or_insert_with(RoaringBitmap::new).insert(doc_id as u32);
+
+                // Write term frequency
+                // Documents are added in order, so this lines up with the postings list
+                self.term_frequencies.entry((*field_id, term_id)).or_insert_with(Vec::new).push(frequency as u32);
+                field_term_frequencies.push((term_id, frequency as u32));
+
+                // Write term positions
+                // These are kept separately from the stored values as the TermIds need to be
+                // remapped into the index's term dictionary when the segment is written
+                self.term_positions.insert((*field_id, term_id, doc_id), positions.iter().collect());
+
+                // Increment term document frequency
+                {
+                    let stat_name = term_doc_frequency_stat_name(field_id.0, term_id.0);
+                    let stat = self.statistics.entry(stat_name).or_insert(0);
+                    *stat += 1;
+                }
+
+                // Increment the sum of term document frequencies in the field
+                {
+                    let stat_name = sum_doc_frequency_stat_name(field_id.0);
+                    let stat = self.statistics.entry(stat_name).or_insert(0);
+                    *stat += 1;
+                }
+            }
+
+            // Field length
+            // Used by the BM25 similarity model. Documents that don't have the field are given a norm of 0
+            let field_norm = encode_field_length(field_token_count);
+            let field_norms = self.field_norms.entry(*field_id).or_insert_with(Vec::new);
+            field_norms.resize(doc_id as usize, 0);
+            field_norms.push(field_norm);
+
+            // Term impacts
+            // These need the length of the field, so can only be updated once all of its terms have been counted
+            for (term_id, frequency) in field_term_frequencies {
+                self.term_impacts.entry((*field_id, term_id))
+                    .or_insert_with(|| TermImpact::new(frequency, field_norm))
+                    .add(frequency, field_norm);
+            }
+
+            // Increment total field docs
+            {
+                let stat_name = total_field_docs_stat_name(field_id.0);
+                let stat = self.statistics.entry(stat_name).or_insert(0);
+                *stat += 1;
+            }
+
+            // Increment total field tokens
+            {
+                let stat_name = total_field_tokens_stat_name(field_id.0);
+                let stat = self.statistics.entry(stat_name).or_insert(0);
+                *stat += field_token_count as i64;
+            }
+        }
+
+        // Insert stored fields
+        for (field, value) in doc.stored_fields.iter() {
+            self.stored_field_values.insert((*field, doc_id, b"val".to_vec()), value.to_bytes());
+
+            // Insert doc values
+            // These are a copy of the stored values of some fields that are kept in a column
+            // for each segment so they can be read quickly for sorting and aggregating
+            if !self.doc_values.contains_key(field) {
+                let builder = schema.get(field).and_then(|field_info| DocValuesBuilder::for_field_type(&field_info.field_type));
+
+                match builder {
+                    Some(builder) => {
+                        self.doc_values.insert(*field, builder);
+                    }
+                    None => continue,
+                }
+            }
+
+            self.doc_values.get_mut(field).unwrap().insert(doc_id, value);
+        }
+
+        // Increment total docs
+        {
+            let stat = self.statistics.entry(b"total_docs".to_vec()).or_insert(0);
+            *stat += 1;
+        }
+
+        Ok(doc_id)
+    }
+
+    /// Merges segments into a new one, leaving out the documents in their deletion lists
+    ///
+    /// The segments must use the same term dictionary. Documents keep their order and are packed
+    /// together, the documents of the first segment come first. Returns the merged segment along
+    /// with the new local id of each document in the source segments (None if it was deleted).
+    pub fn merge(segments: &[(&SegmentBuilder, Option<&RoaringBitmap>)]) -> Result<(SegmentBuilder, Vec<Vec<Option<u32>>>), DocumentInsertError> {
+        let mut merged = SegmentBuilder::new();
+
+        // Map the ids of the documents that haven't been deleted to their ids in the new segment
+        let mut doc_id_mappings = Vec::with_capacity(segments.len());
+        for &(segment, deletion_list) in segments.iter() {
+            let mut doc_id_mapping = Vec::with_capacity(segment.current_doc as usize);
+
+            for doc_id in 0..segment.current_doc {
+                if deletion_list.map_or(false, |deletion_list| deletion_list.contains(doc_id)) {
+                    doc_id_mapping.push(None);
+                } else {
+                    doc_id_mapping.push(Some(merged.current_doc));
+                    merged.current_doc = try!(merged.current_doc.checked_add(1).ok_or(DocumentInsertError::SegmentFull));
+                }
+            }
+
+            doc_id_mappings.push(doc_id_mapping);
+        }
+
+        for (&(segment, _), doc_id_mapping) in segments.iter().zip(doc_id_mappings.iter()) {
+            // Postings lists and term frequencies
+            // The new ids are in the same order as the old ones, so the term frequencies still line up
+            for (key, postings) in segment.postings_lists.iter() {
+                let term_frequencies = segment.term_frequencies.get(key);
+
+                for (i, doc_id) in postings.iter().enumerate() {
+                    if let Some(new_doc_id) = doc_id_mapping[doc_id as usize] {
+                        let term_frequency = term_frequencies.and_then(|term_frequencies| term_frequencies.get(i).cloned()).unwrap_or(1);
+
+                        merged.postings_lists.entry(*key).or_insert_with(RoaringBitmap::new).insert(new_doc_id);
+                        merged.term_frequencies.entry(*key).or_insert_with(Vec::new).push(term_frequency);
+                    }
+                }
+            }
+
+            // Field norms
+            for (field_id, field_norms) in segment.field_norms.iter() {
+                let merged_field_norms = merged.field_norms.entry(*field_id).or_insert_with(Vec::new);
+
+                for (doc_id, field_norm) in field_norms.iter().enumerate() {
+                    if let Some(new_doc_id) = doc_id_mapping[doc_id] {
+                        merged_field_norms.resize(new_doc_id as usize, 0);
+                        merged_field_norms.push(*field_norm);
+                    }
+                }
+            }
+
+            // Stored values and term positions
+            for (&(field_id, doc_id, ref value_type), value) in segment.stored_field_values.iter() {
+                if let Some(new_doc_id) = doc_id_mapping[doc_id as usize] {
+                    merged.stored_field_values.insert((field_id, new_doc_id, value_type.clone()), value.clone());
+                }
+            }
+
+            for (&(field_id, term_id, doc_id), positions) in segment.term_positions.iter() {
+                if let Some(new_doc_id) = doc_id_mapping[doc_id as usize] {
+                    merged.term_positions.insert((field_id, term_id, new_doc_id), positions.clone());
+                }
+            }
+
+            // Doc values
+            for (field_id, builder) in segment.doc_values.iter() {
+                let doc_values = builder.build();
+                let merged_builder = merged.doc_values.entry(*field_id).or_insert_with(|| DocValuesBuilder::like(&doc_values));
+
+                for doc_id in 0..doc_values.len() {
+                    if let Some(new_doc_id) = doc_id_mapping[doc_id] {
+                        merged_builder.insert_from(new_doc_id, &doc_values, doc_id as u32);
+                    }
+                }
+            }
+        }
+
+        // Work out the term impacts and statistics from the documents that were kept
+        // These can't be taken from the source segments as they include the deleted documents
+        let mut field_docs: FnvHashMap<FieldId, RoaringBitmap> = FnvHashMap::default();
+
+        for (&(field_id, term_id), postings) in merged.postings_lists.iter() {
+            let term_frequencies = &merged.term_frequencies[&(field_id, term_id)];
+            let field_norms = merged.field_norms.get(&field_id);
+
+            let mut impact: Option<TermImpact> = None;
+            for (doc_id, term_frequency) in postings.iter().zip(term_frequencies.iter()) {
+                let field_norm = field_norms.and_then(|field_norms| field_norms.get(doc_id as usize)).cloned().unwrap_or(0);
+
+                match impact {
+                    Some(ref mut impact) => impact.add(*term_frequency, field_norm),
+                    None => impact = Some(TermImpact::new(*term_frequency, field_norm)),
+                }
+            }
+
+            if let Some(impact) = impact {
+                merged.term_impacts.insert((field_id, term_id), impact);
+            }
+
+            let doc_frequency = postings.len() as i64;
+            let field_tokens = term_frequencies.iter().map(|term_frequency| *term_frequency as i64).sum::<i64>();
+            merged.statistics.insert(term_doc_frequency_stat_name(field_id.0, term_id.0), doc_frequency);
+            *merged.statistics.entry(sum_doc_frequency_stat_name(field_id.0)).or_insert(0) += doc_frequency;
+            *merged.statistics.entry(total_field_tokens_stat_name(field_id.0)).or_insert(0) += field_tokens;
+            field_docs.entry(field_id).or_insert_with(RoaringBitmap::new).union_with(postings);
+        }
+
+        for (field_id, docs) in field_docs {
+            merged.statistics.insert(total_field_docs_stat_name(field_id.0), docs.len() as i64);
+        }
+
+        merged.statistics.insert(b"total_docs".to_vec(), merged.current_doc as i64);
+
+        Ok((merged, doc_id_mappings))
+    }
+}
+
+impl Segment for SegmentBuilder {
+    fn id(&self) -> SegmentId {
+        SegmentId(0)
+    }
+
+    fn load_statistic(&self, stat_name: &[u8]) -> Result<Option<i64>, String> {
+        Ok(self.statistics.get(stat_name).cloned())
+    }
+
+    fn load_stored_field_value_raw(&self, doc_local_id: u32, field_id: FieldId, value_type: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.stored_field_values.get(&(field_id, doc_local_id, value_type.to_vec())).cloned())
+    }
+
+    fn load_postings_list(&self, field_id: FieldId, term_id: TermId) -> Result<Option<RoaringBitmap>, String> {
+        Ok(self.postings_lists.get(&(field_id, term_id)).cloned())
+    }
+
+    fn load_term_frequencies(&self, field_id: FieldId, term_id: TermId) -> Result<Option<Vec<u32>>, String> {
+        Ok(self.term_frequencies.get(&(field_id, term_id)).cloned())
+    }
+
+    fn load_field_norms(&self, field_id: FieldId) -> Result<Option<Vec<u8>>, String> {
+        Ok(self.field_norms.get(&field_id).cloned())
+    }
+
+    fn load_term_impact(&self, field_id: FieldId, term_id: TermId) -> Result<Option<TermImpact>, String> {
+        Ok(self.term_impacts.get(&(field_id, term_id)).cloned())
+    }
+
+    fn load_deletion_list(&self) -> Result<Option<RoaringBitmap>, String> {
+        Ok(None)
+    }
+
+    fn load_doc_values(&self, field_id: FieldId) -> Result<Option<DocValues>, String> {
+        Ok(self.doc_values.get(&field_id).map(|builder| builder.build()))
+    }
+
+    fn load_term_positions(&self, doc_local_id: u32, field_id: FieldId, term_id: TermId) -> Result<Option<Vec<u32>>, String> {
+        Ok(self.term_positions.get(&(field_id, term_id, doc_local_id)).cloned())
+    }
+}
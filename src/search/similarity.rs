@@ -1,3 +1,5 @@
+use search::explanation::Explanation;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum SimilarityModel {
     TfIdf,
@@ -34,6 +36,45 @@ impl SimilarityModel {
             }
         }
     }
+
+    /// Breaks down the score into its components, the value of the explanation is the same as "score"
+    pub fn explain(&self, term_frequency: u32, length: f32, total_tokens: u64, total_docs: u64, total_docs_with_term: u64) -> Explanation {
+        let tf_explanation = Explanation::with_details(tf(term_frequency), "tf, computed as log(freq + 1) + 1 from:", vec![
+            Explanation::new(term_frequency as f32, "freq, occurrences of term within document"),
+        ]);
+
+        let idf_explanation = Explanation::with_details(idf(total_docs_with_term, total_docs), "idf, computed as log((docCount + 1) / (docFreq + 1)) + 1 from:", vec![
+            Explanation::new(total_docs_with_term as f32, "docFreq"),
+            Explanation::new(total_docs as f32, "docCount"),
+        ]);
+
+        let score = self.score(term_frequency, length, total_tokens, total_docs, total_docs_with_term);
+
+        match *self {
+            SimilarityModel::TfIdf => {
+                Explanation::with_details(score, "score, computed as tf * idf from:", vec![
+                    tf_explanation,
+                    idf_explanation,
+                ])
+            }
+            SimilarityModel::Bm25{k1, b} => {
+                let average_length = (total_tokens as f32 + 1.0f32) / (total_docs as f32 + 1.0f32);
+                let tf = tf_explanation.value;
+                let tf_norm = (k1 + 1.0) * (tf / (tf + (k1 * ((1.0 - b) + b * length.sqrt() / average_length.sqrt())) + 1.0f32));
+
+                Explanation::with_details(score, "score, computed as idf * tfNorm from:", vec![
+                    idf_explanation,
+                    Explanation::with_details(tf_norm, "tfNorm, computed as (k1 + 1) * tf / (tf + k1 * (1 - b + b * sqrt(fieldLength) / sqrt(avgFieldLength)) + 1) from:", vec![
+                        tf_explanation,
+                        Explanation::new(k1, "parameter k1"),
+                        Explanation::new(b, "parameter b"),
+                        Explanation::new(length, "fieldLength"),
+                        Explanation::new(average_length, "avgFieldLength, computed as (totalTokens + 1) / (docCount + 1)"),
+                    ]),
+                ])
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -75,6 +116,16 @@ mod tests {
         assert!(similarity.score(0, 0.0, 0, 0, 0).is_finite());
     }
 
+    #[test]
+    fn test_tf_idf_explain() {
+        let similarity = SimilarityModel::TfIdf;
+        let explanation = similarity.explain(2, 40.0, 100, 10, 5);
+
+        assert_eq!(explanation.value, similarity.score(2, 40.0, 100, 10, 5));
+        assert_eq!(explanation.details.len(), 2);
+        assert_eq!(explanation.details[0].value * explanation.details[1].value, explanation.value);
+    }
+
     #[test]
     fn test_bm25_higher_term_freq_increases_score() {
         let similarity = SimilarityModel::Bm25 {
@@ -115,6 +166,22 @@ mod tests {
         assert!(similarity.score(1, 40.0, 1000, 20, 5) > similarity.score(1, 40.0, 100, 20, 5));
     }
 
+    #[test]
+    fn test_bm25_explain() {
+        let similarity = SimilarityModel::Bm25 {
+            k1: 1.2,
+            b: 0.75,
+        };
+        let explanation = similarity.explain(2, 40.0, 100, 10, 5);
+
+        assert_eq!(explanation.value, similarity.score(2, 40.0, 100, 10, 5));
+
+        // idf * tfNorm
+        let idf = explanation.details[0].value;
+        let tf_norm = explanation.details[1].value;
+        assert!((idf * tf_norm - explanation.value).abs() < 0.0001);
+    }
+
     #[test]
     fn test_bm25_handles_zeros() {
         let similarity = SimilarityModel::Bm25 {
@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc, Timelike};
-use byteorder::{WriteBytesExt, LittleEndian};
+use byteorder::{WriteBytesExt, BigEndian};
 
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
@@ -32,19 +32,21 @@ impl Term {
         }
     }
 
+    /// Integers are encoded so the byte order of the terms matches their numeric order
+    /// This is done by flipping the sign bit (so negative numbers come first) and writing
+    /// the result as big endian. This allows range queries to compare the terms' bytes.
     pub fn from_integer(value: i64) -> Term {
         let mut bytes = Vec::with_capacity(8);
-        bytes.write_i64::<LittleEndian>(value).unwrap();
+        bytes.write_u64::<BigEndian>((value as u64) ^ 0x8000000000000000).unwrap();
         Term(bytes)
     }
 
+    /// Datetimes are encoded as integers (microseconds since the UNIX epoch)
     pub fn from_datetime(value: &DateTime<Utc>) -> Term {
-        let mut bytes = Vec::with_capacity(0);
         let timestamp = value.timestamp();
         let micros = value.nanosecond() / 1000;
         let timestamp_with_micros = timestamp * 1000000 + micros as i64;
-        bytes.write_i64::<LittleEndian>(timestamp_with_micros).unwrap();
-        Term(bytes)
+        Term::from_integer(timestamp_with_micros)
     }
 
     pub fn as_bytes(&self) -> &[u8] {
@@ -98,14 +100,23 @@ mod tests {
     fn test_integer_to_bytes() {
         let term = Term::from_integer(123);
 
-        assert_eq!(term.as_bytes().to_vec(), vec![123, 0, 0, 0, 0, 0, 0, 0])
+        assert_eq!(term.as_bytes().to_vec(), vec![128, 0, 0, 0, 0, 0, 0, 123])
     }
 
     #[test]
     fn test_negative_integer_to_bytes() {
         let term = Term::from_integer(-123);
 
-        assert_eq!(term.as_bytes().to_vec(), vec![133, 255, 255, 255, 255, 255, 255, 255])
+        assert_eq!(term.as_bytes().to_vec(), vec![127, 255, 255, 255, 255, 255, 255, 133])
+    }
+
+    #[test]
+    fn test_integer_byte_order_matches_numeric_order() {
+        let values = vec![i64::min_value(), -1000, -1, 0, 1, 255, 256, 1000, i64::max_value()];
+
+        for pair in values.windows(2) {
+            assert!(Term::from_integer(pair[0]) < Term::from_integer(pair[1]));
+        }
     }
 
     #[test]
@@ -113,7 +124,7 @@ mod tests {
         let date = "2016-07-23T16:15:00+01:00".parse::<DateTime<Utc>>().unwrap();
         let term = Term::from_datetime(&date);
 
-        assert_eq!(term.as_bytes().to_vec(), vec![0, 101, 191, 3, 79, 56, 5, 0])
+        assert_eq!(term.as_bytes().to_vec(), vec![128, 5, 56, 79, 3, 191, 101, 0])
     }
 
     #[test]
@@ -123,7 +134,7 @@ mod tests {
         let term = Term::from_datetime(&date);
 
         // This is exactly 123123 higher than the result of "test_datetime_to_bytes"
-        assert_eq!(term.as_bytes().to_vec(), vec![243, 69, 193, 3, 79, 56, 5, 0])
+        assert_eq!(term.as_bytes().to_vec(), vec![128, 5, 56, 79, 3, 193, 69, 243])
     }
 
     #[test]
@@ -132,6 +143,6 @@ mod tests {
         let term = Term::from_datetime(&date);
 
         // This is exactly 3_600_000_000 lower than the result of "test_datetime_to_bytes"
-        assert_eq!(term.as_bytes().to_vec(), vec![0, 193, 43, 45, 78, 56, 5, 0])
+        assert_eq!(term.as_bytes().to_vec(), vec![128, 5, 56, 78, 45, 43, 193, 0])
     }
 }
@@ -0,0 +1,412 @@
+//! Selects the terms in a dictionary that are matched by a multi term query
+//!
+//! Backends walk their term dictionaries through a cursor, this lets prefix, range, fuzzy and
+//! pattern queries skip over terms that can't match instead of checking every term.
+
+use std::str;
+use std::cmp;
+use std::ops::Bound;
+use std::collections::BTreeMap;
+
+use search::{Term, TermId};
+use search::query::multi_term_selector::{MultiTermSelector, split_at_char};
+use search::query::automaton::Automaton;
+use search::query::levenshtein::LevenshteinAutomaton;
+use search::query::pattern::Pattern;
+
+
+/// Walks over the terms of a dictionary in sorted order
+pub trait TermCursor {
+    /// Moves to the first term that is greater than or equal to the given bytes
+    fn seek(&mut self, term: &[u8]);
+
+    /// Moves to the next term
+    fn next(&mut self);
+
+    /// Returns the term at the cursor, or None if the cursor has walked off the end of the dictionary
+    fn current(&self) -> Option<(Term, TermId)>;
+}
+
+
+/// Walks over an in-memory term dictionary
+pub struct MapTermCursor<'a> {
+    terms: &'a BTreeMap<Term, TermId>,
+    current: Option<(Term, TermId)>,
+}
+
+impl<'a> MapTermCursor<'a> {
+    pub fn new(terms: &'a BTreeMap<Term, TermId>) -> MapTermCursor<'a> {
+        MapTermCursor {
+            terms: terms,
+            current: None,
+        }
+    }
+}
+
+impl<'a> TermCursor for MapTermCursor<'a> {
+    fn seek(&mut self, term: &[u8]) {
+        self.current = self.terms.range((Bound::Included(Term::from_bytes(term)), Bound::Unbounded))
+            .next()
+            .map(|(term, term_id)| (term.clone(), *term_id));
+    }
+
+    fn next(&mut self) {
+        if let Some((term, _term_id)) = self.current.take() {
+            self.current = self.terms.range((Bound::Excluded(term), Bound::Unbounded))
+                .next()
+                .map(|(term, term_id)| (term.clone(), *term_id));
+        }
+    }
+
+    fn current(&self) -> Option<(Term, TermId)> {
+        self.current.clone()
+    }
+}
+
+
+/// Stops walking a dictionary once the search that's using it should stop
+///
+/// The check is run before the cursor is moved. Once it returns true the cursor acts as though it
+/// has walked off the end of the dictionary, so the terms that were selected before then are kept.
+pub struct TerminatingTermCursor<'a, C: TermCursor> {
+    cursor: C,
+    should_terminate: &'a mut FnMut() -> bool,
+    terminated: bool,
+}
+
+impl<'a, C: TermCursor> TerminatingTermCursor<'a, C> {
+    pub fn new(cursor: C, should_terminate: &'a mut FnMut() -> bool) -> TerminatingTermCursor<'a, C> {
+        TerminatingTermCursor {
+            cursor: cursor,
+            should_terminate: should_terminate,
+            terminated: false,
+        }
+    }
+
+    fn check_termination(&mut self) -> bool {
+        if !self.terminated && (self.should_terminate)() {
+            self.terminated = true;
+        }
+
+        self.terminated
+    }
+}
+
+impl<'a, C: TermCursor> TermCursor for TerminatingTermCursor<'a, C> {
+    fn seek(&mut self, term: &[u8]) {
+        if !self.check_termination() {
+            self.cursor.seek(term);
+        }
+    }
+
+    fn next(&mut self) {
+        if !self.check_termination() {
+            self.cursor.next();
+        }
+    }
+
+    fn current(&self) -> Option<(Term, TermId)> {
+        if self.terminated {
+            return None;
+        }
+
+        self.cursor.current()
+    }
+}
+
+
+/// Finds the terms which match the selector
+pub fn select_terms<C: TermCursor>(cursor: &mut C, term_selector: &MultiTermSelector) -> Vec<TermId> {
+    match *term_selector {
+        MultiTermSelector::Prefix(ref prefix) => select_prefix(cursor, prefix),
+        MultiTermSelector::Range{ref lower, ref upper} => select_range(cursor, lower, upper),
+        MultiTermSelector::Fuzzy{ref term, max_edits, prefix_length, transpositions, max_expansions} => {
+            select_fuzzy(cursor, term, max_edits, prefix_length, transpositions, max_expansions)
+        }
+        MultiTermSelector::Wildcard(ref wildcard) => select_pattern(cursor, &Pattern::wildcard(wildcard)),
+        MultiTermSelector::Regexp(ref regexp) => {
+            match Pattern::regexp(regexp) {
+                Ok(pattern) => select_pattern(cursor, &pattern),
+                Err(_) => Vec::new(),
+            }
+        }
+    }
+}
+
+
+/// Returns the smallest term that is greater than all terms starting with the given bytes
+///
+/// Returns None if there isn't one (all the bytes are 0xFF)
+fn prefix_successor(prefix: &[u8]) -> Option<Term> {
+    let mut bytes = prefix.to_vec();
+
+    while let Some(last) = bytes.pop() {
+        if last != 0xFF {
+            bytes.push(last + 1);
+            return Some(Term::from_bytes(&bytes));
+        }
+    }
+
+    None
+}
+
+
+/// Finds terms that start with the given prefix
+fn select_prefix<C: TermCursor>(cursor: &mut C, prefix: &str) -> Vec<TermId> {
+    let mut selected = Vec::new();
+
+    cursor.seek(prefix.as_bytes());
+    while let Some((term, term_id)) = cursor.current() {
+        if !term.as_bytes().starts_with(prefix.as_bytes()) {
+            break;
+        }
+
+        selected.push(term_id);
+        cursor.next();
+    }
+
+    selected
+}
+
+
+/// Finds terms that fall between the two bounds
+fn select_range<C: TermCursor>(cursor: &mut C, lower: &Bound<Term>, upper: &Bound<Term>) -> Vec<TermId> {
+    let mut selected = Vec::new();
+
+    match *lower {
+        Bound::Included(ref lower) | Bound::Excluded(ref lower) => cursor.seek(lower.as_bytes()),
+        Bound::Unbounded => cursor.seek(b""),
+    }
+
+    while let Some((term, term_id)) = cursor.current() {
+        let below_upper = match *upper {
+            Bound::Included(ref upper) => term <= *upper,
+            Bound::Excluded(ref upper) => term < *upper,
+            Bound::Unbounded => true,
+        };
+
+        if !below_upper {
+            break;
+        }
+
+        let above_lower = match *lower {
+            Bound::Excluded(ref lower) => term > *lower,
+            _ => true,
+        };
+
+        if above_lower {
+            selected.push(term_id);
+        }
+
+        cursor.next();
+    }
+
+    selected
+}
+
+
+/// Walks the terms that start with "prefix" in order, feeding the rest of each term into an automaton
+///
+/// Rather than checking every term, terms that share a prefix with the previous term reuse the
+/// automaton states for that prefix, and when the automaton can no longer match the cursor seeks
+/// past all terms starting with the current prefix.
+///
+/// Returns the terms that the automaton accepts, along with the final state for each one.
+fn walk_automaton<C: TermCursor, A: Automaton>(cursor: &mut C, prefix: &str, automaton: &A, start: A::State) -> Vec<(Term, TermId, A::State)> {
+    let mut matches = Vec::new();
+
+    // The automaton state after each character of the previous term's suffix
+    // The first state is the start state
+    let mut states = vec![start];
+    let mut previous_chars: Vec<char> = Vec::new();
+
+    cursor.seek(prefix.as_bytes());
+    while let Some((candidate, term_id)) = cursor.current() {
+        if !candidate.as_bytes().starts_with(prefix.as_bytes()) {
+            // Walked past all the terms with the prefix
+            break;
+        }
+
+        let chars = match str::from_utf8(&candidate.as_bytes()[prefix.len()..]) {
+            Ok(candidate_suffix) => candidate_suffix.chars().collect::<Vec<char>>(),
+            Err(_) => {
+                cursor.next();
+                continue;
+            }
+        };
+
+        // Reuse the states of the characters this term shares with the previous one
+        let shared = chars.iter().zip(previous_chars.iter()).take_while(|&(a, b)| a == b).count();
+        states.truncate(cmp::min(shared, states.len() - 1) + 1);
+
+        let mut dead = false;
+        for c in chars[states.len() - 1..].iter() {
+            let state = automaton.step(states.last().unwrap(), *c);
+
+            if !automaton.can_match(&state) {
+                dead = true;
+                break;
+            }
+
+            states.push(state);
+        }
+
+        if dead {
+            // No term starting with these characters can match, skip to the next term after them
+            let mut dead_prefix = prefix.as_bytes().to_vec();
+            for c in chars[..states.len()].iter() {
+                let mut buffer = String::new();
+                buffer.push(*c);
+                dead_prefix.extend(buffer.as_bytes());
+            }
+
+            previous_chars = chars;
+            match prefix_successor(&dead_prefix) {
+                Some(successor) => cursor.seek(successor.as_bytes()),
+                None => break,
+            }
+            continue;
+        }
+
+        if automaton.is_match(states.last().unwrap()) {
+            matches.push((candidate, term_id, states.last().unwrap().clone()));
+        }
+
+        previous_chars = chars;
+        cursor.next();
+    }
+
+    matches
+}
+
+
+/// Finds terms within an edit distance of a fuzzy term
+fn select_fuzzy<C: TermCursor>(cursor: &mut C, term: &str, max_edits: u32, prefix_length: u32, transpositions: bool, max_expansions: usize) -> Vec<TermId> {
+    let (prefix, suffix) = split_at_char(term, prefix_length as usize);
+    let automaton = LevenshteinAutomaton::new(suffix, max_edits, transpositions);
+
+    let mut matches = walk_automaton(cursor, prefix, &automaton, automaton.start()).into_iter()
+        .map(|(candidate, term_id, state)| (automaton.distance(&state).unwrap(), candidate, term_id))
+        .collect::<Vec<_>>();
+
+    // Keep the closest terms
+    matches.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+    matches.truncate(max_expansions);
+
+    matches.into_iter().map(|(_distance, _term, term_id)| term_id).collect()
+}
+
+
+/// Finds terms that match a wildcard or regexp pattern
+///
+/// Only terms starting with the pattern's literal prefix are walked.
+fn select_pattern<C: TermCursor>(cursor: &mut C, pattern: &Pattern) -> Vec<TermId> {
+    let prefix = pattern.literal_prefix();
+    let start = prefix.chars().fold(pattern.start(), |state, c| pattern.step(&state, c));
+
+    walk_automaton(cursor, prefix, pattern, start).into_iter()
+        .map(|(_term, term_id, _state)| term_id)
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::ops::Bound;
+
+    use search::{Term, TermId};
+    use search::query::pattern::Pattern;
+
+    use super::{MapTermCursor, TerminatingTermCursor, select_prefix, select_range, select_fuzzy, select_pattern};
+
+    fn make_terms(terms: &[&str]) -> BTreeMap<Term, TermId> {
+        terms.iter().enumerate().map(|(i, term)| (Term::from_string(term), TermId(i as u32))).collect()
+    }
+
+    #[test]
+    fn test_select_fuzzy() {
+        let terms = make_terms(&["hallo", "hello", "help", "helo", "hxxxx", "world", "yello"]);
+
+        let mut selected = select_fuzzy(&mut MapTermCursor::new(&terms), "hello", 1, 0, true, 50);
+        selected.sort_by_key(|term_id| term_id.0);
+
+        assert_eq!(selected, vec![TermId(0), TermId(1), TermId(3), TermId(6)]);
+    }
+
+    #[test]
+    fn test_select_fuzzy_prefix_length() {
+        let terms = make_terms(&["hallo", "hello", "jello", "yello"]);
+
+        let mut selected = select_fuzzy(&mut MapTermCursor::new(&terms), "hello", 1, 1, true, 50);
+        selected.sort_by_key(|term_id| term_id.0);
+
+        assert_eq!(selected, vec![TermId(0), TermId(1)]);
+    }
+
+    #[test]
+    fn test_select_fuzzy_max_expansions() {
+        let terms = make_terms(&["hallo", "hello", "hellp", "yello"]);
+
+        // The exact match should be selected first, then the rest in term order
+        let selected = select_fuzzy(&mut MapTermCursor::new(&terms), "hello", 1, 0, true, 2);
+
+        assert_eq!(selected, vec![TermId(1), TermId(0)]);
+    }
+
+    #[test]
+    fn test_select_prefix() {
+        let terms = make_terms(&["hell", "hello", "help", "helpful", "world"]);
+
+        let selected = select_prefix(&mut MapTermCursor::new(&terms), "help");
+
+        assert_eq!(selected, vec![TermId(2), TermId(3)]);
+    }
+
+    #[test]
+    fn test_select_wildcard() {
+        let terms = make_terms(&["hall", "hello", "help", "helpful", "world"]);
+
+        let selected = select_pattern(&mut MapTermCursor::new(&terms), &Pattern::wildcard("he*l?"));
+
+        assert_eq!(selected, vec![TermId(1), TermId(2)]);
+    }
+
+    #[test]
+    fn test_select_regexp() {
+        let terms = make_terms(&["hall", "hello", "help", "helpful", "world"]);
+
+        let selected = select_pattern(&mut MapTermCursor::new(&terms), &Pattern::regexp("h(a|e)l+(o|p)?").unwrap());
+
+        assert_eq!(selected, vec![TermId(0), TermId(1), TermId(2)]);
+    }
+
+    #[test]
+    fn test_select_range() {
+        let terms = make_terms(&["a", "b", "c", "d", "e"]);
+
+        let selected = select_range(&mut MapTermCursor::new(&terms), &Bound::Excluded(Term::from_string("b")), &Bound::Included(Term::from_string("d")));
+        assert_eq!(selected, vec![TermId(2), TermId(3)]);
+
+        let selected = select_range(&mut MapTermCursor::new(&terms), &Bound::Unbounded, &Bound::Excluded(Term::from_string("c")));
+        assert_eq!(selected, vec![TermId(0), TermId(1)]);
+    }
+
+    #[test]
+    fn test_select_prefix_terminated() {
+        let terms = make_terms(&["hallo", "hello", "help", "helo", "world"]);
+
+        // The check is run before the cursor seeks to the first term and before each move after that
+        let mut checks = 0;
+        let selected = select_prefix(&mut TerminatingTermCursor::new(MapTermCursor::new(&terms), &mut || {
+            checks += 1;
+            checks > 2
+        }), "hel");
+
+        assert_eq!(selected, vec![TermId(1), TermId(3)]);
+        assert_eq!(checks, 3);
+
+        let selected = select_prefix(&mut TerminatingTermCursor::new(MapTermCursor::new(&terms), &mut || true), "hel");
+        assert!(selected.is_empty());
+    }
+}
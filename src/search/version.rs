@@ -0,0 +1,218 @@
+//! Document versions and the conditions that writes can place on them
+//!
+//! Each write to a document gives it a new version and a new sequence number. Clients can make
+//! a write depend on the version that they last read, so concurrent writers can't overwrite each
+//! other's changes without noticing.
+
+use std::fmt;
+
+/// The primary term of every write
+///
+/// Primary terms count the times that the primary copy of a shard has moved to another node.
+/// Indices only ever have a single copy, so this never changes.
+pub const PRIMARY_TERM: u64 = 1;
+
+/// The version of a document, this changes every time the document is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DocumentVersion {
+    /// Counts the writes to the document, or is set by the client when using external versioning
+    pub version: u64,
+
+    /// The position of the write in the index's sequence of writes
+    pub seq_no: u64,
+
+    pub primary_term: u64,
+}
+
+/// A condition that the current version of a document must meet for a write to go ahead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VersionCondition {
+    /// The document must exist and be at this version
+    Internal(u64),
+
+    /// The write sets the version of the document, this must be higher than its current version
+    External(u64),
+
+    /// Like External, but the version may also be equal to the current version
+    ExternalGte(u64),
+
+    /// The document must exist and have last been written with this sequence number and primary term
+    SeqNo { seq_no: u64, primary_term: u64 },
+
+    /// The document must not exist, this is used by writes that create documents
+    MustNotExist,
+}
+
+/// The current version of a document didn't meet the condition of a write
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionConflict {
+    pub condition: VersionCondition,
+
+    /// The current version of the document, None if it doesn't exist
+    pub current: Option<DocumentVersion>,
+
+    /// The version of the document's deletion, if it doesn't exist because it was deleted
+    pub deleted: Option<DocumentVersion>,
+}
+
+impl fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.condition, self.current) {
+            (VersionCondition::Internal(version), Some(current)) => {
+                write!(f, "version conflict, current version [{}] is different than the one provided [{}]", current.version, version)
+            }
+            (VersionCondition::Internal(version), None) => {
+                write!(f, "version conflict, document does not exist (expected version [{}])", version)
+            }
+            (VersionCondition::External(version), current) => {
+                write!(f, "version conflict, current version [{}] is higher or equal to the one provided [{}]", current.or(self.deleted).map(|current| current.version).unwrap_or(0), version)
+            }
+            (VersionCondition::ExternalGte(version), current) => {
+                write!(f, "version conflict, current version [{}] is higher than the one provided [{}]", current.or(self.deleted).map(|current| current.version).unwrap_or(0), version)
+            }
+            (VersionCondition::SeqNo { seq_no, primary_term }, Some(current)) => {
+                write!(f, "version conflict, required seqNo [{}], primary term [{}]. current document has seqNo [{}] and primary term [{}]", seq_no, primary_term, current.seq_no, current.primary_term)
+            }
+            (VersionCondition::SeqNo { seq_no, primary_term }, None) => {
+                write!(f, "version conflict, required seqNo [{}], primary term [{}]. but no document was found", seq_no, primary_term)
+            }
+            (VersionCondition::MustNotExist, current) => {
+                write!(f, "version conflict, document already exists (current version [{}])", current.map(|current| current.version).unwrap_or(0))
+            }
+        }
+    }
+}
+
+/// Works out the version that a write gives a document
+///
+/// "current" is the version of the document before the write, or None if it doesn't exist. Returns
+/// a conflict if the document doesn't meet the condition.
+///
+/// "deleted" is the version of the document's deletion if it was deleted. A deleted document
+/// doesn't exist, but its versions carry on from the deletion so writes with an external version
+/// from before the deletion can't bring it back.
+pub fn next_version(current: Option<DocumentVersion>, deleted: Option<DocumentVersion>, condition: Option<VersionCondition>) -> Result<u64, VersionConflict> {
+    let current_version = current.or(deleted).map(|current| current.version);
+
+    let condition_met = match condition {
+        None => true,
+        Some(VersionCondition::Internal(version)) => current.map(|current| current.version) == Some(version),
+        Some(VersionCondition::External(version)) => current_version.map_or(true, |current_version| version > current_version),
+        Some(VersionCondition::ExternalGte(version)) => current_version.map_or(true, |current_version| version >= current_version),
+        Some(VersionCondition::SeqNo { seq_no, primary_term }) => {
+            current.map_or(false, |current| current.seq_no == seq_no && current.primary_term == primary_term)
+        }
+        Some(VersionCondition::MustNotExist) => current.is_none(),
+    };
+
+    if !condition_met {
+        return Err(VersionConflict {
+            condition: condition.unwrap(),
+            current: current,
+            deleted: if current.is_none() { deleted } else { None },
+        });
+    }
+
+    match condition {
+        Some(VersionCondition::External(version)) | Some(VersionCondition::ExternalGte(version)) => Ok(version),
+        _ => Ok(current_version.unwrap_or(0) + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DocumentVersion, VersionCondition, VersionConflict, next_version, PRIMARY_TERM};
+
+    fn version(version: u64, seq_no: u64) -> Option<DocumentVersion> {
+        Some(DocumentVersion {
+            version: version,
+            seq_no: seq_no,
+            primary_term: PRIMARY_TERM,
+        })
+    }
+
+    #[test]
+    fn test_no_condition() {
+        assert_eq!(next_version(None, None, None), Ok(1));
+        assert_eq!(next_version(version(3, 10), None, None), Ok(4));
+    }
+
+    #[test]
+    fn test_internal() {
+        assert_eq!(next_version(version(3, 10), None, Some(VersionCondition::Internal(3))), Ok(4));
+
+        assert_eq!(next_version(version(3, 10), None, Some(VersionCondition::Internal(2))), Err(VersionConflict {
+            condition: VersionCondition::Internal(2),
+            current: version(3, 10),
+            deleted: None,
+        }));
+
+        // The document must exist
+        assert!(next_version(None, None, Some(VersionCondition::Internal(1))).is_err());
+    }
+
+    #[test]
+    fn test_external() {
+        assert_eq!(next_version(None, None, Some(VersionCondition::External(5))), Ok(5));
+        assert_eq!(next_version(version(3, 10), None, Some(VersionCondition::External(5))), Ok(5));
+        assert!(next_version(version(5, 10), None, Some(VersionCondition::External(5))).is_err());
+        assert!(next_version(version(6, 10), None, Some(VersionCondition::External(5))).is_err());
+    }
+
+    #[test]
+    fn test_external_gte() {
+        assert_eq!(next_version(version(5, 10), None, Some(VersionCondition::ExternalGte(5))), Ok(5));
+        assert!(next_version(version(6, 10), None, Some(VersionCondition::ExternalGte(5))).is_err());
+    }
+
+    #[test]
+    fn test_seq_no() {
+        let condition = Some(VersionCondition::SeqNo { seq_no: 10, primary_term: PRIMARY_TERM });
+
+        assert_eq!(next_version(version(3, 10), None, condition), Ok(4));
+        assert!(next_version(version(3, 11), None, condition).is_err());
+        assert!(next_version(None, None, condition).is_err());
+
+        let wrong_term = Some(VersionCondition::SeqNo { seq_no: 10, primary_term: PRIMARY_TERM + 1 });
+        assert!(next_version(version(3, 10), None, wrong_term).is_err());
+    }
+
+    #[test]
+    fn test_must_not_exist() {
+        let condition = Some(VersionCondition::MustNotExist);
+
+        assert_eq!(next_version(None, None, condition), Ok(1));
+        assert!(next_version(version(3, 10), None, condition).is_err());
+
+        // Deleted documents can be created again
+        assert_eq!(next_version(None, version(5, 10), condition), Ok(6));
+
+        let conflict = next_version(version(3, 10), None, condition).unwrap_err();
+        assert_eq!(conflict.to_string(), "version conflict, document already exists (current version [3])");
+    }
+
+    #[test]
+    fn test_deleted() {
+        // Versions carry on from the deletion
+        assert_eq!(next_version(None, version(5, 10), None), Ok(6));
+        assert_eq!(next_version(None, version(5, 10), Some(VersionCondition::External(6))), Ok(6));
+
+        // External versions from before the deletion conflict
+        assert_eq!(next_version(None, version(5, 10), Some(VersionCondition::External(3))), Err(VersionConflict {
+            condition: VersionCondition::External(3),
+            current: None,
+            deleted: version(5, 10),
+        }));
+        assert!(next_version(None, version(5, 10), Some(VersionCondition::ExternalGte(4))).is_err());
+
+        // The document doesn't exist, so conditions on its current version can't be met
+        assert!(next_version(None, version(5, 10), Some(VersionCondition::Internal(5))).is_err());
+        assert!(next_version(None, version(5, 10), Some(VersionCondition::SeqNo { seq_no: 10, primary_term: PRIMARY_TERM })).is_err());
+    }
+
+    #[test]
+    fn test_conflict_message() {
+        let conflict = next_version(version(3, 10), None, Some(VersionCondition::SeqNo { seq_no: 9, primary_term: 1 })).unwrap_err();
+        assert_eq!(conflict.to_string(), "version conflict, required seqNo [9], primary term [1]. current document has seqNo [10] and primary term [1]");
+    }
+}
@@ -41,7 +41,7 @@ impl System {
         metadata_path.push("metadata.json");
         let metadata = IndexMetadata::load(metadata_path)?;
 
-        Ok(Index::new(id, name, metadata, store))
+        Ok(Index::new(id, name, metadata, Box::new(store)))
     }
 
     pub fn load_indices(&self) {